@@ -12,15 +12,15 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use bip_utils::read_from_bip_buffer;
+use bip_utils::read_frame_from_bip_buffer;
 use filter::errors::*;
 use filter::*;
 use logging::*;
-use socket_utils::buffered_socket_reader::BufferedSocketReader;
-use socket_utils::buffered_socket_writer::BufferedSocketWriter;
-use spsc_bip_buffer::bip_buffer_with_len;
+use socket_utils::link::*;
+use bip_utils::bip_buffer_with_len;
 use statistics_handler::*;
 use std::thread;
+use std::time::{Duration, Instant};
 use structopt::StructOpt;
 
 fn main() {
@@ -42,7 +42,7 @@ fn main() {
 }
 
 /// This filter checks for the first bytes of the incoming data. If it matches the configured "word_to_filter" then it drops the data.
-/// If the data is corrupted and cannot be read as KafkaMessage then the data will always drop.
+/// If the data is corrupted and cannot be read as Envelope then the data will always drop.
 fn filter() -> Result<()> {
     let opt = arguments::OptIngress::from_args();
     set_syslog(
@@ -60,6 +60,15 @@ fn filter() -> Result<()> {
     let stats_addr = format!("{}:{}", opt.host_stats_server, opt.port_stats_server);
     let stats: StatsdClient<StatsAllHandlers> =
         StatsdClient::<StatsAllHandlers>::new_with_custom_fields(Some("filtered"), None);
+    let stats = stats
+        .with_metrics_format(opt.metrics_format)
+        .with_interval(Duration::from_millis(opt.stats_interval_ms));
+    let stats = match &opt.metrics_address {
+        Some(metrics_address) => stats
+            .with_metrics_endpoint(metrics_address, &opt.handler_name)
+            .chain_err(|| "Error while serving metrics")?,
+        None => stats,
+    };
     stats
         .run(stats_addr, opt.handler_name)
         .chain_err(|| "Error while running statitics")?;
@@ -72,13 +81,26 @@ fn filter() -> Result<()> {
     //Create bipbuffers with the a size of 1Mb times the incoming bip_bupffer_element_count in argument
     let (bip_writer_first, mut bip_reader_first) =
         bip_buffer_with_len(opt.bip_buffer_element_count * BUFFER_SIZE_BYTES);
-    let (mut bip_writer_second, mut bip_reader_second) =
+    let bip_writer_first = bip_writer_first
+        .with_overflow_policy(opt.overflow_policy, &opt.spill_directory, opt.spill_max_bytes)
+        .with_stats("bip_first", stats.get_data_clone());
+    let (bip_writer_second, mut bip_reader_second) =
         bip_buffer_with_len(opt.bip_buffer_element_count * BUFFER_SIZE_BYTES);
+    let mut bip_writer_second = bip_writer_second
+        .with_overflow_policy(opt.overflow_policy, &opt.spill_directory, opt.spill_max_bytes)
+        .with_stats("bip_second", stats.get_data_clone());
 
-    let mut socket_reader = BufferedSocketReader::new(&opt.socket_path_in, bip_writer_first)
-        .chain_err(|| "Error while creating socket reader")?;
-    let mut socket_writer = BufferedSocketWriter::start_listening(&opt.socket_path_out)
-        .chain_err(|| "Error creating socket writer")?;
+    let mut socket_reader = LinkReader::new(opt.link_type, &opt.socket_path_in, bip_writer_first)
+        .chain_err(|| "Error while creating socket reader")?
+        .with_stats(stats.get_data_clone());
+    let mut socket_writer = LinkWriter::start_listening_fan_out(
+        opt.link_type,
+        &opt.socket_path_out,
+        opt.fan_out,
+        opt.fan_out_mode,
+    )
+    .chain_err(|| "Error creating socket writer")?
+    .with_stats(stats.get_data_clone());
 
     //3 threads:
     //- get_data_from_socket_send_to_bip_buffer
@@ -89,20 +111,22 @@ fn filter() -> Result<()> {
 
     //Clone word_to_filter for the filtering thread
     let word_to_filter = opt.word_to_filter;
+    let filter_time = stats_data.register_timer("filter.time");
 
     let filtering = thread::Builder::new()
         .name("filtering".into())
         .spawn(move || {
             let mut buffer = [0; BUFFER_SIZE_BYTES];
             loop {
-                let element_length = read_from_bip_buffer(&mut bip_reader_first, &mut buffer);
+                let frame_length = read_frame_from_bip_buffer(&mut bip_reader_first, &mut buffer);
+                let start = Instant::now();
                 filtering(
-                    &buffer,
-                    element_length,
+                    &buffer[..frame_length],
                     &mut bip_writer_second,
                     &word_to_filter,
                     &stats_data,
                 );
+                filter_time.record_duration(start.elapsed());
             }
         })?;
 
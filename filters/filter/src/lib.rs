@@ -12,9 +12,11 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use bip_utils::write_to_bip_buffer;
-use ph_kafka::KafkaMessage;
-use spsc_bip_buffer::BipBufferWriter;
+use bip_utils::frame::{FrameHeader, FrameKind};
+use bip_utils::write_frame_bytes_to_bip_buffer;
+use framework_constants::FRAME_HEADER_LEN;
+use socket_utils::envelope::Envelope;
+use bip_utils::BipBufferWriter;
 use std::sync::Arc;
 
 use std::str;
@@ -24,46 +26,74 @@ pub mod arguments;
 pub mod errors;
 
 ///The maximum size in bytes of a single bipbuffer message.
-//a bit more allocated then needed. 1_048_576(1 Mb) is needed + BIP_BUFFER_LEN_FIELD_LEN
+//a bit more allocated then needed. 1_048_576(1 Mb) is needed + FRAME_HEADER_LEN
 pub const BUFFER_SIZE_BYTES: usize = 1_050_000;
 
-///Check for the first bytes of a kafka message. If it matches the word_to_filter then it drops the data. Else it is written to the bipbuffer.
+///Check for the first bytes of the payload of a frame. If it matches the word_to_filter then it drops the data. Else the frame is written unchanged to the bipbuffer.
+///The payload of an envelope is checked, so the metadata of the envelope is kept.
 pub fn filtering(
-    buffer: &[u8; BUFFER_SIZE_BYTES],
-    element_length: usize,
+    frame: &[u8],
     bip_writer_second: &mut BipBufferWriter,
     word_to_filter: &str,
     stats_data: &Arc<statistics_handler::StatsAllHandlers>,
 ) {
-    match KafkaMessage::deserialize_packet(&buffer[..element_length]) {
-        Ok(kafka_message) => {
-            let message = kafka_message.payload;
-            if message.len() >= word_to_filter.len() {
-                match str::from_utf8(&message[0..word_to_filter.len()]) {
-                    Ok(first_x_bytes_tex) => {
-                        if first_x_bytes_tex == word_to_filter {
-                            log::info!("Did not sent packet because it was filtered. Packet contained: {}!", word_to_filter);
-                            stats_data.dropped_packets.add(1);
-                            stats_data.dropped_bytes.add(element_length as u64);
-                        } else {
-                            write_to_bip_buffer(bip_writer_second, &buffer[..element_length]);
-                        }
-                    }
-                    //The the first x bytes of the incoming data is not utf8 then it cannot be checked and will be sent to the bipbuffer
-                    Err(_) => {
-                        write_to_bip_buffer(bip_writer_second, &buffer[..element_length])
-                    }
+    let message = match FrameHeader::from_bytes(frame) {
+        Ok(header) if header.kind == FrameKind::Envelope => {
+            match Envelope::from_bytes(&frame[FRAME_HEADER_LEN..]) {
+                Ok(envelope) => envelope.payload,
+                //The data cannot be converted to an envelope. It is problably corrupted data so it will be dropped.
+                Err(e) => {
+                    log::warn!("Cannot read the envelope: {}", e);
+                    stats_data.dropped_packets.add(1);
+                    stats_data.dropped_bytes.add(frame.len() as u64);
+                    return;
                 }
-            //The data is smaller then the word_to_filter it will never match so it will be sent to the bipbuffer
-            } else {
-                write_to_bip_buffer(bip_writer_second, &buffer[..element_length])
             }
         }
-        //The data cannot be converted to a kafka message. It is problably corrupted data so it will be dropped.
+        Ok(_) => frame[FRAME_HEADER_LEN..].to_vec(),
         Err(e) => {
-            log::warn!("Cannot read the kafka message: {}", e);
+            log::warn!("Cannot read the frame: {}", e);
             stats_data.dropped_packets.add(1);
-            stats_data.dropped_bytes.add(element_length as u64);
+            stats_data.dropped_bytes.add(frame.len() as u64);
+            return;
         }
     };
+    if message.len() >= word_to_filter.len() {
+        match str::from_utf8(&message[0..word_to_filter.len()]) {
+            Ok(first_x_bytes_tex) => {
+                if first_x_bytes_tex == word_to_filter {
+                    log::info!(
+                        "Did not sent packet because it was filtered. Packet contained: {}!",
+                        word_to_filter
+                    );
+                    stats_data.dropped_packets.add(1);
+                    stats_data.dropped_bytes.add(frame.len() as u64);
+                    stats_data
+                        .registry
+                        .counter("filter.matched", &[("rule", word_to_filter)])
+                        .add(1);
+                } else {
+                    forward(bip_writer_second, frame, stats_data);
+                }
+            }
+            //The the first x bytes of the incoming data is not utf8 then it cannot be checked and will be sent to the bipbuffer
+            Err(_) => forward(bip_writer_second, frame, stats_data),
+        }
+    //The data is smaller then the word_to_filter it will never match so it will be sent to the bipbuffer
+    } else {
+        forward(bip_writer_second, frame, stats_data)
+    }
+}
+
+///Write the frame unchanged to the bipbuffer.
+fn forward(
+    bip_writer_second: &mut BipBufferWriter,
+    frame: &[u8],
+    stats_data: &Arc<statistics_handler::StatsAllHandlers>,
+) {
+    if let Err(e) = write_frame_bytes_to_bip_buffer(bip_writer_second, frame) {
+        log::warn!("Cannot forward the frame: {}", e);
+        stats_data.dropped_packets.add(1);
+        stats_data.dropped_bytes.add(frame.len() as u64);
+    }
 }
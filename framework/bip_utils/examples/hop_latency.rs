@@ -0,0 +1,182 @@
+// Copyright 2020 Ministerie van Defensie
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!Measures the latency of a message through a chain of bip_buffers, with a thread per hop.
+//!The messages are sent at irregular intervals, so the waiting threads are idle in between like in a quiet chain.
+//!Run with `cargo run --release -p bip_utils --example hop_latency`.
+//!With `-- --baseline` the hops wait like the bip_buffer functions did before they were woken up:
+//!spinning 100,000 times and then sleeping 100 ms.
+
+use bip_utils::frame::{FrameHeader, FrameKind};
+use bip_utils::{bip_buffer_with_len, read_frame_from_bip_buffer, write_frame_bytes_to_bip_buffer};
+use framework_constants::*;
+use std::time::{Duration, Instant};
+
+const HOPS: usize = 4;
+const MESSAGE_COUNT: usize = 200;
+const IDLE_MEASUREMENT: Duration = Duration::from_secs(2);
+
+///Writes a frame to the first bip_buffer of a chain.
+type SendFrame = Box<dyn FnMut(&[u8])>;
+///Reads a frame from the last bip_buffer of a chain and returns its length.
+type ReceiveFrame = Box<dyn FnMut(&mut [u8]) -> usize + Send>;
+
+fn main() {
+    let baseline = std::env::args().any(|arg| arg == "--baseline");
+    let start = Instant::now();
+    let (mut send, mut receive) = if baseline {
+        polling_chain()
+    } else {
+        wakeup_chain()
+    };
+    let receiving = std::thread::spawn(move || {
+        let mut frame = vec![0; MAX_BIP_BUFFER_MESSAGE_SIZE];
+        (0..MESSAGE_COUNT)
+            .map(|_| {
+                receive(&mut frame);
+                let mut sent = [0; 8];
+                sent.copy_from_slice(&frame[FRAME_HEADER_LEN..FRAME_HEADER_LEN + 8]);
+                start.elapsed() - Duration::from_nanos(u64::from_le_bytes(sent))
+            })
+            .collect::<Vec<Duration>>()
+    });
+
+    for i in 0..MESSAGE_COUNT {
+        //between 1 and 20 ms between messages
+        std::thread::sleep(Duration::from_micros(1_000 + (i as u64 * 7_919) % 19_000));
+        let mut frame = vec![0; FRAME_HEADER_LEN + 1_000];
+        frame[..FRAME_HEADER_LEN]
+            .copy_from_slice(&FrameHeader::new(FrameKind::Data, 1_000).to_bytes());
+        frame[FRAME_HEADER_LEN..FRAME_HEADER_LEN + 8]
+            .copy_from_slice(&(start.elapsed().as_nanos() as u64).to_le_bytes());
+        send(&frame);
+    }
+    let mut latencies = receiving.join().expect("receiving thread failed");
+    latencies.sort_unstable();
+    let per_hop = |latency: Duration| latency / HOPS as u32;
+    println!(
+        "{}: {HOPS} hops, {MESSAGE_COUNT} messages of 1000 bytes, 1 to 20 ms apart",
+        if baseline { "polling" } else { "wakeups" }
+    );
+    println!(
+        "per hop latency: p50 {:?}, p90 {:?}, p99 {:?}, max {:?}",
+        per_hop(latencies[MESSAGE_COUNT / 2]),
+        per_hop(latencies[MESSAGE_COUNT * 9 / 10]),
+        per_hop(latencies[MESSAGE_COUNT * 99 / 100]),
+        per_hop(latencies[MESSAGE_COUNT - 1])
+    );
+
+    //the hop threads are waiting for data now
+    let cpu_before = cpu_time();
+    std::thread::sleep(IDLE_MEASUREMENT);
+    let cpu_idle = cpu_time() - cpu_before;
+    println!(
+        "cpu time of {} idle waiting threads: {:.1}% of one core",
+        HOPS - 1,
+        cpu_idle.as_secs_f64() / IDLE_MEASUREMENT.as_secs_f64() * 100.0
+    );
+}
+
+///A chain of bip_buffers whose waiting threads are woken up.
+fn wakeup_chain() -> (SendFrame, ReceiveFrame) {
+    let (mut first_writer, mut reader) = bip_buffer_with_len(MAX_BIP_BUFFER_MESSAGE_SIZE * 2);
+    //every hop reads a frame from one bip_buffer and writes it to the next
+    for _ in 1..HOPS {
+        let (mut writer, next_reader) = bip_buffer_with_len(MAX_BIP_BUFFER_MESSAGE_SIZE * 2);
+        let mut hop_reader = reader;
+        reader = next_reader;
+        std::thread::spawn(move || {
+            let mut frame = vec![0; MAX_BIP_BUFFER_MESSAGE_SIZE];
+            loop {
+                let length = read_frame_from_bip_buffer(&mut hop_reader, &mut frame);
+                write_frame_bytes_to_bip_buffer(&mut writer, &frame[..length])
+                    .expect("invalid frame");
+            }
+        });
+    }
+    (
+        Box::new(move |frame| {
+            write_frame_bytes_to_bip_buffer(&mut first_writer, frame)
+                .expect("Can't write to bip buffer");
+        }),
+        Box::new(move |frame| read_frame_from_bip_buffer(&mut reader, frame)),
+    )
+}
+
+///A chain of bip_buffers whose waiting threads poll, the way the bip_buffer functions waited before.
+fn polling_chain() -> (SendFrame, ReceiveFrame) {
+    let (mut first_writer, mut reader) =
+        spsc_bip_buffer::bip_buffer_with_len(MAX_BIP_BUFFER_MESSAGE_SIZE * 2);
+    for _ in 1..HOPS {
+        let (mut writer, next_reader) =
+            spsc_bip_buffer::bip_buffer_with_len(MAX_BIP_BUFFER_MESSAGE_SIZE * 2);
+        let mut hop_reader = reader;
+        reader = next_reader;
+        std::thread::spawn(move || {
+            let mut frame = vec![0; MAX_BIP_BUFFER_MESSAGE_SIZE];
+            loop {
+                let length = polling_read(&mut hop_reader, &mut frame);
+                polling_write(&mut writer, &frame[..length]);
+            }
+        });
+    }
+    (
+        Box::new(move |frame| polling_write(&mut first_writer, frame)),
+        Box::new(move |frame| polling_read(&mut reader, frame)),
+    )
+}
+
+fn polling_write(writer: &mut spsc_bip_buffer::BipBufferWriter, frame: &[u8]) {
+    let mut reservation = writer.spin_reserve(frame.len());
+    reservation.copy_from_slice(frame);
+    reservation.send();
+}
+
+fn polling_read(reader: &mut spsc_bip_buffer::BipBufferReader, frame: &mut [u8]) -> usize {
+    polling_wait_for_data(reader, FRAME_HEADER_LEN);
+    let header =
+        FrameHeader::from_bytes(&reader.valid()[..FRAME_HEADER_LEN]).expect("invalid frame");
+    let length = FRAME_HEADER_LEN + header.payload_length();
+    polling_wait_for_data(reader, length);
+    frame[..length].copy_from_slice(&reader.valid()[..length]);
+    reader.consume(length);
+    length
+}
+
+///The loop `bip_utils::wait_for_data` used before waiting threads were woken up.
+fn polling_wait_for_data(reader: &mut spsc_bip_buffer::BipBufferReader, bytes: usize) {
+    let mut spin_count = 0;
+    while reader.valid().len() < bytes {
+        if spin_count < 100_000 {
+            std::hint::spin_loop();
+            spin_count += 1;
+        } else {
+            std::thread::sleep(Duration::from_millis(100));
+            spin_count = 0;
+        }
+    }
+}
+
+///The user and system time used by this process, read from /proc/self/stat.
+fn cpu_time() -> Duration {
+    let stat = std::fs::read_to_string("/proc/self/stat").expect("can't read /proc/self/stat");
+    //the fields after the process name, which is between parentheses
+    let fields = stat[stat.rfind(')').expect("malformed /proc/self/stat") + 2..]
+        .split_whitespace()
+        .collect::<Vec<&str>>();
+    let ticks = fields[11].parse::<u64>().expect("malformed utime")
+        + fields[12].parse::<u64>().expect("malformed stime");
+    //the kernel reports in clock ticks of 10 ms
+    Duration::from_millis(ticks * 10)
+}
@@ -0,0 +1,178 @@
+// Copyright 2020 Ministerie van Defensie
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::errors::ErrorKind::InvalidFrame;
+use crate::errors::*;
+use crate::frame::{FrameHeader, FrameKind};
+use crate::{BipBufferReader, BipBufferWriter, BipBufferWriterReservation, OverflowPolicy};
+use framework_constants::*;
+
+///Writes to the bip_buffer, see `bip_utils::write_to_bip_buffer`.
+///Waits for space without blocking the thread of the runtime.
+/// # Arguments
+/// * `writer` - The bipBufferWriter used to write to the bip_buffer.
+/// * `buffer` - The buffer that should be written to the bip_buffer.
+/// # Returns
+/// * `bool` - False when the frame was dropped because the bip_buffer is full.
+pub async fn write_to_bip_buffer(writer: &mut BipBufferWriter, buffer: &[u8]) -> Result<bool> {
+    write_frame_to_bip_buffer(
+        writer,
+        FrameHeader::new(FrameKind::Data, buffer.len()),
+        buffer,
+    )
+    .await
+}
+
+///Writes a frame to the bip_buffer, see `bip_utils::write_frame_to_bip_buffer`.
+///Waits for space without blocking the thread of the runtime.
+/// # Arguments
+/// * `writer` - The bipBufferWriter used to write to the bip_buffer.
+/// * `header` - The header of the frame, its length should equal the length of `buffer`.
+/// * `buffer` - The payload of the frame.
+/// # Returns
+/// * `bool` - False when the frame was dropped because the bip_buffer is full.
+pub async fn write_frame_to_bip_buffer(
+    writer: &mut BipBufferWriter,
+    header: FrameHeader,
+    buffer: &[u8],
+) -> Result<bool> {
+    debug_assert_eq!(header.payload_length(), buffer.len());
+    write_frame(writer, &[&header.to_bytes(), buffer]).await
+}
+
+///Writes a complete frame (header and payload) to the bip_buffer, see `bip_utils::write_frame_bytes_to_bip_buffer`.
+///Waits for space without blocking the thread of the runtime.
+/// # Arguments
+/// * `writer` - The bipBufferWriter used to write to the bip_buffer.
+/// * `frame` - The frame header followed by the payload.
+/// # Returns
+/// * `bool` - False when the frame was dropped because the bip_buffer is full.
+pub async fn write_frame_bytes_to_bip_buffer(
+    writer: &mut BipBufferWriter,
+    frame: &[u8],
+) -> Result<bool> {
+    let header = FrameHeader::from_bytes(frame)?;
+    if header.payload_length() + FRAME_HEADER_LEN != frame.len() {
+        return Err(InvalidFrame(format!(
+            "frame header announces {} bytes of payload, but {} bytes were received",
+            header.payload_length(),
+            frame.len() - FRAME_HEADER_LEN
+        ))
+        .into());
+    }
+    write_frame(writer, &[frame]).await
+}
+
+///Writes a frame, given as its parts, following the OverflowPolicy of the writer.
+///Only OverflowPolicy::Block waits, the other policies never wait longer than a moment.
+async fn write_frame(writer: &mut BipBufferWriter, parts: &[&[u8]]) -> Result<bool> {
+    if writer.overflow_policy() != OverflowPolicy::Block {
+        return writer.write_frame(parts);
+    }
+    let len = parts.iter().map(|part| part.len()).sum();
+    let mut reservation = reserve_frame(writer, len)
+        .await?
+        .expect("a blocking reservation always succeeds");
+    let mut offset = 0;
+    for part in parts {
+        reservation[offset..offset + part.len()].copy_from_slice(part);
+        offset += part.len();
+    }
+    reservation.send();
+    Ok(true)
+}
+
+///Reads from the bip_buffer, see `bip_utils::read_from_bip_buffer`.
+///Waits for data without blocking the thread of the runtime.
+/// # Arguments
+/// * `reader` - The bipBufferReader used to read from the bip_buffer.
+/// * `buffer` - The buffer to be filled with data from the bip_buffer.
+/// # Returns
+/// * `usize` - The amount of bytes read from the bip_buffer.
+pub async fn read_from_bip_buffer(reader: &mut BipBufferReader, buffer: &mut [u8]) -> usize {
+    let element_length = read_frame_header(reader).await.payload_length();
+    wait_for_data(reader, element_length).await;
+    buffer[..element_length].copy_from_slice(&reader.valid()[..element_length]);
+    reader.consume(element_length);
+    element_length
+}
+
+///Reads a complete frame (header and payload) from the bip_buffer, see `bip_utils::read_frame_from_bip_buffer`.
+///Waits for data without blocking the thread of the runtime.
+/// # Arguments
+/// * `reader` - The bipBufferReader used to read from the bip_buffer.
+/// * `buffer` - The buffer to be filled with the frame.
+/// # Returns
+/// * `usize` - The length of the frame in bytes, including the frame header.
+pub async fn read_frame_from_bip_buffer(reader: &mut BipBufferReader, buffer: &mut [u8]) -> usize {
+    let frame_length = FRAME_HEADER_LEN + peek_frame_header(reader).await.payload_length();
+    wait_for_data(reader, frame_length).await;
+    buffer[..frame_length].copy_from_slice(&reader.valid()[..frame_length]);
+    reader.consume(frame_length);
+    frame_length
+}
+
+///Gets and consumes the frame header of the next element, see `bip_utils::read_frame_header`.
+/// # Arguments
+/// * `reader` - The bipBufferReader used to read the frame header.
+/// # Returns
+/// * `FrameHeader` - The header of the next element.
+pub async fn read_frame_header(reader: &mut BipBufferReader) -> FrameHeader {
+    let header = peek_frame_header(reader).await;
+    reader.consume(FRAME_HEADER_LEN);
+    header
+}
+
+///Gets the frame header of the next element without consuming it, see `bip_utils::peek_frame_header`.
+/// # Arguments
+/// * `reader` - The bipBufferReader used to read the frame header.
+/// # Returns
+/// * `FrameHeader` - The header of the next element.
+pub async fn peek_frame_header(reader: &mut BipBufferReader) -> FrameHeader {
+    reader.drop_requested_frames();
+    wait_for_data(reader, FRAME_HEADER_LEN).await;
+    FrameHeader::from_bytes(&reader.valid()[..FRAME_HEADER_LEN])
+        .expect("Invalid frame header in bip_buffer")
+}
+
+///Wait for the given amount of bytes to be available for reading in the bip_buffer.
+///The task is woken up by the writer, it does not block the thread of the runtime.
+/// # Arguments
+/// * `reader` - The bipBufferReader used to read from the bip_buffer.
+/// * `bytes` - The amount of available bytes to wait for
+pub async fn wait_for_data(reader: &mut BipBufferReader, bytes: usize) {
+    reader.wait_for_data_async(bytes).await;
+}
+
+///Reserves space for a frame that still has to be received, see `BipBufferWriter::reserve_frame`.
+///With OverflowPolicy::Block this waits for space without blocking the thread of the runtime.
+/// # Arguments
+/// * `writer` - The bipBufferWriter used to write to the bip_buffer.
+/// * `len` - The length of the frame, including its frame header.
+pub async fn reserve_frame(
+    writer: &mut BipBufferWriter,
+    len: usize,
+) -> Result<Option<BipBufferWriterReservation<'_>>> {
+    if writer.overflow_policy() != OverflowPolicy::Block {
+        return writer.reserve_frame(len);
+    }
+    writer.check_frame_length(len)?;
+    writer.wait_for_space_async(len).await;
+    //only the reader changes the bip_buffer besides this writer, and it only frees space
+    Ok(Some(
+        writer
+            .reserve(len)
+            .expect("No space in bip_buffer after waiting for it"),
+    ))
+}
@@ -0,0 +1,650 @@
+// Copyright 2020 Ministerie van Defensie
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::errors::ErrorKind::ElementTooLarge;
+use crate::errors::*;
+use crate::frame::FrameHeader;
+use crate::overflow::{OverflowPolicy, SpillFile};
+use framework_constants::FRAME_HEADER_LEN;
+use statistics_handler::{BufferStatistics, StatsAllHandlers};
+use std::path::PathBuf;
+use std::sync::atomic::{fence, AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, OnceLock, PoisonError};
+use std::time::{Duration, Instant};
+
+///The number of times a waiting thread checks the bip_buffer before it blocks.
+///Spinning a little avoids the cost of blocking when data follows quickly.
+const SPIN_COUNT: usize = 1_000;
+///A blocked thread checks the bip_buffer at least this often.
+///Wakeups are not lost, this only bounds the damage should that ever happen.
+const WAIT_TIMEOUT: Duration = Duration::from_millis(100);
+///How long a writer with OverflowPolicy::DropOldest waits for the reader to drop the oldest frames.
+///The reader can only drop frames it has not started on, when it is stuck in a frame the new frame is dropped.
+const DROP_OLDEST_TIMEOUT: Duration = Duration::from_millis(100);
+///The maximum size of a spill file when no other limit is given, 1 GiB.
+pub const DEFAULT_SPILL_MAX_BYTES: u64 = 1 << 30;
+const WRITER_GIVEN_AWAY: &str = "the writing end is only given away with OverflowPolicy::Spill";
+
+///Creates a bip_buffer of `len` bytes that wakes up a waiting reader when data is written,
+///and a waiting writer when data is consumed.
+///The writer waits for space when the bip_buffer is full, see `BipBufferWriter::with_overflow_policy` for alternatives.
+/// # Arguments
+/// * `len` - The size of the bip_buffer in bytes.
+pub fn bip_buffer_with_len(len: usize) -> (BipBufferWriter, BipBufferReader) {
+    let (writer, reader) = spsc_bip_buffer::bip_buffer_with_len(len);
+    let shared = Arc::new(Shared::default());
+    (
+        BipBufferWriter {
+            writer: Some(writer),
+            shared: shared.clone(),
+            len,
+            policy: OverflowPolicy::Block,
+            spill_directory: std::env::temp_dir(),
+            spill_max_bytes: DEFAULT_SPILL_MAX_BYTES,
+            spill_file: None,
+            stalled_at: None,
+        },
+        BipBufferReader { reader, shared },
+    )
+}
+
+///The writing end of a bip_buffer, see `spsc_bip_buffer::BipBufferWriter`.
+pub struct BipBufferWriter {
+    ///None while spilled frames wait, the writing end is then in `Shared::spill` together with the spill file.
+    writer: Option<spsc_bip_buffer::BipBufferWriter>,
+    shared: Arc<Shared>,
+    len: usize,
+    policy: OverflowPolicy,
+    spill_directory: PathBuf,
+    spill_max_bytes: u64,
+    ///Created when the first frame is spilled, kept here while no frames are spilled.
+    spill_file: Option<SpillFile>,
+    ///The number of bytes the reader had consumed when it last failed to drop the oldest frames.
+    stalled_at: Option<u64>,
+}
+
+impl BipBufferWriter {
+    ///Sets what happens with a frame that does not fit in the bip_buffer.
+    /// # Arguments
+    /// * `policy` - The OverflowPolicy.
+    /// * `spill_directory` - The directory the spill file is created in, used with OverflowPolicy::Spill.
+    /// * `spill_max_bytes` - The maximum size of the spill file, frames that would exceed it are dropped.
+    pub fn with_overflow_policy(
+        mut self,
+        policy: OverflowPolicy,
+        spill_directory: &str,
+        spill_max_bytes: u64,
+    ) -> BipBufferWriter {
+        self.policy = policy;
+        self.spill_directory = PathBuf::from(spill_directory);
+        self.spill_max_bytes = spill_max_bytes;
+        self
+    }
+
+    ///Reports the fill level, the high-water mark and the time spent waiting for space as gauges named after the bip_buffer,
+    ///and counts the frames that are dropped because of the OverflowPolicy in `dropped_packets` and `dropped_bytes`.
+    /// # Arguments
+    /// * `name` - The name of the bip_buffer in the statistics, for example "bip_first".
+    /// * `stats_data` - The statistics of the handler.
+    pub fn with_stats(self, name: &str, stats_data: Arc<StatsAllHandlers>) -> BipBufferWriter {
+        let buffer_stats = stats_data.register_buffer(name, self.len);
+        //a bip_buffer reports to the statistics it was given first
+        let _ = self.shared.buffer_stats.set(buffer_stats);
+        let _ = self.shared.stats_data.set(stats_data);
+        self
+    }
+
+    pub fn overflow_policy(&self) -> OverflowPolicy {
+        self.policy
+    }
+
+    ///Reserves `len` bytes, returns None when there is not enough space or when spilled frames have to be written first.
+    ///The data becomes available to the reader when the reservation is sent or dropped.
+    pub fn reserve(&mut self, len: usize) -> Option<BipBufferWriterReservation<'_>> {
+        if !self.take_back_writer() {
+            return None;
+        }
+        let shared = &self.shared;
+        self.writer
+            .as_mut()?
+            .reserve(len)
+            .map(|reservation| BipBufferWriterReservation {
+                reservation: Some(reservation),
+                shared,
+            })
+    }
+
+    ///Reserves `len` bytes, blocks until the reader has consumed enough data to make space.
+    ///The data becomes available to the reader when the reservation is sent or dropped.
+    ///A reservation can be at most half the bip_buffer, a larger one may never fit, depending on where the last one ended.
+    pub fn blocking_reserve(&mut self, len: usize) -> BipBufferWriterReservation<'_> {
+        assert!(
+            len <= self.len / 2,
+            "reservation larger than half the bip_buffer"
+        );
+        self.wait_for_spilled_frames();
+        self.wait_for_space(len);
+        self.reserve(len)
+            .expect("space in the bip_buffer can only be freed while waiting")
+    }
+
+    ///Reserves space for a frame of `len` bytes that still has to be received, following the OverflowPolicy.
+    ///With OverflowPolicy::Block this waits for space. With the other policies it returns None when the frame does not fit,
+    ///the frame should then be received in a separate buffer and passed to `write_frame`.
+    /// # Arguments
+    /// * `len` - The length of the frame, including its frame header.
+    pub fn reserve_frame(&mut self, len: usize) -> Result<Option<BipBufferWriterReservation<'_>>> {
+        self.check_frame_length(len)?;
+        if self.policy == OverflowPolicy::Block {
+            return Ok(Some(self.blocking_reserve(len)));
+        }
+        self.drain_spill()?;
+        Ok(self.reserve(len))
+    }
+
+    ///Writes a frame, given as its parts, to the bip_buffer following the OverflowPolicy.
+    ///Returns an error when the frame is larger than half the bip_buffer, such a frame may never fit.
+    /// # Arguments
+    /// * `parts` - The frame header and the payload, in one or more parts.
+    /// # Returns
+    /// * `bool` - False when the frame was dropped.
+    pub fn write_frame(&mut self, parts: &[&[u8]]) -> Result<bool> {
+        let len = parts.iter().map(|part| part.len()).sum();
+        self.check_frame_length(len)?;
+        self.drain_spill()?;
+        let has_space = match self.policy {
+            OverflowPolicy::Block => {
+                self.wait_for_space(len);
+                true
+            }
+            OverflowPolicy::DropNewest => fits(self.writer.as_mut().expect(WRITER_GIVEN_AWAY), len),
+            OverflowPolicy::DropOldest => self.make_space(len),
+            OverflowPolicy::Spill => {
+                self.take_back_writer() && fits(self.writer.as_mut().expect(WRITER_GIVEN_AWAY), len)
+            }
+        };
+        if !has_space {
+            if self.policy == OverflowPolicy::Spill {
+                return self.spill(parts, len);
+            }
+            self.shared.count_dropped(len);
+            return Ok(false);
+        }
+        let mut reservation = self
+            .reserve(len)
+            .expect("space in the bip_buffer can only be freed while waiting");
+        let mut offset = 0;
+        for part in parts {
+            reservation[offset..offset + part.len()].copy_from_slice(part);
+            offset += part.len();
+        }
+        reservation.send();
+        Ok(true)
+    }
+
+    ///Returns an error when a frame of `len` bytes, including its frame header, is larger than half the bip_buffer.
+    ///A larger frame may never fit, depending on where the last frame ended.
+    pub fn check_frame_length(&self, len: usize) -> Result<()> {
+        if len > self.len / 2 {
+            return Err(ElementTooLarge(format!(
+                "a frame of {len} bytes may never fit in a bip_buffer of {} bytes, frames can be at most half the bip_buffer",
+                self.len
+            ))
+            .into());
+        }
+        Ok(())
+    }
+
+    ///Blocks until `len` bytes can be reserved.
+    fn wait_for_space(&mut self, len: usize) {
+        let writer = self.writer.as_mut().expect(WRITER_GIVEN_AWAY);
+        if fits(writer, len) {
+            return;
+        }
+        let start = Instant::now();
+        self.shared.space.wait_until(|| fits(writer, len), None);
+        self.add_blocked(start.elapsed());
+    }
+
+    ///Waits on the tokio runtime until `len` bytes can be reserved, without blocking the thread.
+    #[cfg(feature = "tokio")]
+    pub(crate) async fn wait_for_space_async(&mut self, len: usize) {
+        let writer = self.writer.as_mut().expect(WRITER_GIVEN_AWAY);
+        if fits(writer, len) {
+            return;
+        }
+        let start = Instant::now();
+        self.shared
+            .space
+            .wait_until_async(|| fits(writer, len))
+            .await;
+        self.add_blocked(start.elapsed());
+    }
+
+    ///Registers time spent waiting for space in the statistics of the bip_buffer.
+    pub(crate) fn add_blocked(&self, duration: Duration) {
+        if let Some(buffer_stats) = self.shared.buffer_stats.get() {
+            buffer_stats.add_blocked(duration);
+        }
+    }
+
+    ///Takes the writing end back from the spill when all spilled frames have been written to the bip_buffer.
+    ///Returns false while spilled frames wait.
+    fn take_back_writer(&mut self) -> bool {
+        if self.writer.is_some() {
+            return true;
+        }
+        let mut spill = self.shared.lock_spill();
+        if spill.as_ref().is_some_and(|spill| !spill.file.is_empty()) {
+            return false;
+        }
+        let Spill { writer, file } = spill.take().expect(WRITER_GIVEN_AWAY);
+        self.shared.spilling.store(false, Ordering::Relaxed);
+        self.writer = Some(writer);
+        self.spill_file = Some(file);
+        log::info!("bip_buffer has space again, all spilled frames are written");
+        true
+    }
+
+    ///Blocks until all spilled frames have been written to the bip_buffer, the reader writes them after consuming data.
+    fn wait_for_spilled_frames(&mut self) {
+        if self.take_back_writer() {
+            return;
+        }
+        let start = Instant::now();
+        let shared = self.shared.clone();
+        shared.space.wait_until(|| self.take_back_writer(), None);
+        self.add_blocked(start.elapsed());
+    }
+
+    ///Asks the reader to drop the oldest frames until the frame fits.
+    ///Gives up right away when the reader did not consume anything since it last failed.
+    fn make_space(&mut self, len: usize) -> bool {
+        if fits(self.writer.as_mut().expect(WRITER_GIVEN_AWAY), len) {
+            self.stalled_at = None;
+            return true;
+        }
+        if self.stalled_at == Some(self.shared.consumed.load(Ordering::Acquire)) {
+            return false;
+        }
+        let start = Instant::now();
+        let writer = self.writer.as_mut().expect(WRITER_GIVEN_AWAY);
+        let shared = &self.shared;
+        let has_space = shared.space.wait_until(
+            || {
+                let has_space = fits(writer, len);
+                shared
+                    .discard_request
+                    .store(if has_space { 0 } else { len }, Ordering::Release);
+                has_space
+            },
+            Some(Instant::now() + DROP_OLDEST_TIMEOUT),
+        );
+        self.shared.discard_request.store(0, Ordering::Release);
+        self.add_blocked(start.elapsed());
+        self.stalled_at = if has_space {
+            None
+        } else {
+            Some(self.shared.consumed.load(Ordering::Acquire))
+        };
+        has_space
+    }
+
+    ///Writes a frame to the spill file, it is dropped when the spill file would exceed its maximum size.
+    ///The first spilled frame hands the writing end over to the spill, so the reader can move the frames to the bip_buffer.
+    /// # Returns
+    /// * `bool` - False when the frame was dropped.
+    fn spill(&mut self, parts: &[&[u8]], len: usize) -> Result<bool> {
+        if let Some(writer) = self.writer.take() {
+            let file = match self.spill_file.take() {
+                Some(file) => file,
+                None => match SpillFile::create(&self.spill_directory) {
+                    Ok(file) => file,
+                    Err(e) => {
+                        self.writer = Some(writer);
+                        return Err(e);
+                    }
+                },
+            };
+            log::warn!(
+                "bip_buffer is full, spilling frames to {}",
+                self.spill_directory.display()
+            );
+            *self.shared.lock_spill() = Some(Spill { writer, file });
+            self.shared.spilling.store(true, Ordering::Relaxed);
+            //pairs with the fence in BipBufferReader::consume: either the reader sees the spill, or the drain below sees the consumed space
+            fence(Ordering::SeqCst);
+        }
+        let mut spill = self.shared.lock_spill();
+        let spill = spill.as_mut().expect(WRITER_GIVEN_AWAY);
+        if spill.file.size() + len as u64 > self.spill_max_bytes {
+            self.shared.count_dropped(len);
+            return Ok(false);
+        }
+        spill.file.append(parts)?;
+        self.shared.drain_spill(spill)?;
+        Ok(true)
+    }
+
+    ///Moves spilled frames to the bip_buffer, as far as they fit.
+    fn drain_spill(&mut self) -> Result<()> {
+        if self.writer.is_some() {
+            return Ok(());
+        }
+        match self.shared.lock_spill().as_mut() {
+            Some(spill) => self.shared.drain_spill(spill),
+            None => Ok(()),
+        }
+    }
+}
+
+///Returns true when `len` bytes can be reserved.
+///A reservation is only committed when it is dropped, forgetting it leaves the bip_buffer unchanged.
+fn fits(writer: &mut spsc_bip_buffer::BipBufferWriter, len: usize) -> bool {
+    writer.reserve(len).map(std::mem::forget).is_some()
+}
+
+///A reservation in the bip_buffer, see `spsc_bip_buffer::BipBufferWriterReservation`.
+///Sending or dropping the reservation wakes up the reader.
+pub struct BipBufferWriterReservation<'a> {
+    reservation: Option<spsc_bip_buffer::BipBufferWriterReservation<'a>>,
+    shared: &'a Shared,
+}
+
+impl<'a> BipBufferWriterReservation<'a> {
+    ///Makes the data available to the reader, the same as dropping the reservation.
+    pub fn send(self) {}
+}
+
+impl<'a> std::ops::Deref for BipBufferWriterReservation<'a> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.reservation.as_ref().expect("reservation already sent")
+    }
+}
+
+impl<'a> std::ops::DerefMut for BipBufferWriterReservation<'a> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        self.reservation.as_mut().expect("reservation already sent")
+    }
+}
+
+impl<'a> Drop for BipBufferWriterReservation<'a> {
+    fn drop(&mut self) {
+        //commit before waking up the reader
+        if let Some(reservation) = self.reservation.take() {
+            let len = reservation.len();
+            drop(reservation);
+            self.shared.add_written(len);
+        }
+        self.shared.data.notify();
+    }
+}
+
+///The reading end of a bip_buffer, see `spsc_bip_buffer::BipBufferReader`.
+pub struct BipBufferReader {
+    reader: spsc_bip_buffer::BipBufferReader,
+    shared: Arc<Shared>,
+}
+
+impl BipBufferReader {
+    ///Returns the data that has been written and not yet consumed.
+    pub fn valid(&mut self) -> &mut [u8] {
+        self.reader.valid()
+    }
+
+    ///Marks `len` bytes as read, wakes up the writer when it waits for space.
+    ///Frames the writer has spilled are moved to the freed space, so they are not stuck in the spill file when the writer has nothing more to write.
+    pub fn consume(&mut self, len: usize) -> bool {
+        let consumed = self.reader.consume(len);
+        self.shared
+            .consumed
+            .fetch_add(len as u64, Ordering::Release);
+        if let Some(buffer_stats) = self.shared.buffer_stats.get() {
+            buffer_stats.add_consumed(len);
+        }
+        //pairs with the fence in BipBufferWriter::spill
+        fence(Ordering::SeqCst);
+        if self.shared.spilling.load(Ordering::Relaxed) {
+            if let Some(spill) = self.shared.lock_spill().as_mut() {
+                if let Err(e) = self.shared.drain_spill(spill) {
+                    log::error!("Can't move spilled frames to the bip_buffer: {}", e);
+                }
+            }
+        }
+        self.shared.space.notify();
+        consumed
+    }
+
+    ///Blocks until `bytes` bytes can be read.
+    pub fn wait_for_data(&mut self, bytes: usize) {
+        let reader = &mut self.reader;
+        self.shared
+            .data
+            .wait_until(|| reader.valid().len() >= bytes, None);
+    }
+
+    ///Blocks until `bytes` bytes can be read, or until the deadline has passed.
+    /// # Returns
+    /// * `bool` - True when `bytes` bytes can be read.
+    pub fn wait_for_data_until(&mut self, bytes: usize, deadline: Instant) -> bool {
+        let reader = &mut self.reader;
+        self.shared
+            .data
+            .wait_until(|| reader.valid().len() >= bytes, Some(deadline))
+    }
+
+    ///Waits on the tokio runtime until `bytes` bytes can be read, without blocking the thread.
+    #[cfg(feature = "tokio")]
+    pub(crate) async fn wait_for_data_async(&mut self, bytes: usize) {
+        let reader = &mut self.reader;
+        self.shared
+            .data
+            .wait_until_async(|| reader.valid().len() >= bytes)
+            .await;
+    }
+
+    ///Drops the oldest frames when the writer asks for space with OverflowPolicy::DropOldest.
+    ///Must only be called before the reader starts on the next frame.
+    pub fn drop_requested_frames(&mut self) {
+        while self.shared.discard_request.load(Ordering::Acquire) > 0 {
+            let valid = self.reader.valid();
+            if valid.len() < FRAME_HEADER_LEN {
+                return;
+            }
+            let frame_length = FrameHeader::from_bytes(&valid[..FRAME_HEADER_LEN])
+                .expect("Invalid frame header in bip_buffer")
+                .payload_length()
+                + FRAME_HEADER_LEN;
+            if valid.len() < frame_length {
+                return;
+            }
+            self.consume(frame_length);
+            self.shared.count_dropped(frame_length);
+            //the writer sets the request again when the frame still does not fit
+            let _ = self.shared.discard_request.fetch_update(
+                Ordering::AcqRel,
+                Ordering::Acquire,
+                |requested| Some(requested.saturating_sub(frame_length)),
+            );
+        }
+    }
+}
+
+///The state shared by the writer and the reader of a bip_buffer.
+#[derive(Default)]
+struct Shared {
+    ///Raised when data is written.
+    data: Signal,
+    ///Raised when data is consumed.
+    space: Signal,
+    ///The number of bytes the writer wants the reader to free by dropping the oldest frames.
+    discard_request: AtomicUsize,
+    ///The total number of bytes consumed by the reader.
+    consumed: AtomicU64,
+    stats_data: OnceLock<Arc<StatsAllHandlers>>,
+    buffer_stats: OnceLock<Arc<BufferStatistics>>,
+    ///The spilled frames and the writing end of the bip_buffer, while frames are spilled.
+    spill: Mutex<Option<Spill>>,
+    ///True while frames are spilled, so the reader only takes the lock of `spill` when there is something to move.
+    spilling: AtomicBool,
+}
+
+///Frames that did not fit in the bip_buffer, together with the writing end of the bip_buffer they are moved to.
+struct Spill {
+    writer: spsc_bip_buffer::BipBufferWriter,
+    file: SpillFile,
+}
+
+impl Shared {
+    fn lock_spill(&self) -> MutexGuard<'_, Option<Spill>> {
+        self.spill.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    ///Moves spilled frames to the bip_buffer, as far as they fit.
+    fn drain_spill(&self, spill: &mut Spill) -> Result<()> {
+        while !spill.file.is_empty() {
+            let len = spill.file.next_frame_length()?;
+            let mut reservation = match spill.writer.reserve(len) {
+                Some(reservation) => reservation,
+                None => break,
+            };
+            if let Err(e) = spill.file.take_frame(&mut reservation) {
+                //dropping the reservation would commit it, forgetting it leaves the bip_buffer unchanged
+                std::mem::forget(reservation);
+                return Err(e);
+            }
+            drop(reservation);
+            self.add_written(len);
+            self.data.notify();
+        }
+        Ok(())
+    }
+
+    fn add_written(&self, len: usize) {
+        if let Some(buffer_stats) = self.buffer_stats.get() {
+            buffer_stats.add_written(len);
+        }
+    }
+
+    ///Counts a dropped frame of `len` bytes, including its frame header.
+    fn count_dropped(&self, len: usize) {
+        if let Some(stats_data) = self.stats_data.get() {
+            stats_data.dropped_packets.add(1);
+            stats_data
+                .dropped_bytes
+                .add(len.saturating_sub(FRAME_HEADER_LEN) as u64);
+        }
+    }
+}
+
+///Wakes up a thread or tokio task that waits for a change in the bip_buffer.
+///Notifying is only a fence and an atomic load when nobody waits.
+#[derive(Default)]
+struct Signal {
+    waiters: AtomicUsize,
+    mutex: Mutex<()>,
+    condvar: Condvar,
+    #[cfg(feature = "tokio")]
+    tasks: tokio::sync::Notify,
+}
+
+impl Signal {
+    fn notify(&self) {
+        //pairs with the fence in wait_until: either the waiter sees the change, or this sees the waiter
+        fence(Ordering::SeqCst);
+        if self.waiters.load(Ordering::Relaxed) > 0 {
+            {
+                //the waiter holds the mutex from its last check until it waits, so the notification cannot fall in between
+                let _guard = self.mutex.lock().unwrap_or_else(PoisonError::into_inner);
+                self.condvar.notify_all();
+            }
+            #[cfg(feature = "tokio")]
+            self.tasks.notify_waiters();
+        }
+    }
+
+    ///Waits until `condition` is true without blocking the thread, the task is woken up by `notify`.
+    #[cfg(feature = "tokio")]
+    async fn wait_until_async(&self, mut condition: impl FnMut() -> bool) {
+        loop {
+            //the task is registered before its last check, so a notification after that check wakes it up
+            let notified = self.tasks.notified();
+            let mut notified = std::pin::pin!(notified);
+            notified.as_mut().enable();
+            let _waiter = AsyncWaiter::register(&self.waiters);
+            fence(Ordering::SeqCst);
+            if condition() {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    ///Waits until `condition` is true, or until the deadline has passed.
+    ///Returns the last result of `condition`.
+    fn wait_until(&self, mut condition: impl FnMut() -> bool, deadline: Option<Instant>) -> bool {
+        for _ in 0..SPIN_COUNT {
+            if condition() {
+                return true;
+            }
+            std::hint::spin_loop();
+        }
+        let mut guard = self.mutex.lock().unwrap_or_else(PoisonError::into_inner);
+        loop {
+            self.waiters.fetch_add(1, Ordering::Relaxed);
+            fence(Ordering::SeqCst);
+            if condition() {
+                self.waiters.fetch_sub(1, Ordering::Relaxed);
+                return true;
+            }
+            let timeout = match deadline {
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    Some(remaining) => remaining.min(WAIT_TIMEOUT),
+                    None => {
+                        self.waiters.fetch_sub(1, Ordering::Relaxed);
+                        return false;
+                    }
+                },
+                None => WAIT_TIMEOUT,
+            };
+            guard = self
+                .condvar
+                .wait_timeout(guard, timeout)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+            self.waiters.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
+///Counts a task in the waiters of a Signal while it waits, also when the task is cancelled.
+#[cfg(feature = "tokio")]
+struct AsyncWaiter<'a>(&'a AtomicUsize);
+
+#[cfg(feature = "tokio")]
+impl<'a> AsyncWaiter<'a> {
+    fn register(waiters: &'a AtomicUsize) -> AsyncWaiter<'a> {
+        waiters.fetch_add(1, Ordering::Relaxed);
+        AsyncWaiter(waiters)
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<'a> Drop for AsyncWaiter<'a> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,163 @@
+// Copyright 2020 Ministerie van Defensie
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::errors::ErrorKind::InvalidFrame;
+use crate::errors::*;
+use framework_constants::*;
+
+///The kind of message carried by a frame.
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FrameKind {
+    ///The payload is the data of a protocol handler.
+    Data = 1u8,
+    ///The payload is a serialized Envelope (see socket_utils) with metadata and data.
+    Envelope = 2u8,
+}
+
+impl FrameKind {
+    ///Returns the byte value of a given FrameKind.
+    pub fn as_u8(self) -> u8 {
+        self as u8
+    }
+
+    ///Returns the FrameKind for a given byte value, or None when the kind is unknown.
+    pub fn from_u8(byte: u8) -> Option<FrameKind> {
+        match byte {
+            byte if byte == FrameKind::Data.as_u8() => Some(FrameKind::Data),
+            byte if byte == FrameKind::Envelope.as_u8() => Some(FrameKind::Envelope),
+            _ => None,
+        }
+    }
+}
+
+///The header in front of every message sent between handlers and stored in a bip buffer.
+///All fields are little-endian, so handlers built for different architectures stay compatible:
+/// * 4 bytes: payload length
+/// * 4 bytes: magic number (FRAME_MAGIC)
+/// * 1 byte: version (FRAME_VERSION)
+/// * 1 byte: kind
+/// * 2 bytes: flags
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct FrameHeader {
+    ///The length of the payload following the header.
+    pub length: u32,
+    ///The kind of message carried in the payload.
+    pub kind: FrameKind,
+    ///Flags for the payload, no flags are defined yet.
+    pub flags: u16,
+}
+
+impl FrameHeader {
+    ///Creates a frame header without flags for a payload of `length` bytes.
+    ///Panics when `length` is larger than MAX_FRAME_PAYLOAD_LEN.
+    pub fn new(kind: FrameKind, length: usize) -> FrameHeader {
+        assert!(
+            length <= MAX_FRAME_PAYLOAD_LEN,
+            "frame payload of {} bytes exceeds the maximum of {} bytes",
+            length,
+            MAX_FRAME_PAYLOAD_LEN
+        );
+        FrameHeader {
+            length: length as u32,
+            kind,
+            flags: 0,
+        }
+    }
+
+    ///Returns the length of the payload as usize.
+    pub fn payload_length(&self) -> usize {
+        self.length as usize
+    }
+
+    ///Serializes the header.
+    pub fn to_bytes(&self) -> [u8; FRAME_HEADER_LEN] {
+        let mut bytes = [0; FRAME_HEADER_LEN];
+        bytes[0..4].copy_from_slice(&self.length.to_le_bytes());
+        bytes[4..8].copy_from_slice(&FRAME_MAGIC.to_le_bytes());
+        bytes[8] = FRAME_VERSION;
+        bytes[9] = self.kind.as_u8();
+        bytes[10..12].copy_from_slice(&self.flags.to_le_bytes());
+        bytes
+    }
+
+    ///Deserializes and validates a header.
+    ///Headers with a wrong magic number, an unknown version or kind, or a payload length
+    ///larger than MAX_FRAME_PAYLOAD_LEN are rejected.
+    /// # Arguments
+    /// * `bytes` - At least FRAME_HEADER_LEN bytes, only the first FRAME_HEADER_LEN bytes are read.
+    pub fn from_bytes(bytes: &[u8]) -> Result<FrameHeader> {
+        if bytes.len() < FRAME_HEADER_LEN {
+            return Err(InvalidFrame(format!("header of {} bytes is too short", bytes.len())).into());
+        }
+        let magic = u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+        if magic != FRAME_MAGIC {
+            return Err(InvalidFrame(format!("wrong magic number {magic:#010x}")).into());
+        }
+        if bytes[8] != FRAME_VERSION {
+            return Err(InvalidFrame(format!("unsupported version {}", bytes[8])).into());
+        }
+        let kind = FrameKind::from_u8(bytes[9])
+            .ok_or_else(|| InvalidFrame(format!("unknown kind {}", bytes[9])))?;
+        let length = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        if length as usize > MAX_FRAME_PAYLOAD_LEN {
+            return Err(InvalidFrame(format!(
+                "payload length {length} exceeds the maximum of {MAX_FRAME_PAYLOAD_LEN}"
+            ))
+            .into());
+        }
+        Ok(FrameHeader {
+            length,
+            kind,
+            flags: u16::from_le_bytes([bytes[10], bytes[11]]),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::frame::*;
+
+    #[test]
+    fn frame_header_round_trip_test() {
+        let mut header = FrameHeader::new(FrameKind::Data, 1234);
+        header.flags = 0x0102;
+        let bytes = header.to_bytes();
+        assert_eq!(&bytes[0..4], &1234u32.to_le_bytes());
+        assert_eq!(&bytes[4..8], b"OSDD");
+        assert_eq!(FrameHeader::from_bytes(&bytes).expect("valid header"), header);
+    }
+
+    #[test]
+    fn reject_malformed_frame_header_test() {
+        let valid = FrameHeader::new(FrameKind::Data, 10).to_bytes();
+        assert!(FrameHeader::from_bytes(&valid[..FRAME_HEADER_LEN - 1]).is_err());
+
+        let mut wrong_magic = valid;
+        wrong_magic[5] ^= 0xFF;
+        assert!(FrameHeader::from_bytes(&wrong_magic).is_err());
+
+        let mut wrong_version = valid;
+        wrong_version[8] = FRAME_VERSION + 1;
+        assert!(FrameHeader::from_bytes(&wrong_version).is_err());
+
+        let mut unknown_kind = valid;
+        unknown_kind[9] = 0xFF;
+        assert!(FrameHeader::from_bytes(&unknown_kind).is_err());
+
+        let mut too_long = valid;
+        too_long[0..4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(FrameHeader::from_bytes(&too_long).is_err());
+    }
+}
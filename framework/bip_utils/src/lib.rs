@@ -12,21 +12,78 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::errors::ErrorKind::InvalidFrame;
+use crate::errors::*;
+use crate::frame::{FrameHeader, FrameKind};
 use framework_constants::*;
-use spsc_bip_buffer::BipBufferReader;
-use spsc_bip_buffer::BipBufferWriter;
+
+pub use crate::bip_buffer::{
+    bip_buffer_with_len, BipBufferReader, BipBufferWriter, BipBufferWriterReservation,
+    DEFAULT_SPILL_MAX_BYTES,
+};
+pub use crate::overflow::OverflowPolicy;
+pub use crate::spool::Spool;
+
+///Async versions of the functions in this module, for handlers that run on tokio.
+#[cfg(feature = "tokio")]
+pub mod async_bip;
+///A bip_buffer that wakes up a waiting reader or writer.
+pub mod bip_buffer;
+///Error chain for bip_utils.
+pub mod errors;
+///The frame header used between handlers and in the bip buffers.
+pub mod frame;
+///What a bip_buffer writer does when the bip_buffer is full.
+pub mod overflow;
+///A persistent queue on disk for messages an egress handler cannot deliver.
+pub mod spool;
 
 ///This function is used to write to the bip_buffer using the supplied writer.
+///The buffer is written as a frame of kind FrameKind::Data.
+///When the bip_buffer is full the OverflowPolicy of the writer decides what happens.
 /// # Arguments
 /// * `writer` - The bipBufferWriter used to write to the bip_buffer.
 /// * `buffer` - The buffer that should be written to the bip_buffer.
-pub fn write_to_bip_buffer(writer: &mut BipBufferWriter, buffer: &[u8]) {
-    let mut reservation = writer.spin_reserve(buffer.len() + BIP_BUFFER_LEN_FIELD_LEN);
-    let byte_len = buffer.len().to_le_bytes();
-    reservation[0..BIP_BUFFER_LEN_FIELD_LEN].copy_from_slice(&byte_len);
-    reservation[BIP_BUFFER_LEN_FIELD_LEN..buffer.len() + BIP_BUFFER_LEN_FIELD_LEN]
-        .copy_from_slice(buffer);
-    reservation.send();
+/// # Returns
+/// * `bool` - False when the frame was dropped because the bip_buffer is full.
+pub fn write_to_bip_buffer(writer: &mut BipBufferWriter, buffer: &[u8]) -> Result<bool> {
+    write_frame_to_bip_buffer(writer, FrameHeader::new(FrameKind::Data, buffer.len()), buffer)
+}
+
+///This function is used to write a frame to the bip_buffer using the supplied writer.
+/// # Arguments
+/// * `writer` - The bipBufferWriter used to write to the bip_buffer.
+/// * `header` - The header of the frame, its length should equal the length of `buffer`.
+/// * `buffer` - The payload of the frame.
+/// # Returns
+/// * `bool` - False when the frame was dropped because the bip_buffer is full.
+pub fn write_frame_to_bip_buffer(
+    writer: &mut BipBufferWriter,
+    header: FrameHeader,
+    buffer: &[u8],
+) -> Result<bool> {
+    debug_assert_eq!(header.payload_length(), buffer.len());
+    writer.write_frame(&[&header.to_bytes(), buffer])
+}
+
+///This function is used to write a complete frame (header and payload) to the bip_buffer.
+///The frame is validated first, an invalid frame is not written.
+/// # Arguments
+/// * `writer` - The bipBufferWriter used to write to the bip_buffer.
+/// * `frame` - The frame header followed by the payload.
+/// # Returns
+/// * `bool` - False when the frame was dropped because the bip_buffer is full.
+pub fn write_frame_bytes_to_bip_buffer(writer: &mut BipBufferWriter, frame: &[u8]) -> Result<bool> {
+    let header = FrameHeader::from_bytes(frame)?;
+    if header.payload_length() + FRAME_HEADER_LEN != frame.len() {
+        return Err(InvalidFrame(format!(
+            "frame header announces {} bytes of payload, but {} bytes were received",
+            header.payload_length(),
+            frame.len() - FRAME_HEADER_LEN
+        ))
+        .into());
+    }
+    writer.write_frame(&[frame])
 }
 
 ///This function is used to read from the bip_buffer using the supplied reader.
@@ -40,44 +97,70 @@ pub fn read_from_bip_buffer(reader: &mut BipBufferReader, buffer: &mut [u8]) ->
     //read data from the buffer
     wait_for_data(reader, element_length);
     let incoming = reader.valid();
-    //copy incoming data (excluding length field) into buffer
+    //copy incoming data (excluding frame header) into buffer
     buffer[..element_length].copy_from_slice(&incoming[..element_length]);
     //mark incoming data as consumed
     reader.consume(element_length);
     element_length
 }
 
+///This function is used to read a complete frame (header and payload) from the bip_buffer.
+///Handlers that pass data on without changing it use this to keep the frame kind and flags.
+/// # Arguments
+/// * `reader` - The bipBufferReader used to read from the bip_buffer.
+/// * `buffer` - The buffer to be filled with the frame.
+/// # Returns
+/// * `usize` - The length of the frame in bytes, including the frame header.
+pub fn read_frame_from_bip_buffer(reader: &mut BipBufferReader, buffer: &mut [u8]) -> usize {
+    let frame_length = FRAME_HEADER_LEN + peek_frame_header(reader).payload_length();
+    wait_for_data(reader, frame_length);
+    buffer[..frame_length].copy_from_slice(&reader.valid()[..frame_length]);
+    reader.consume(frame_length);
+    frame_length
+}
+
 ///Function used to get the element length field from the bip_buffer.
-///The element length is consumed from the buffer by calling this function.
+///The frame header is consumed from the buffer by calling this function.
 /// # Arguments
 /// * `reader` - The bipBufferReader used to read the element length.
 /// # Returns
 /// * `usize` - The length of the read element in bytes.
 pub fn get_element_length(reader: &mut BipBufferReader) -> usize {
-    wait_for_data(reader, BIP_BUFFER_LEN_FIELD_LEN);
-    let length_buffer = &mut reader.valid()[..BIP_BUFFER_LEN_FIELD_LEN];
-    let mut length_bytes_fixed = [0; BIP_BUFFER_LEN_FIELD_LEN];
-    length_bytes_fixed.copy_from_slice(&length_buffer[0..BIP_BUFFER_LEN_FIELD_LEN]);
-    let element_length = usize::from_le_bytes(length_bytes_fixed);
-    reader.consume(BIP_BUFFER_LEN_FIELD_LEN);
-    element_length
+    read_frame_header(reader).payload_length()
+}
+
+///Function used to get the frame header of the next element from the bip_buffer.
+///The frame header is consumed from the buffer by calling this function.
+///Only valid frames are written to a bip_buffer, so an invalid header means the buffer is corrupt and the function panics.
+/// # Arguments
+/// * `reader` - The bipBufferReader used to read the frame header.
+/// # Returns
+/// * `FrameHeader` - The header of the next element.
+pub fn read_frame_header(reader: &mut BipBufferReader) -> FrameHeader {
+    let header = peek_frame_header(reader);
+    reader.consume(FRAME_HEADER_LEN);
+    header
+}
+
+///Function used to get the frame header of the next element without consuming it.
+/// # Arguments
+/// * `reader` - The bipBufferReader used to read the frame header.
+/// # Returns
+/// * `FrameHeader` - The header of the next element.
+pub fn peek_frame_header(reader: &mut BipBufferReader) -> FrameHeader {
+    reader.drop_requested_frames();
+    wait_for_data(reader, FRAME_HEADER_LEN);
+    FrameHeader::from_bytes(&reader.valid()[..FRAME_HEADER_LEN])
+        .expect("Invalid frame header in bip_buffer")
 }
 
 ///Wait for the given amount of bytes to be available for reading in the bip_buffer.
+///The thread blocks until the writer has written enough data.
 /// # Arguments
 /// * `reader` - The bipBufferReader used to read from the bip_buffer.
 /// * `bytes` - The amount of available bytes to wait for
 pub fn wait_for_data(reader: &mut BipBufferReader, bytes: usize) {
-    let mut spin_count = 0;
-    while reader.valid().len() < (bytes) {
-        if spin_count < 100_000 {
-            std::hint::spin_loop();
-            spin_count += 1;
-        } else {
-            std::thread::sleep(std::time::Duration::from_millis(100));
-            spin_count = 0;
-        }
-    }
+    reader.wait_for_data(bytes);
 }
 
 #[cfg(test)]
@@ -86,19 +169,195 @@ mod tests {
     use crate::wait_for_data;
     use crate::write_to_bip_buffer;
     use framework_constants::*;
-    use spsc_bip_buffer::bip_buffer_with_len;
-    use spsc_bip_buffer::BipBufferReader;
+    use crate::bip_buffer_with_len;
+    use crate::read_from_bip_buffer;
+    use crate::BipBufferReader;
+    use crate::OverflowPolicy;
+    use crate::DEFAULT_SPILL_MAX_BYTES;
+    use statistics_handler::StatsAllHandlers;
+    use std::sync::Arc;
     #[test]
     ///Is used to test reading and writing of multiple buffers to a bip_buffer.
     fn write_read_bip_buffer() {
         let mut test_buffers = create_test_buffers();
         let (mut writer, mut reader) = bip_buffer_with_len(MAX_BIP_BUFFER_MESSAGE_SIZE * 10);
         for i in 0..test_buffers.len() {
-            write_to_bip_buffer(&mut writer, &test_buffers[i]);
+            write_to_bip_buffer(&mut writer, &test_buffers[i]).expect("Can't write to bip buffer");
             assert_on_byte_array(&mut reader, &mut test_buffers[i]);
         }
     }
 
+    #[test]
+    ///Is used to test that a waiting reader is woken up by the writer, and a waiting writer by the reader.
+    fn wake_up_bip_buffer() {
+        let (mut writer, mut reader) = bip_buffer_with_len(1000);
+        let handle = std::thread::spawn(move || {
+            //the reader waits for the first reservation
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            for i in 0..10 {
+                //two reservations fit, the writer waits for the reader to consume the rest
+                writer.blocking_reserve(400)[0] = i;
+            }
+        });
+        for i in 0..10 {
+            wait_for_data(&mut reader, 400);
+            assert_eq!(reader.valid()[0], i);
+            std::thread::sleep(std::time::Duration::from_millis(5));
+            reader.consume(400);
+        }
+        handle.join().unwrap();
+    }
+
+    #[test]
+    ///Is used to test that an element larger than half the bip_buffer is refused instead of waiting forever.
+    fn element_too_large_bip_buffer() {
+        let (mut writer, mut reader) = bip_buffer_with_len(1000);
+        assert!(write_to_bip_buffer(&mut writer, &[0; 500 - FRAME_HEADER_LEN + 1]).is_err());
+        //a frame of half the bip_buffer fits wherever the last frame ended
+        let handle = std::thread::spawn(move || {
+            for i in 0..10 {
+                assert!(
+                    write_to_bip_buffer(&mut writer, &[i; 500 - FRAME_HEADER_LEN])
+                        .expect("Can't write")
+                );
+            }
+        });
+        for i in 0..10 {
+            assert_on_byte_array(&mut reader, &[i; 500 - FRAME_HEADER_LEN]);
+        }
+        handle.join().unwrap();
+    }
+
+    #[test]
+    ///Is used to test that new elements are dropped and counted when the bip_buffer is full.
+    fn drop_newest_bip_buffer() {
+        let stats_data = Arc::new(StatsAllHandlers::default());
+        let (writer, mut reader) = bip_buffer_with_len(1000);
+        let mut writer = writer
+            .with_overflow_policy(OverflowPolicy::DropNewest, "/tmp", DEFAULT_SPILL_MAX_BYTES)
+            .with_stats("bip", stats_data.clone());
+        for i in 1..=3 {
+            let written = write_to_bip_buffer(&mut writer, &[i; 388]).expect("Can't write");
+            assert_eq!(written, i < 3);
+        }
+        assert_eq!(stats_data.dropped_packets.load(), 1);
+        assert_eq!(stats_data.dropped_bytes.load(), 388);
+        assert_on_byte_array(&mut reader, &[1; 388]);
+        assert_on_byte_array(&mut reader, &[2; 388]);
+    }
+
+    #[test]
+    ///Is used to test that the oldest elements are dropped to make space for a new element.
+    fn drop_oldest_bip_buffer() {
+        let stats_data = Arc::new(StatsAllHandlers::default());
+        let (writer, mut reader) = bip_buffer_with_len(1000);
+        let mut writer = writer
+            .with_overflow_policy(OverflowPolicy::DropOldest, "/tmp", DEFAULT_SPILL_MAX_BYTES)
+            .with_stats("bip", stats_data.clone());
+        let handle = std::thread::spawn(move || {
+            for i in 1..=4 {
+                assert!(write_to_bip_buffer(&mut writer, &[i; 288]).expect("Can't write"));
+            }
+        });
+        //the fourth element does not fit until the reader drops the first
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        let mut buffer = [0; 288];
+        let mut received = vec![];
+        while received.last() != Some(&4) {
+            read_from_bip_buffer(&mut reader, &mut buffer);
+            received.push(buffer[0]);
+        }
+        handle.join().unwrap();
+        assert_ne!(received[0], 1);
+        assert_eq!(
+            stats_data.dropped_packets.load() as usize,
+            4 - received.len()
+        );
+    }
+
+    #[test]
+    ///Is used to test that the fill level and high-water mark of a bip_buffer are reported.
+    fn buffer_statistics_bip_buffer() {
+        let stats_data = Arc::new(StatsAllHandlers::default());
+        let (writer, mut reader) = bip_buffer_with_len(1000);
+        let mut writer = writer.with_stats("bip", stats_data.clone());
+        let buffer_stats = stats_data.buffers.lock().unwrap()[0].1.clone();
+        for i in 1..=2 {
+            assert!(write_to_bip_buffer(&mut writer, &[i; 288]).expect("Can't write"));
+        }
+        let high_water = reader.valid().len() as u64;
+        assert_eq!(buffer_stats.fill(), high_water);
+        assert_on_byte_array(&mut reader, &[1; 288]);
+        assert_eq!(buffer_stats.fill(), reader.valid().len() as u64);
+        assert!(buffer_stats.fill() < high_water);
+    }
+
+    #[test]
+    ///Is used to test that spilled elements are written to the bip_buffer in order when there is space.
+    fn spill_bip_buffer() {
+        let (writer, mut reader) = bip_buffer_with_len(1000);
+        let mut writer =
+            writer.with_overflow_policy(OverflowPolicy::Spill, "/tmp", DEFAULT_SPILL_MAX_BYTES);
+        for i in 1..=5 {
+            assert!(write_to_bip_buffer(&mut writer, &[i; 288]).expect("Can't write"));
+        }
+        for i in 1..=3 {
+            assert_on_byte_array(&mut reader, &[i; 288]);
+        }
+        //spilled elements wait for space, new elements are written after them
+        write_to_bip_buffer(&mut writer, &[6; 288]).expect("Can't write");
+        for i in 4..=6 {
+            assert_on_byte_array(&mut reader, &[i; 288]);
+        }
+        write_to_bip_buffer(&mut writer, &[7; 288]).expect("Can't write");
+        assert_on_byte_array(&mut reader, &[7; 288]);
+        assert!(reader.valid().is_empty());
+    }
+
+    #[test]
+    ///Is used to test that the reader gets the spilled elements when the writer stops writing.
+    fn spill_stop_writing_bip_buffer() {
+        let (writer, mut reader) = bip_buffer_with_len(1000);
+        let mut writer =
+            writer.with_overflow_policy(OverflowPolicy::Spill, "/tmp", DEFAULT_SPILL_MAX_BYTES);
+        for i in 1..=10 {
+            assert!(write_to_bip_buffer(&mut writer, &[i; 288]).expect("Can't write"));
+        }
+        //the writer is idle, consuming moves the spilled elements to the bip_buffer
+        let handle = std::thread::spawn(move || {
+            for i in 1..=10 {
+                assert_on_byte_array(&mut reader, &[i; 288]);
+            }
+            reader
+        });
+        let mut reader = handle.join().unwrap();
+        assert!(reader.valid().is_empty());
+        //the writer takes the bip_buffer back once all spilled elements are written
+        assert!(write_to_bip_buffer(&mut writer, &[11; 288]).expect("Can't write"));
+        assert_on_byte_array(&mut reader, &[11; 288]);
+    }
+
+    #[test]
+    ///Is used to test that elements are dropped and counted when the spill file is full.
+    fn spill_max_bytes_bip_buffer() {
+        let stats_data = Arc::new(StatsAllHandlers::default());
+        let (writer, mut reader) = bip_buffer_with_len(1000);
+        let mut writer = writer
+            .with_overflow_policy(OverflowPolicy::Spill, "/tmp", 600)
+            .with_stats("bip", stats_data.clone());
+        //three elements fit in the bip_buffer, two in the spill file
+        for i in 1..=6 {
+            let written = write_to_bip_buffer(&mut writer, &[i; 288]).expect("Can't write");
+            assert_eq!(written, i <= 5);
+        }
+        assert_eq!(stats_data.dropped_packets.load(), 1);
+        assert_eq!(stats_data.dropped_bytes.load(), 288);
+        for i in 1..=5 {
+            assert_on_byte_array(&mut reader, &[i; 288]);
+        }
+        assert!(reader.valid().is_empty());
+    }
+
     ///asserts if the given buffer equals the buffer read from the bip_buffer.
     fn assert_on_byte_array(receiver_reader: &mut BipBufferReader, send_buffer: &[u8]) {
         let element_length = get_element_length(receiver_reader);
@@ -136,4 +395,75 @@ mod tests {
         }
         vec![buffer1, buffer2, buffer3, buffer4, buffer5]
     }
+
+    #[cfg(feature = "tokio")]
+    mod tokio {
+        use crate::async_bip::{read_from_bip_buffer, write_to_bip_buffer};
+        use crate::bip_buffer_with_len;
+        use framework_constants::*;
+        use std::time::Duration;
+
+        fn runtime() -> tokio::runtime::Runtime {
+            tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .expect("can't create runtime")
+        }
+
+        #[test]
+        ///A reader task waiting for data is woken up by a writer task on the same runtime.
+        fn async_round_trip_test() {
+            runtime().block_on(async {
+                let (mut writer, mut reader) = bip_buffer_with_len(MAX_BIP_BUFFER_MESSAGE_SIZE);
+                let reading = tokio::spawn(async move {
+                    let mut buffer = vec![0; MAX_BIP_BUFFER_MESSAGE_SIZE];
+                    let mut received = Vec::new();
+                    for _ in 0..100 {
+                        let length = read_from_bip_buffer(&mut reader, &mut buffer).await;
+                        received.push(buffer[..length].to_vec());
+                    }
+                    received
+                });
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                for element in 0..100u8 {
+                    assert!(write_to_bip_buffer(&mut writer, &[element; 1000])
+                        .await
+                        .expect("Can't write to bip buffer"));
+                }
+                let received = tokio::time::timeout(Duration::from_secs(10), reading)
+                    .await
+                    .expect("reader task was not woken up")
+                    .expect("reading failed");
+                for (element, data) in received.iter().enumerate() {
+                    assert_eq!(data, &vec![element as u8; 1000]);
+                }
+            });
+        }
+
+        #[test]
+        ///A writer task waiting for space in a full bip_buffer is woken up when a task reads.
+        fn async_full_buffer_test() {
+            runtime().block_on(async {
+                let (mut writer, mut reader) = bip_buffer_with_len(10_000);
+                let writing = tokio::spawn(async move {
+                    for element in 0..50u8 {
+                        write_to_bip_buffer(&mut writer, &[element; 3000])
+                            .await
+                            .expect("Can't write to bip buffer");
+                    }
+                });
+                let mut buffer = vec![0; 10_000];
+                for element in 0..50u8 {
+                    let length = tokio::time::timeout(
+                        Duration::from_secs(10),
+                        read_from_bip_buffer(&mut reader, &mut buffer),
+                    )
+                    .await
+                    .expect("writer task was not woken up");
+                    assert_eq!(&buffer[..length], &[element; 3000][..]);
+                }
+                writing.await.expect("writing failed");
+            });
+        }
+    }
 }
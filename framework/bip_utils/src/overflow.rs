@@ -0,0 +1,133 @@
+// Copyright 2020 Ministerie van Defensie
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::errors::ErrorKind::*;
+use crate::errors::*;
+use framework_constants::FRAME_HEADER_LEN;
+use std::fs::{File, OpenOptions};
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+///Used to give every spill file of a process its own name.
+static SPILL_FILE_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+///What a BipBufferWriter does with a frame that does not fit in the bip_buffer.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum OverflowPolicy {
+    ///Wait until the reader has consumed enough data.
+    Block,
+    ///Drop the new frame.
+    DropNewest,
+    ///Drop the oldest frames in the bip_buffer to make space for the new frame.
+    DropOldest,
+    ///Write the frame to a file, it is moved to the bip_buffer when there is space again.
+    Spill,
+}
+
+impl FromStr for OverflowPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<OverflowPolicy> {
+        match s {
+            "block" => Ok(OverflowPolicy::Block),
+            "drop_newest" => Ok(OverflowPolicy::DropNewest),
+            "drop_oldest" => Ok(OverflowPolicy::DropOldest),
+            "spill" => Ok(OverflowPolicy::Spill),
+            _ => Err(InvalidOverflowPolicy(format!(
+                "unknown overflow policy {s}, can be \"block\", \"drop_newest\", \"drop_oldest\" or \"spill\""
+            ))
+            .into()),
+        }
+    }
+}
+
+///Frames that did not fit in a bip_buffer, in the order they were written.
+///The file is removed from the directory as soon as it is created, it disappears when the handler stops.
+pub(crate) struct SpillFile {
+    file: File,
+    read_offset: u64,
+    write_offset: u64,
+}
+
+impl SpillFile {
+    ///Creates a new spill file in `directory`.
+    pub(crate) fn create(directory: &Path) -> Result<SpillFile> {
+        let path = directory.join(format!(
+            "osdd_spill_{}_{}",
+            std::process::id(),
+            SPILL_FILE_COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .chain_err(|| SpillError(format!("Can't create spill file {}", path.display())))?;
+        std::fs::remove_file(&path)
+            .chain_err(|| SpillError(format!("Can't unlink spill file {}", path.display())))?;
+        Ok(SpillFile {
+            file,
+            read_offset: 0,
+            write_offset: 0,
+        })
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.read_offset == self.write_offset
+    }
+
+    ///The size of the spill file, it starts over when all frames have been taken.
+    pub(crate) fn size(&self) -> u64 {
+        self.write_offset
+    }
+
+    ///Appends a frame, given as its parts.
+    pub(crate) fn append(&mut self, parts: &[&[u8]]) -> Result<()> {
+        for part in parts {
+            self.file
+                .write_all_at(part, self.write_offset)
+                .chain_err(|| SpillError("Can't write to spill file".to_string()))?;
+            self.write_offset += part.len() as u64;
+        }
+        Ok(())
+    }
+
+    ///Returns the length of the oldest frame, including its frame header.
+    pub(crate) fn next_frame_length(&self) -> Result<usize> {
+        let mut header = [0; FRAME_HEADER_LEN];
+        self.file
+            .read_exact_at(&mut header, self.read_offset)
+            .chain_err(|| SpillError("Can't read from spill file".to_string()))?;
+        Ok(crate::frame::FrameHeader::from_bytes(&header)?.payload_length() + FRAME_HEADER_LEN)
+    }
+
+    ///Moves the oldest frame into `buffer`, which has the length returned by next_frame_length.
+    pub(crate) fn take_frame(&mut self, buffer: &mut [u8]) -> Result<()> {
+        self.file
+            .read_exact_at(buffer, self.read_offset)
+            .chain_err(|| SpillError("Can't read from spill file".to_string()))?;
+        self.read_offset += buffer.len() as u64;
+        if self.is_empty() {
+            //start over at the beginning, so the file does not keep growing
+            self.file
+                .set_len(0)
+                .chain_err(|| SpillError("Can't truncate spill file".to_string()))?;
+            self.read_offset = 0;
+            self.write_offset = 0;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,437 @@
+// Copyright 2020 Ministerie van Defensie
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::errors::ErrorKind::SpoolError;
+use crate::errors::*;
+use statistics_handler::{Gauge, StatsAllHandlers};
+use std::collections::VecDeque;
+use std::convert::TryInto;
+use std::fs::{File, OpenOptions};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+///A new segment file is started when a message does not fit in the current segment anymore.
+const SEGMENT_LEN: u64 = 16 * 1024 * 1024;
+///Every message in a segment starts with its length as a little endian u32.
+const LENGTH_PREFIX_LEN: u64 = 4;
+///The file with the segment number and offset of the oldest message in the spool.
+const POSITION_FILE_NAME: &str = "position";
+const SEGMENT_FILE_PREFIX: &str = "segment_";
+
+///A persistent, append-only queue of messages on disk.
+///An egress handler puts the messages it cannot deliver in the spool, and replays them in order when the destination is back.
+///The messages are kept in segment files in the spool directory, a segment is removed when all its messages are taken.
+///The spool is opened again when the handler restarts, only a message that was taken just before the restart can be replayed twice.
+pub struct Spool {
+    directory: PathBuf,
+    max_bytes: u64,
+    segment_len: u64,
+    position_file: File,
+    ///The segments from old to new, messages are taken from the front and added to the back.
+    segments: VecDeque<Segment>,
+    next_segment_number: u64,
+    ///The offset of the oldest message in the front segment.
+    read_offset: u64,
+    messages: u64,
+    ///The bytes of the messages in the spool, including their length prefixes.
+    bytes: u64,
+    ///The size of all segment files, including messages already taken from the front segment.
+    disk_bytes: u64,
+    stats: Option<(Arc<Gauge>, Arc<Gauge>)>,
+}
+
+struct Segment {
+    number: u64,
+    file: File,
+    len: u64,
+}
+
+impl Spool {
+    ///Opens the spool in `directory`, the directory is created when it does not exist.
+    ///The messages left in the spool by a previous run are kept.
+    /// # Arguments
+    /// * `directory` - The directory of the spool, it must not be used by another spool.
+    /// * `max_bytes` - The maximum size of the segment files in the spool.
+    pub fn open(directory: &str, max_bytes: u64) -> Result<Spool> {
+        let directory = PathBuf::from(directory);
+        std::fs::create_dir_all(&directory)
+            .chain_err(|| spool_error("Can't create spool directory", &directory))?;
+        let position_path = directory.join(POSITION_FILE_NAME);
+        let position_file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&position_path)
+            .chain_err(|| spool_error("Can't open position file", &position_path))?;
+        let mut position = [0; 16];
+        let (read_segment, mut read_offset) = match position_file.read_exact_at(&mut position, 0) {
+            Ok(()) => (
+                u64::from_le_bytes(position[..8].try_into().expect("slice of 8 bytes")),
+                u64::from_le_bytes(position[8..].try_into().expect("slice of 8 bytes")),
+            ),
+            Err(_) => (0, 0),
+        };
+
+        let mut segment_numbers = vec![];
+        for entry in std::fs::read_dir(&directory)
+            .chain_err(|| spool_error("Can't read spool directory", &directory))?
+        {
+            let entry =
+                entry.chain_err(|| spool_error("Can't read spool directory", &directory))?;
+            if let Some(number) = entry
+                .file_name()
+                .to_str()
+                .and_then(|name| name.strip_prefix(SEGMENT_FILE_PREFIX))
+                .and_then(|number| number.parse::<u64>().ok())
+            {
+                segment_numbers.push(number);
+            }
+        }
+        segment_numbers.sort_unstable();
+
+        let mut segments = VecDeque::new();
+        for number in segment_numbers {
+            let path = segment_path(&directory, number);
+            if number < read_segment {
+                //all messages in this segment were taken before the segment could be removed
+                std::fs::remove_file(&path)
+                    .chain_err(|| spool_error("Can't remove segment", &path))?;
+                continue;
+            }
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(&path)
+                .chain_err(|| spool_error("Can't open segment", &path))?;
+            let len = file
+                .metadata()
+                .chain_err(|| spool_error("Can't read segment", &path))?
+                .len();
+            segments.push_back(Segment { number, file, len });
+        }
+        if segments.front().map(|segment| segment.number) != Some(read_segment) {
+            read_offset = 0;
+        }
+
+        let mut spool = Spool {
+            next_segment_number: segments
+                .back()
+                .map_or(read_segment, |segment| segment.number + 1),
+            directory,
+            max_bytes,
+            segment_len: SEGMENT_LEN,
+            position_file,
+            segments,
+            read_offset,
+            messages: 0,
+            bytes: 0,
+            disk_bytes: 0,
+            stats: None,
+        };
+        spool.count_messages()?;
+        if spool.messages > 0 {
+            log::info!(
+                "spool {} has {} messages to replay",
+                spool.directory.display(),
+                spool.messages
+            );
+        }
+        Ok(spool)
+    }
+
+    ///Reports the number of messages and bytes in the spool as the gauges `spool.messages` and `spool.bytes`.
+    pub fn with_stats(mut self, stats_data: Arc<StatsAllHandlers>) -> Spool {
+        self.stats = Some((
+            stats_data.register_gauge("spool.messages"),
+            stats_data.register_gauge("spool.bytes"),
+        ));
+        self.update_stats();
+        self
+    }
+
+    ///Returns the number of messages in the spool.
+    pub fn len(&self) -> u64 {
+        self.messages
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages == 0
+    }
+
+    ///Adds a message to the back of the spool.
+    /// # Returns
+    /// * `bool` - False when the message was not added because the spool is full.
+    pub fn push(&mut self, message: &[u8]) -> Result<bool> {
+        let record_len = LENGTH_PREFIX_LEN + message.len() as u64;
+        if message.len() > u32::MAX as usize || self.disk_bytes + record_len > self.max_bytes {
+            return Ok(false);
+        }
+        let start_segment = match self.segments.back() {
+            Some(segment) => segment.len > 0 && segment.len + record_len > self.segment_len,
+            None => true,
+        };
+        if start_segment {
+            let number = self.next_segment_number;
+            let path = segment_path(&self.directory, number);
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(&path)
+                .chain_err(|| spool_error("Can't create segment", &path))?;
+            self.segments.push_back(Segment {
+                number,
+                file,
+                len: 0,
+            });
+            self.next_segment_number += 1;
+            if self.segments.len() == 1 {
+                self.read_offset = 0;
+                self.write_position()?;
+            }
+        }
+        let directory = &self.directory;
+        let segment = self.segments.back_mut().expect("segment just added");
+        let mut record = Vec::with_capacity(record_len as usize);
+        record.extend_from_slice(&(message.len() as u32).to_le_bytes());
+        record.extend_from_slice(message);
+        segment
+            .file
+            .write_all_at(&record, segment.len)
+            .chain_err(|| {
+                spool_error(
+                    "Can't write to segment",
+                    &segment_path(directory, segment.number),
+                )
+            })?;
+        segment.len += record_len;
+        self.messages += 1;
+        self.bytes += record_len;
+        self.disk_bytes += record_len;
+        self.update_stats();
+        Ok(true)
+    }
+
+    ///Returns the oldest message in the spool without taking it, or None when the spool is empty.
+    pub fn peek(&self) -> Result<Option<Vec<u8>>> {
+        if self.messages == 0 {
+            return Ok(None);
+        }
+        let segment = self
+            .segments
+            .front()
+            .expect("spool with messages has a segment");
+        let len = self.message_len(segment)?;
+        let mut message = vec![0; len as usize];
+        segment
+            .file
+            .read_exact_at(&mut message, self.read_offset + LENGTH_PREFIX_LEN)
+            .chain_err(|| spool_error("Can't read from segment", &self.directory))?;
+        Ok(Some(message))
+    }
+
+    ///Takes the oldest message from the spool, call this when the message returned by peek has been delivered.
+    pub fn pop(&mut self) -> Result<()> {
+        if self.messages == 0 {
+            return Ok(());
+        }
+        let segment = self
+            .segments
+            .front()
+            .expect("spool with messages has a segment");
+        let record_len = LENGTH_PREFIX_LEN + self.message_len(segment)?;
+        self.read_offset += record_len;
+        self.messages -= 1;
+        self.bytes -= record_len;
+        if self.read_offset >= segment.len {
+            if self.segments.len() > 1 {
+                let segment = self.segments.pop_front().expect("front segment");
+                let path = segment_path(&self.directory, segment.number);
+                std::fs::remove_file(&path)
+                    .chain_err(|| spool_error("Can't remove segment", &path))?;
+                self.disk_bytes -= segment.len;
+            } else {
+                //the spool is empty, start over at the beginning of the segment
+                let directory = &self.directory;
+                let segment = self.segments.front_mut().expect("front segment");
+                segment.file.set_len(0).chain_err(|| {
+                    spool_error(
+                        "Can't truncate segment",
+                        &segment_path(directory, segment.number),
+                    )
+                })?;
+                segment.len = 0;
+                self.disk_bytes = 0;
+            }
+            self.read_offset = 0;
+        }
+        self.write_position()?;
+        self.update_stats();
+        Ok(())
+    }
+
+    ///Counts the messages after the read offset, a message that was only partly written when the handler stopped is removed.
+    fn count_messages(&mut self) -> Result<()> {
+        let mut offset = self.read_offset;
+        let directory = &self.directory;
+        for segment in self.segments.iter_mut() {
+            let mut prefix = [0; LENGTH_PREFIX_LEN as usize];
+            while offset < segment.len {
+                let record_len = match segment.file.read_exact_at(&mut prefix, offset) {
+                    Ok(()) => LENGTH_PREFIX_LEN + u32::from_le_bytes(prefix) as u64,
+                    Err(_) => segment.len,
+                };
+                if offset + record_len > segment.len {
+                    log::warn!(
+                        "removing incomplete message at offset {} of spool segment {}",
+                        offset,
+                        segment.number
+                    );
+                    segment.file.set_len(offset).chain_err(|| {
+                        spool_error(
+                            "Can't truncate segment",
+                            &segment_path(directory, segment.number),
+                        )
+                    })?;
+                    segment.len = offset;
+                    break;
+                }
+                offset += record_len;
+                self.messages += 1;
+                self.bytes += record_len;
+            }
+            self.disk_bytes += segment.len;
+            offset = 0;
+        }
+        Ok(())
+    }
+
+    ///Returns the length of the oldest message in `segment`, without its length prefix.
+    fn message_len(&self, segment: &Segment) -> Result<u64> {
+        let mut prefix = [0; LENGTH_PREFIX_LEN as usize];
+        segment
+            .file
+            .read_exact_at(&mut prefix, self.read_offset)
+            .chain_err(|| spool_error("Can't read from segment", &self.directory))?;
+        Ok(u32::from_le_bytes(prefix) as u64)
+    }
+
+    ///Stores the position of the oldest message, so a restarted handler does not replay delivered messages.
+    fn write_position(&self) -> Result<()> {
+        let mut position = [0; 16];
+        if let Some(segment) = self.segments.front() {
+            position[..8].copy_from_slice(&segment.number.to_le_bytes());
+        }
+        position[8..].copy_from_slice(&self.read_offset.to_le_bytes());
+        self.position_file
+            .write_all_at(&position, 0)
+            .chain_err(|| spool_error("Can't write position file", &self.directory))
+    }
+
+    fn update_stats(&self) {
+        if let Some((messages, bytes)) = &self.stats {
+            messages.set(self.messages);
+            bytes.set(self.bytes);
+        }
+    }
+}
+
+fn segment_path(directory: &Path, number: u64) -> PathBuf {
+    directory.join(format!("{SEGMENT_FILE_PREFIX}{number:020}"))
+}
+
+fn spool_error(message: &str, path: &Path) -> ErrorKind {
+    SpoolError(format!("{} {}", message, path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::spool::*;
+
+    fn test_directory(name: &str) -> String {
+        let directory =
+            std::env::temp_dir().join(format!("osdd_spool_test_{}_{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&directory);
+        directory.to_str().expect("temp dir is utf-8").to_string()
+    }
+
+    #[test]
+    ///Is used to test that messages are replayed in order, also after the spool is opened again.
+    fn spool_replay_in_order_test() {
+        let directory = test_directory("replay");
+        let mut spool = Spool::open(&directory, 1_000_000).expect("Can't open spool");
+        spool.segment_len = 100;
+        for i in 0..10u8 {
+            assert!(spool.push(&[i; 30]).expect("Can't push"));
+        }
+        assert!(spool.segments.len() > 1);
+        for i in 0..3u8 {
+            assert_eq!(spool.peek().expect("Can't peek"), Some(vec![i; 30]));
+            spool.pop().expect("Can't pop");
+        }
+        drop(spool);
+
+        let mut spool = Spool::open(&directory, 1_000_000).expect("Can't open spool");
+        assert_eq!(spool.len(), 7);
+        for i in 3..10u8 {
+            assert_eq!(spool.peek().expect("Can't peek"), Some(vec![i; 30]));
+            spool.pop().expect("Can't pop");
+        }
+        assert!(spool.is_empty());
+        assert_eq!(spool.peek().expect("Can't peek"), None);
+        assert_eq!(spool.disk_bytes, 0);
+        std::fs::remove_dir_all(&directory).expect("Can't remove spool");
+    }
+
+    #[test]
+    ///Is used to test that a full spool refuses messages and accepts them again when messages are taken.
+    fn spool_max_bytes_test() {
+        let directory = test_directory("max_bytes");
+        let mut spool = Spool::open(&directory, 100).expect("Can't open spool");
+        spool.segment_len = 50;
+        assert!(spool.push(&[1; 46]).expect("Can't push"));
+        assert!(spool.push(&[2; 46]).expect("Can't push"));
+        assert!(!spool.push(&[3; 46]).expect("Can't push"));
+        spool.pop().expect("Can't pop");
+        assert!(spool.push(&[3; 46]).expect("Can't push"));
+        assert_eq!(spool.len(), 2);
+        std::fs::remove_dir_all(&directory).expect("Can't remove spool");
+    }
+
+    #[test]
+    ///Is used to test that a message that was only partly written is removed when the spool is opened.
+    fn spool_incomplete_message_test() {
+        let directory = test_directory("incomplete");
+        let mut spool = Spool::open(&directory, 1_000).expect("Can't open spool");
+        assert!(spool.push(&[1; 10]).expect("Can't push"));
+        assert!(spool.push(&[2; 10]).expect("Can't push"));
+        let segment = spool.segments.back().expect("segment");
+        segment
+            .file
+            .set_len(segment.len - 5)
+            .expect("Can't truncate");
+        drop(spool);
+
+        let mut spool = Spool::open(&directory, 1_000).expect("Can't open spool");
+        assert_eq!(spool.len(), 1);
+        assert_eq!(spool.peek().expect("Can't peek"), Some(vec![1; 10]));
+        spool.pop().expect("Can't pop");
+        assert!(spool.push(&[3; 10]).expect("Can't push"));
+        assert_eq!(spool.peek().expect("Can't peek"), Some(vec![3; 10]));
+        std::fs::remove_dir_all(&directory).expect("Can't remove spool");
+    }
+}
@@ -29,13 +29,23 @@ pub const MAX_PAYLOAD_SIZE_BYTES: usize = MAX_BUFFER_SIZE_BYTES - HEADER_SIZE_BY
 ///The amount of times all special messages are sent.
 pub const SPECIAL_MESSAGE_COUNT: usize = 200;
 
-///The size in bytes of the length field used by the bip buffer.
-pub const BIP_BUFFER_LEN_FIELD_LEN: usize = std::mem::size_of::<usize>();
+///The size in bytes of the frame header in front of every message on a socket and in a bip buffer.
+//u32 length + u32 magic + u8 version + u8 kind + u16 flags = 12 bytes.
+pub const FRAME_HEADER_LEN: usize = 12;
+
+///The magic number in every frame header, it reads "OSDD" in ascii when sent little-endian.
+pub const FRAME_MAGIC: u32 = 0x4444_534F;
+
+///The version of the frame header. Frames with another version are rejected.
+pub const FRAME_VERSION: u8 = 1;
 
 ///The maximum size in bytes of a single bipbuffer message.
-//a bit more allocated then needed. 1_048_576(1 Mb) is needed + BIP_BUFFER_LEN_FIELD_LEN
+//a bit more allocated then needed. 1_048_576(1 Mb) is needed + FRAME_HEADER_LEN
 pub const MAX_BIP_BUFFER_MESSAGE_SIZE: usize = 1_050_000;
 
+///The maximum payload length a frame header may announce.
+pub const MAX_FRAME_PAYLOAD_LEN: usize = MAX_BIP_BUFFER_MESSAGE_SIZE - FRAME_HEADER_LEN;
+
 ///The messagetype used to determine the type of packet that was sent.
 #[repr(u8)]
 #[derive(Debug, Copy, Clone)]
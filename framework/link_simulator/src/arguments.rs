@@ -0,0 +1,103 @@
+// Copyright 2020 Ministerie van Defensie
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::profile::{GilbertElliott, LinkProfile};
+use structopt::StructOpt;
+
+///Commandline arguments used to run the link simulator.
+#[derive(StructOpt)]
+pub struct OptLinkSimulator {
+    ///The address the link simulator listens on, transport_udp_send should send to this address.
+    #[structopt(long = "listen_address", default_value = "127.0.0.1:1235")]
+    pub listen_address: String,
+
+    ///The address of transport_udp_receive.
+    #[structopt(long = "forward_address", default_value = "127.0.0.1:1234")]
+    pub forward_address: String,
+
+    ///The seed of the random generator, the same seed always gives the same decisions.
+    #[structopt(long = "seed", default_value = "0")]
+    pub seed: u64,
+
+    ///The probability a datagram is dropped.
+    #[structopt(long = "drop", default_value = "0.0")]
+    pub drop: f64,
+
+    ///The probability a datagram is sent twice.
+    #[structopt(long = "duplicate", default_value = "0.0")]
+    pub duplicate: f64,
+
+    ///The probability a datagram is sent after its successor.
+    #[structopt(long = "reorder", default_value = "0.0")]
+    pub reorder: f64,
+
+    ///The fixed delay in milliseconds added to every datagram.
+    #[structopt(long = "delay_ms", default_value = "0")]
+    pub delay_ms: u64,
+
+    ///The maximum random delay in milliseconds added to every datagram.
+    #[structopt(long = "jitter_ms", default_value = "0")]
+    pub jitter_ms: u64,
+
+    ///The probability a bit of a datagram is flipped.
+    #[structopt(long = "corrupt", default_value = "0.0")]
+    pub corrupt: f64,
+
+    ///Bytes in front of this offset are never corrupted.
+    #[structopt(long = "corrupt_offset", default_value = "0")]
+    pub corrupt_offset: usize,
+
+    ///Gilbert-Elliott: the probability of switching from the good to the bad state.
+    ///Burst loss is only enabled when this argument is given.
+    #[structopt(long = "ge_good_to_bad")]
+    pub ge_good_to_bad: Option<f64>,
+
+    ///Gilbert-Elliott: the probability of switching from the bad to the good state.
+    #[structopt(long = "ge_bad_to_good", default_value = "0.5")]
+    pub ge_bad_to_good: f64,
+
+    ///Gilbert-Elliott: the loss probability in the good state.
+    #[structopt(long = "ge_loss_good", default_value = "0.0")]
+    pub ge_loss_good: f64,
+
+    ///Gilbert-Elliott: the loss probability in the bad state.
+    #[structopt(long = "ge_loss_bad", default_value = "1.0")]
+    pub ge_loss_bad: f64,
+
+    ///The interval in seconds at which the link statistics are logged.
+    #[structopt(long = "stats_interval_sec", default_value = "1")]
+    pub stats_interval_sec: u64,
+}
+
+impl OptLinkSimulator {
+    ///Returns the LinkProfile described by the arguments.
+    pub fn profile(&self) -> LinkProfile {
+        LinkProfile {
+            seed: self.seed,
+            drop_probability: self.drop,
+            gilbert_elliott: self.ge_good_to_bad.map(|good_to_bad| GilbertElliott {
+                good_to_bad,
+                bad_to_good: self.ge_bad_to_good,
+                loss_good: self.ge_loss_good,
+                loss_bad: self.ge_loss_bad,
+            }),
+            duplicate_probability: self.duplicate,
+            reorder_probability: self.reorder,
+            delay_ms: self.delay_ms,
+            jitter_ms: self.jitter_ms,
+            corrupt_probability: self.corrupt,
+            corrupt_offset: self.corrupt_offset,
+        }
+    }
+}
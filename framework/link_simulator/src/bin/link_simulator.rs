@@ -0,0 +1,44 @@
+// Copyright 2020 Ministerie van Defensie
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use link_simulator::arguments::OptLinkSimulator;
+use link_simulator::errors::*;
+use link_simulator::simulator::LinkSimulator;
+use std::time::Duration;
+use structopt::StructOpt;
+
+fn main() {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+    run_link_simulator().chain_unwrap();
+}
+
+///Runs the link simulator until the process is stopped, logging the link statistics periodically.
+fn run_link_simulator() -> Result<()> {
+    let opt = OptLinkSimulator::from_args();
+    let profile = opt.profile();
+    log::info!("Starting link simulator with {:?}", profile);
+    let simulator = LinkSimulator::new(&opt.listen_address, &opt.forward_address, profile)?;
+    simulator
+        .run()
+        .chain_err(|| "Error starting link simulator thread")?;
+    log::info!(
+        "Forwarding datagrams from {} to {}",
+        simulator.local_addr()?,
+        opt.forward_address
+    );
+    loop {
+        std::thread::sleep(Duration::from_secs(opt.stats_interval_sec.max(1)));
+        log::info!("{:?}", simulator.stats());
+    }
+}
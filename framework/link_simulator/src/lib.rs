@@ -0,0 +1,24 @@
+// Copyright 2020 Ministerie van Defensie
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+///Commandline arguments used to run the link simulator.
+pub mod arguments;
+///Error chain for the link simulator.
+pub mod errors;
+///The seeded model deciding what happens to every datagram.
+pub mod model;
+///The description of the impairments applied by the link simulator.
+pub mod profile;
+///The UDP proxy that applies the model to datagrams on their way to the receiver.
+pub mod simulator;
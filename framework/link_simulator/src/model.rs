@@ -0,0 +1,309 @@
+// Copyright 2020 Ministerie van Defensie
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::profile::LinkProfile;
+use std::time::Duration;
+
+///A small deterministic random generator (splitmix64).
+///It is used instead of an external crate so the decisions for a seed never change between versions.
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    ///Returns a value in the range 0.0 to 1.0 (exclusive).
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    ///Returns true with the given probability. No random value is drawn for a probability of 0.0.
+    fn chance(&mut self, probability: f64) -> bool {
+        probability > 0.0 && self.next_f64() < probability
+    }
+
+    ///Returns a value in the range 0 to `bound` (exclusive), `bound` should be larger than 0.
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next_u64() % bound
+    }
+}
+
+///A datagram that should be sent to the receiver after the given delay.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Delivery {
+    ///The delay relative to the moment the datagram was received by the link simulator.
+    pub delay: Duration,
+    ///The (possibly corrupted) datagram.
+    pub payload: Vec<u8>,
+}
+
+///Counts what the link did with the datagrams passed to it.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct LinkStats {
+    ///The amount of datagrams passed to the link.
+    pub received: u64,
+    ///The amount of datagrams sent to the receiver, duplicates included.
+    pub forwarded: u64,
+    ///The amount of datagrams dropped.
+    pub dropped: u64,
+    ///The amount of datagrams dropped while the Gilbert-Elliott channel was in the bad state.
+    pub dropped_in_burst: u64,
+    ///The amount of extra copies sent.
+    pub duplicated: u64,
+    ///The amount of datagrams held back and sent after their successor.
+    pub reordered: u64,
+    ///The amount of datagrams with a flipped bit.
+    pub corrupted: u64,
+}
+
+///The LinkModel decides what happens to every datagram, based on a LinkProfile.
+///It does not do any IO, so the same profile and input always give the same output.
+pub struct LinkModel {
+    profile: LinkProfile,
+    rng: Rng,
+    in_bad_state: bool,
+    held_back: Vec<Vec<u8>>,
+    stats: LinkStats,
+}
+
+impl LinkModel {
+    ///Creates a new LinkModel, the random generator is seeded with the seed of the profile.
+    pub fn new(profile: LinkProfile) -> LinkModel {
+        LinkModel {
+            rng: Rng(profile.seed),
+            profile,
+            in_bad_state: false,
+            held_back: Vec::new(),
+            stats: LinkStats::default(),
+        }
+    }
+
+    ///Returns the statistics of all datagrams passed to the model so far.
+    pub fn stats(&self) -> LinkStats {
+        self.stats
+    }
+
+    ///Applies the profile to the next datagram.
+    ///The returned deliveries are in sending order, deliveries with an equal delay should be sent in this order.
+    /// # Arguments
+    /// * `datagram` - The datagram as it was sent by the UdpSender.
+    /// # Returns
+    /// `Vec<Delivery>` - Zero or more datagrams to send to the receiver.
+    pub fn impair(&mut self, datagram: &[u8]) -> Vec<Delivery> {
+        self.stats.received += 1;
+        if self.is_lost() {
+            return Vec::new();
+        }
+        let mut payload = datagram.to_vec();
+        if self.rng.chance(self.profile.corrupt_probability)
+            && payload.len() > self.profile.corrupt_offset
+        {
+            let range = (payload.len() - self.profile.corrupt_offset) as u64;
+            let index = self.profile.corrupt_offset + self.rng.below(range) as usize;
+            payload[index] ^= 1 << self.rng.below(8);
+            self.stats.corrupted += 1;
+        }
+        let copies = if self.rng.chance(self.profile.duplicate_probability) {
+            self.stats.duplicated += 1;
+            2
+        } else {
+            1
+        };
+        if self.held_back.is_empty() && self.rng.chance(self.profile.reorder_probability) {
+            self.stats.reordered += 1;
+            self.held_back = vec![payload; copies];
+            return Vec::new();
+        }
+        let delay = self.next_delay();
+        let mut deliveries = vec![Delivery { delay, payload }; copies];
+        deliveries.extend(
+            self.held_back
+                .drain(..)
+                .map(|payload| Delivery { delay, payload }),
+        );
+        self.stats.forwarded += deliveries.len() as u64;
+        deliveries
+    }
+
+    ///Returns the datagram that is held back for reordering, if any.
+    ///Used when the link stops and no successor will arrive anymore.
+    pub fn flush(&mut self) -> Vec<Delivery> {
+        let delay = self.next_delay();
+        let deliveries: Vec<Delivery> = self
+            .held_back
+            .drain(..)
+            .map(|payload| Delivery { delay, payload })
+            .collect();
+        self.stats.forwarded += deliveries.len() as u64;
+        deliveries
+    }
+
+    ///Decides if the next datagram is lost, updating the Gilbert-Elliott state first.
+    fn is_lost(&mut self) -> bool {
+        if let Some(gilbert_elliott) = self.profile.gilbert_elliott {
+            let switch_probability = if self.in_bad_state {
+                gilbert_elliott.bad_to_good
+            } else {
+                gilbert_elliott.good_to_bad
+            };
+            if self.rng.chance(switch_probability) {
+                self.in_bad_state = !self.in_bad_state;
+            }
+            let loss_probability = if self.in_bad_state {
+                gilbert_elliott.loss_bad
+            } else {
+                gilbert_elliott.loss_good
+            };
+            if self.rng.chance(loss_probability) {
+                self.stats.dropped += 1;
+                if self.in_bad_state {
+                    self.stats.dropped_in_burst += 1;
+                }
+                return true;
+            }
+        }
+        if self.rng.chance(self.profile.drop_probability) {
+            self.stats.dropped += 1;
+            return true;
+        }
+        false
+    }
+
+    fn next_delay(&mut self) -> Duration {
+        let jitter = if self.profile.jitter_ms > 0 {
+            self.rng.below(self.profile.jitter_ms + 1)
+        } else {
+            0
+        };
+        Duration::from_millis(self.profile.delay_ms + jitter)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::model::*;
+    use crate::profile::*;
+
+    fn datagrams() -> Vec<Vec<u8>> {
+        (0..1000u32).map(|i| i.to_le_bytes().to_vec()).collect()
+    }
+
+    fn run(profile: &LinkProfile) -> (Vec<Delivery>, LinkStats) {
+        let mut model = LinkModel::new(profile.clone());
+        let mut deliveries: Vec<Delivery> = datagrams()
+            .iter()
+            .flat_map(|datagram| model.impair(datagram))
+            .collect();
+        deliveries.extend(model.flush());
+        (deliveries, model.stats())
+    }
+
+    #[test]
+    fn perfect_link_test() {
+        let (deliveries, stats) = run(&LinkProfile::default());
+        let payloads: Vec<Vec<u8>> = deliveries.into_iter().map(|d| d.payload).collect();
+        assert_eq!(payloads, datagrams());
+        assert_eq!(stats.received, 1000);
+        assert_eq!(stats.forwarded, 1000);
+    }
+
+    #[test]
+    fn same_seed_same_decisions_test() {
+        let profile = LinkProfile {
+            seed: 42,
+            drop_probability: 0.1,
+            duplicate_probability: 0.1,
+            reorder_probability: 0.1,
+            corrupt_probability: 0.1,
+            jitter_ms: 5,
+            ..Default::default()
+        };
+        assert_eq!(run(&profile), run(&profile));
+        let other_seed = LinkProfile {
+            seed: 43,
+            ..profile.clone()
+        };
+        assert_ne!(run(&profile), run(&other_seed));
+    }
+
+    #[test]
+    fn impairments_test() {
+        let profile = LinkProfile {
+            seed: 1,
+            drop_probability: 0.1,
+            duplicate_probability: 0.1,
+            reorder_probability: 0.1,
+            corrupt_probability: 0.1,
+            ..Default::default()
+        };
+        let (deliveries, stats) = run(&profile);
+        assert!(stats.dropped > 50 && stats.dropped < 150);
+        assert!(stats.duplicated > 0 && stats.reordered > 0 && stats.corrupted > 0);
+        assert_eq!(deliveries.len() as u64, stats.forwarded);
+        assert_eq!(
+            stats.forwarded,
+            stats.received - stats.dropped + stats.duplicated
+        );
+        //a reordered datagram is sent after its successor
+        let sequence: Vec<&[u8]> = deliveries.iter().map(|d| &d.payload[..]).collect();
+        assert!(sequence.windows(2).any(|pair| pair[0] > pair[1]));
+    }
+
+    #[test]
+    fn corrupt_offset_test() {
+        let profile = LinkProfile {
+            seed: 3,
+            corrupt_probability: 1.0,
+            corrupt_offset: 2,
+            ..Default::default()
+        };
+        let (deliveries, stats) = run(&profile);
+        assert_eq!(stats.corrupted, 1000);
+        for (delivery, datagram) in deliveries.iter().zip(datagrams()) {
+            assert_eq!(delivery.payload[..2], datagram[..2]);
+            assert_ne!(delivery.payload, datagram);
+        }
+    }
+
+    #[test]
+    fn gilbert_elliott_burst_test() {
+        let profile = LinkProfile {
+            seed: 9,
+            gilbert_elliott: Some(GilbertElliott {
+                good_to_bad: 0.02,
+                bad_to_good: 0.2,
+                loss_good: 0.0,
+                loss_bad: 1.0,
+            }),
+            ..Default::default()
+        };
+        let mut model = LinkModel::new(profile);
+        let lost: Vec<bool> = datagrams()
+            .iter()
+            .map(|datagram| model.impair(datagram).is_empty())
+            .collect();
+        let stats = model.stats();
+        assert!(stats.dropped > 0);
+        assert_eq!(stats.dropped, stats.dropped_in_burst);
+        //losses come in bursts: the mean burst length is well above 1.
+        let bursts = lost.windows(2).filter(|pair| !pair[0] && pair[1]).count() as u64;
+        assert!(bursts > 0);
+        assert!(stats.dropped as f64 / bursts as f64 > 2.0);
+    }
+}
@@ -0,0 +1,108 @@
+// Copyright 2020 Ministerie van Defensie
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::errors::ErrorKind::ProfileError;
+use crate::errors::*;
+
+///The parameters of a Gilbert-Elliott burst loss channel.
+///The channel is either in the good or in the bad state, each state has its own loss probability.
+///Before every datagram the channel can switch state, which results in bursts of lost datagrams.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct GilbertElliott {
+    ///The probability of switching from the good to the bad state.
+    pub good_to_bad: f64,
+    ///The probability of switching from the bad to the good state.
+    pub bad_to_good: f64,
+    ///The loss probability while the channel is in the good state.
+    pub loss_good: f64,
+    ///The loss probability while the channel is in the bad state.
+    pub loss_bad: f64,
+}
+
+///Describes the impairments applied to every datagram that passes the link simulator.
+///All probabilities are in the range 0.0 to 1.0, the default profile is a perfect link.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct LinkProfile {
+    ///The seed of the random generator. The same seed and profile always give the same decisions.
+    pub seed: u64,
+    ///The probability a datagram is dropped.
+    pub drop_probability: f64,
+    ///Burst loss applied on top of `drop_probability`.
+    pub gilbert_elliott: Option<GilbertElliott>,
+    ///The probability a datagram is sent twice.
+    pub duplicate_probability: f64,
+    ///The probability a datagram is held back and sent after the next datagram.
+    pub reorder_probability: f64,
+    ///The fixed delay added to every datagram.
+    pub delay_ms: u64,
+    ///A random delay of up to this amount of milliseconds added to every datagram.
+    ///Datagrams with a different delay can overtake each other.
+    pub jitter_ms: u64,
+    ///The probability a single bit of a datagram is flipped.
+    pub corrupt_probability: f64,
+    ///Bytes in front of this offset are never corrupted, for example to keep the transport header intact.
+    pub corrupt_offset: usize,
+}
+
+impl LinkProfile {
+    ///Checks if all probabilities in the profile are in the range 0.0 to 1.0.
+    pub fn validate(&self) -> Result<()> {
+        let mut probabilities = vec![
+            ("drop", self.drop_probability),
+            ("duplicate", self.duplicate_probability),
+            ("reorder", self.reorder_probability),
+            ("corrupt", self.corrupt_probability),
+        ];
+        if let Some(gilbert_elliott) = &self.gilbert_elliott {
+            probabilities.push(("good_to_bad", gilbert_elliott.good_to_bad));
+            probabilities.push(("bad_to_good", gilbert_elliott.bad_to_good));
+            probabilities.push(("loss_good", gilbert_elliott.loss_good));
+            probabilities.push(("loss_bad", gilbert_elliott.loss_bad));
+        }
+        for (name, probability) in probabilities {
+            if !(0.0..=1.0).contains(&probability) {
+                return Err(ProfileError(format!(
+                    "{name} probability {probability} is not between 0.0 and 1.0"
+                ))
+                .into());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::profile::*;
+
+    #[test]
+    fn validate_profile_test() {
+        assert!(LinkProfile::default().validate().is_ok());
+        let profile = LinkProfile {
+            drop_probability: 1.5,
+            ..Default::default()
+        };
+        assert!(profile.validate().is_err());
+        let profile = LinkProfile {
+            gilbert_elliott: Some(GilbertElliott {
+                good_to_bad: 0.1,
+                bad_to_good: -0.1,
+                loss_good: 0.0,
+                loss_bad: 1.0,
+            }),
+            ..Default::default()
+        };
+        assert!(profile.validate().is_err());
+    }
+}
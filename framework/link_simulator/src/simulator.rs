@@ -0,0 +1,193 @@
+// Copyright 2020 Ministerie van Defensie
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::errors::ErrorKind::UdpSocketError;
+use crate::errors::*;
+use crate::model::{Delivery, LinkModel, LinkStats};
+use crate::profile::LinkProfile;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+///The largest datagram the link simulator can forward.
+const MAX_DATAGRAM_SIZE: usize = 65535;
+
+///The time the simulator thread waits for a datagram when nothing is scheduled.
+const IDLE_POLL_MS: u64 = 10;
+
+///The LinkSimulator is a UDP proxy placed between the UdpSender and the UdpReceiver.
+///Every datagram received on the listening socket is passed through a LinkModel,
+///the resulting datagrams are forwarded to the receiver address.
+pub struct LinkSimulator {
+    socket: UdpSocket,
+    forward_addr: SocketAddr,
+    profile: LinkProfile,
+    should_stop: Arc<AtomicBool>,
+    stats: Arc<Mutex<LinkStats>>,
+}
+
+impl LinkSimulator {
+    ///Creates a new LinkSimulator.
+    /// # Arguments
+    /// * `listen_addr` - The address the UdpSender sends to.
+    /// * `forward_addr` - The address of the UdpReceiver.
+    /// * `profile` - The impairments applied to the datagrams.
+    pub fn new(
+        listen_addr: &str,
+        forward_addr: &str,
+        profile: LinkProfile,
+    ) -> Result<LinkSimulator> {
+        profile.validate()?;
+        let socket = UdpSocket::bind(listen_addr)
+            .chain_err(|| UdpSocketError(format!("Error binding {listen_addr}")))?;
+        let forward_addr = forward_addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| UdpSocketError(format!("Invalid forward address {forward_addr}")))?;
+        Ok(LinkSimulator {
+            socket,
+            forward_addr,
+            profile,
+            should_stop: Arc::new(AtomicBool::new(false)),
+            stats: Arc::new(Mutex::new(LinkStats::default())),
+        })
+    }
+
+    ///Returns the address the LinkSimulator is listening on.
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    ///Returns the statistics of all datagrams handled so far.
+    pub fn stats(&self) -> LinkStats {
+        *self.stats.lock().expect("Error locking mutex")
+    }
+
+    ///This function is used to start the LinkSimulator.
+    ///One thread receives datagrams as fast as possible, so no datagrams are lost in the socket buffer.
+    ///A second thread applies the model and forwards the datagrams.
+    ///The joinhandle to the forwarding thread is returned, it stops after the receiving thread.
+    /// # Returns
+    /// `JoinHandle<()>` - The JoinHandle of the forwarding thread.
+    pub fn run(&self) -> std::io::Result<JoinHandle<()>> {
+        let receive_socket = self.socket.try_clone()?;
+        receive_socket.set_read_timeout(Some(Duration::from_millis(IDLE_POLL_MS)))?;
+        let send_socket = self.socket.try_clone()?;
+        let forward_addr = self.forward_addr;
+        let model = LinkModel::new(self.profile.clone());
+        let should_stop = Arc::clone(&self.should_stop);
+        let stats = Arc::clone(&self.stats);
+        let (datagram_sender, datagram_receiver) = mpsc::channel();
+        std::thread::Builder::new()
+            .name("link_receive_thread".into())
+            .spawn(move || link_receive_thread(receive_socket, datagram_sender, should_stop))?;
+        std::thread::Builder::new()
+            .name("link_simulator_thread".into())
+            .spawn(move || {
+                link_simulator_thread(send_socket, forward_addr, model, datagram_receiver, stats)
+            })
+    }
+
+    ///This function is used to stop the LinkSimulator thread.
+    ///Datagrams that are still delayed or held back are sent before the thread stops.
+    pub fn stop(&self) {
+        self.should_stop.store(true, Ordering::SeqCst);
+    }
+}
+
+///Datagrams waiting for their delay to pass, ordered by due time and arrival.
+type Schedule = BinaryHeap<Reverse<(Instant, u64, Vec<u8>)>>;
+
+///Receives datagrams and passes them to the link_simulator_thread until the LinkSimulator is stopped.
+fn link_receive_thread(
+    socket: UdpSocket,
+    datagram_sender: Sender<Vec<u8>>,
+    should_stop: Arc<AtomicBool>,
+) {
+    let mut buffer = vec![0; MAX_DATAGRAM_SIZE];
+    while !should_stop.load(Ordering::SeqCst) {
+        match socket.recv(&mut buffer) {
+            Ok(length) => {
+                if datagram_sender.send(buffer[..length].to_vec()).is_err() {
+                    break;
+                }
+            }
+            Err(e)
+                if e.kind() == std::io::ErrorKind::WouldBlock
+                    || e.kind() == std::io::ErrorKind::TimedOut => {}
+            Err(e) => log::debug!("Couldn't receive datagram: {}", e),
+        }
+    }
+}
+
+///Applies the model to every received datagram and forwards the result when its delay has passed.
+fn link_simulator_thread(
+    socket: UdpSocket,
+    forward_addr: SocketAddr,
+    mut model: LinkModel,
+    datagram_receiver: Receiver<Vec<u8>>,
+    stats: Arc<Mutex<LinkStats>>,
+) {
+    let mut schedule = Schedule::new();
+    let mut order = 0;
+    loop {
+        let timeout = match schedule.peek() {
+            Some(Reverse((due, _, _))) => due.saturating_duration_since(Instant::now()),
+            None => Duration::from_millis(IDLE_POLL_MS),
+        };
+        match datagram_receiver.recv_timeout(timeout) {
+            Ok(datagram) => {
+                let deliveries = model.impair(&datagram);
+                push_deliveries(&mut schedule, &mut order, deliveries);
+                *stats.lock().expect("Error locking mutex") = model.stats();
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+        send_due(&socket, forward_addr, &mut schedule, Instant::now());
+    }
+    push_deliveries(&mut schedule, &mut order, model.flush());
+    *stats.lock().expect("Error locking mutex") = model.stats();
+    while let Some(Reverse((due, _, _))) = schedule.peek() {
+        std::thread::sleep(due.saturating_duration_since(Instant::now()));
+        send_due(&socket, forward_addr, &mut schedule, Instant::now());
+    }
+}
+
+fn push_deliveries(schedule: &mut Schedule, order: &mut u64, deliveries: Vec<Delivery>) {
+    let now = Instant::now();
+    for delivery in deliveries {
+        schedule.push(Reverse((now + delivery.delay, *order, delivery.payload)));
+        *order += 1;
+    }
+}
+
+///Sends all datagrams of which the delay has passed.
+fn send_due(socket: &UdpSocket, forward_addr: SocketAddr, schedule: &mut Schedule, now: Instant) {
+    while let Some(Reverse((due, _, _))) = schedule.peek() {
+        if *due > now {
+            break;
+        }
+        if let Some(Reverse((_, _, payload))) = schedule.pop() {
+            if let Err(e) = socket.send_to(&payload, forward_addr) {
+                log::warn!("Failed forwarding datagram: {}", e);
+            }
+        }
+    }
+}
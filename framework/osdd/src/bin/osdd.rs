@@ -12,12 +12,18 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use error_chain::ChainedError;
 use logging::set_syslog;
+use osdd::docker_monitor::*;
 use osdd::docker_runner::*;
 use osdd::errors::Result;
 use osdd::errors::*;
+use osdd::export::*;
+use osdd::lifecycle::*;
+use osdd::process_supervisor::*;
 use osdd::read_toml::*;
 use osdd::udp_multiplexer_stats::*;
+use osdd::validate::*;
 use osdd::*;
 use std::panic;
 use std::thread;
@@ -30,6 +36,38 @@ const HANDLER_NAME_STRING: &str = "OSSD";
 struct Opt {
     #[structopt(short, long = "config_file", default_value = "/home/osdd/Config.toml")]
     config_file: String,
+    #[structopt(subcommand)]
+    command: Option<OsddCommand>,
+}
+
+#[derive(StructOpt)]
+enum OsddCommand {
+    /// Checks the configuration file and reports its problems, without starting any handler.
+    Validate {
+        #[structopt(short, long = "config_file", default_value = "/home/osdd/Config.toml")]
+        config_file: String,
+    },
+    /// Lists the handlers of the instance and network in the configuration file with their state.
+    Status {
+        #[structopt(short, long = "config_file", default_value = "/home/osdd/Config.toml")]
+        config_file: String,
+    },
+    /// Stops and removes the handlers of the instance and network in the configuration file, in reverse chain order.
+    Stop {
+        #[structopt(short, long = "config_file", default_value = "/home/osdd/Config.toml")]
+        config_file: String,
+    },
+    /// Writes the handlers in the configuration file as a docker-compose file or as systemd units, without starting them.
+    Export {
+        #[structopt(short, long = "config_file", default_value = "/home/osdd/Config.toml")]
+        config_file: String,
+        /// compose or systemd
+        #[structopt(short, long, possible_values = &["compose", "systemd"])]
+        format: ExportFormat,
+        /// The file of the compose format or the directory of the systemd units, standard output when not given
+        #[structopt(short, long)]
+        output: Option<String>,
+    },
 }
 
 fn main() {
@@ -52,23 +90,30 @@ fn main() {
         std::process::exit(1);
     }));
 
-    osdd().chain_unwrap();
-    loop {
-        thread::sleep(time::Duration::from_millis(5000));
+    let opt = Opt::from_args();
+    match &opt.command {
+        Some(OsddCommand::Validate { config_file }) => std::process::exit(validate(config_file)),
+        Some(OsddCommand::Status { config_file }) => std::process::exit(status(config_file)),
+        Some(OsddCommand::Stop { config_file }) => std::process::exit(stop(config_file)),
+        Some(OsddCommand::Export {
+            config_file,
+            format,
+            output,
+        }) => std::process::exit(export(config_file, *format, output.as_deref())),
+        None => osdd(&opt.config_file).chain_unwrap(),
     }
 }
 
 /// This function starts the open source data diode.
 /// It loads the configuration from a TOML file.
 /// It starts a UDP multiplexer for statitics.
-/// It create and execute commands to run Docker containers.
-fn osdd() -> Result<()> {
-    let opt = Opt::from_args();
-
+/// It create and execute commands to run Docker containers, or the handlers as child processes with the native runtime.
+/// It runs until it receives SIGTERM or SIGINT, then it stops the handlers and removes their sockets.
+fn osdd(config_file: &str) -> Result<()> {
     eprintln!("start {HANDLER_NAME_STRING}");
 
     //Read handlers, settings_options and chains from the config file
-    let toml_config = read_toml(&opt.config_file)?;
+    let toml_config = read_toml(config_file)?;
 
     let chain_handler_name = format!(
         "osdd.{}.{}",
@@ -87,7 +132,7 @@ fn osdd() -> Result<()> {
     .chain_err(|| "Error initializing syslog")?;
 
     //creating /sockets path
-    match std::fs::create_dir(format!("{}/sockets", &toml_config.settings.path)) {
+    match std::fs::create_dir(toml_config.settings.socket_directory()) {
         Ok(_) => {
             log::trace!("created /sockets path");
         }
@@ -98,17 +143,43 @@ fn osdd() -> Result<()> {
             return Err(Error::with_chain(
                 e,
                 format!(
-                    "Error while creating {}/sockets path",
-                    &toml_config.settings.path
+                    "Error while creating {} path",
+                    toml_config.settings.socket_directory()
                 ),
             ))
         }
     }
 
+    //creating the shared-memory rings path when a chain uses it
+    if toml_config
+        .chains
+        .iter()
+        .any(|chain| chain.link_type == LINK_TYPE_SHM)
+    {
+        std::fs::create_dir_all(PATH_SHM_RINGS)
+            .chain_err(|| format!("Error while creating {PATH_SHM_RINGS} path"))?;
+    }
+
     let stats_multiplexer_listening_port_u16 = toml_config
         .settings
         .stats_multiplexer_listening_port
         .parse::<u16>()?;
+    let stats_flush_interval_sec = match &toml_config.settings.stats_flush_interval_sec {
+        Some(interval) => interval.parse::<u64>()?,
+        None => DEFAULT_STATS_FLUSH_INTERVAL_SEC,
+    };
+    if stats_flush_interval_sec == 0 {
+        return Err(ErrorKind::ConfigurationError(
+            "stats_flush_interval_sec must be at least 1".to_string(),
+        )
+        .into());
+    }
+    let stats_snapshot_path = match &toml_config.settings.stats_snapshot_path {
+        Some(path) => path.to_string(),
+        None => format!("{}/stats_snapshot.json", toml_config.settings.path),
+    };
+
+    let native = toml_config.settings.is_native();
 
     //create commands to run processes
     let commands = create_commands_all_handlers(
@@ -117,15 +188,257 @@ fn osdd() -> Result<()> {
         stats_multiplexer_listening_port_u16,
         &toml_config.settings,
     )?;
+    let names: Vec<String> = commands
+        .iter()
+        .map(|command_with_name| command_with_name.name.to_string())
+        .collect();
+    let sockets = sockets(&commands);
+
+    //SIGTERM and SIGINT stop the handlers before osdd ends
+    handle_termination_signals()?;
+
+    //Docker containers outlive osdd, the handlers of a previous run and their sockets are removed first
+    match stop_handlers(&toml_config.settings, &names, &sockets) {
+        Ok(stopped) if !stopped.is_empty() => {
+            log::warn!("removed {} handler(s) of a previous run", stopped.len())
+        }
+        Ok(_) => (),
+        Err(e) => log::warn!("Couldn't remove the handlers of a previous run: {}", e),
+    }
+
+    //creating the directories that are mounted in the containers, they are kept between runs
+    for directory in commands.iter().flat_map(|command| &command.directories) {
+        std::fs::create_dir_all(directory)
+            .chain_err(|| format!("Error while creating {directory} path"))?;
+    }
 
     //start udp multiplexer in other thread
     run(
         stats_multiplexer_listening_port_u16,
-        toml_config.settings.stats_servers,
+        toml_config.settings.stats_servers.clone(),
+        time::Duration::from_secs(stats_flush_interval_sec),
+        stats_snapshot_path,
     )?;
 
-    //Starting dockers and monitoring
-    handle_processes(commands)?;
+    let supervisors = if native {
+        //Starting the handlers as child processes and restarting them when they stop
+        supervise_processes(commands)?
+    } else {
+        //Starting dockers and monitoring
+        handle_processes(commands)?;
+        monitor_containers(names.clone(), stats_multiplexer_listening_port_u16)?;
+        Vec::new()
+    };
+
+    while !termination_requested() {
+        thread::sleep(time::Duration::from_millis(200));
+    }
+
+    //Teardown in reverse chain order
+    log::info!("stopping the handlers");
+    stop_handlers(&toml_config.settings, &names, &sockets)?;
+    for supervisor in supervisors {
+        let _ = supervisor.join();
+    }
+    log::info!("stopped {}", HANDLER_NAME_STRING);
 
     Ok(())
 }
+
+/// Reads the settings and creates the commands of the configured handlers, in chain order, from a TOML file.
+fn configured_commands(
+    config_file: &str,
+) -> std::result::Result<(Settings, Vec<CommandWithName>), String> {
+    let toml_config = read_toml(config_file).map_err(|e| e.display_chain().to_string())?;
+    let stats_port = toml_config
+        .settings
+        .stats_multiplexer_listening_port
+        .parse::<u16>()
+        .map_err(|e| format!("stats_multiplexer_listening_port: {e}"))?;
+    let commands = create_commands_all_handlers(
+        toml_config.chains,
+        toml_config.handlers,
+        stats_port,
+        &toml_config.settings,
+    )
+    .map_err(|e| e.display_chain().to_string())?;
+    Ok((toml_config.settings, commands))
+}
+
+/// Reads the settings and the names of the configured handlers, in chain order, from a TOML file.
+fn configured_handlers(config_file: &str) -> std::result::Result<(Settings, Vec<String>), String> {
+    let (settings, commands) = configured_commands(config_file)?;
+    let names = commands
+        .into_iter()
+        .map(|command_with_name| command_with_name.name)
+        .collect();
+    Ok((settings, names))
+}
+
+/// Returns the file names of the sockets the handlers create.
+fn sockets(commands: &[CommandWithName]) -> Vec<String> {
+    commands
+        .iter()
+        .flat_map(|command_with_name| command_with_name.sockets.iter().cloned())
+        .collect()
+}
+
+/// This function prints the handlers of the instance and network in a TOML file with their state.
+/// Configured handlers without a container or process are `missing`, handlers that are no longer configured are marked.
+/// Returns the exit code: 0 when every configured handler runs, 1 when not and 2 if the state cannot be read.
+fn status(config_file: &str) -> i32 {
+    let (settings, names) = match configured_handlers(config_file) {
+        Ok(configured) => configured,
+        Err(e) => {
+            eprintln!("Cannot read {config_file}: {}", e.trim_end());
+            return 2;
+        }
+    };
+    let mut deployed = match deployed_handlers(&settings) {
+        Ok(deployed) => deployed,
+        Err(e) => {
+            eprintln!("Cannot read the state of the handlers: {e}");
+            return 2;
+        }
+    };
+    let width = names
+        .iter()
+        .chain(deployed.iter().map(|handler| &handler.name))
+        .map(|name| name.len())
+        .max()
+        .unwrap_or_default();
+    let mut all_running = true;
+    for name in &names {
+        match deployed.iter().position(|handler| &handler.name == name) {
+            Some(index) => {
+                let handler = deployed.remove(index);
+                all_running &= handler.state == "running";
+                println!(
+                    "{:width$}  {:10}  {}",
+                    handler.name, handler.state, handler.status
+                );
+            }
+            None => {
+                all_running = false;
+                println!("{name:width$}  missing");
+            }
+        }
+    }
+    for handler in deployed {
+        println!(
+            "{:width$}  {:10}  {} (not configured)",
+            handler.name, handler.state, handler.status
+        );
+    }
+    if all_running {
+        0
+    } else {
+        1
+    }
+}
+
+/// This function stops and removes the handlers of the instance and network in a TOML file, in reverse chain order,
+/// and removes their sockets.
+/// Returns the exit code: 0 when the handlers are stopped and 2 if they cannot be stopped.
+fn stop(config_file: &str) -> i32 {
+    let (settings, commands) = match configured_commands(config_file) {
+        Ok(configured) => configured,
+        Err(e) => {
+            eprintln!("Cannot read {config_file}: {}", e.trim_end());
+            return 2;
+        }
+    };
+    let names: Vec<String> = commands
+        .iter()
+        .map(|command_with_name| command_with_name.name.to_string())
+        .collect();
+    match stop_handlers(&settings, &names, &sockets(&commands)) {
+        Ok(stopped) => {
+            for name in &stopped {
+                println!("stopped {name}");
+            }
+            println!("{} handler(s) stopped", stopped.len());
+            0
+        }
+        Err(e) => {
+            eprintln!("Cannot stop the handlers: {e}");
+            2
+        }
+    }
+}
+
+/// This function validates the configuration in a TOML file.
+/// It prints every problem with its line, like `Config.toml:12: error: ...`.
+/// Returns the exit code: 0 without errors, 1 with errors and 2 if the file cannot be read.
+fn validate(config_file: &str) -> i32 {
+    let toml_string = match std::fs::read_to_string(config_file) {
+        Ok(toml_string) => toml_string,
+        Err(e) => {
+            eprintln!("Cannot read {config_file}: {e}");
+            return 2;
+        }
+    };
+    let issues = validate_config(&toml_string);
+    for issue in &issues {
+        match issue.line {
+            Some(line) => println!("{config_file}:{line}: {issue}"),
+            None => println!("{config_file}: {issue}"),
+        }
+    }
+    let errors = issues
+        .iter()
+        .filter(|issue| issue.severity == Severity::Error)
+        .count();
+    println!(
+        "{config_file}: {errors} error(s), {} warning(s)",
+        issues.len() - errors
+    );
+    if errors == 0 {
+        0
+    } else {
+        1
+    }
+}
+
+/// This function writes the handlers in a TOML file as a docker-compose file or as systemd units.
+/// The compose file is written to `output`, the units to files in the directory `output`, or both to standard output.
+/// Returns the exit code: 0 when the deployment is written and 2 if it cannot be created or written.
+fn export(config_file: &str, format: ExportFormat, output: Option<&str>) -> i32 {
+    let (_, commands) = match configured_commands(config_file) {
+        Ok(configured) => configured,
+        Err(e) => {
+            eprintln!("Cannot read {config_file}: {}", e.trim_end());
+            return 2;
+        }
+    };
+    let files = match format {
+        ExportFormat::Compose => match compose_file(&commands, config_file) {
+            Ok(compose) => vec![("docker-compose.yml".to_string(), compose)],
+            Err(e) => {
+                eprintln!("Cannot export {config_file}: {e}");
+                return 2;
+            }
+        },
+        ExportFormat::Systemd => systemd_units(&commands, config_file),
+    };
+    for (file_name, content) in files {
+        let path = match (format, output) {
+            (ExportFormat::Compose, None) => {
+                print!("{content}");
+                continue;
+            }
+            (ExportFormat::Systemd, None) => {
+                println!("# {file_name}\n{content}");
+                continue;
+            }
+            (ExportFormat::Compose, Some(file)) => file.to_string(),
+            (ExportFormat::Systemd, Some(directory)) => format!("{directory}/{file_name}"),
+        };
+        if let Err(e) = std::fs::write(&path, content) {
+            eprintln!("Cannot write {path}: {e}");
+            return 2;
+        }
+        println!("wrote {path}");
+    }
+    0
+}
@@ -0,0 +1,354 @@
+// Copyright 2020 Ministerie van Defensie
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::lifecycle::termination_requested;
+use serde::Deserialize;
+use statistics_handler::format::datagrams;
+use std::collections::HashMap;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind, Read, Write};
+use std::net::UdpSocket;
+use std::os::unix::net::UnixStream;
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// The Unix socket of the Docker Engine API.
+const DOCKER_SOCKET: &str = "/var/run/docker.sock";
+/// The time between two inspections of the containers, the first inspection waits as long for `docker run` to create them.
+const MONITOR_INTERVAL: Duration = Duration::from_secs(5);
+/// The longest osdd waits for the Docker Engine to answer.
+pub(crate) const DOCKER_TIMEOUT: Duration = Duration::from_secs(5);
+/// The status of a container that does not exist, Docker itself has no status for it.
+const STATUS_MISSING: &str = "missing";
+
+/// The part of `GET /containers/{name}/json` osdd uses.
+#[derive(Deserialize, Default)]
+#[serde(default, rename_all = "PascalCase")]
+struct ContainerInspect {
+    state: ContainerState,
+    restart_count: u64,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, rename_all = "PascalCase")]
+struct ContainerState {
+    /// created, running, paused, restarting, removing, exited or dead
+    status: String,
+    exit_code: i64,
+    #[serde(rename = "OOMKilled")]
+    oom_killed: bool,
+    error: String,
+}
+
+/// The health of the container of a handler.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HandlerHealth {
+    /// The status Docker reports, or `missing` when the container does not exist.
+    pub status: String,
+    /// The number of times Docker restarted the container.
+    pub restart_count: u64,
+    /// Why the container stopped the last time, for example `exit code 137, out of memory`.
+    pub last_exit_reason: Option<String>,
+}
+
+impl HandlerHealth {
+    /// Creates the health from an inspected container, or from `None` when the container does not exist.
+    /// The last exit reason is kept from the previous health while the container did not stop again.
+    fn new(container: Option<ContainerInspect>, previous: Option<&HandlerHealth>) -> HandlerHealth {
+        let previous_exit_reason = previous.and_then(|previous| previous.last_exit_reason.clone());
+        match container {
+            None => HandlerHealth {
+                status: STATUS_MISSING.to_string(),
+                restart_count: previous.map_or(0, |previous| previous.restart_count),
+                last_exit_reason: previous_exit_reason,
+            },
+            Some(container) => {
+                let state = container.state;
+                let stopped = !matches!(state.status.as_str(), "created" | "running" | "paused");
+                let last_exit_reason = if stopped || state.exit_code != 0 {
+                    Some(exit_reason(&state))
+                } else {
+                    previous_exit_reason
+                };
+                HandlerHealth {
+                    status: state.status,
+                    restart_count: container.restart_count,
+                    last_exit_reason,
+                }
+            }
+        }
+    }
+
+    /// Returns the health as statsd gauges `<handler name>.health.<metric>`.
+    fn statsd_lines(&self, name: &str) -> Vec<String> {
+        vec![
+            format!(
+                "{}.health.exists:{}|g",
+                name,
+                (self.status != STATUS_MISSING) as u8
+            ),
+            format!(
+                "{}.health.running:{}|g",
+                name,
+                (self.status == "running") as u8
+            ),
+            format!(
+                "{}.health.restarting:{}|g",
+                name,
+                (self.status == "restarting") as u8
+            ),
+            format!("{}.health.restart_count:{}|g", name, self.restart_count),
+        ]
+    }
+}
+
+/// Watches the containers of the handlers through the Docker Engine API.
+/// Changes in their state and restarts are logged, and their health is sent as statistics to the stats multiplexer.
+/// # Arguments
+/// * `names` - The names of the containers, the names of the handlers.
+/// * `stats_port` - The port of the stats multiplexer.
+pub fn monitor_containers(names: Vec<String>, stats_port: u16) -> std::io::Result<JoinHandle<()>> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    thread::Builder::new()
+        .name("docker_monitor".into())
+        .spawn(move || monitor(&names, &socket, stats_port))
+}
+
+fn monitor(names: &[String], socket: &UdpSocket, stats_port: u16) {
+    let stats_address = format!("127.0.0.1:{stats_port}");
+    let mut healths: HashMap<String, HandlerHealth> = HashMap::new();
+    let mut docker_reachable = true;
+    loop {
+        thread::sleep(MONITOR_INTERVAL);
+        //the handlers are stopped, their state changes are expected
+        if termination_requested() {
+            return;
+        }
+        let mut lines = Vec::new();
+        for name in names {
+            let container = match inspect_container(name) {
+                Ok(container) => container,
+                Err(e) => {
+                    if docker_reachable {
+                        log::error!(
+                            "Cannot inspect the containers through {}: {}",
+                            DOCKER_SOCKET,
+                            e
+                        );
+                        docker_reachable = false;
+                    }
+                    continue;
+                }
+            };
+            if !docker_reachable {
+                log::info!("Inspecting the containers through {} again", DOCKER_SOCKET);
+                docker_reachable = true;
+            }
+            let previous = healths.get(name);
+            let health = HandlerHealth::new(container, previous);
+            log_transition(name, previous, &health);
+            lines.extend(health.statsd_lines(name));
+            healths.insert(name.to_string(), health);
+        }
+        for datagram in datagrams(&lines) {
+            if let Err(e) = socket.send_to(datagram.as_bytes(), &stats_address) {
+                log::warn!(
+                    "Couldn't send health statistics to {}. Error {}:",
+                    stats_address,
+                    e
+                );
+            }
+        }
+    }
+}
+
+/// Logs a change of the status and every restart of a container.
+fn log_transition(name: &str, previous: Option<&HandlerHealth>, health: &HandlerHealth) {
+    let last_exit_reason = health.last_exit_reason.as_deref().unwrap_or("unknown");
+    if previous.map(|previous| previous.status.as_str()) != Some(health.status.as_str()) {
+        match health.status.as_str() {
+            "running" => log::info!("{} is running", name),
+            "created" => log::info!("{} is created", name),
+            STATUS_MISSING => log::error!("The container of {} does not exist", name),
+            status => log::error!("{} is {}, last exit: {}", name, status, last_exit_reason),
+        }
+    }
+    if let Some(previous) = previous {
+        if health.restart_count > previous.restart_count {
+            log::warn!(
+                "{} was restarted {} time(s), {} restarts in total, last exit: {}",
+                name,
+                health.restart_count - previous.restart_count,
+                health.restart_count,
+                last_exit_reason
+            );
+        }
+    }
+}
+
+fn exit_reason(state: &ContainerState) -> String {
+    if state.oom_killed {
+        format!("exit code {}, out of memory", state.exit_code)
+    } else if !state.error.is_empty() {
+        format!("exit code {}, {}", state.exit_code, state.error)
+    } else {
+        format!("exit code {}", state.exit_code)
+    }
+}
+
+/// Inspects a container with the Docker Engine API, returns None when the container does not exist.
+fn inspect_container(name: &str) -> std::io::Result<Option<ContainerInspect>> {
+    match docker_request("GET", &format!("/containers/{name}/json"), DOCKER_TIMEOUT)? {
+        (200, body) => Ok(Some(serde_json::from_str(&body)?)),
+        (404, _) => Ok(None),
+        (status, body) => Err(unexpected_response(status, &body)),
+    }
+}
+
+/// Sends a request without a body to the Docker Engine API, returns the status code and the body of the response.
+/// # Arguments
+/// * `method` - The HTTP method, such as `GET`.
+/// * `path` - The path with the query, such as `/containers/json?all=1`.
+/// * `timeout` - The longest the Docker Engine may take to answer.
+pub(crate) fn docker_request(
+    method: &str,
+    path: &str,
+    timeout: Duration,
+) -> std::io::Result<(u16, String)> {
+    let mut stream = UnixStream::connect(DOCKER_SOCKET)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    //HTTP/1.0 makes the Docker Engine close the connection after the body, instead of sending it in chunks
+    write!(stream, "{method} {path} HTTP/1.0\r\nHost: docker\r\n\r\n")?;
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response)?;
+    let response = String::from_utf8_lossy(&response);
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or_else(|| IoError::new(IoErrorKind::InvalidData, "incomplete response"))?;
+    let status = head
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse::<u16>().ok())
+        .ok_or_else(|| IoError::new(IoErrorKind::InvalidData, "response without status"))?;
+    Ok((status, body.to_string()))
+}
+
+/// Creates the error of a response osdd did not expect, with the message of the Docker Engine.
+pub(crate) fn unexpected_response(status: u16, body: &str) -> IoError {
+    IoError::other(format!(
+        "Docker Engine answered {}: {}",
+        status,
+        body.trim()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn container(json: &str) -> Option<ContainerInspect> {
+        Some(serde_json::from_str(json).unwrap())
+    }
+
+    #[test]
+    fn running_test() {
+        let health = HandlerHealth::new(
+            container(
+                r#"{"State": {"Status": "running", "ExitCode": 0}, "RestartCount": 2, "Name": "x"}"#,
+            ),
+            None,
+        );
+        assert_eq!(
+            health,
+            HandlerHealth {
+                status: "running".to_string(),
+                restart_count: 2,
+                last_exit_reason: None,
+            }
+        );
+        assert_eq!(
+            health.statsd_lines("osdd.1.ingress.topic.ph.kafka"),
+            vec![
+                "osdd.1.ingress.topic.ph.kafka.health.exists:1|g",
+                "osdd.1.ingress.topic.ph.kafka.health.running:1|g",
+                "osdd.1.ingress.topic.ph.kafka.health.restarting:0|g",
+                "osdd.1.ingress.topic.ph.kafka.health.restart_count:2|g",
+            ]
+        );
+    }
+
+    #[test]
+    fn exit_reason_test() {
+        let oom_killed = HandlerHealth::new(
+            container(r#"{"State": {"Status": "exited", "ExitCode": 137, "OOMKilled": true}}"#),
+            None,
+        );
+        assert_eq!(oom_killed.status, "exited");
+        assert_eq!(
+            oom_killed.last_exit_reason.as_deref(),
+            Some("exit code 137, out of memory")
+        );
+        let error = HandlerHealth::new(
+            container(r#"{"State": {"Status": "dead", "ExitCode": 1, "Error": "no such file"}}"#),
+            None,
+        );
+        assert_eq!(
+            error.last_exit_reason.as_deref(),
+            Some("exit code 1, no such file")
+        );
+        //a running container that was restarted after a failure reports the exit code of the failure
+        let restarted = HandlerHealth::new(
+            container(r#"{"State": {"Status": "running", "ExitCode": 2}, "RestartCount": 1}"#),
+            None,
+        );
+        assert_eq!(restarted.last_exit_reason.as_deref(), Some("exit code 2"));
+        let restarting = HandlerHealth::new(
+            container(r#"{"State": {"Status": "restarting", "ExitCode": 0}}"#),
+            None,
+        );
+        assert_eq!(restarting.last_exit_reason.as_deref(), Some("exit code 0"));
+        assert_eq!(restarting.statsd_lines("h")[2], "h.health.restarting:1|g");
+    }
+
+    #[test]
+    fn previous_test() {
+        let previous = HandlerHealth {
+            status: "exited".to_string(),
+            restart_count: 3,
+            last_exit_reason: Some("exit code 137, out of memory".to_string()),
+        };
+        let running = HandlerHealth::new(
+            container(r#"{"State": {"Status": "running", "ExitCode": 0}, "RestartCount": 4}"#),
+            Some(&previous),
+        );
+        assert_eq!(running.restart_count, 4);
+        assert_eq!(running.last_exit_reason, previous.last_exit_reason);
+        let missing = HandlerHealth::new(None, Some(&previous));
+        assert_eq!(
+            missing,
+            HandlerHealth {
+                status: STATUS_MISSING.to_string(),
+                restart_count: 3,
+                last_exit_reason: previous.last_exit_reason.clone(),
+            }
+        );
+        assert_eq!(missing.statsd_lines("h")[0], "h.health.exists:0|g");
+        let new_missing = HandlerHealth::new(None, None);
+        assert_eq!(
+            (new_missing.restart_count, new_missing.last_exit_reason),
+            (0, None)
+        );
+    }
+}
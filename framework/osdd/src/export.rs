@@ -0,0 +1,573 @@
+// Copyright 2020 Ministerie van Defensie
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::CommandWithName;
+use std::fmt::Write;
+use std::str::FromStr;
+
+/// The Docker client the systemd units run, systemd needs an absolute path.
+const DOCKER_EXECUTABLE: &str = "/usr/bin/docker";
+/// The options of `docker run` that take the next argument as their value.
+const DOCKER_OPTIONS_WITH_VALUE: &[&str] = &[
+    "--network",
+    "--name",
+    "--mount",
+    "--restart",
+    "--entrypoint",
+];
+
+/// The formats the deployment can be written in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    /// One docker-compose file with a service per handler
+    Compose,
+    /// A systemd service unit per handler
+    Systemd,
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "compose" => Ok(ExportFormat::Compose),
+            "systemd" => Ok(ExportFormat::Systemd),
+            _ => Err(format!(
+                "Unknown format {format}, can be compose or systemd"
+            )),
+        }
+    }
+}
+
+/// A `docker run` command made by `create_commands_all_handlers`, split into its options, image and arguments.
+#[derive(Default)]
+struct DockerRun {
+    /// The options in order, with their value
+    options: Vec<(String, Option<String>)>,
+    image: String,
+    arguments: Vec<String>,
+}
+
+impl DockerRun {
+    /// Splits a docker command, returns None when the command does not run Docker.
+    fn parse(command_with_name: &CommandWithName) -> Option<DockerRun> {
+        let command = &command_with_name.command;
+        if command.get_program() != "docker" {
+            return None;
+        }
+        let mut arguments = command
+            .get_args()
+            .map(|argument| argument.to_string_lossy().to_string());
+        if arguments.next().as_deref() != Some("run") {
+            return None;
+        }
+        let mut docker_run = DockerRun::default();
+        while let Some(argument) = arguments.next() {
+            if !argument.starts_with('-') {
+                docker_run.image = argument;
+                docker_run.arguments = arguments.collect();
+                break;
+            }
+            let option = match argument.split_once('=') {
+                Some((option, value)) => (option.to_string(), Some(value.to_string())),
+                None if DOCKER_OPTIONS_WITH_VALUE.contains(&argument.as_str()) => {
+                    (argument, arguments.next())
+                }
+                None => (argument, None),
+            };
+            docker_run.options.push(option);
+        }
+        Some(docker_run)
+    }
+
+    /// Returns the values of an option, in order.
+    fn values<'a>(&'a self, option: &'a str) -> impl Iterator<Item = &'a str> {
+        self.options
+            .iter()
+            .filter(move |(name, _)| name == option)
+            .filter_map(|(_, value)| value.as_deref())
+    }
+
+    /// Returns the source and target of every bind mount.
+    fn bind_mounts(&self) -> Vec<(&str, &str)> {
+        self.values("--mount")
+            .filter_map(|mount| {
+                let field = |key: &str| {
+                    mount
+                        .split(',')
+                        .find_map(|field| field.strip_prefix(key)?.strip_prefix('='))
+                };
+                Some((field("source")?, field("target")?))
+            })
+            .collect()
+    }
+}
+
+/// Writes the deployment as a docker-compose file, with a service per handler.
+/// A service depends on the services of the handlers it receives from.
+/// # Arguments
+/// * `commands` - The commands of the handlers, created with the Docker runtime.
+/// * `source` - The configuration file the commands were created from, it is mentioned in the file.
+pub fn compose_file(commands: &[CommandWithName], source: &str) -> Result<String, String> {
+    let mut compose = String::new();
+    //writing to a String cannot fail
+    let _ = writeln!(compose, "# Generated by osdd export from {source}");
+    let _ = writeln!(compose, "version: \"3.7\"");
+    let _ = writeln!(compose, "services:");
+    for command_with_name in commands {
+        let docker_run = DockerRun::parse(command_with_name).ok_or_else(|| {
+            format!(
+                "{} does not run in Docker, the compose format needs runtime \"docker\"",
+                command_with_name.name
+            )
+        })?;
+        compose_service(&mut compose, command_with_name, &docker_run)?;
+    }
+    Ok(compose)
+}
+
+fn compose_service(
+    compose: &mut String,
+    command_with_name: &CommandWithName,
+    docker_run: &DockerRun,
+) -> Result<(), String> {
+    let _ = writeln!(compose, "  {}:", quoted(&command_with_name.name));
+    let _ = writeln!(compose, "    image: {}", quoted(&docker_run.image));
+    let mut network_mode = "bridge";
+    let mut cap_add = Vec::new();
+    let mut ports = Vec::new();
+    let mut ulimits = Vec::new();
+    let mut extra_hosts = Vec::new();
+    for (option, value) in &docker_run.options {
+        let value = value.as_deref().unwrap_or_default();
+        match option.as_str() {
+            "-d" | "--mount" => (),
+            "--name" => {
+                let _ = writeln!(compose, "    container_name: {}", quoted(value));
+            }
+            "--entrypoint" => {
+                let _ = writeln!(compose, "    entrypoint: [{}]", quoted(value));
+            }
+            "--restart" => {
+                let _ = writeln!(compose, "    restart: {}", quoted(value));
+            }
+            "--memory" => {
+                let _ = writeln!(compose, "    mem_limit: {}", quoted(value));
+            }
+            "--cpus" => {
+                let _ = writeln!(compose, "    cpus: {}", quoted(value));
+            }
+            "--network" => network_mode = value,
+            "--cap-add" => cap_add.push(value),
+            "--publish" => ports.push(value),
+            "--ulimit" => ulimits.push(value),
+            "--add-host" => extra_hosts.push(value),
+            _ => {
+                return Err(format!(
+                    "{}: docker option {} cannot be written to a compose file",
+                    command_with_name.name, option
+                ))
+            }
+        }
+    }
+    //the handlers outside the host network reach the stats multiplexer on the default Docker bridge
+    let _ = writeln!(compose, "    network_mode: {}", quoted(network_mode));
+    write_list(compose, "cap_add", &cap_add);
+    write_list(compose, "ports", &ports);
+    write_list(compose, "extra_hosts", &extra_hosts);
+    if !ulimits.is_empty() {
+        let _ = writeln!(compose, "    ulimits:");
+        for ulimit in ulimits {
+            let (name, soft, hard) = ulimit
+                .split_once('=')
+                .and_then(|(name, limits)| {
+                    let (soft, hard) = limits.split_once(':')?;
+                    Some((name, soft, hard))
+                })
+                .ok_or_else(|| {
+                    format!("{}: cannot read ulimit {}", command_with_name.name, ulimit)
+                })?;
+            let _ = writeln!(compose, "      {name}:");
+            let _ = writeln!(compose, "        soft: {soft}");
+            let _ = writeln!(compose, "        hard: {hard}");
+        }
+    }
+    let mounts = docker_run.bind_mounts();
+    if !mounts.is_empty() {
+        let _ = writeln!(compose, "    volumes:");
+        for (source, target) in mounts {
+            let _ = writeln!(compose, "      - type: bind");
+            let _ = writeln!(compose, "        source: {}", quoted(source));
+            let _ = writeln!(compose, "        target: {}", quoted(target));
+        }
+    }
+    let command: Vec<&str> = docker_run.arguments.iter().map(String::as_str).collect();
+    write_list(compose, "command", &command);
+    let upstream: Vec<&str> = command_with_name
+        .upstream
+        .iter()
+        .map(String::as_str)
+        .collect();
+    write_list(compose, "depends_on", &upstream);
+    Ok(())
+}
+
+/// Writes a list of strings as a key of a service, nothing when it is empty.
+fn write_list(compose: &mut String, key: &str, values: &[&str]) {
+    if values.is_empty() {
+        return;
+    }
+    let _ = writeln!(compose, "    {key}:");
+    for value in values {
+        let _ = writeln!(compose, "      - {}", quoted(value));
+    }
+}
+
+/// Returns the string as a double quoted YAML scalar, the escapes of JSON strings are valid in YAML.
+fn quoted(value: &str) -> String {
+    serde_json::Value::from(value).to_string()
+}
+
+/// Writes the deployment as systemd service units, one per handler, named `<handler name>.service`.
+/// A unit wants and is started after the units of the handlers it receives from, so systemd stops it before them.
+/// The handlers reconnect to their sockets, a unit is not restarted when the units it receives from are.
+/// Docker handlers run in the foreground in their unit and are restarted by systemd instead of Docker.
+/// # Arguments
+/// * `commands` - The commands of the handlers.
+/// * `source` - The configuration file the commands were created from, it is mentioned in the units.
+/// # Returns
+/// The file name and the content of every unit.
+pub fn systemd_units(commands: &[CommandWithName], source: &str) -> Vec<(String, String)> {
+    let mut units = Vec::new();
+    for command_with_name in commands {
+        let docker_run = DockerRun::parse(command_with_name);
+        let upstream_units: Vec<String> = command_with_name
+            .upstream
+            .iter()
+            .map(|name| format!("{name}.service"))
+            .collect();
+        let mut unit = String::new();
+        let _ = writeln!(unit, "# Generated by osdd export from {source}");
+        let _ = writeln!(unit, "[Unit]");
+        let _ = writeln!(unit, "Description=osdd handler {}", command_with_name.name);
+        let mut after = upstream_units.clone();
+        if docker_run.is_some() {
+            after.insert(0, "docker.service".to_string());
+            let _ = writeln!(unit, "Requires=docker.service");
+        } else {
+            after.insert(0, "network.target".to_string());
+        }
+        let _ = writeln!(unit, "After={}", after.join(" "));
+        if !upstream_units.is_empty() {
+            let _ = writeln!(unit, "Wants={}", upstream_units.join(" "));
+        }
+        let _ = writeln!(unit);
+        let _ = writeln!(unit, "[Service]");
+        let _ = writeln!(unit, "Type=simple");
+        let _ = writeln!(unit, "Restart=always");
+        let _ = writeln!(unit, "RestartSec=1");
+        match &docker_run {
+            Some(docker_run) => {
+                systemd_docker_service(&mut unit, &command_with_name.name, docker_run)
+            }
+            None => systemd_native_service(&mut unit, command_with_name),
+        }
+        let _ = writeln!(unit);
+        let _ = writeln!(unit, "[Install]");
+        let _ = writeln!(unit, "WantedBy=multi-user.target");
+        units.push((format!("{}.service", command_with_name.name), unit));
+    }
+    units
+}
+
+fn systemd_docker_service(unit: &mut String, name: &str, docker_run: &DockerRun) {
+    for (source, _) in docker_run.bind_mounts() {
+        let _ = writeln!(
+            unit,
+            "ExecStartPre=/bin/mkdir -p {}",
+            systemd_argument(source)
+        );
+    }
+    let _ = writeln!(
+        unit,
+        "ExecStartPre=-{} rm -f {}",
+        DOCKER_EXECUTABLE,
+        systemd_argument(name)
+    );
+    //the container runs in the foreground and is removed when it stops, systemd restarts it
+    let mut arguments = vec!["run".to_string(), "--rm".to_string()];
+    for (option, value) in &docker_run.options {
+        match (option.as_str(), value) {
+            ("-d" | "--restart", _) => (),
+            (option, Some(value)) if DOCKER_OPTIONS_WITH_VALUE.contains(&option) => {
+                arguments.push(option.to_string());
+                arguments.push(value.to_string());
+            }
+            (option, Some(value)) => arguments.push(format!("{option}={value}")),
+            (option, None) => arguments.push(option.to_string()),
+        }
+    }
+    arguments.push(docker_run.image.to_string());
+    arguments.extend(docker_run.arguments.iter().cloned());
+    let _ = writeln!(
+        unit,
+        "ExecStart={} {}",
+        DOCKER_EXECUTABLE,
+        systemd_arguments(&arguments)
+    );
+    let _ = writeln!(
+        unit,
+        "ExecStop={} stop -t 10 {}",
+        DOCKER_EXECUTABLE,
+        systemd_argument(name)
+    );
+}
+
+fn systemd_native_service(unit: &mut String, command_with_name: &CommandWithName) {
+    let command = &command_with_name.command;
+    if let Some(directory) = command.get_current_dir() {
+        let _ = writeln!(unit, "WorkingDirectory={}", directory.display());
+    }
+    if let Some(memory_limit_mb) = command_with_name.limits.memory_limit_mb {
+        let _ = writeln!(unit, "MemoryMax={memory_limit_mb}M");
+    }
+    if let Some(open_files_limit) = command_with_name.limits.open_files_limit {
+        let _ = writeln!(unit, "LimitNOFILE={open_files_limit}");
+    }
+    let arguments: Vec<String> = std::iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(|argument| argument.to_string_lossy().to_string())
+        .collect();
+    let _ = writeln!(unit, "ExecStart={}", systemd_arguments(&arguments));
+}
+
+fn systemd_arguments(arguments: &[String]) -> String {
+    arguments
+        .iter()
+        .map(|argument| systemd_argument(argument))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// Quotes an argument of a command line in a unit when needed, `%` and `$` are escaped so systemd does not expand them.
+fn systemd_argument(argument: &str) -> String {
+    let escaped = argument.replace('%', "%%").replace('$', "$$");
+    if !escaped.is_empty()
+        && !escaped
+            .chars()
+            .any(|c| c.is_whitespace() || matches!(c, '"' | '\'' | '\\' | ';'))
+    {
+        return escaped;
+    }
+    format!("\"{}\"", escaped.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ResourceLimits;
+    use std::process::Command;
+
+    fn command_with_name(program: &str, arguments: &[&str], upstream: &[&str]) -> CommandWithName {
+        let mut command = Command::new(program);
+        command.args(arguments);
+        CommandWithName {
+            command,
+            name: "osdd.1.ingress.topic.ph.kafka".to_string(),
+            upstream: upstream.iter().map(|name| name.to_string()).collect(),
+            directories: Vec::new(),
+            sockets: Vec::new(),
+            limits: ResourceLimits::default(),
+        }
+    }
+
+    fn docker_command(upstream: &[&str]) -> CommandWithName {
+        command_with_name(
+            "docker",
+            &[
+                "run",
+                "-d",
+                "--network",
+                "host",
+                "--name",
+                "osdd.1.ingress.topic.ph.kafka",
+                "--restart=on-failure",
+                "--memory=512m",
+                "--ulimit=nofile=1024:1024",
+                "--cpus=1.50",
+                "--mount",
+                "type=bind,source=/home/osdd/spool/kafka,target=/tmp/osdd_spool",
+                "--add-host=kafka-server-rx:10.0.0.1",
+                "osdd",
+                "ph_kafka_egress",
+                "--topic_name",
+                "a b",
+            ],
+            upstream,
+        )
+    }
+
+    #[test]
+    fn format_test() {
+        assert_eq!("compose".parse(), Ok(ExportFormat::Compose));
+        assert_eq!("systemd".parse(), Ok(ExportFormat::Systemd));
+        assert!("helm".parse::<ExportFormat>().is_err());
+    }
+
+    #[test]
+    fn parse_test() {
+        let docker_run = DockerRun::parse(&docker_command(&[])).unwrap();
+        assert_eq!(docker_run.image, "osdd");
+        assert_eq!(
+            docker_run.arguments,
+            vec!["ph_kafka_egress", "--topic_name", "a b"]
+        );
+        assert_eq!(docker_run.options[0], ("-d".to_string(), None));
+        assert_eq!(
+            docker_run.values("--network").collect::<Vec<&str>>(),
+            vec!["host"]
+        );
+        assert_eq!(
+            docker_run.values("--restart").collect::<Vec<&str>>(),
+            vec!["on-failure"]
+        );
+        assert_eq!(
+            docker_run.bind_mounts(),
+            vec![("/home/osdd/spool/kafka", "/tmp/osdd_spool")]
+        );
+        assert!(DockerRun::parse(&command_with_name("ph_udp_ingress", &[], &[])).is_none());
+        assert!(DockerRun::parse(&command_with_name("docker", &["rm", "x"], &[])).is_none());
+    }
+
+    #[test]
+    fn compose_file_test() {
+        let compose = compose_file(
+            &[docker_command(&["osdd.1.ingress.topic.transport.udp"])],
+            "osdd.toml",
+        )
+        .unwrap();
+        let expected = r#"# Generated by osdd export from osdd.toml
+version: "3.7"
+services:
+  "osdd.1.ingress.topic.ph.kafka":
+    image: "osdd"
+    container_name: "osdd.1.ingress.topic.ph.kafka"
+    restart: "on-failure"
+    mem_limit: "512m"
+    cpus: "1.50"
+    network_mode: "host"
+    extra_hosts:
+      - "kafka-server-rx:10.0.0.1"
+    ulimits:
+      nofile:
+        soft: 1024
+        hard: 1024
+    volumes:
+      - type: bind
+        source: "/home/osdd/spool/kafka"
+        target: "/tmp/osdd_spool"
+    command:
+      - "ph_kafka_egress"
+      - "--topic_name"
+      - "a b"
+    depends_on:
+      - "osdd.1.ingress.topic.transport.udp"
+"#;
+        assert_eq!(compose, expected);
+        let native = compose_file(
+            &[command_with_name("ph_udp_ingress", &[], &[])],
+            "osdd.toml",
+        );
+        assert!(native.unwrap_err().contains("needs runtime \"docker\""));
+        let privileged = compose_file(
+            &[command_with_name(
+                "docker",
+                &["run", "--privileged", "osdd"],
+                &[],
+            )],
+            "osdd.toml",
+        );
+        assert!(privileged
+            .unwrap_err()
+            .contains("--privileged cannot be written"));
+    }
+
+    #[test]
+    fn systemd_docker_unit_test() {
+        let units = systemd_units(
+            &[docker_command(&["osdd.1.ingress.topic.transport.udp"])],
+            "osdd.toml",
+        );
+        assert_eq!(units.len(), 1);
+        let (file_name, unit) = &units[0];
+        assert_eq!(file_name, "osdd.1.ingress.topic.ph.kafka.service");
+        let lines: Vec<&str> = unit.lines().collect();
+        assert!(lines.contains(&"Requires=docker.service"));
+        assert!(lines.contains(&"After=docker.service osdd.1.ingress.topic.transport.udp.service"));
+        assert!(lines.contains(&"Wants=osdd.1.ingress.topic.transport.udp.service"));
+        assert!(lines.contains(&"ExecStartPre=/bin/mkdir -p /home/osdd/spool/kafka"));
+        assert!(
+            lines.contains(&"ExecStartPre=-/usr/bin/docker rm -f osdd.1.ingress.topic.ph.kafka")
+        );
+        assert!(lines.contains(
+            &"ExecStart=/usr/bin/docker run --rm --network host --name osdd.1.ingress.topic.ph.kafka \
+              --memory=512m --ulimit=nofile=1024:1024 --cpus=1.50 \
+              --mount type=bind,source=/home/osdd/spool/kafka,target=/tmp/osdd_spool \
+              --add-host=kafka-server-rx:10.0.0.1 osdd ph_kafka_egress --topic_name \"a b\""
+        ));
+        assert!(
+            lines.contains(&"ExecStop=/usr/bin/docker stop -t 10 osdd.1.ingress.topic.ph.kafka")
+        );
+    }
+
+    #[test]
+    fn systemd_native_unit_test() {
+        let mut command_with_name = command_with_name(
+            "/usr/local/bin/ph_udp_ingress",
+            &["--listening_port", "7654"],
+            &[],
+        );
+        command_with_name.command.current_dir("/home/osdd");
+        command_with_name.limits = ResourceLimits {
+            memory_limit_mb: Some(256),
+            open_files_limit: Some(4096),
+            cpu_limit_percent: None,
+        };
+        let units = systemd_units(&[command_with_name], "osdd.toml");
+        let lines: Vec<&str> = units[0].1.lines().collect();
+        assert!(lines.contains(&"After=network.target"));
+        assert!(!lines.iter().any(|line| line.starts_with("Wants=")));
+        assert!(lines.contains(&"WorkingDirectory=/home/osdd"));
+        assert!(lines.contains(&"MemoryMax=256M"));
+        assert!(lines.contains(&"LimitNOFILE=4096"));
+        assert!(lines.contains(&"ExecStart=/usr/local/bin/ph_udp_ingress --listening_port 7654"));
+    }
+
+    #[test]
+    fn systemd_argument_test() {
+        assert_eq!(systemd_argument("plain"), "plain");
+        assert_eq!(systemd_argument(""), "\"\"");
+        assert_eq!(systemd_argument("a b"), "\"a b\"");
+        assert_eq!(systemd_argument("100%"), "100%%");
+        assert_eq!(systemd_argument("$HOME"), "$$HOME");
+        assert_eq!(systemd_argument("a;b"), "\"a;b\"");
+        assert_eq!(systemd_argument("it's"), "\"it's\"");
+        assert_eq!(systemd_argument("say \"hi\""), "\"say \\\"hi\\\"\"");
+        assert_eq!(systemd_argument("a\\b"), "\"a\\\\b\"");
+        assert_eq!(
+            systemd_arguments(&["echo".to_string(), "a b".to_string()]),
+            "echo \"a b\""
+        );
+    }
+}
@@ -12,23 +12,66 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+/// Watches the health of the Docker containers of the handlers
+pub mod docker_monitor;
 /// Starts processes from the given commands
 pub mod docker_runner;
 /// Error chain for OSDD
 pub mod errors;
+/// Writes the deployment as a docker-compose file or as systemd units
+pub mod export;
+/// Status, stop and teardown of the handlers
+pub mod lifecycle;
+/// Runs the handlers as child processes, without Docker
+pub mod process_supervisor;
 /// Read configuration out of the toml file
 pub mod read_toml;
+/// The arguments of the handler types osdd knows
+mod schema;
+/// Aggregation of the statistics of the handlers
+pub mod stats_aggregator;
 /// UDP multiplexer for statitics
 pub mod udp_multiplexer_stats;
+/// Validation of the configuration file
+pub mod validate;
 use crate::errors::ErrorKind::ConfigurationError;
 use crate::errors::*;
 use serde::Deserialize;
+use std::collections::HashMap;
 use std::fs;
+use std::os::unix::process::CommandExt;
 use std::process::Command;
 use toml::Value;
 
-const PATH_PREFIX_UNIX_SOCKETS_ON_PROXY: &str = "/home/osdd/sockets/";
 const PATH_PREFIX_UNIX_SOCKETS_IN_DOCKER: &str = "/tmp/";
+/// Shared-memory rings are created in this directory, on the proxy and in the docker containers.
+pub const PATH_SHM_RINGS: &str = "/dev/shm/osdd/";
+/// Directories that must outlive the container of a handler, as executable, argument and default value.
+/// In Docker osdd mounts `<path>/spool/<handler name>` of the proxy on them.
+const PERSISTENT_DIRECTORIES: &[(&str, &str, &str)] =
+    &[("ph_kafka_egress", "spool_directory", "/tmp/osdd_spool")];
+/// Host names a handler must resolve, as executable, argument with the IP address and host name.
+/// The Kafka brokers advertise these names. In Docker osdd adds them to the hosts of the container,
+/// with the native runtime they must resolve on the proxy itself.
+const HOST_ALIASES: &[(&str, &str, &str)] = &[
+    ("ph_kafka_ingress", "host_kafka_server", "kafka-server-tx"),
+    ("ph_kafka_egress", "host_kafka_server", "kafka-server-rx"),
+];
+
+/// The handlers run in Docker containers.
+pub const RUNTIME_DOCKER: &str = "docker";
+/// The handlers run as child processes of osdd.
+pub const RUNTIME_NATIVE: &str = "native";
+
+/// Handlers in a chain are linked with Unix domain sockets.
+pub const LINK_TYPE_SOCKET: &str = "socket";
+/// Handlers in a chain are linked with shared-memory rings.
+pub const LINK_TYPE_SHM: &str = "shm";
+
+/// A handler with several downstream handlers sends every message to all of them.
+pub const FAN_OUT_MODE_BROADCAST: &str = "broadcast";
+/// A handler with several downstream handlers sends every message to one of them, in turns.
+pub const FAN_OUT_MODE_ROUND_ROBIN: &str = "round_robin";
 
 /// Set from socket port to `0` for syslog. (0 is auto assiging to a port)
 pub const PORT_FROM_UDP_SYSLOG: u16 = 0;
@@ -39,6 +82,9 @@ pub const FROM_HOST_UDP_SYSLOG: &str = "0.0.0.0";
 /// However the actual limit for the data length, which is imposed by the underlying IPv4 protocol, is 65,507 bytes (65,535 − 8 byte UDP header − 20 byte IP header).
 pub const MAX_BUFFER_SIZE_BYTES: usize = 65507;
 
+/// The default seconds between two flushes of the aggregated statistics.
+pub const DEFAULT_STATS_FLUSH_INTERVAL_SEC: u64 = 10;
+
 /// OSDD Settings
 #[derive(Debug, Deserialize)]
 pub struct Settings {
@@ -58,17 +104,52 @@ pub struct Settings {
     pub network: String,
     /// The port the stats multiplexer is listening on
     pub stats_multiplexer_listening_port: String,
+    /// The seconds between two flushes of the aggregated statistics, default "10"
+    pub stats_flush_interval_sec: Option<String>,
+    /// The file the stats multiplexer writes the JSON snapshot of the last interval to, default "<path>/stats_snapshot.json"
+    pub stats_snapshot_path: Option<String>,
+    /// How the handlers are run, RUNTIME_DOCKER (default) or RUNTIME_NATIVE
+    pub runtime: Option<String>,
+}
+
+impl Settings {
+    /// Returns whether the handlers run as child processes of osdd instead of in Docker containers.
+    pub fn is_native(&self) -> bool {
+        self.runtime.as_deref() == Some(RUNTIME_NATIVE)
+    }
+
+    /// Returns the directory on the proxy with the Unix sockets between the handlers, `<path>/sockets/`.
+    /// Native handlers use it directly, Docker mounts it in the containers.
+    pub fn socket_directory(&self) -> String {
+        format!("{}/sockets/", self.path)
+    }
 }
 
-/// A chain consists of exactly one transport handler and exactly one protocol handler. A chain can also contain one or more filters. Filters are placed between the protocol handler and the transport handler.
+/// A chain links protocol handlers, filters and transport handlers. In its simplest form it is a line of exactly one protocol handler, zero or more filters and exactly one transport handler.
+/// A chain can also be a graph, where a handler sends to several downstream handlers (fan-out) or receives from several upstream handlers (fan-in).
 pub struct Chain {
     pub name: String,
-    ///The name of the protocol handler must match the name given in the handler
-    pub protocol_handler: String,
-    ///The name of the filter handler must match the name given in the handler
-    pub filter_handlers: Vec<String>,
-    ///The name of the transport handler must match the name given in the handler
-    pub transport_handler: String,
+    ///The links between the handlers, in the direction the data flows
+    pub edges: Vec<Edge>,
+    ///How the handlers in the chain are linked, LINK_TYPE_SOCKET or LINK_TYPE_SHM
+    pub link_type: String,
+    ///How a handler with several downstream handlers divides its messages, FAN_OUT_MODE_BROADCAST or FAN_OUT_MODE_ROUND_ROBIN
+    pub fan_out_mode: String,
+}
+
+///A link from one handler to another, the names must match the names given in the handlers
+#[derive(Debug, PartialEq)]
+pub struct Edge {
+    pub from: String,
+    pub to: String,
+}
+
+///The sockets (or rings) of one handler in a chain
+#[derive(Debug, Default)]
+struct HandlerLinks {
+    incoming: Vec<String>,
+    outgoing: Option<String>,
+    fan_out: usize,
 }
 
 ///A handler read from the TOML file
@@ -78,13 +159,40 @@ pub struct Handler {
     executable: String,
     arguments: Vec<(String, String)>,
     handler_type: HandlerType,
-    incoming_socket: Option<String>,
-    outgoing_socket: Option<String>,
     udp_port_option: Option<u16>,
     tcp_port_option: Option<u16>,
+    limits: ResourceLimits,
+}
+
+///The resource limits of a handler
+#[derive(Debug, Default, Clone, Copy)]
+struct ResourceLimits {
+    ///The maximum resident memory in MiB, like `docker run --memory` it does not limit the address space
+    memory_limit_mb: Option<u64>,
+    ///The maximum number of open files
+    open_files_limit: Option<u64>,
+    ///The CPU time in percent of one core, like `docker run --cpus`, only Docker can limit it
+    cpu_limit_percent: Option<u64>,
 }
 
-#[derive(PartialEq, Debug)]
+impl ResourceLimits {
+    ///Applies the limits to the current process, it is called in a new child process before the handler is executed.
+    ///The memory limit is not a limit of the process, the supervisor stops a handler that uses more memory.
+    fn apply(&self) -> std::io::Result<()> {
+        if let Some(open_files_limit) = self.open_files_limit {
+            let limit = libc::rlimit {
+                rlim_cur: open_files_limit,
+                rlim_max: open_files_limit,
+            };
+            if unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, &limit) } != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
 enum HandlerType {
     Transport,
     Filter,
@@ -96,33 +204,25 @@ enum HandlerType {
 pub struct CommandWithName {
     pub command: Command,
     pub name: String,
+    /// The names of the handlers this handler receives from, they create the sockets it connects to
+    pub upstream: Vec<String>,
+    /// The directories on the proxy that are mounted in the container, they are created before it starts
+    pub directories: Vec<String>,
+    /// The file names of the Unix sockets the handler creates in `<path>/sockets`
+    pub sockets: Vec<String>,
+    /// The resource limits, with the native runtime they are not part of the command
+    limits: ResourceLimits,
 }
 
 impl Handler {
-    /// Create Docker command from a handler
+    /// Create the command of a handler, a Docker command or with the native runtime the executable itself
     fn create_command(
         &self,
-        chain_name: &str,
+        chain: &Chain,
+        links: &HandlerLinks,
         stats_port: u16,
         settings: &Settings,
     ) -> Result<CommandWithName> {
-        let mut command = Command::new("docker");
-        command.args(["run"]);
-        command.args(["-d"]);
-
-        if self.handler_type == HandlerType::Transport {
-            command.args(["--network", "host"]);
-            command.args(["--cap-add=sys_nice"]);
-        }
-
-        //if "open_udp_port" is given to the handler then publish on the same port
-        if let Some(port) = self.udp_port_option {
-            command.args(&[format!("--publish={port}:{port}/udp")]);
-        }
-        if let Some(port) = self.tcp_port_option {
-            command.args(&[format!("--publish={port}:{port}")]);
-        }
-
         //short name is needed for the correct naming format
         let handler_type_short_name = match self.handler_type {
             HandlerType::Transport => "transport",
@@ -132,25 +232,34 @@ impl Handler {
 
         let chain_handler_name = format!(
             "osdd.{}.{}.{}.{}.{}",
-            &settings.instance, &settings.network, chain_name, handler_type_short_name, &self.name
+            &settings.instance, &settings.network, chain.name, handler_type_short_name, &self.name
         );
 
-        command.args(["--name", &chain_handler_name]);
-
-        //mount sockets path
-        command.args([
-            "--mount",
-            &format!(
-                "type=bind,source={PATH_PREFIX_UNIX_SOCKETS_ON_PROXY},target={PATH_PREFIX_UNIX_SOCKETS_IN_DOCKER}"
-            ),
-        ]);
+        //a process cannot be given a share of the CPU without a cgroup, which Docker creates
+        if settings.is_native() && self.limits.cpu_limit_percent.is_some() {
+            return Err(ConfigurationError(format!(
+                "Handler {} has a cpu_limit_percent, the native runtime cannot limit the CPU, use runtime \"{RUNTIME_DOCKER}\"",
+                self.name
+            ))
+            .into());
+        }
 
-        //Removes the Container after stopping
-        command.args(["--restart", "always"]);
+        //persistent directories are mounted from the proxy, containers of a previous run are removed
+        let mounts: Vec<(String, String)> = if settings.is_native() {
+            Vec::new()
+        } else {
+            self.persistent_directories()
+                .into_iter()
+                .map(|target| (format!("{}/spool/{}", settings.path, chain_handler_name), target))
+                .collect()
+        };
 
-        command.args(["--entrypoint", &format!("./{}", &self.executable)]);
+        let mut command = if settings.is_native() {
+            self.native_command(settings)
+        } else {
+            self.docker_command(chain, &chain_handler_name, &mounts, settings)
+        };
 
-        command.args([&self.executable]);
         //Load all arguments
         for argument in &self.arguments {
             let dash_dash_argument = format!("--{}", argument.0);
@@ -160,14 +269,22 @@ impl Handler {
         //Arguments for sockets
         match self.handler_type {
             HandlerType::Protocol | HandlerType::Transport => {
-                command_socket_path_transport_protocol(self, &mut command)?
+                command_socket_path_transport_protocol(self, links, &mut command)?
             }
-            HandlerType::Filter => command_socket_paths_filter(self, &mut command)?,
+            HandlerType::Filter => command_socket_paths_filter(self, links, &mut command)?,
         };
+        if chain.link_type == LINK_TYPE_SHM {
+            command.args(["--link_type", LINK_TYPE_SHM]);
+        }
+        if links.fan_out > 1 {
+            command.args(["--fan_out", &links.fan_out.to_string()]);
+            command.args(["--fan_out_mode", &chain.fan_out_mode]);
+        }
 
-        //Set standard arguments
-        if self.handler_type == HandlerType::Protocol
-            || self.handler_type == HandlerType::Filter
+        //Set standard arguments, handlers in their own Docker network reach the proxy through the Docker bridge
+        if !settings.is_native()
+            && (self.handler_type == HandlerType::Protocol
+                || self.handler_type == HandlerType::Filter)
         {
             command.args(["--stats_server_address", "172.17.0.1"]);
         } else {
@@ -182,46 +299,178 @@ impl Handler {
 
         command.current_dir(&settings.path);
 
+        //the upstream handler of a link creates the socket
+        let sockets = match &links.outgoing {
+            Some(outgoing) if chain.link_type != LINK_TYPE_SHM => outgoing
+                .rsplit('/')
+                .next()
+                .map(str::to_string)
+                .into_iter()
+                .collect(),
+            _ => Vec::new(),
+        };
+
         Ok(CommandWithName {
             command,
             name: chain_handler_name,
+            upstream: Vec::new(),
+            directories: mounts.into_iter().map(|(source, _)| source).collect(),
+            sockets,
+            limits: self.limits,
         })
     }
+
+    /// Returns the directories of the handler that must outlive its container, see PERSISTENT_DIRECTORIES.
+    fn persistent_directories(&self) -> Vec<String> {
+        PERSISTENT_DIRECTORIES
+            .iter()
+            .filter(|(executable, _, _)| *executable == self.executable)
+            .map(|(_, argument, default)| {
+                self.arguments
+                    .iter()
+                    .find(|(name, _)| name == argument)
+                    .map_or(default.to_string(), |(_, value)| value.to_string())
+            })
+            .collect()
+    }
+
+    /// Returns the host names the handler must resolve with their IP address, see HOST_ALIASES.
+    /// A host name instead of an IP address is resolved by the handler itself.
+    fn host_aliases(&self) -> Vec<(&'static str, String)> {
+        HOST_ALIASES
+            .iter()
+            .filter(|(executable, _, _)| *executable == self.executable)
+            .filter_map(|(_, argument, host_name)| {
+                self.arguments
+                    .iter()
+                    .find(|(name, _)| name == argument)
+                    .filter(|(_, address)| address.parse::<std::net::IpAddr>().is_ok())
+                    .map(|(_, address)| (*host_name, address.to_string()))
+            })
+            .collect()
+    }
+
+    /// Create the command that runs the handler in a Docker container
+    /// `mounts` are the persistent directories, as source on the proxy and target in the container.
+    fn docker_command(
+        &self,
+        chain: &Chain,
+        chain_handler_name: &str,
+        mounts: &[(String, String)],
+        settings: &Settings,
+    ) -> Command {
+        let mut command = Command::new("docker");
+        command.args(["run"]);
+        command.args(["-d"]);
+
+        if self.handler_type == HandlerType::Transport {
+            command.args(["--network", "host"]);
+            command.args(["--cap-add=sys_nice"]);
+        }
+
+        //if "open_udp_port" is given to the handler then publish on the same port
+        if let Some(port) = self.udp_port_option {
+            command.args(&[format!("--publish={port}:{port}/udp")]);
+        }
+        if let Some(port) = self.tcp_port_option {
+            command.args(&[format!("--publish={port}:{port}")]);
+        }
+
+        command.args(["--name", chain_handler_name]);
+
+        //mount sockets path
+        command.args([
+            "--mount",
+            &format!(
+                "type=bind,source={},target={PATH_PREFIX_UNIX_SOCKETS_IN_DOCKER}",
+                settings.socket_directory()
+            ),
+        ]);
+
+        //mount shared-memory rings path
+        if chain.link_type == LINK_TYPE_SHM {
+            command.args([
+                "--mount",
+                &format!("type=bind,source={PATH_SHM_RINGS},target={PATH_SHM_RINGS}"),
+            ]);
+        }
+
+        for (source, target) in mounts {
+            command.args(["--mount", &format!("type=bind,source={source},target={target}")]);
+        }
+
+        for (host_name, address) in self.host_aliases() {
+            command.args(&[format!("--add-host={host_name}:{address}")]);
+        }
+
+        //Resource limits
+        if let Some(memory_limit_mb) = self.limits.memory_limit_mb {
+            command.args(&[format!("--memory={memory_limit_mb}m")]);
+        }
+        if let Some(open_files_limit) = self.limits.open_files_limit {
+            command.args(&[format!("--ulimit=nofile={open_files_limit}:{open_files_limit}")]);
+        }
+        if let Some(cpu_limit_percent) = self.limits.cpu_limit_percent {
+            command.args(&[format!(
+                "--cpus={}.{:02}",
+                cpu_limit_percent / 100,
+                cpu_limit_percent % 100
+            )]);
+        }
+
+        //Removes the Container after stopping
+        command.args(["--restart", "always"]);
+
+        command.args(["--entrypoint", &format!("./{}", &self.executable)]);
+
+        command.args([&self.executable]);
+        command
+    }
+
+    /// Create the command that runs the executable of the handler as a child process of osdd.
+    /// The resource limits are applied in the child, and the child is stopped when osdd stops.
+    fn native_command(&self, settings: &Settings) -> Command {
+        let mut command = Command::new(format!("{}/{}", settings.path, self.executable));
+        let limits = self.limits;
+        //the closure runs in the child between fork and exec, it only makes system calls that are safe there
+        unsafe {
+            command.pre_exec(move || {
+                limits.apply()?;
+                if libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGTERM) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        command
+    }
 }
 
 /// Creates docker commands of the given handlers
 pub fn create_commands_all_handlers(
     chains: Vec<Chain>,
-    mut handlers_config: Vec<Handler>,
+    handlers_config: Vec<Handler>,
     stats_multiplexer_listening_port_u16: u16,
     settings: &Settings,
 ) -> Result<Vec<CommandWithName>> {
     let mut commands: Vec<CommandWithName> = Vec::new();
-    for mut chain in chains {
-        //Create a vector of the chain
-        let mut handlers_to_create: Vec<String> = Vec::new();
-        handlers_to_create.push(chain.protocol_handler);
-        handlers_to_create.append(&mut chain.filter_handlers);
-        handlers_to_create.push(chain.transport_handler);
-
-        //loop all pairs of the chain
-        //Set outgoing socket for the first and incoming socket fot the second
-        for item in handlers_to_create.windows(2) {
-            let process_to_start1 = &item[0];
-            let process_to_start2 = &item[1];
-            assign_sockets(
-                &mut handlers_config,
-                process_to_start1,
-                process_to_start2,
-                &chain.name,
-            )?;
-        }
+    //native handlers use the sockets path on the proxy, handlers in docker have it mounted
+    let socket_prefix = if settings.is_native() {
+        settings.socket_directory()
+    } else {
+        PATH_PREFIX_UNIX_SOCKETS_IN_DOCKER.to_string()
+    };
+    for chain in chains {
+        //Set the outgoing socket of the upstream and the incoming socket of the downstream handler of every edge
+        let (handlers_to_create, links) = assign_sockets(&chain, &socket_prefix)?;
 
         //Create commands to run dockers with all settings get and set before
-        for handler_to_create in handlers_to_create {
-            match handlers_config.iter().find(|x| x.name == handler_to_create) {
+        let chain_start = commands.len();
+        for handler_to_create in &handlers_to_create {
+            match handlers_config.iter().find(|x| &x.name == handler_to_create) {
                 Some(handler_config) => commands.push(handler_config.create_command(
-                    &chain.name,
+                    &chain,
+                    &links[handler_to_create],
                     stats_multiplexer_listening_port_u16,
                     settings,
                 )?),
@@ -233,69 +482,133 @@ pub fn create_commands_all_handlers(
                 }
             }
         }
+
+        //The upstream handler of every edge is the one that creates the socket
+        for (index, handler) in handlers_to_create.iter().enumerate() {
+            let upstream = chain
+                .edges
+                .iter()
+                .filter(|edge| &edge.to == handler)
+                .filter_map(|edge| handlers_to_create.iter().position(|x| x == &edge.from))
+                .map(|upstream_index| commands[chain_start + upstream_index].name.to_string())
+                .collect();
+            commands[chain_start + index].upstream = upstream;
+        }
     }
     Ok(commands)
 }
 
+/// Assigns the sockets (or rings) to the handlers of a chain.
+/// A handler with one downstream handler gets a socket per edge, a handler with several downstream handlers gets one socket all of them connect to.
+/// The sockets are created in `socket_prefix`, the rings in `PATH_SHM_RINGS`.
+/// Returns the handlers in the order they appear in the chain, together with their sockets.
 fn assign_sockets(
-    handlers_config: &mut [Handler],
-    process1: &str,
-    process2: &str,
-    chain_name: &str,
-) -> Result<()> {
-    match handlers_config.iter_mut().find(|x| x.name == process1) {
-        Some(handler) => {
-            handler.outgoing_socket = Some(format!(
-                "{PATH_PREFIX_UNIX_SOCKETS_IN_DOCKER}{chain_name}_{process1}_{process2}"
+    chain: &Chain,
+    socket_prefix: &str,
+) -> Result<(Vec<String>, HashMap<String, HandlerLinks>)> {
+    let path_prefix = if chain.link_type == LINK_TYPE_SHM {
+        PATH_SHM_RINGS
+    } else {
+        socket_prefix
+    };
+    let mut handlers: Vec<String> = Vec::new();
+    let mut links: HashMap<String, HandlerLinks> = HashMap::new();
+    for (index, edge) in chain.edges.iter().enumerate() {
+        if chain.edges[..index].contains(edge) {
+            return Err(ConfigurationError(format!(
+                "Chain {} links {} to {} more than once",
+                chain.name, edge.from, edge.to
             ))
+            .into());
         }
-        None => {
+        for handler in [&edge.from, &edge.to] {
+            if !handlers.contains(handler) {
+                handlers.push(handler.to_string());
+            }
+        }
+    }
+    for handler in &handlers {
+        let downstream = chain
+            .edges
+            .iter()
+            .filter(|edge| &edge.from == handler)
+            .collect::<Vec<&Edge>>();
+        let socket_path = match downstream.as_slice() {
+            [] => continue,
+            [edge] => format!("{path_prefix}{}_{}_{}", chain.name, edge.from, edge.to),
+            _ => format!("{path_prefix}{}_{}", chain.name, handler),
+        };
+        if downstream.len() > 1 && chain.link_type == LINK_TYPE_SHM {
             return Err(ConfigurationError(format!(
-                "Cannot find {process1} as handler in config"
+                "Chain {} uses link_type \"{}\", {} cannot send to several handlers",
+                chain.name, LINK_TYPE_SHM, handler
             ))
-            .into())
+            .into());
         }
-    };
-
-    match handlers_config.iter_mut().find(|x| x.name == process2) {
-        Some(handler) => {
-            handler.incoming_socket = Some(format!(
-                "{PATH_PREFIX_UNIX_SOCKETS_IN_DOCKER}{chain_name}_{process1}_{process2}"
-            ))
+        let handler_links = links.entry(handler.to_string()).or_default();
+        handler_links.outgoing = Some(socket_path.clone());
+        handler_links.fan_out = downstream.len();
+        for edge in downstream {
+            links
+                .entry(edge.to.to_string())
+                .or_default()
+                .incoming
+                .push(socket_path.clone());
         }
-        None => {
+    }
+    for (handler, handler_links) in &links {
+        if handler_links.incoming.len() > 1 && chain.link_type == LINK_TYPE_SHM {
             return Err(ConfigurationError(format!(
-                "Cannot find {process2} as handler in config"
+                "Chain {} uses link_type \"{}\", {} cannot receive from several handlers",
+                chain.name, LINK_TYPE_SHM, handler
             ))
-            .into())
+            .into());
         }
-    };
-
-    Ok(())
+    }
+    Ok((handlers, links))
 }
 
-fn command_socket_path_transport_protocol(handler: &Handler, command: &mut Command) -> Result<()> {
-    let socket_path = match &handler.incoming_socket {
-        Some(x) => x,
-        None => handler.outgoing_socket.as_ref().chain_err(|| {
-            ConfigurationError(format!(
+fn command_socket_path_transport_protocol(
+    handler: &Handler,
+    links: &HandlerLinks,
+    command: &mut Command,
+) -> Result<()> {
+    //a protocol or transport handler is an end of the chain, it has either incoming or outgoing sockets
+    let socket_path = match (links.incoming.is_empty(), &links.outgoing) {
+        (false, None) => links.incoming.join(","),
+        (true, Some(x)) => x.to_string(),
+        (false, Some(_)) => {
+            return Err(ConfigurationError(format!(
+                "{} is an end of the chain and cannot both receive from and send to other handlers",
+                handler.name
+            ))
+            .into())
+        }
+        (true, None) => {
+            return Err(ConfigurationError(format!(
                 "Cannot bind {} to other handler in chain",
                 handler.name
             ))
-        })?,
+            .into())
+        }
     };
-    command.args(["--socket_path", socket_path]);
+    command.args(["--socket_path", &socket_path]);
     Ok(())
 }
-fn command_socket_paths_filter(handler: &Handler, command: &mut Command) -> Result<()> {
-    let incoming_socket = handler.incoming_socket.as_ref().chain_err(|| {
-        ConfigurationError(format!(
+fn command_socket_paths_filter(
+    handler: &Handler,
+    links: &HandlerLinks,
+    command: &mut Command,
+) -> Result<()> {
+    if links.incoming.is_empty() {
+        return Err(ConfigurationError(format!(
             "Cannot bind {} to other handler in chain",
             handler.name
         ))
-    })?;
-    command.args(["--socket_path_in", incoming_socket]);
-    let outgoing_socket = handler.outgoing_socket.as_ref().chain_err(|| {
+        .into());
+    }
+    command.args(["--socket_path_in", &links.incoming.join(",")]);
+    let outgoing_socket = links.outgoing.as_ref().chain_err(|| {
         ConfigurationError(format!(
             "Cannot bind {} to other handler in chain",
             handler.name
@@ -304,3 +617,212 @@ fn command_socket_paths_filter(handler: &Handler, command: &mut Command) -> Resu
     command.args(["--socket_path_out", outgoing_socket]);
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(runtime: &str) -> Settings {
+        Settings {
+            path: "/opt/osdd".to_string(),
+            stats_servers: Vec::new(),
+            syslog_host: "127.0.0.1".to_string(),
+            syslog_port: "8082".to_string(),
+            log_level: "Info".to_string(),
+            instance: "1".to_string(),
+            network: "egress".to_string(),
+            stats_multiplexer_listening_port: "8125".to_string(),
+            stats_flush_interval_sec: None,
+            stats_snapshot_path: None,
+            runtime: Some(runtime.to_string()),
+        }
+    }
+
+    fn chain() -> Chain {
+        Chain {
+            name: "topic".to_string(),
+            edges: vec![Edge {
+                from: "receive".to_string(),
+                to: "kafka".to_string(),
+            }],
+            link_type: LINK_TYPE_SOCKET.to_string(),
+            fan_out_mode: FAN_OUT_MODE_BROADCAST.to_string(),
+        }
+    }
+
+    fn kafka_egress(host_kafka_server: &str, cpu_limit_percent: Option<u64>) -> Handler {
+        Handler {
+            name: "kafka".to_string(),
+            executable: "ph_kafka_egress".to_string(),
+            arguments: vec![
+                (
+                    "host_kafka_server".to_string(),
+                    host_kafka_server.to_string(),
+                ),
+                ("port_kafka_server".to_string(), "9092".to_string()),
+            ],
+            handler_type: HandlerType::Protocol,
+            udp_port_option: None,
+            tcp_port_option: None,
+            limits: ResourceLimits {
+                cpu_limit_percent,
+                ..ResourceLimits::default()
+            },
+        }
+    }
+
+    fn command_arguments(command: &Command) -> Vec<String> {
+        command
+            .get_args()
+            .map(|argument| argument.to_string_lossy().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn docker_command_test() {
+        let command = kafka_egress("10.0.0.1", Some(150)).docker_command(
+            &chain(),
+            "osdd.kafka",
+            &[],
+            &settings(RUNTIME_DOCKER),
+        );
+        let arguments = command_arguments(&command);
+        assert!(
+            arguments.contains(&"--add-host=kafka-server-rx:10.0.0.1".to_string()),
+            "{:?}",
+            arguments
+        );
+        assert!(
+            arguments.contains(&"--cpus=1.50".to_string()),
+            "{:?}",
+            arguments
+        );
+
+        //a host name is resolved by the handler itself
+        let command = kafka_egress("kafka.example.org", None).docker_command(
+            &chain(),
+            "osdd.kafka",
+            &[],
+            &settings(RUNTIME_DOCKER),
+        );
+        let arguments = command_arguments(&command);
+        assert!(
+            !arguments
+                .iter()
+                .any(|argument| argument.starts_with("--add-host")),
+            "{:?}",
+            arguments
+        );
+        assert!(
+            !arguments
+                .iter()
+                .any(|argument| argument.starts_with("--cpus")),
+            "{:?}",
+            arguments
+        );
+    }
+
+    #[test]
+    fn native_cpu_limit_test() {
+        let handlers = |cpu_limit_percent| {
+            vec![
+                Handler {
+                    name: "receive".to_string(),
+                    executable: "transport_udp_receive".to_string(),
+                    arguments: Vec::new(),
+                    handler_type: HandlerType::Transport,
+                    udp_port_option: None,
+                    tcp_port_option: None,
+                    limits: ResourceLimits::default(),
+                },
+                kafka_egress("10.0.0.1", cpu_limit_percent),
+            ]
+        };
+        let error = create_commands_all_handlers(
+            vec![chain()],
+            handlers(Some(150)),
+            8125,
+            &settings(RUNTIME_NATIVE),
+        )
+        .unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("the native runtime cannot limit the CPU"),
+            "{}",
+            error
+        );
+
+        //Docker limits the CPU, the native runtime runs the executable itself
+        assert!(create_commands_all_handlers(
+            vec![chain()],
+            handlers(Some(150)),
+            8125,
+            &settings(RUNTIME_DOCKER)
+        )
+        .is_ok());
+        let commands = create_commands_all_handlers(
+            vec![chain()],
+            handlers(None),
+            8125,
+            &settings(RUNTIME_NATIVE),
+        )
+        .unwrap();
+        assert_eq!(
+            commands[1].command.get_program(),
+            "/opt/osdd/ph_kafka_egress"
+        );
+    }
+
+    #[test]
+    fn socket_directory_test() {
+        let handlers = || {
+            vec![
+                Handler {
+                    name: "receive".to_string(),
+                    executable: "transport_udp_receive".to_string(),
+                    arguments: Vec::new(),
+                    handler_type: HandlerType::Transport,
+                    udp_port_option: None,
+                    tcp_port_option: None,
+                    limits: ResourceLimits::default(),
+                },
+                kafka_egress("10.0.0.1", None),
+            ]
+        };
+        //Docker mounts the socket directory under the path of the settings
+        let commands = create_commands_all_handlers(
+            vec![chain()],
+            handlers(),
+            8125,
+            &settings(RUNTIME_DOCKER),
+        )
+        .unwrap();
+        let arguments = command_arguments(&commands[0].command);
+        assert!(
+            arguments.contains(&"type=bind,source=/opt/osdd/sockets/,target=/tmp/".to_string()),
+            "{:?}",
+            arguments
+        );
+        assert!(
+            arguments.contains(&"/tmp/topic_receive_kafka".to_string()),
+            "{:?}",
+            arguments
+        );
+
+        //native handlers use it directly
+        let commands = create_commands_all_handlers(
+            vec![chain()],
+            handlers(),
+            8125,
+            &settings(RUNTIME_NATIVE),
+        )
+        .unwrap();
+        let arguments = command_arguments(&commands[0].command);
+        assert!(
+            arguments.contains(&"/opt/osdd/sockets/topic_receive_kafka".to_string()),
+            "{:?}",
+            arguments
+        );
+    }
+}
@@ -0,0 +1,363 @@
+// Copyright 2020 Ministerie van Defensie
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::docker_monitor::{docker_request, unexpected_response, DOCKER_TIMEOUT};
+use crate::Settings;
+use serde::Deserialize;
+use std::fs;
+use std::os::unix::fs::FileTypeExt;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The time a handler gets to stop after SIGTERM, before it is killed.
+const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The command name of osdd in `/proc/<pid>/comm`.
+const OSDD_COMMAND_NAME: &str = "osdd";
+
+/// Set when osdd received SIGTERM or SIGINT.
+static TERMINATION_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn request_termination(_signal: libc::c_int) {
+    TERMINATION_REQUESTED.store(true, Ordering::SeqCst);
+}
+
+/// Makes SIGTERM and SIGINT request osdd to stop, so it can stop its handlers before it ends.
+pub fn handle_termination_signals() -> std::io::Result<()> {
+    for signal in [libc::SIGTERM, libc::SIGINT] {
+        let handler = request_termination as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        if unsafe { libc::signal(signal, handler) } == libc::SIG_ERR {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Returns whether osdd received SIGTERM or SIGINT.
+pub fn termination_requested() -> bool {
+    TERMINATION_REQUESTED.load(Ordering::SeqCst)
+}
+
+/// A handler that runs, or ran, for this instance and network.
+#[derive(Debug)]
+pub struct DeployedHandler {
+    /// The name of the handler, `osdd.<instance>.<network>.<chain>.<type>.<name>`
+    pub name: String,
+    /// The state Docker reports, such as `running` or `exited`, with the native runtime always `running`
+    pub state: String,
+    /// A description of the state, such as `Up 2 hours` or the process id
+    pub status: String,
+    /// The process of the handler with the native runtime
+    process_id: Option<libc::pid_t>,
+    /// The process that started the handler with the native runtime, the osdd that supervises it
+    parent_process_id: Option<libc::pid_t>,
+}
+
+/// The part of a container in `GET /containers/json` osdd uses.
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ContainerSummary {
+    names: Vec<String>,
+    state: String,
+    status: String,
+}
+
+/// Returns the handlers of the instance and network in the settings: the Docker containers, or with the native runtime
+/// the processes, with a name that starts with `osdd.<instance>.<network>.`.
+pub fn deployed_handlers(settings: &Settings) -> std::io::Result<Vec<DeployedHandler>> {
+    let prefix = format!("osdd.{}.{}.", settings.instance, settings.network);
+    if settings.is_native() {
+        return handler_processes(&prefix);
+    }
+    let containers: Vec<ContainerSummary> =
+        match docker_request("GET", "/containers/json?all=1", DOCKER_TIMEOUT)? {
+            (200, body) => serde_json::from_str(&body)?,
+            (status, body) => return Err(unexpected_response(status, &body)),
+        };
+    Ok(containers
+        .into_iter()
+        .filter_map(|container| {
+            //Docker starts the names of containers with a slash
+            let name = container
+                .names
+                .iter()
+                .map(|name| name.trim_start_matches('/'))
+                .find(|name| name.starts_with(&prefix))?;
+            Some(DeployedHandler {
+                name: name.to_string(),
+                state: container.state,
+                status: container.status,
+                process_id: None,
+                parent_process_id: None,
+            })
+        })
+        .collect())
+}
+
+/// Finds the processes started with `--handler_name <prefix>...`.
+fn handler_processes(prefix: &str) -> std::io::Result<Vec<DeployedHandler>> {
+    let mut handlers = Vec::new();
+    for entry in fs::read_dir("/proc")? {
+        let entry = entry?;
+        let process_id = match entry.file_name().to_string_lossy().parse::<libc::pid_t>() {
+            Ok(process_id) => process_id,
+            Err(_) => continue,
+        };
+        //the process can end while it is read
+        let command_line = match fs::read(entry.path().join("cmdline")) {
+            Ok(command_line) => command_line,
+            Err(_) => continue,
+        };
+        let arguments: Vec<String> = command_line
+            .split(|byte| *byte == 0)
+            .map(|argument| String::from_utf8_lossy(argument).to_string())
+            .collect();
+        let name = arguments
+            .windows(2)
+            .find(|pair| pair[0] == "--handler_name" && pair[1].starts_with(prefix))
+            .map(|pair| pair[1].to_string());
+        if let Some(name) = name {
+            if process_running(process_id) {
+                handlers.push(DeployedHandler {
+                    name,
+                    state: "running".to_string(),
+                    status: format!("pid {process_id}"),
+                    process_id: Some(process_id),
+                    parent_process_id: parent_process(process_id),
+                });
+            }
+        }
+    }
+    handlers.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(handlers)
+}
+
+/// Returns the fields of `/proc/<pid>/stat` after the command name, starting with the state and the parent process.
+fn process_stat(process_id: libc::pid_t) -> Option<Vec<String>> {
+    let stat = fs::read_to_string(format!("/proc/{process_id}/stat")).ok()?;
+    //the command name is between parentheses and can contain spaces
+    let (_, rest) = stat.rsplit_once(')')?;
+    Some(rest.split_whitespace().map(str::to_string).collect())
+}
+
+/// Returns whether a process exists and did not end, a process that ended but was not waited for is a zombie.
+fn process_running(process_id: libc::pid_t) -> bool {
+    process_stat(process_id).is_some_and(|fields| fields.first().is_some_and(|state| state != "Z"))
+}
+
+/// Returns the parent of a process.
+fn parent_process(process_id: libc::pid_t) -> Option<libc::pid_t> {
+    process_stat(process_id)?.get(1)?.parse().ok()
+}
+
+/// Returns the osdd processes, other than this one, that supervise the handlers.
+/// Such an osdd starts a handler again when it stops, so it is stopped itself and stops its handlers.
+fn supervisors(handlers: &[DeployedHandler]) -> Vec<libc::pid_t> {
+    let own_process_id = std::process::id() as libc::pid_t;
+    let mut supervisors: Vec<libc::pid_t> = handlers
+        .iter()
+        .filter_map(|handler| handler.parent_process_id)
+        .filter(|parent| *parent != own_process_id)
+        .filter(|parent| {
+            fs::read_to_string(format!("/proc/{parent}/comm"))
+                .is_ok_and(|command| command.trim_end() == OSDD_COMMAND_NAME)
+        })
+        .collect();
+    supervisors.sort_unstable();
+    supervisors.dedup();
+    supervisors
+}
+
+/// Stops and removes the handlers of the instance and network in the settings, and removes their sockets.
+/// The configured handlers are stopped in reverse chain order, handlers that are no longer configured after them.
+/// With the native runtime an osdd that supervises the handlers is sent SIGTERM, so it stops its handlers itself.
+/// # Arguments
+/// * `settings` - The settings of the deployment.
+/// * `configured_names` - The names of the configured handlers, in chain order.
+/// * `sockets` - The file names of the sockets the configured handlers create in `<path>/sockets`.
+/// # Returns
+/// The names of the stopped handlers.
+pub fn stop_handlers(
+    settings: &Settings,
+    configured_names: &[String],
+    sockets: &[String],
+) -> std::io::Result<Vec<String>> {
+    let mut deployed = deployed_handlers(settings)?;
+    let mut stopped = Vec::new();
+    let supervisors = supervisors(&deployed);
+    if !supervisors.is_empty() {
+        //the supervisor stops its handlers one by one, each can take STOP_TIMEOUT
+        let timeout = STOP_TIMEOUT * (deployed.len() as u32 + 1);
+        for supervisor in supervisors {
+            log::info!("stopping osdd with pid {}", supervisor);
+            stop_process(supervisor, timeout)?;
+        }
+        let remaining = deployed_handlers(settings)?;
+        for handler in deployed {
+            if !remaining.iter().any(|other| other.name == handler.name) {
+                stopped.push(handler.name);
+            }
+        }
+        deployed = remaining;
+    }
+    let mut ordered = Vec::new();
+    for name in configured_names.iter().rev() {
+        if let Some(index) = deployed.iter().position(|handler| &handler.name == name) {
+            ordered.push(deployed.remove(index));
+        }
+    }
+    ordered.append(&mut deployed);
+    for handler in ordered {
+        log::info!("stopping {}", handler.name);
+        match handler.process_id {
+            Some(process_id) => stop_process(process_id, STOP_TIMEOUT)?,
+            None => remove_container(&handler.name)?,
+        }
+        stopped.push(handler.name);
+    }
+    remove_stale_sockets(settings, sockets)?;
+    Ok(stopped)
+}
+
+/// Stops a container, giving the handler `STOP_TIMEOUT` to end, and removes it.
+fn remove_container(name: &str) -> std::io::Result<()> {
+    let stop_path = format!("/containers/{}/stop?t={}", name, STOP_TIMEOUT.as_secs());
+    match docker_request("POST", &stop_path, STOP_TIMEOUT + DOCKER_TIMEOUT)? {
+        //stopped, already stopped or already removed
+        (204 | 304 | 404, _) => (),
+        (status, body) => return Err(unexpected_response(status, &body)),
+    }
+    match docker_request("DELETE", &format!("/containers/{name}"), DOCKER_TIMEOUT)? {
+        (204 | 404, _) => Ok(()),
+        (status, body) => Err(unexpected_response(status, &body)),
+    }
+}
+
+/// Sends SIGTERM to a process and waits until it ended, a process that takes longer than `timeout` is killed.
+fn stop_process(process_id: libc::pid_t, timeout: Duration) -> std::io::Result<()> {
+    if unsafe { libc::kill(process_id, libc::SIGTERM) } != 0 {
+        let e = std::io::Error::last_os_error();
+        //the process already ended
+        return match e.raw_os_error() {
+            Some(libc::ESRCH) => Ok(()),
+            _ => Err(e),
+        };
+    }
+    let deadline = Instant::now() + timeout;
+    while process_running(process_id) {
+        if Instant::now() >= deadline {
+            log::warn!("process {} did not stop, killing it", process_id);
+            unsafe { libc::kill(process_id, libc::SIGKILL) };
+            break;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    Ok(())
+}
+
+/// Removes the sockets of the configured handlers in `<path>/sockets/` that handlers which no longer run left behind.
+/// Sockets of other deployments in the same directory are kept.
+/// # Arguments
+/// * `settings` - The settings of the deployment.
+/// * `sockets` - The file names of the sockets the configured handlers create.
+/// # Returns
+/// The number of removed sockets.
+pub fn remove_stale_sockets(settings: &Settings, sockets: &[String]) -> std::io::Result<usize> {
+    let mut removed = 0;
+    for socket in sockets {
+        let path = Path::new(&settings.socket_directory()).join(socket);
+        match fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.file_type().is_socket() => {
+                fs::remove_file(&path)?;
+                log::debug!("removed stale socket {:?}", path);
+                removed += 1;
+            }
+            Ok(_) => (),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixListener;
+
+    fn settings(path: &str) -> Settings {
+        Settings {
+            path: path.to_string(),
+            stats_servers: Vec::new(),
+            syslog_host: "127.0.0.1".to_string(),
+            syslog_port: "8082".to_string(),
+            log_level: "Info".to_string(),
+            instance: "1".to_string(),
+            network: "ingress".to_string(),
+            stats_multiplexer_listening_port: "8125".to_string(),
+            stats_flush_interval_sec: None,
+            stats_snapshot_path: None,
+            runtime: None,
+        }
+    }
+
+    #[test]
+    fn remove_stale_sockets_test() {
+        let path = std::env::temp_dir().join(format!("osdd_lifecycle_{}", std::process::id()));
+        let sockets_path = path.join("sockets");
+        fs::create_dir_all(&sockets_path).unwrap();
+        let configured = UnixListener::bind(sockets_path.join("chain_ph_transport")).unwrap();
+        let other = UnixListener::bind(sockets_path.join("other_ph_transport")).unwrap();
+        fs::write(sockets_path.join("chain_file"), b"").unwrap();
+
+        let settings = settings(path.to_str().unwrap());
+        let sockets = [
+            "chain_ph_transport".to_string(),
+            "chain_file".to_string(),
+            "chain_missing".to_string(),
+        ];
+        assert_eq!(remove_stale_sockets(&settings, &sockets).unwrap(), 1);
+        //the socket of another deployment and files that are no socket are kept
+        assert!(!sockets_path.join("chain_ph_transport").exists());
+        assert!(sockets_path.join("other_ph_transport").exists());
+        assert!(sockets_path.join("chain_file").exists());
+
+        drop((configured, other));
+        fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn process_test() {
+        let own_process_id = std::process::id() as libc::pid_t;
+        assert!(process_running(own_process_id));
+        assert_eq!(
+            parent_process(own_process_id),
+            Some(unsafe { libc::getppid() })
+        );
+        assert!(!process_running(libc::pid_t::MAX));
+        assert_eq!(parent_process(libc::pid_t::MAX), None);
+
+        //a handler started by this process is not stopped through a supervisor
+        let handler = DeployedHandler {
+            name: "osdd.1.ingress.chain.ph.udp".to_string(),
+            state: "running".to_string(),
+            status: String::new(),
+            process_id: None,
+            parent_process_id: Some(own_process_id),
+        };
+        assert!(supervisors(&[handler]).is_empty());
+    }
+}
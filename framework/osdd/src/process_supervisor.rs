@@ -0,0 +1,203 @@
+// Copyright 2020 Ministerie van Defensie
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::lifecycle::termination_requested;
+use crate::*;
+use std::io::{BufRead, BufReader, Read};
+use std::process::{Child, ExitStatus, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The wait before a handler that stopped is started again, it doubles every time the handler stops soon after it started.
+const MIN_RESTART_DELAY: Duration = Duration::from_secs(1);
+/// The maximum wait before a handler that stopped is started again.
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(60);
+/// A handler that ran at least this long ran fine, the wait before it is started again is reset to the minimum.
+const HEALTHY_RUN_TIME: Duration = Duration::from_secs(60);
+/// How often a wait before a restart checks whether osdd is stopping.
+const TERMINATION_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+/// How often the resident memory of a handler with a memory limit is checked.
+const MEMORY_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Starts the handlers as child processes and keeps them running.
+/// Every handler gets a thread that starts it, logs its output and starts it again when it stops.
+/// # Arguments
+/// * `commands` - The commands of the handlers, created with the native runtime.
+/// # Returns
+/// The supervisor threads, they end after osdd received SIGTERM and their handler stopped.
+pub fn supervise_processes(
+    commands: Vec<CommandWithName>,
+) -> std::io::Result<Vec<thread::JoinHandle<()>>> {
+    let mut supervisors = Vec::new();
+    for command_with_name in commands {
+        supervisors.push(
+            thread::Builder::new()
+                .name(format!("supervise {}", command_with_name.name))
+                .spawn(move || supervise(command_with_name))?,
+        );
+    }
+    Ok(supervisors)
+}
+
+/// Runs a handler, and runs it again when it stops, with a growing wait when it keeps stopping.
+/// A handler that stops after osdd received SIGTERM is not started again.
+fn supervise(command_with_name: CommandWithName) {
+    let CommandWithName {
+        mut command,
+        name,
+        limits,
+        ..
+    } = command_with_name;
+    let memory_limit = limits.memory_limit_mb.map(|mb| mb * 1024 * 1024);
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    let mut restart_delay = MIN_RESTART_DELAY;
+    loop {
+        let started = Instant::now();
+        match command.spawn() {
+            Ok(mut child) => {
+                log::info!("started {} with pid {}", name, child.id());
+                let forwarders = vec![
+                    child
+                        .stdout
+                        .take()
+                        .map(|output| forward_output(&name, output, log::Level::Info)),
+                    child
+                        .stderr
+                        .take()
+                        .map(|output| forward_output(&name, output, log::Level::Warn)),
+                ];
+                match wait(&mut child, &name, memory_limit) {
+                    Ok(status) if termination_requested() => {
+                        log::info!("{} stopped, {}", name, status)
+                    }
+                    Ok(status) => log::error!("{} stopped, {}", name, status),
+                    Err(e) => log::error!("Error waiting for {}: {}", name, e),
+                }
+                for forwarder in forwarders.into_iter().flatten().flatten() {
+                    let _ = forwarder.join();
+                }
+            }
+            Err(e) => log::error!("Error starting {}: {:?}: {}", name, command, e),
+        }
+        if termination_requested() {
+            return;
+        }
+        if started.elapsed() >= HEALTHY_RUN_TIME {
+            restart_delay = MIN_RESTART_DELAY;
+        }
+        log::warn!("restarting {} in {} seconds", name, restart_delay.as_secs());
+        let restart_at = Instant::now() + restart_delay;
+        while Instant::now() < restart_at {
+            if termination_requested() {
+                return;
+            }
+            thread::sleep(TERMINATION_CHECK_INTERVAL);
+        }
+        restart_delay = (restart_delay * 2).min(MAX_RESTART_DELAY);
+    }
+}
+
+/// Waits until a handler stops. A handler that uses more resident memory than `memory_limit` bytes is killed,
+/// as Docker does with a container that uses more than `--memory`.
+fn wait(child: &mut Child, name: &str, memory_limit: Option<u64>) -> std::io::Result<ExitStatus> {
+    let memory_limit = match memory_limit {
+        Some(memory_limit) => memory_limit,
+        None => return child.wait(),
+    };
+    loop {
+        //the process of a child that is not waited for stays, so its pid is not reused
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+        match resident_memory(child.id()) {
+            Ok(resident) if resident > memory_limit => {
+                log::error!(
+                    "{} uses {} MiB, more than its memory_limit_mb of {} MiB, killing it",
+                    name,
+                    resident / 1024 / 1024,
+                    memory_limit / 1024 / 1024
+                );
+                child.kill()?;
+                return child.wait();
+            }
+            Ok(_) => (),
+            Err(e) => log::warn!("Couldn't read the memory use of {}: {}", name, e),
+        }
+        thread::sleep(MEMORY_CHECK_INTERVAL);
+    }
+}
+
+/// Returns the resident memory of a process in bytes, from `/proc/<pid>/statm`.
+fn resident_memory(pid: u32) -> std::io::Result<u64> {
+    let statm = std::fs::read_to_string(format!("/proc/{pid}/statm"))?;
+    let pages = statm
+        .split_whitespace()
+        .nth(1)
+        .and_then(|pages| pages.parse::<u64>().ok())
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, statm.clone()))?;
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    Ok(pages * page_size as u64)
+}
+
+/// Logs every line of the output of a handler, until the handler closes it.
+fn forward_output(
+    name: &str,
+    output: impl Read + Send + 'static,
+    level: log::Level,
+) -> std::io::Result<thread::JoinHandle<()>> {
+    let name = name.to_string();
+    thread::Builder::new()
+        .name(format!("output {name}"))
+        .spawn(move || {
+            for line in BufReader::new(output).lines() {
+                match line {
+                    Ok(line) => log::log!(level, "{}: {}", name, line),
+                    Err(_) => break,
+                }
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::Command;
+
+    #[test]
+    fn resident_memory_test() {
+        let resident = resident_memory(std::process::id()).unwrap();
+        assert!(resident > 0);
+        assert!(resident_memory(u32::MAX).is_err());
+    }
+
+    #[test]
+    fn wait_memory_limit_test() {
+        //without a memory limit the handler runs until it stops
+        let mut child = Command::new("true").spawn().unwrap();
+        assert!(wait(&mut child, "true", None).unwrap().success());
+        let mut child = Command::new("sleep").arg("0.2").spawn().unwrap();
+        assert!(wait(&mut child, "sleep", Some(u64::MAX)).unwrap().success());
+
+        //a handler that uses more memory is killed
+        let mut child = Command::new("sleep").arg("10").spawn().unwrap();
+        let started = Instant::now();
+        let status = wait(&mut child, "sleep", Some(1)).unwrap();
+        assert_eq!(status.signal(), Some(libc::SIGKILL));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -24,17 +24,32 @@ pub struct TomlConfig {
 }
 
 #[derive(Debug, Deserialize)]
-struct ChainToml {
-    pub protocol_handler: String,
+pub(crate) struct ChainToml {
+    pub protocol_handler: Option<String>,
+    #[serde(default)]
     pub filter_handlers: Vec<String>,
-    pub transport_handler: String,
+    pub transport_handler: Option<String>,
+    #[serde(default)]
+    pub edges: Vec<String>,
+    #[serde(default = "default_link_type")]
+    pub link_type: String,
+    #[serde(default = "default_fan_out_mode")]
+    pub fan_out_mode: String,
+}
+
+fn default_link_type() -> String {
+    LINK_TYPE_SOCKET.to_string()
+}
+
+fn default_fan_out_mode() -> String {
+    FAN_OUT_MODE_BROADCAST.to_string()
 }
 
 /// Convert TOML file to settings, chains and handlers.
 /// println errors because logging is not initialized yet.
 pub fn read_toml(config_file: &str) -> Result<TomlConfig> {
     let mut handlers: Vec<Handler> = Vec::new();
-    let mut chains: Vec<Chain> = Vec::new();
+    let mut chain_tomls: Vec<(String, ChainToml)> = Vec::new();
     let mut settings_option: Option<Settings> = None;
     let toml_string = fs::read_to_string(config_file)
         .chain_err(|| ConfigurationError("Config not found".to_string()))?;
@@ -58,14 +73,7 @@ pub fn read_toml(config_file: &str) -> Result<TomlConfig> {
                                         chain_toml.0
                                     ))
                                 })?;
-                            let chain_with_name = Chain {
-                                name: chain_toml.0.to_string(),
-                                protocol_handler: chain_struct.protocol_handler,
-                                filter_handlers: chain_struct.filter_handlers,
-                                transport_handler: chain_struct.transport_handler,
-                            };
-
-                            chains.push(chain_with_name);
+                            chain_tomls.push((chain_toml.0.to_string(), chain_struct));
                         }
                     }
                 }
@@ -94,9 +102,20 @@ pub fn read_toml(config_file: &str) -> Result<TomlConfig> {
             }
         }
     };
+    if let Some(runtime) = settings_option.as_ref().and_then(|x| x.runtime.as_ref()) {
+        if runtime != RUNTIME_DOCKER && runtime != RUNTIME_NATIVE {
+            return Err(ConfigurationError(format!(
+                "Unknown runtime {runtime}, can be \"{RUNTIME_DOCKER}\" or \"{RUNTIME_NATIVE}\""
+            ))
+            .into());
+        }
+    }
     match settings_option {
         Some(settings) => Ok(TomlConfig {
-            chains,
+            chains: chain_tomls
+                .into_iter()
+                .map(|(name, chain_toml)| read_chain(name, chain_toml, &settings.network))
+                .collect::<Result<Vec<Chain>>>()?,
             handlers,
             settings,
         }),
@@ -106,90 +125,147 @@ pub fn read_toml(config_file: &str) -> Result<TomlConfig> {
     }
 }
 
+/// Convert a chain from a TOML file to a chain struct.
+/// A chain is either given as a line, with `protocol_handler`, `filter_handlers` and `transport_handler`, or as a graph with `edges`.
+/// The edges of a line follow the data: from the protocol handler to the transport handler on the ingress side, and the other way around on the egress side.
+pub(crate) fn read_chain(name: String, chain_toml: ChainToml, network: &str) -> Result<Chain> {
+    if chain_toml.link_type != LINK_TYPE_SOCKET && chain_toml.link_type != LINK_TYPE_SHM {
+        return Err(ConfigurationError(format!(
+            "Chain {} has unknown link_type {}, can be \"{}\" or \"{}\"",
+            name, chain_toml.link_type, LINK_TYPE_SOCKET, LINK_TYPE_SHM
+        ))
+        .into());
+    }
+    if chain_toml.fan_out_mode != FAN_OUT_MODE_BROADCAST
+        && chain_toml.fan_out_mode != FAN_OUT_MODE_ROUND_ROBIN
+    {
+        return Err(ConfigurationError(format!(
+            "Chain {} has unknown fan_out_mode {}, can be \"{}\" or \"{}\"",
+            name, chain_toml.fan_out_mode, FAN_OUT_MODE_BROADCAST, FAN_OUT_MODE_ROUND_ROBIN
+        ))
+        .into());
+    }
+    let edges = match (
+        chain_toml.protocol_handler,
+        chain_toml.transport_handler,
+        chain_toml.edges.is_empty(),
+    ) {
+        (Some(protocol_handler), Some(transport_handler), true) => {
+            let mut handlers = vec![protocol_handler];
+            handlers.extend(chain_toml.filter_handlers);
+            handlers.push(transport_handler);
+            if network == "egress" {
+                handlers.reverse();
+            }
+            handlers
+                .windows(2)
+                .map(|pair| Edge {
+                    from: pair[0].to_string(),
+                    to: pair[1].to_string(),
+                })
+                .collect()
+        }
+        (None, None, false) if chain_toml.filter_handlers.is_empty() => chain_toml
+            .edges
+            .iter()
+            .map(|edge| read_edge(&name, edge))
+            .collect::<Result<Vec<Edge>>>()?,
+        _ => {
+            return Err(ConfigurationError(format!(
+                "Chain {name} needs either protocol_handler, filter_handlers and transport_handler, or edges"
+            ))
+            .into())
+        }
+    };
+    Ok(Chain {
+        name,
+        edges,
+        link_type: chain_toml.link_type,
+        fan_out_mode: chain_toml.fan_out_mode,
+    })
+}
+
+/// Convert an edge of the form `"upstream -> downstream"` to an edge struct.
+fn read_edge(chain_name: &str, edge: &str) -> Result<Edge> {
+    match edge
+        .split("->")
+        .map(str::trim)
+        .collect::<Vec<&str>>()
+        .as_slice()
+    {
+        [from, to] if !from.is_empty() && !to.is_empty() => Ok(Edge {
+            from: from.to_string(),
+            to: to.to_string(),
+        }),
+        _ => Err(ConfigurationError(format!(
+            "Edge \"{edge}\" in chain {chain_name} is not of the form \"upstream -> downstream\""
+        ))
+        .into()),
+    }
+}
+
 /// Convert a handler from a TOML file to a handler struct.
-/// `type` and `open_udp_port` are specials cases and needed in the configuration in osdd.
-/// All other arguments are store in a vec and given as argument to the executable.
+/// `type`, `open_udp_port` and `open_tcp_port` are specials cases and needed in the configuration in osdd.
+/// All other arguments are checked against the schema of the handler type and store in a vec, they are given as argument to the executable.
 /// `type` is the executabe and docker name
 /// `open_udp_port` is to open een udp port in the docker container
 fn read_handler(handler_config: (&String, &Value), handler_type: HandlerType) -> Result<Handler> {
-    let mut executable_option: Option<&str> = None;
+    let name = handler_config.0;
+    let toml_arguments = handler_config
+        .1
+        .as_table()
+        .chain_err(|| ConfigurationError(format!("Handler {name} must be a table of arguments")))?;
+    let executable = toml_arguments
+        .get("type")
+        .and_then(Value::as_str)
+        .chain_err(|| ConfigurationError(format!("Cannot read the the type of handler {name}")))?;
+    let schema = schema::handler_schema(executable);
+
     let mut udp_port_option: Option<u16> = None;
     let mut tcp_port_option: Option<u16> = None;
+    let mut limits = ResourceLimits::default();
     let mut arguments = Vec::new();
 
     //read arguments from the handler_config.
-    if let Some(toml_arguments) = handler_config.1.as_table() {
-        for argument in toml_arguments {
-            if let Some(x) = argument.1.as_str() {
-                match argument.0.as_ref() {
-                    //Type is to define which type of handler it is
-                    "type" => {
-                        executable_option = argument.1.as_str();
-                    }
-                    "open_udp_port" => {
-                        udp_port_option = match argument.1.as_str() {
-                            Some(x) => Some(match x.parse::<u16>() {
-                                Ok(v) => v,
-                                Err(_) => {
-                                    return Err(ConfigurationError(format!(
-                                        "Cannot parse open udp port to a port in {}",
-                                        handler_config.0
-                                    ))
-                                    .into())
-                                }
-                            }),
-                            None => {
-                                return Err(ConfigurationError(format!(
-                                    "Cannot parse open udp port to a port in {}",
-                                    handler_config.0
-                                ))
-                                .into())
-                            }
-                        }
-                    }
-                    "open_tcp_port" => {
-                        tcp_port_option = match argument.1.as_str() {
-                            Some(x) => Some(match x.parse::<u16>() {
-                                Ok(v) => v,
-                                Err(_) => {
-                                    return Err(ConfigurationError(format!(
-                                        "Cannot parse open tcp port to a port in {}",
-                                        handler_config.0
-                                    ))
-                                    .into())
-                                }
-                            }),
-                            None => {
-                                return Err(ConfigurationError(format!(
-                                    "Cannot parse open tcp port to a port in {}",
-                                    handler_config.0
-                                ))
-                                .into())
-                            }
-                        }
-                    }
-                    //All other arguments are arguments for the handler
-                    _ => arguments.push((argument.0.to_string(), x.to_string())),
-                }
-            }
+    for (key, value) in toml_arguments {
+        let argument = schema::argument_value(schema, key, value).map_err(|message| {
+            Error::from(ConfigurationError(format!(
+                "{key} of handler {name}: {message}"
+            )))
+        })?;
+        match key.as_ref() {
+            //Type is to define which type of handler it is
+            "type" => {}
+            //The ports are checked by the schema
+            "open_udp_port" => udp_port_option = argument.parse::<u16>().ok(),
+            "open_tcp_port" => tcp_port_option = argument.parse::<u16>().ok(),
+            //Resource limits are applied by osdd
+            "memory_limit_mb" => limits.memory_limit_mb = argument.parse::<u64>().ok(),
+            "open_files_limit" => limits.open_files_limit = argument.parse::<u64>().ok(),
+            "cpu_limit_percent" => limits.cpu_limit_percent = argument.parse::<u64>().ok(),
+            //All other arguments are arguments for the handler
+            _ => arguments.push((key.to_string(), argument)),
         }
     }
-    if let Some(executable) = executable_option {
-        Ok(Handler {
-            name: handler_config.0.to_string(),
-            executable: executable.to_string(),
-            arguments,
-            handler_type,
-            incoming_socket: None,
-            outgoing_socket: None,
-            udp_port_option,
-            tcp_port_option,
-        })
-    } else {
-        Err(ConfigurationError(format!(
-            "Cannot read the the type of handler {}",
-            handler_config.0
-        ))
-        .into())
+    if let Some(schema) = schema {
+        if let Some(missing) = schema
+            .required_arguments()
+            .find(|argument| !toml_arguments.contains_key(argument.name))
+        {
+            return Err(ConfigurationError(format!(
+                "Handler {name} of type {executable} needs {}",
+                missing.name
+            ))
+            .into());
+        }
     }
+    Ok(Handler {
+        name: name.to_string(),
+        executable: executable.to_string(),
+        arguments,
+        handler_type,
+        udp_port_option,
+        tcp_port_option,
+        limits,
+    })
 }
@@ -0,0 +1,730 @@
+// Copyright 2020 Ministerie van Defensie
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::*;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+
+/// The kind of value of an argument, it decides which TOML values are accepted and how they are given on the command line.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum ArgumentKind {
+    /// A string, a number or a boolean
+    Text,
+    /// An integer between a minimum and a maximum, both included
+    Integer(i64, i64),
+    /// A port from 1 up to 65535
+    Port,
+    /// An IP address, the handlers do not resolve host names
+    IpAddress,
+    /// An IP address or a host name
+    Host,
+    /// A host and a port, separated by a colon
+    SocketAddress,
+    /// A log level, "Error", "Warn", "Info", "Debug" or "Trace"
+    LogLevel,
+    /// One of the given strings
+    Choice(&'static [&'static str]),
+    /// An array of integers between a minimum and a maximum, given as a comma separated list
+    IntegerList(i64, i64),
+}
+
+/// An argument of a handler.
+pub(crate) struct ArgumentSchema {
+    pub name: &'static str,
+    pub kind: ArgumentKind,
+    /// Required arguments have a default that is only an example, they must be configured
+    pub required: bool,
+}
+
+const fn optional(name: &'static str, kind: ArgumentKind) -> ArgumentSchema {
+    ArgumentSchema {
+        name,
+        kind,
+        required: false,
+    }
+}
+
+const fn required(name: &'static str, kind: ArgumentKind) -> ArgumentSchema {
+    ArgumentSchema {
+        name,
+        kind,
+        required: true,
+    }
+}
+
+/// The arguments and other properties of a handler type osdd knows about.
+pub(crate) struct HandlerSchema {
+    /// The executable, the `type` of the handler in the configuration
+    pub executable: &'static str,
+    pub handler_type: HandlerType,
+    /// The network the handler runs in, None if it runs in both
+    pub network: Option<&'static str>,
+    /// The arguments of the handler, in groups
+    pub arguments: &'static [&'static [ArgumentSchema]],
+    /// Arguments with a UDP port the handler binds on the proxy, transport handlers use the network of the proxy
+    pub proxy_udp_ports: &'static [&'static str],
+    /// The arguments with the address and the port the handler sends to over the data diode
+    pub destination: Option<(&'static str, &'static str)>,
+}
+
+impl HandlerSchema {
+    /// Returns the argument with the given name.
+    pub(crate) fn argument(&self, name: &str) -> Option<&ArgumentSchema> {
+        self.arguments
+            .iter()
+            .flat_map(|group| group.iter())
+            .find(|argument| argument.name == name)
+    }
+
+    /// Returns the arguments that must be configured.
+    pub(crate) fn required_arguments(&self) -> impl Iterator<Item = &ArgumentSchema> {
+        self.arguments
+            .iter()
+            .flat_map(|group| group.iter())
+            .filter(|argument| argument.required)
+    }
+}
+
+/// Arguments osdd gives every handler itself, configuring them gives the handler the argument twice.
+const ARGUMENTS_SET_BY_OSDD: &[&str] = &[
+    "socket_path",
+    "socket_path_in",
+    "socket_path_out",
+    "link_type",
+    "fan_out",
+    "fan_out_mode",
+    "stats_server_address",
+    "stats_server_port",
+    "from_host_sys_log",
+    "from_port_sys_log",
+    "to_host_sys_log",
+    "to_port_sys_log",
+    "handler_name",
+];
+
+/// Arguments of every handler that osdd uses itself, they are not given to the handler.
+const OSDD_ARGUMENTS: &[ArgumentSchema] = &[
+    optional("type", ArgumentKind::Text),
+    optional("open_udp_port", ArgumentKind::Port),
+    optional("open_tcp_port", ArgumentKind::Port),
+    optional("memory_limit_mb", ArgumentKind::Integer(1, 1_048_576)),
+    optional("open_files_limit", ArgumentKind::Integer(16, 1_048_576)),
+    optional("cpu_limit_percent", ArgumentKind::Integer(1, 100_000)),
+];
+
+const LOG_ARGUMENTS: &[ArgumentSchema] = &[optional("log_level", ArgumentKind::LogLevel)];
+
+const BUFFER_ARGUMENTS: &[ArgumentSchema] = &[
+    //a message can be at most half the bip buffer, with 2 elements a message of 1Mb fits
+    optional("bip_buffer_element_count", ArgumentKind::Integer(2, 1000)),
+    optional(
+        "overflow_policy",
+        ArgumentKind::Choice(&["block", "drop_newest", "drop_oldest", "spill"]),
+    ),
+    optional("spill_directory", ArgumentKind::Text),
+    optional("spill_max_bytes", ArgumentKind::Integer(1, i64::MAX)),
+];
+
+const METRICS_ARGUMENTS: &[ArgumentSchema] = &[
+    optional("metrics_address", ArgumentKind::SocketAddress),
+    optional(
+        "metrics_format",
+        ArgumentKind::Choice(&["statsd", "dogstatsd", "influx"]),
+    ),
+    optional("stats_interval_ms", ArgumentKind::Integer(1, 3_600_000)),
+];
+
+const MODBUS_MODES: &[&str] = &["read", "write"];
+
+/// The handler types osdd knows about.
+static HANDLER_SCHEMAS: &[HandlerSchema] = &[
+    HandlerSchema {
+        executable: "ph_kafka_ingress",
+        handler_type: HandlerType::Protocol,
+        network: Some("ingress"),
+        arguments: &[
+            LOG_ARGUMENTS,
+            BUFFER_ARGUMENTS,
+            METRICS_ARGUMENTS,
+            &[
+                required("topic_name", ArgumentKind::Text),
+                required("host_kafka_server", ArgumentKind::Host),
+                required("port_kafka_server", ArgumentKind::Port),
+                optional(
+                    "max_bytes_per_partition",
+                    ArgumentKind::Integer(1, i32::MAX as i64),
+                ),
+            ],
+        ],
+        proxy_udp_ports: &[],
+        destination: None,
+    },
+    HandlerSchema {
+        executable: "ph_kafka_egress",
+        handler_type: HandlerType::Protocol,
+        network: Some("egress"),
+        arguments: &[
+            LOG_ARGUMENTS,
+            BUFFER_ARGUMENTS,
+            METRICS_ARGUMENTS,
+            &[
+                required("host_kafka_server", ArgumentKind::Host),
+                required("port_kafka_server", ArgumentKind::Port),
+                required("in_replacement", ArgumentKind::Text),
+                required("out_replacement", ArgumentKind::Text),
+                optional("spool_directory", ArgumentKind::Text),
+                optional("spool_max_bytes", ArgumentKind::Integer(1, i64::MAX)),
+            ],
+        ],
+        proxy_udp_ports: &[],
+        destination: None,
+    },
+    HandlerSchema {
+        executable: "ph_udp_ingress",
+        handler_type: HandlerType::Protocol,
+        network: Some("ingress"),
+        arguments: &[
+            LOG_ARGUMENTS,
+            BUFFER_ARGUMENTS,
+            METRICS_ARGUMENTS,
+            &[required("listening_port", ArgumentKind::Port)],
+        ],
+        proxy_udp_ports: &[],
+        destination: None,
+    },
+    HandlerSchema {
+        executable: "ph_udp_egress",
+        handler_type: HandlerType::Protocol,
+        network: Some("egress"),
+        arguments: &[
+            LOG_ARGUMENTS,
+            BUFFER_ARGUMENTS,
+            METRICS_ARGUMENTS,
+            &[
+                required("listening_port", ArgumentKind::Port),
+                required("udp_receiver_host", ArgumentKind::IpAddress),
+                required("udp_receiver_port", ArgumentKind::Port),
+            ],
+        ],
+        proxy_udp_ports: &[],
+        destination: None,
+    },
+    HandlerSchema {
+        executable: "ph_modbus_ingress",
+        handler_type: HandlerType::Protocol,
+        network: Some("ingress"),
+        arguments: &[
+            LOG_ARGUMENTS,
+            BUFFER_ARGUMENTS,
+            &[
+                optional("listening_port", ArgumentKind::Port),
+                optional("modbus_mode", ArgumentKind::Choice(MODBUS_MODES)),
+                optional("modbus_address", ArgumentKind::Host),
+                optional("modbus_port", ArgumentKind::Port),
+                optional("modbus_delay_ms", ArgumentKind::Integer(0, 60_000)),
+                optional(
+                    "modbus_coil_addresses_to_read",
+                    ArgumentKind::IntegerList(0, 65535),
+                ),
+                optional(
+                    "modbus_input_addresses_to_read",
+                    ArgumentKind::IntegerList(0, 65535),
+                ),
+                optional(
+                    "modbus_holding_register_addresses_to_read",
+                    ArgumentKind::IntegerList(0, 65535),
+                ),
+                optional(
+                    "modbus_input_register_addresses_to_read",
+                    ArgumentKind::IntegerList(0, 65535),
+                ),
+                optional("modbus_tcp_timeout", ArgumentKind::Integer(1, 60_000)),
+                optional("fec_resend_count", ArgumentKind::Integer(0, 255)),
+            ],
+        ],
+        proxy_udp_ports: &[],
+        destination: None,
+    },
+    HandlerSchema {
+        executable: "ph_modbus_egress",
+        handler_type: HandlerType::Protocol,
+        network: Some("egress"),
+        arguments: &[
+            LOG_ARGUMENTS,
+            BUFFER_ARGUMENTS,
+            &[
+                optional("listening_port", ArgumentKind::Port),
+                optional("modbus_mode", ArgumentKind::Choice(MODBUS_MODES)),
+                optional("modbus_address", ArgumentKind::Host),
+                optional("modbus_port", ArgumentKind::Port),
+                optional("modbus_delay_ms", ArgumentKind::Integer(0, 60_000)),
+                optional("modbus_tcp_timeout", ArgumentKind::Integer(1, 60_000)),
+            ],
+        ],
+        proxy_udp_ports: &[],
+        destination: None,
+    },
+    HandlerSchema {
+        executable: "ph_mock_ingress",
+        handler_type: HandlerType::Protocol,
+        network: Some("ingress"),
+        arguments: &[],
+        proxy_udp_ports: &[],
+        destination: None,
+    },
+    HandlerSchema {
+        executable: "ph_mock_egress",
+        handler_type: HandlerType::Protocol,
+        network: Some("egress"),
+        arguments: &[],
+        proxy_udp_ports: &[],
+        destination: None,
+    },
+    HandlerSchema {
+        executable: "filter",
+        handler_type: HandlerType::Filter,
+        network: None,
+        arguments: &[
+            LOG_ARGUMENTS,
+            BUFFER_ARGUMENTS,
+            METRICS_ARGUMENTS,
+            &[
+                required("word_to_filter", ArgumentKind::Text),
+                optional("max_message_size", ArgumentKind::Integer(1, i64::MAX)),
+            ],
+        ],
+        proxy_udp_ports: &[],
+        destination: None,
+    },
+    HandlerSchema {
+        executable: "transport_udp_send",
+        handler_type: HandlerType::Transport,
+        network: Some("ingress"),
+        arguments: &[
+            LOG_ARGUMENTS,
+            BUFFER_ARGUMENTS,
+            METRICS_ARGUMENTS,
+            &[
+                required("receiver_address", ArgumentKind::IpAddress),
+                required("receiver_port", ArgumentKind::Port),
+                required("sender_address", ArgumentKind::IpAddress),
+                required("sender_port", ArgumentKind::Integer(0, 65535)),
+                optional("send_delay_ms", ArgumentKind::Integer(0, 60_000)),
+            ],
+        ],
+        proxy_udp_ports: &["sender_port"],
+        destination: Some(("receiver_address", "receiver_port")),
+    },
+    HandlerSchema {
+        executable: "transport_udp_receive",
+        handler_type: HandlerType::Transport,
+        network: Some("egress"),
+        arguments: &[
+            LOG_ARGUMENTS,
+            BUFFER_ARGUMENTS,
+            METRICS_ARGUMENTS,
+            &[
+                required("receiver_address", ArgumentKind::IpAddress),
+                required("receiver_port", ArgumentKind::Port),
+            ],
+        ],
+        proxy_udp_ports: &["receiver_port"],
+        destination: None,
+    },
+];
+
+/// Returns the schema of a handler type, None if osdd does not know the type.
+pub(crate) fn handler_schema(executable: &str) -> Option<&'static HandlerSchema> {
+    HANDLER_SCHEMAS
+        .iter()
+        .find(|schema| schema.executable == executable)
+}
+
+/// Returns the known handler types of the given handler type.
+pub(crate) fn known_executables(handler_type: HandlerType) -> Vec<&'static str> {
+    HANDLER_SCHEMAS
+        .iter()
+        .filter(|schema| schema.handler_type == handler_type)
+        .map(|schema| schema.executable)
+        .collect()
+}
+
+/// Converts the value of an argument in the configuration to its value on the command line.
+/// Arguments of handler types osdd knows must be in the schema and fit their kind, arguments of other handler types can be
+/// strings, numbers, booleans or arrays of these.
+/// # Arguments
+/// * `schema` - The schema of the handler, None if osdd does not know its type.
+/// * `name` - The name of the argument.
+/// * `value` - The value in the configuration.
+/// # Returns
+/// The value on the command line, or the reason it is wrong.
+pub(crate) fn argument_value(
+    schema: Option<&HandlerSchema>,
+    name: &str,
+    value: &Value,
+) -> std::result::Result<String, String> {
+    if ARGUMENTS_SET_BY_OSDD.contains(&name) {
+        return Err("osdd sets it itself".to_string());
+    }
+    let argument = OSDD_ARGUMENTS.iter().find(|argument| argument.name == name);
+    match (argument, schema) {
+        (Some(argument), _) => kind_value(argument.kind, value),
+        (None, Some(schema)) => match schema.argument(name) {
+            Some(argument) => kind_value(argument.kind, value),
+            None => Err(format!("{} has no such argument", schema.executable)),
+        },
+        (None, None) => match value {
+            Value::Array(values) => values
+                .iter()
+                .map(scalar_value)
+                .collect::<std::result::Result<Vec<String>, String>>()
+                .map(|values| values.join(",")),
+            value => scalar_value(value),
+        },
+    }
+}
+
+fn kind_value(kind: ArgumentKind, value: &Value) -> std::result::Result<String, String> {
+    match kind {
+        ArgumentKind::Text => scalar_value(value),
+        ArgumentKind::Integer(min, max) => integer_value(value, min, max).map(|x| x.to_string()),
+        ArgumentKind::Port => integer_value(value, 1, 65535).map(|x| x.to_string()),
+        ArgumentKind::IntegerList(min, max) => {
+            let values = match value {
+                Value::Array(values) => values
+                    .iter()
+                    .map(|value| integer_value(value, min, max))
+                    .collect::<std::result::Result<Vec<i64>, String>>()?,
+                Value::String(text) if text.is_empty() => Vec::new(),
+                Value::String(text) => text
+                    .split(',')
+                    .map(|x| integer_value(&Value::String(x.trim().to_string()), min, max))
+                    .collect::<std::result::Result<Vec<i64>, String>>()?,
+                _ => return Err(format!("{value} is not an array of integers")),
+            };
+            Ok(values
+                .iter()
+                .map(|x| x.to_string())
+                .collect::<Vec<String>>()
+                .join(","))
+        }
+        ArgumentKind::IpAddress
+        | ArgumentKind::Host
+        | ArgumentKind::SocketAddress
+        | ArgumentKind::LogLevel
+        | ArgumentKind::Choice(_) => {
+            let text = value
+                .as_str()
+                .ok_or_else(|| format!("{value} is not a string"))?;
+            let valid = match kind {
+                ArgumentKind::IpAddress => text.parse::<IpAddr>().is_ok(),
+                ArgumentKind::Host => is_host(text),
+                ArgumentKind::SocketAddress => is_socket_address(text),
+                ArgumentKind::LogLevel => log::Level::from_str(text).is_ok(),
+                ArgumentKind::Choice(choices) => choices.contains(&text),
+                _ => true,
+            };
+            match (valid, kind) {
+                (true, _) => Ok(text.to_string()),
+                (false, ArgumentKind::IpAddress) => Err(format!("{text} is not an IP address")),
+                (false, ArgumentKind::Host) => {
+                    Err(format!("{text} is not an IP address or a host name"))
+                }
+                (false, ArgumentKind::LogLevel) => Err(format!(
+                    "{text} is unknown, can be \"Error\", \"Warn\", \"Info\", \"Debug\" or \"Trace\""
+                )),
+                (false, ArgumentKind::Choice(choices)) => Err(format!(
+                    "{text} is unknown, can be \"{}\"",
+                    choices.join("\", \"")
+                )),
+                (false, _) => Err(format!(
+                    "{text} is not a host and a port separated by a colon"
+                )),
+            }
+        }
+    }
+}
+
+/// Converts a string, number or boolean to text.
+fn scalar_value(value: &Value) -> std::result::Result<String, String> {
+    match value {
+        Value::String(text) => Ok(text.to_string()),
+        Value::Integer(_) | Value::Float(_) | Value::Boolean(_) => Ok(value.to_string()),
+        _ => Err(format!("{value} is not a string, a number or a boolean")),
+    }
+}
+
+/// Reads an integer, given as a TOML integer or as a string, and checks its range.
+fn integer_value(value: &Value, min: i64, max: i64) -> std::result::Result<i64, String> {
+    let integer = match value {
+        Value::Integer(integer) => *integer,
+        Value::String(text) => text
+            .parse::<i64>()
+            .map_err(|_| format!("{text} is not an integer"))?,
+        _ => return Err(format!("{value} is not an integer")),
+    };
+    if integer < min || integer > max {
+        return Err(format!("{integer} is not between {min} and {max}"));
+    }
+    Ok(integer)
+}
+
+/// Returns whether the text is an IP address or a host name.
+fn is_host(text: &str) -> bool {
+    text.parse::<IpAddr>().is_ok()
+        || (!text.is_empty()
+            && text.split('.').all(|label| {
+                !label.is_empty()
+                    && !label.starts_with('-')
+                    && !label.ends_with('-')
+                    && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+            }))
+}
+
+/// Returns whether the text is a host and a port separated by a colon, an IPv6 address between brackets.
+fn is_socket_address(text: &str) -> bool {
+    text.parse::<SocketAddr>().is_ok()
+        || match text.rsplit_once(':') {
+            Some((host, port)) => is_host(host) && port.parse::<u16>().is_ok(),
+            None => false,
+        }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value(text: &str) -> Value {
+        text.parse::<toml::Value>().unwrap()["value"].clone()
+    }
+
+    fn udp_value(name: &str, text: &str) -> std::result::Result<String, String> {
+        argument_value(handler_schema("ph_udp_ingress"), name, &value(text))
+    }
+
+    #[test]
+    fn integer_test() {
+        let kind = ArgumentKind::Integer(2, 1000);
+        assert_eq!(kind_value(kind, &value("value = 2")), Ok("2".to_string()));
+        assert_eq!(
+            kind_value(kind, &value("value = 1000")),
+            Ok("1000".to_string())
+        );
+        assert_eq!(
+            kind_value(kind, &value("value = \"42\"")),
+            Ok("42".to_string())
+        );
+        assert_eq!(
+            kind_value(kind, &value("value = 1")),
+            Err("1 is not between 2 and 1000".to_string())
+        );
+        assert_eq!(
+            kind_value(kind, &value("value = 1001")),
+            Err("1001 is not between 2 and 1000".to_string())
+        );
+        assert_eq!(
+            kind_value(kind, &value("value = \"ten\"")),
+            Err("ten is not an integer".to_string())
+        );
+        assert!(kind_value(kind, &value("value = 1.5")).is_err());
+        assert!(kind_value(kind, &value("value = true")).is_err());
+    }
+
+    #[test]
+    fn port_test() {
+        assert_eq!(
+            kind_value(ArgumentKind::Port, &value("value = 1")),
+            Ok("1".to_string())
+        );
+        assert_eq!(
+            kind_value(ArgumentKind::Port, &value("value = 65535")),
+            Ok("65535".to_string())
+        );
+        assert!(kind_value(ArgumentKind::Port, &value("value = 0")).is_err());
+        assert!(kind_value(ArgumentKind::Port, &value("value = 65536")).is_err());
+        assert!(kind_value(ArgumentKind::Port, &value("value = -1")).is_err());
+    }
+
+    #[test]
+    fn integer_list_test() {
+        let kind = ArgumentKind::IntegerList(0, 65535);
+        assert_eq!(
+            kind_value(kind, &value("value = [1, 2, 65535]")),
+            Ok("1,2,65535".to_string())
+        );
+        assert_eq!(
+            kind_value(kind, &value("value = \"1, 2,3\"")),
+            Ok("1,2,3".to_string())
+        );
+        assert_eq!(kind_value(kind, &value("value = \"\"")), Ok("".to_string()));
+        assert_eq!(kind_value(kind, &value("value = []")), Ok("".to_string()));
+        assert_eq!(
+            kind_value(kind, &value("value = [1, 65536]")),
+            Err("65536 is not between 0 and 65535".to_string())
+        );
+        assert_eq!(
+            kind_value(kind, &value("value = \"1,x\"")),
+            Err("x is not an integer".to_string())
+        );
+        assert!(kind_value(kind, &value("value = 1")).is_err());
+    }
+
+    #[test]
+    fn text_kinds_test() {
+        let ip_address = ArgumentKind::IpAddress;
+        assert_eq!(
+            kind_value(ip_address, &value("value = \"::1\"")),
+            Ok("::1".to_string())
+        );
+        assert_eq!(
+            kind_value(ip_address, &value("value = \"localhost\"")),
+            Err("localhost is not an IP address".to_string())
+        );
+        assert!(kind_value(ip_address, &value("value = 1")).is_err());
+
+        let host = ArgumentKind::Host;
+        assert!(kind_value(host, &value("value = \"kafka-1.example.org\"")).is_ok());
+        assert!(kind_value(host, &value("value = \"10.0.0.1\"")).is_ok());
+        assert!(kind_value(host, &value("value = \"-kafka\"")).is_err());
+        assert!(kind_value(host, &value("value = \"kafka..org\"")).is_err());
+        assert!(kind_value(host, &value("value = \"kafka server\"")).is_err());
+        assert!(kind_value(host, &value("value = \"\"")).is_err());
+
+        let socket_address = ArgumentKind::SocketAddress;
+        assert!(kind_value(socket_address, &value("value = \"127.0.0.1:8125\"")).is_ok());
+        assert!(kind_value(socket_address, &value("value = \"[::1]:8125\"")).is_ok());
+        assert!(kind_value(socket_address, &value("value = \"statsd:8125\"")).is_ok());
+        assert_eq!(
+            kind_value(socket_address, &value("value = \"statsd\"")),
+            Err("statsd is not a host and a port separated by a colon".to_string())
+        );
+        assert!(kind_value(socket_address, &value("value = \"statsd:70000\"")).is_err());
+
+        let log_level = ArgumentKind::LogLevel;
+        assert_eq!(
+            kind_value(log_level, &value("value = \"Debug\"")),
+            Ok("Debug".to_string())
+        );
+        assert!(kind_value(log_level, &value("value = \"Verbose\""))
+            .unwrap_err()
+            .starts_with("Verbose is unknown"));
+
+        let choice = ArgumentKind::Choice(MODBUS_MODES);
+        assert_eq!(
+            kind_value(choice, &value("value = \"read\"")),
+            Ok("read".to_string())
+        );
+        assert_eq!(
+            kind_value(choice, &value("value = \"append\"")),
+            Err("append is unknown, can be \"read\", \"write\"".to_string())
+        );
+    }
+
+    #[test]
+    fn known_handler_test() {
+        assert_eq!(
+            udp_value("listening_port", "value = 7654"),
+            Ok("7654".to_string())
+        );
+        assert_eq!(
+            udp_value("listening_port", "value = 0"),
+            Err("0 is not between 1 and 65535".to_string())
+        );
+        assert_eq!(
+            udp_value("log_level", "value = \"Info\""),
+            Ok("Info".to_string())
+        );
+        assert_eq!(
+            udp_value("stats_interval_ms", "value = 500"),
+            Ok("500".to_string())
+        );
+        assert!(udp_value("stats_interval_ms", "value = 0").is_err());
+        assert_eq!(
+            udp_value("memory_limit_mb", "value = 256"),
+            Ok("256".to_string())
+        );
+        assert_eq!(
+            udp_value("unknown_argument", "value = 1"),
+            Err("ph_udp_ingress has no such argument".to_string())
+        );
+    }
+
+    #[test]
+    fn unknown_handler_test() {
+        assert_eq!(
+            argument_value(None, "name", &value("value = \"x\"")),
+            Ok("x".to_string())
+        );
+        assert_eq!(
+            argument_value(None, "count", &value("value = 42")),
+            Ok("42".to_string())
+        );
+        assert_eq!(
+            argument_value(None, "ratio", &value("value = 0.5")),
+            Ok("0.5".to_string())
+        );
+        assert_eq!(
+            argument_value(None, "enabled", &value("value = true")),
+            Ok("true".to_string())
+        );
+        assert_eq!(
+            argument_value(None, "list", &value("value = [1, \"a\", false]")),
+            Ok("1,a,false".to_string())
+        );
+        assert!(argument_value(None, "nested", &value("value = [[1]]")).is_err());
+        assert!(argument_value(None, "table", &value("value = { a = 1 }")).is_err());
+        //the arguments osdd uses itself are checked for every handler
+        assert_eq!(
+            argument_value(None, "memory_limit_mb", &value("value = 0")),
+            Err("0 is not between 1 and 1048576".to_string())
+        );
+        assert_eq!(
+            argument_value(None, "open_udp_port", &value("value = 0")),
+            Err("0 is not between 1 and 65535".to_string())
+        );
+    }
+
+    #[test]
+    fn arguments_set_by_osdd_test() {
+        for name in ARGUMENTS_SET_BY_OSDD {
+            assert_eq!(
+                argument_value(None, name, &value("value = \"x\"")),
+                Err("osdd sets it itself".to_string())
+            );
+            assert_eq!(
+                udp_value(name, "value = \"x\""),
+                Err("osdd sets it itself".to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn schema_test() {
+        let schema = handler_schema("ph_kafka_egress").unwrap();
+        let required: Vec<&str> = schema.required_arguments().map(|x| x.name).collect();
+        assert_eq!(
+            required,
+            vec![
+                "host_kafka_server",
+                "port_kafka_server",
+                "in_replacement",
+                "out_replacement"
+            ]
+        );
+        assert!(schema.argument("spool_directory").is_some());
+        assert!(handler_schema("ph_unknown").is_none());
+        assert!(known_executables(HandlerType::Protocol).contains(&"ph_udp_ingress"));
+        assert!(!known_executables(HandlerType::Filter).contains(&"ph_udp_ingress"));
+    }
+}
@@ -0,0 +1,637 @@
+// Copyright 2020 Ministerie van Defensie
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use serde::{Serialize, Serializer};
+use statistics_handler::metrics::statsd_values;
+use statistics_handler::{Histogram, HistogramSnapshot, Metric, MetricValue};
+use std::collections::BTreeMap;
+
+/// The kind of a statsd metric, from the type after the `|`.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MetricKind {
+    /// `c`, the values are added up.
+    Counter,
+    /// `g`, the last value is kept, `+` and `-` values change it.
+    Gauge,
+    /// `ms` or `h`, the values are recorded in a histogram, and the count, sum, minimum and maximum are kept.
+    Timer,
+}
+
+/// The value of a metric in one flush interval.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AggregatedMetric {
+    pub name: String,
+    /// The DogStatsD tags as received, empty without tags.
+    pub tags: String,
+    pub kind: MetricKind,
+    /// The sum of a counter, the last value of a gauge or the sum of a timer.
+    pub value: f64,
+    /// The number of values received.
+    pub count: u64,
+    /// The smallest and largest value received.
+    pub min: f64,
+    pub max: f64,
+    /// The values of a timer, in the JSON snapshot as its quantiles.
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_quantiles"
+    )]
+    pub histogram: Option<HistogramSnapshot>,
+}
+
+/// The packets and bytes counted by a handler in one flush interval.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct Traffic {
+    pub packets: f64,
+    pub bytes: f64,
+}
+
+/// The totals of all handlers of a chain in one flush interval.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct ChainTotals {
+    pub instance: String,
+    pub network: String,
+    pub chain: String,
+    /// True when the handlers send their statistics with DogStatsD tags.
+    pub tagged: bool,
+    /// The sum of every counter over the handlers of the chain.
+    pub counters: BTreeMap<String, f64>,
+    /// What entered the chain: `in` of the protocol handler on ingress, of the transport handler on egress.
+    pub entry: Traffic,
+    /// What left the chain: `out` of the transport handler on ingress, of the protocol handler on egress.
+    pub exit: Traffic,
+    /// The entry minus the exit.
+    pub delta: Traffic,
+}
+
+/// The aggregated statistics of one flush interval.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct Snapshot {
+    /// The end of the interval, in seconds since the Unix epoch.
+    pub timestamp: u64,
+    pub interval_sec: u64,
+    pub metrics: Vec<AggregatedMetric>,
+    pub chains: Vec<ChainTotals>,
+    /// The number of lines that could not be parsed, they are forwarded as received.
+    pub unparsed_lines: u64,
+}
+
+/// The handler a metric belongs to, taken from the name osdd gives the handler.
+struct HandlerMetric<'a> {
+    instance: &'a str,
+    network: &'a str,
+    chain: &'a str,
+    handler_type: &'a str,
+    metric: String,
+    tagged: bool,
+}
+
+/// Adds up statsd metrics received from the handlers until they are flushed.
+#[derive(Default)]
+pub struct StatsAggregator {
+    metrics: BTreeMap<(String, String), AggregatedMetric>,
+    /// The values of the timers, `ms` in a timer histogram and `h` in a histogram of values.
+    histograms: BTreeMap<(String, String), Histogram>,
+    /// Lines that are not statsd, for example InfluxDB lines, forwarded as received.
+    unparsed: Vec<String>,
+}
+
+impl StatsAggregator {
+    /// Adds all lines of a received datagram.
+    pub fn add_datagram(&mut self, datagram: &[u8]) {
+        for line in String::from_utf8_lossy(datagram).lines() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            if self.add_line(line).is_none() {
+                log::trace!("Forwarding unparsed statistics line {}", line);
+                self.unparsed.push(line.to_string());
+            }
+        }
+    }
+
+    /// Adds a statsd line `<name>:<value>|<type>[|@<rate>][|#<tags>]`, returns None when it cannot be parsed.
+    fn add_line(&mut self, line: &str) -> Option<()> {
+        let (name, rest) = line.split_once(':')?;
+        let mut fields = rest.split('|');
+        let value_text = fields.next()?;
+        let value: f64 = value_text.parse().ok()?;
+        let type_text = fields.next()?;
+        let kind = match type_text {
+            "c" => MetricKind::Counter,
+            "g" => MetricKind::Gauge,
+            "ms" | "h" => MetricKind::Timer,
+            _ => return None,
+        };
+        let mut rate = 1.0;
+        let mut tags = "";
+        for field in fields {
+            if let Some(sample_rate) = field.strip_prefix('@') {
+                rate = sample_rate.parse().ok().filter(|rate| *rate > 0.0)?;
+            } else if let Some(field_tags) = field.strip_prefix('#') {
+                tags = field_tags;
+            }
+        }
+        let key = (name.to_string(), tags.to_string());
+        let metric = self
+            .metrics
+            .entry(key.clone())
+            .or_insert_with(|| AggregatedMetric {
+                name: name.to_string(),
+                tags: tags.to_string(),
+                kind,
+                value: 0.0,
+                count: 0,
+                min: f64::MAX,
+                max: f64::MIN,
+                histogram: None,
+            });
+        if metric.kind != kind {
+            return None;
+        }
+        metric.count += 1;
+        metric.min = metric.min.min(value);
+        metric.max = metric.max.max(value);
+        match kind {
+            MetricKind::Counter => metric.value += value / rate,
+            MetricKind::Gauge if value_text.starts_with(['+', '-']) => metric.value += value,
+            MetricKind::Gauge => metric.value = value,
+            MetricKind::Timer => {
+                metric.value += value;
+                //a timer histogram records microseconds, the values of `h` have no unit
+                let timer = type_text == "ms";
+                let histogram = self.histograms.entry(key).or_insert_with(|| {
+                    if timer {
+                        Histogram::new_timer()
+                    } else {
+                        Histogram::new()
+                    }
+                });
+                let recorded = if timer { value * 1000.0 } else { value };
+                histogram.record(recorded.round() as u64);
+            }
+        }
+        Some(())
+    }
+
+    /// Returns the statistics received since the previous flush, and starts over.
+    /// Gauges keep their value, a gauge with a count of 0 was not received in this interval.
+    /// # Arguments
+    /// * `timestamp` - The end of the interval, in seconds since the Unix epoch.
+    /// * `interval_sec` - The length of the interval.
+    pub fn flush(&mut self, timestamp: u64, interval_sec: u64) -> Snapshot {
+        let metrics: Vec<AggregatedMetric> = self
+            .metrics
+            .iter()
+            .map(|(key, metric)| AggregatedMetric {
+                histogram: self.histograms.get(key).map(Histogram::take),
+                ..metric.clone()
+            })
+            .collect();
+        self.histograms.clear();
+        let mut chains: BTreeMap<(String, String, String, bool), ChainTotals> = BTreeMap::new();
+        for metric in metrics.iter().filter(|m| m.kind == MetricKind::Counter) {
+            let handler_metric = match handler_metric(&metric.name, &metric.tags) {
+                Some(handler_metric) => handler_metric,
+                None => continue,
+            };
+            let totals = chains
+                .entry((
+                    handler_metric.instance.to_string(),
+                    handler_metric.network.to_string(),
+                    handler_metric.chain.to_string(),
+                    handler_metric.tagged,
+                ))
+                .or_insert_with(|| ChainTotals {
+                    instance: handler_metric.instance.to_string(),
+                    network: handler_metric.network.to_string(),
+                    chain: handler_metric.chain.to_string(),
+                    tagged: handler_metric.tagged,
+                    ..ChainTotals::default()
+                });
+            *totals
+                .counters
+                .entry(handler_metric.metric.clone())
+                .or_default() += metric.value;
+            let (entry_type, exit_type) = match handler_metric.network {
+                "egress" => ("transport", "ph"),
+                _ => ("ph", "transport"),
+            };
+            let traffic = match (handler_metric.handler_type, handler_metric.metric.as_str()) {
+                (handler_type, "in.packets" | "in.bytes") if handler_type == entry_type => {
+                    &mut totals.entry
+                }
+                (handler_type, "out.packets" | "out.bytes") if handler_type == exit_type => {
+                    &mut totals.exit
+                }
+                _ => continue,
+            };
+            if handler_metric.metric.ends_with(".packets") {
+                traffic.packets += metric.value;
+            } else {
+                traffic.bytes += metric.value;
+            }
+        }
+        let mut chains: Vec<ChainTotals> = chains.into_values().collect();
+        for totals in &mut chains {
+            totals.delta = Traffic {
+                packets: totals.entry.packets - totals.exit.packets,
+                bytes: totals.entry.bytes - totals.exit.bytes,
+            };
+        }
+        //counters and timers start over, gauges keep their value
+        self.metrics
+            .retain(|_, metric| metric.kind == MetricKind::Gauge);
+        for metric in self.metrics.values_mut() {
+            metric.count = 0;
+            metric.min = metric.value;
+            metric.max = metric.value;
+        }
+        Snapshot {
+            timestamp,
+            interval_sec,
+            metrics,
+            chains,
+            unparsed_lines: self.unparsed.len() as u64,
+        }
+    }
+
+    /// Returns the lines that could not be parsed since the previous call.
+    pub fn take_unparsed(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.unparsed)
+    }
+}
+
+impl Snapshot {
+    /// Returns the statsd lines to forward: counters that are not zero, gauges received in the interval, timers like
+    /// the histograms of the handlers as `<name>.count`, `<name>.p50`, `<name>.p90`, `<name>.p99` and `<name>.max`,
+    /// and the totals of every chain
+    /// as `osdd.<instance>.<network>.<chain>.total.<metric>`, `.entry.*`, `.exit.*` and `.delta.*`.
+    pub fn statsd_lines(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        for metric in &self.metrics {
+            let tags = &metric.tags;
+            match metric.kind {
+                MetricKind::Counter if metric.value != 0.0 => {
+                    lines.push(statsd_line(&metric.name, metric.value, "c", tags))
+                }
+                MetricKind::Counter => {}
+                MetricKind::Gauge if metric.count > 0 => {
+                    lines.push(statsd_line(&metric.name, metric.value, "g", tags))
+                }
+                MetricKind::Gauge => {}
+                MetricKind::Timer => {
+                    if let Some(histogram) = &metric.histogram {
+                        for (name, value, kind) in histogram_values(&metric.name, histogram) {
+                            lines.push(statsd_line(&name, value, kind, tags));
+                        }
+                    }
+                }
+            }
+        }
+        for totals in &self.chains {
+            let (prefix, tags) = if totals.tagged {
+                (
+                    "osdd.chain".to_string(),
+                    format!(
+                        "instance:{},network:{},chain:{}",
+                        totals.instance, totals.network, totals.chain
+                    ),
+                )
+            } else {
+                (
+                    format!(
+                        "osdd.{}.{}.{}",
+                        totals.instance, totals.network, totals.chain
+                    ),
+                    String::new(),
+                )
+            };
+            for (metric, value) in totals.counters.iter().filter(|(_, value)| **value != 0.0) {
+                lines.push(statsd_line(
+                    &format!("{prefix}.total.{metric}"),
+                    *value,
+                    "c",
+                    &tags,
+                ));
+            }
+            for (name, traffic) in [
+                ("entry", &totals.entry),
+                ("exit", &totals.exit),
+                ("delta", &totals.delta),
+            ] {
+                lines.push(statsd_line(
+                    &format!("{prefix}.{name}.packets"),
+                    traffic.packets,
+                    "g",
+                    &tags,
+                ));
+                lines.push(statsd_line(
+                    &format!("{prefix}.{name}.bytes"),
+                    traffic.bytes,
+                    "g",
+                    &tags,
+                ));
+            }
+        }
+        lines
+    }
+}
+
+/// Returns the statsd values of a timer as name, value and statsd type, the same as those of a histogram of a handler.
+fn histogram_values(name: &str, histogram: &HistogramSnapshot) -> Vec<(String, f64, &'static str)> {
+    statsd_values(&Metric {
+        name: name.to_string(),
+        labels: Vec::new(),
+        value: MetricValue::Histogram(histogram.clone()),
+    })
+}
+
+/// Writes the histogram of a timer as its quantiles and maximum, `{"p50": 1.5, ...}`.
+fn serialize_quantiles<S: Serializer>(
+    histogram: &Option<HistogramSnapshot>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let quantiles: BTreeMap<String, f64> = match histogram {
+        Some(histogram) => histogram_values("", histogram)
+            .into_iter()
+            .filter(|(_, _, kind)| *kind == "g")
+            .map(|(name, value, _)| (name.trim_start_matches('.').to_string(), value))
+            .collect(),
+        None => BTreeMap::new(),
+    };
+    quantiles.serialize(serializer)
+}
+
+fn statsd_line(name: &str, value: f64, kind: &str, tags: &str) -> String {
+    if tags.is_empty() {
+        format!("{name}:{value}|{kind}")
+    } else {
+        format!("{name}:{value}|{kind}|#{tags}")
+    }
+}
+
+/// Finds the handler of a metric, in the name `osdd.<instance>.<network>.<chain>.<type>.<name>.<metric>`
+/// or in the tags of a metric `osdd.<metric>`.
+fn handler_metric<'a>(name: &'a str, tags: &'a str) -> Option<HandlerMetric<'a>> {
+    if tags.is_empty() {
+        let parts: Vec<&str> = name.splitn(7, '.').collect();
+        return match parts.as_slice() {
+            ["osdd", instance, network, chain, handler_type, _, metric] => Some(HandlerMetric {
+                instance,
+                network,
+                chain,
+                handler_type,
+                metric: metric.to_string(),
+                tagged: false,
+            }),
+            _ => None,
+        };
+    }
+    let tag = |key: &str| {
+        tags.split(',')
+            .find_map(|tag| tag.strip_prefix(key)?.strip_prefix(':'))
+    };
+    Some(HandlerMetric {
+        instance: tag("instance")?,
+        network: tag("network")?,
+        chain: tag("chain")?,
+        handler_type: tag("handler_type")?,
+        metric: name.strip_prefix("osdd.")?.to_string(),
+        tagged: true,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn aggregator(lines: &[&str]) -> StatsAggregator {
+        let mut aggregator = StatsAggregator::default();
+        aggregator.add_datagram(lines.join("\n").as_bytes());
+        aggregator
+    }
+
+    fn metric<'a>(snapshot: &'a Snapshot, name: &str) -> &'a AggregatedMetric {
+        snapshot.metrics.iter().find(|m| m.name == name).unwrap()
+    }
+
+    #[test]
+    fn add_line_test() {
+        let mut aggregator = aggregator(&[
+            "packets:2|c",
+            "packets:3|c|@0.5",
+            "level:5|g",
+            "level:+2|g",
+            "level:-1|g",
+            "latency:10|ms",
+            "latency:30|h",
+            "  ",
+            "cpu,host=a value=1",
+            "packets:1|g",
+            "packets:x|c",
+            "packets:1|s",
+            "packets:1|c|@0",
+        ]);
+        let snapshot = aggregator.flush(100, 1);
+        assert_eq!(snapshot.timestamp, 100);
+        assert_eq!(snapshot.interval_sec, 1);
+        assert_eq!(metric(&snapshot, "packets").value, 8.0);
+        assert_eq!(metric(&snapshot, "packets").count, 2);
+        assert_eq!(metric(&snapshot, "level").value, 6.0);
+        let latency = metric(&snapshot, "latency");
+        assert_eq!(latency.kind, MetricKind::Timer);
+        assert_eq!(
+            (latency.value, latency.count, latency.min, latency.max),
+            (40.0, 2, 10.0, 30.0)
+        );
+        assert_eq!(snapshot.unparsed_lines, 5);
+        assert_eq!(
+            aggregator.take_unparsed(),
+            vec![
+                "cpu,host=a value=1",
+                "packets:1|g",
+                "packets:x|c",
+                "packets:1|s",
+                "packets:1|c|@0"
+            ]
+        );
+        assert!(aggregator.take_unparsed().is_empty());
+    }
+
+    #[test]
+    fn tags_test() {
+        let mut aggregator = aggregator(&[
+            "osdd.in.packets:1|c|#chain:a",
+            "osdd.in.packets:2|c|#chain:b",
+            "osdd.in.packets:3|c|#chain:a",
+        ]);
+        let snapshot = aggregator.flush(0, 1);
+        let values: Vec<(&str, f64)> = snapshot
+            .metrics
+            .iter()
+            .map(|m| (m.tags.as_str(), m.value))
+            .collect();
+        assert_eq!(values, vec![("chain:a", 4.0), ("chain:b", 2.0)]);
+    }
+
+    #[test]
+    fn flush_test() {
+        let mut aggregator = aggregator(&["packets:2|c", "level:5|g", "latency:10|ms"]);
+        aggregator.flush(1, 1);
+        let snapshot = aggregator.flush(2, 1);
+        let level = metric(&snapshot, "level");
+        assert_eq!((level.value, level.count), (5.0, 0));
+        assert_eq!(snapshot.metrics.len(), 1);
+        assert!(snapshot.statsd_lines().is_empty());
+    }
+
+    #[test]
+    fn chain_totals_test() {
+        let mut aggregator = aggregator(&[
+            "osdd.1.ingress.topic.ph.kafka.in.packets:10|c",
+            "osdd.1.ingress.topic.ph.kafka.in.bytes:1000|c",
+            "osdd.1.ingress.topic.ph.kafka.out.packets:10|c",
+            "osdd.1.ingress.topic.filter.secret.in.packets:10|c",
+            "osdd.1.ingress.topic.filter.secret.out.packets:8|c",
+            "osdd.1.ingress.topic.transport.udp.in.packets:8|c",
+            "osdd.1.ingress.topic.transport.udp.out.packets:8|c",
+            "osdd.1.ingress.topic.transport.udp.out.bytes:800|c",
+            "osdd.1.egress.topic.transport.udp.in.packets:8|c",
+            "osdd.1.egress.topic.ph.kafka.out.packets:7|c",
+            "osdd.1.ingress.topic.ph.kafka.queue:3|g",
+            "other.metric:1|c",
+        ]);
+        let snapshot = aggregator.flush(0, 1);
+        assert_eq!(snapshot.chains.len(), 2);
+        let egress = &snapshot.chains[0];
+        assert_eq!(egress.network, "egress");
+        assert_eq!(egress.entry.packets, 8.0);
+        assert_eq!(egress.exit.packets, 7.0);
+        assert_eq!(egress.delta.packets, 1.0);
+        let ingress = &snapshot.chains[1];
+        assert_eq!(
+            (
+                ingress.instance.as_str(),
+                ingress.chain.as_str(),
+                ingress.tagged
+            ),
+            ("1", "topic", false)
+        );
+        assert_eq!(ingress.counters["in.packets"], 28.0);
+        assert_eq!(ingress.counters["out.packets"], 26.0);
+        assert!(!ingress.counters.contains_key("queue"));
+        assert_eq!(
+            ingress.entry,
+            Traffic {
+                packets: 10.0,
+                bytes: 1000.0
+            }
+        );
+        assert_eq!(
+            ingress.exit,
+            Traffic {
+                packets: 8.0,
+                bytes: 800.0
+            }
+        );
+        assert_eq!(
+            ingress.delta,
+            Traffic {
+                packets: 2.0,
+                bytes: 200.0
+            }
+        );
+        let lines = snapshot.statsd_lines();
+        assert!(lines.contains(&"osdd.1.ingress.topic.total.in.packets:28|c".to_string()));
+        assert!(lines.contains(&"osdd.1.ingress.topic.delta.packets:2|g".to_string()));
+        assert!(lines.contains(&"osdd.1.egress.topic.exit.bytes:0|g".to_string()));
+    }
+
+    #[test]
+    fn tagged_chain_totals_test() {
+        let tags = "instance:1,network:ingress,chain:topic";
+        let mut aggregator = aggregator(&[
+            &format!("osdd.in.packets:5|c|#{tags},handler_type:ph,handler:kafka"),
+            &format!("osdd.out.packets:4|c|#{tags},handler_type:transport,handler:udp"),
+            "osdd.in.packets:1|c|#instance:1,network:ingress",
+        ]);
+        let snapshot = aggregator.flush(0, 1);
+        assert_eq!(snapshot.chains.len(), 1);
+        let totals = &snapshot.chains[0];
+        assert!(totals.tagged);
+        assert_eq!(totals.entry.packets, 5.0);
+        assert_eq!(totals.exit.packets, 4.0);
+        assert_eq!(totals.delta.packets, 1.0);
+        let lines = snapshot.statsd_lines();
+        assert!(lines.contains(&format!("osdd.chain.delta.packets:1|g|#{tags}")));
+        assert!(lines.contains(&format!("osdd.chain.total.in.packets:5|c|#{tags}")));
+    }
+
+    #[test]
+    fn statsd_lines_test() {
+        let mut aggregator =
+            aggregator(&["packets:0|c", "level:5|g", "latency:1|ms", "latency:2|ms"]);
+        let lines = aggregator.flush(0, 1).statsd_lines();
+        assert_eq!(
+            lines,
+            vec![
+                "latency.count:2|c",
+                "latency.p50:1|g",
+                "latency.p90:2|g",
+                "latency.p99:2|g",
+                "latency.max:2|g",
+                "level:5|g"
+            ]
+        );
+    }
+
+    #[test]
+    fn histogram_test() {
+        let mut lines: Vec<String> = (1..=100).map(|value| format!("size:{value}|h")).collect();
+        lines.push("latency:0.5|ms|#chain:a".to_string());
+        lines.push("latency:1.5|ms|#chain:a".to_string());
+        let lines: Vec<&str> = lines.iter().map(String::as_str).collect();
+        let mut aggregator = aggregator(&lines);
+        let snapshot = aggregator.flush(0, 1);
+        let size = metric(&snapshot, "size");
+        assert_eq!(
+            (size.value, size.count, size.min, size.max),
+            (5050.0, 100, 1.0, 100.0)
+        );
+        let lines = snapshot.statsd_lines();
+        //the quantiles of the values, not only their mean
+        assert!(lines.contains(&"size.p50:50|g".to_string()), "{:?}", lines);
+        assert!(lines.contains(&"size.p90:90|g".to_string()), "{:?}", lines);
+        assert!(lines.contains(&"size.p99:99|g".to_string()), "{:?}", lines);
+        assert!(lines.contains(&"size.max:100|g".to_string()), "{:?}", lines);
+        //a timer is recorded in microseconds and sent in milliseconds, with its tags
+        assert!(
+            lines.contains(&"latency.p50:0.5|g|#chain:a".to_string()),
+            "{:?}",
+            lines
+        );
+        assert!(
+            lines.contains(&"latency.max:1.5|g|#chain:a".to_string()),
+            "{:?}",
+            lines
+        );
+        let json = serde_json::to_value(metric(&snapshot, "size")).unwrap();
+        assert_eq!(json["histogram"]["p90"], 90.0);
+        //the histogram starts over
+        assert!(aggregator.flush(1, 1).metrics.is_empty());
+    }
+}
@@ -13,21 +13,46 @@
 // limitations under the License.
 
 use crate::errors::*;
+use crate::stats_aggregator::{Snapshot, StatsAggregator};
 use crate::MAX_BUFFER_SIZE_BYTES;
+use statistics_handler::format::datagrams;
+use std::io::ErrorKind as IoErrorKind;
 use std::net::{SocketAddr, UdpSocket};
 use std::thread;
 use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-/// Receives all the stats form the handlers and send it to multiple addresses
-pub fn run(stats_port: u16, stats_servers_string: Vec<String>) -> std::io::Result<JoinHandle<()>> {
+/// Receives all the stats from the handlers, aggregates them and sends them to multiple addresses every flush interval
+/// # Arguments
+/// * `stats_port` - The port the handlers send their statistics to.
+/// * `stats_servers_string` - The socket addresses of the statsd servers.
+/// * `flush_interval` - The time between two flushes of the aggregated statistics.
+/// * `snapshot_path` - The file the JSON snapshot of the last interval is written to.
+pub fn run(
+    stats_port: u16,
+    stats_servers_string: Vec<String>,
+    flush_interval: Duration,
+    snapshot_path: String,
+) -> std::io::Result<JoinHandle<()>> {
     thread::Builder::new()
         .name("udp_multiplexer".into())
         .spawn(move || {
-            mutiplex(stats_port, stats_servers_string).chain_unwrap();
+            mutiplex(
+                stats_port,
+                stats_servers_string,
+                flush_interval,
+                &snapshot_path,
+            )
+            .chain_unwrap();
         })
 }
 
-fn mutiplex(stats_port: u16, stats_servers_string: Vec<String>) -> Result<()> {
+fn mutiplex(
+    stats_port: u16,
+    stats_servers_string: Vec<String>,
+    flush_interval: Duration,
+    snapshot_path: &str,
+) -> Result<()> {
     let socket_addres = format!("0.0.0.0:{stats_port}");
     let socket = UdpSocket::bind(socket_addres.to_string())
         .chain_err(|| format!("Binding udp socket on {socket_addres}"))?;
@@ -37,26 +62,62 @@ fn mutiplex(stats_port: u16, stats_servers_string: Vec<String>) -> Result<()> {
     for stats_server_string in &stats_servers_string {
         stats_servers.push(stats_server_string.parse()?);
     }
+    let mut aggregator = StatsAggregator::default();
+    let mut next_flush = Instant::now() + flush_interval;
     loop {
+        let now = Instant::now();
+        if now >= next_flush {
+            next_flush = now + flush_interval;
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or_default();
+            let snapshot = aggregator.flush(timestamp, flush_interval.as_secs());
+            let mut lines = snapshot.statsd_lines();
+            lines.extend(aggregator.take_unparsed());
+            forward(&socket, &stats_servers, &lines);
+            if let Err(e) = write_snapshot(&snapshot, snapshot_path) {
+                log::warn!(
+                    "Couldn't write statistics snapshot to {}. Error {}:",
+                    snapshot_path,
+                    e
+                );
+            }
+            continue;
+        }
+        socket.set_read_timeout(Some(next_flush - now))?;
         match socket.recv_from(&mut buffer) {
             Ok((length, _)) => {
                 log::trace!("Received packet with size {}", length);
-                if length != 0 {
-                    for stats_server in &stats_servers {
-                        match socket.send_to(&buffer[0..length], stats_server) {
-                            Ok(_) => (),
-                            Err(e) => log::warn!(
-                                "Couldn't send statsD message to {}. Error {}:",
-                                stats_server,
-                                e
-                            ),
-                        }
-                    }
-                }
+                aggregator.add_datagram(&buffer[0..length]);
             }
+            Err(e) if matches!(e.kind(), IoErrorKind::WouldBlock | IoErrorKind::TimedOut) => (),
             Err(e) => {
                 log::debug!("Couldn't receive statsd packet, error: {}", e);
             }
         }
     }
 }
+
+/// Sends the lines in as few datagrams as possible to every statsd server
+fn forward(socket: &UdpSocket, stats_servers: &[SocketAddr], lines: &[String]) {
+    for datagram in datagrams(lines) {
+        for stats_server in stats_servers {
+            match socket.send_to(datagram.as_bytes(), stats_server) {
+                Ok(_) => (),
+                Err(e) => log::warn!(
+                    "Couldn't send statsD message to {}. Error {}:",
+                    stats_server,
+                    e
+                ),
+            }
+        }
+    }
+}
+
+/// Writes the snapshot to a temporary file next to `snapshot_path` and moves it in place, readers never see half a snapshot
+fn write_snapshot(snapshot: &Snapshot, snapshot_path: &str) -> std::io::Result<()> {
+    let temporary_path = format!("{snapshot_path}.tmp");
+    std::fs::write(&temporary_path, serde_json::to_string_pretty(snapshot)?)?;
+    std::fs::rename(&temporary_path, snapshot_path)
+}
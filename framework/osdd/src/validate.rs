@@ -0,0 +1,990 @@
+// Copyright 2020 Ministerie van Defensie
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::errors::ErrorKind::ConfigurationError;
+use crate::read_toml::{read_chain, ChainToml};
+use crate::schema::{argument_value, handler_schema, known_executables, HandlerSchema};
+use crate::*;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::net::SocketAddr;
+use std::str::FromStr;
+
+/// The tables of the handlers, with the type of their handlers.
+const HANDLER_TABLES: [(&str, HandlerType); 3] = [
+    ("protocolhandler", HandlerType::Protocol),
+    ("filterhandler", HandlerType::Filter),
+    ("transporthandler", HandlerType::Transport),
+];
+
+/// The keys of the `[settings]` table.
+const SETTINGS_KEYS: &[&str] = &[
+    "path",
+    "stats_servers",
+    "syslog_host",
+    "syslog_port",
+    "log_level",
+    "instance",
+    "network",
+    "stats_multiplexer_listening_port",
+    "stats_flush_interval_sec",
+    "stats_snapshot_path",
+    "runtime",
+];
+
+/// The keys of a `[chain.<name>]` table.
+const CHAIN_KEYS: &[&str] = &[
+    "protocol_handler",
+    "filter_handlers",
+    "transport_handler",
+    "edges",
+    "link_type",
+    "fan_out_mode",
+];
+
+/// How serious a problem in the configuration is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// osdd does not start, or starts handlers that do not work.
+    Error,
+    /// osdd starts, but probably not as intended.
+    Warning,
+}
+
+/// A problem found in the configuration file.
+#[derive(Debug)]
+pub struct Issue {
+    pub severity: Severity,
+    /// The line in the configuration file, None if the problem is not on one line
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{}: {}", severity, self.message)
+    }
+}
+
+/// Checks a configuration file, without starting anything.
+/// Unlike `read_toml`, which stops at the first error it needs to, all problems are reported: unknown tables and handler types,
+/// values that are not strings, handlers no chain uses, missing arguments, invalid ports and addresses, ports used twice,
+/// and chains that cannot be built.
+/// # Arguments
+/// * `toml_string` - The contents of the configuration file.
+/// # Returns
+/// The problems, in the order of their lines.
+pub fn validate_config(toml_string: &str) -> Vec<Issue> {
+    let mut validator = Validator {
+        lines: LineIndex::new(toml_string),
+        issues: Vec::new(),
+    };
+    match toml::from_str::<Value>(toml_string) {
+        Ok(config) => validator.validate(&config),
+        Err(e) => validator.issues.push(Issue {
+            severity: Severity::Error,
+            line: e.line_col().map(|(line, _)| line + 1),
+            message: format!("cannot read the configuration: {e}"),
+        }),
+    }
+    validator.issues.sort_by_key(|issue| issue.line);
+    validator.issues
+}
+
+/// The lines of the tables and keys in a TOML text, the parsed values do not have them.
+struct LineIndex {
+    lines: HashMap<Vec<String>, usize>,
+}
+
+impl LineIndex {
+    fn new(toml_string: &str) -> LineIndex {
+        let mut lines = HashMap::new();
+        let mut table: Vec<String> = Vec::new();
+        for (index, line) in toml_string.lines().enumerate() {
+            let line = line.trim();
+            if line.starts_with('[') {
+                let header = line.trim_start_matches('[').split(']').next().unwrap_or("");
+                table = key_path(header);
+                //`[chain.name]` also defines `[chain]`
+                for length in 1..=table.len() {
+                    lines.entry(table[..length].to_vec()).or_insert(index + 1);
+                }
+            } else if !line.starts_with('#') {
+                if let Some((key, _)) = line.split_once('=') {
+                    let mut path = table.clone();
+                    path.extend(key_path(key));
+                    lines.entry(path).or_insert(index + 1);
+                }
+            }
+        }
+        LineIndex { lines }
+    }
+
+    /// Returns the line of the key, or of the nearest table around it.
+    fn line(&self, path: &[&str]) -> Option<usize> {
+        (1..=path.len()).rev().find_map(|length| {
+            let path: Vec<String> = path[..length].iter().map(|key| key.to_string()).collect();
+            self.lines.get(&path).copied()
+        })
+    }
+}
+
+/// Splits a dotted TOML key into its parts, without quotes.
+fn key_path(key: &str) -> Vec<String> {
+    key.split('.')
+        .map(|part| part.trim().trim_matches('"').trim_matches('\'').to_string())
+        .collect()
+}
+
+/// A handler from the configuration.
+struct ConfiguredHandler<'a> {
+    table: &'a str,
+    name: &'a str,
+    handler_type: HandlerType,
+    schema: Option<&'static HandlerSchema>,
+    /// The arguments that are valid, as given on the command line
+    arguments: BTreeMap<&'a str, String>,
+}
+
+struct Validator {
+    lines: LineIndex,
+    issues: Vec<Issue>,
+}
+
+impl Validator {
+    fn report(&mut self, severity: Severity, path: &[&str], message: String) {
+        self.issues.push(Issue {
+            severity,
+            line: self.lines.line(path),
+            message,
+        });
+    }
+
+    fn error(&mut self, path: &[&str], message: String) {
+        self.report(Severity::Error, path, message);
+    }
+
+    fn warning(&mut self, path: &[&str], message: String) {
+        self.report(Severity::Warning, path, message);
+    }
+
+    fn validate(&mut self, config: &Value) {
+        let tables = match config.as_table() {
+            Some(tables) => tables,
+            None => return,
+        };
+        let settings = self.validate_settings(tables.get("settings"));
+        let network = settings
+            .as_ref()
+            .map(|settings| settings.network.as_str())
+            .filter(|network| *network == "ingress" || *network == "egress");
+
+        let mut handlers: Vec<ConfiguredHandler> = Vec::new();
+        for (table_name, table) in tables {
+            if table_name == "settings" || table_name == "chain" {
+                continue;
+            }
+            let handler_type = match HANDLER_TABLES.iter().find(|(name, _)| name == table_name) {
+                Some((_, handler_type)) => *handler_type,
+                None => {
+                    self.warning(
+                        &[table_name],
+                        format!(
+                            "[{table_name}] is unknown and ignored, expected settings, chain, protocolhandler, filterhandler or transporthandler"
+                        ),
+                    );
+                    continue;
+                }
+            };
+            match table.as_table() {
+                Some(table) => {
+                    for (name, handler) in table {
+                        if let Some(handler) =
+                            self.validate_handler(table_name, name, handler, handler_type, network)
+                        {
+                            handlers.push(handler);
+                        }
+                    }
+                }
+                None => self.error(&[table_name], format!("{table_name} must be a table")),
+            }
+        }
+        for (index, handler) in handlers.iter().enumerate() {
+            if let Some(first) = handlers[..index].iter().find(|x| x.name == handler.name) {
+                self.error(
+                    &[handler.table, handler.name],
+                    format!(
+                        "handler {} is configured twice, also in [{}.{}]",
+                        handler.name, first.table, first.name
+                    ),
+                );
+            }
+        }
+        if settings.as_ref().is_some_and(Settings::is_native) {
+            for handler in handlers
+                .iter()
+                .filter(|handler| handler.arguments.contains_key("cpu_limit_percent"))
+            {
+                self.error(
+                    &[handler.table, handler.name, "cpu_limit_percent"],
+                    format!(
+                        "cpu_limit_percent of handler {}: the native runtime cannot limit the CPU, use runtime \"{RUNTIME_DOCKER}\"",
+                        handler.name
+                    ),
+                );
+            }
+        }
+
+        let uses = self.validate_chains(tables.get("chain"), &handlers, network);
+        for handler in &handlers {
+            if !uses.iter().any(|(_, name)| name == handler.name) {
+                self.warning(
+                    &[handler.table, handler.name],
+                    format!(
+                        "handler {} is not used by any chain, osdd does not start it",
+                        handler.name
+                    ),
+                );
+            }
+        }
+        self.validate_ports(settings.as_ref(), &handlers, &uses);
+    }
+
+    /// Checks the settings and returns them if they can be read.
+    fn validate_settings(&mut self, settings: Option<&Value>) -> Option<Settings> {
+        let settings = match settings {
+            Some(settings) => settings,
+            None => {
+                self.error(&[], "there is no [settings] table".to_string());
+                return None;
+            }
+        };
+        if let Some(table) = settings.as_table() {
+            for key in table.keys() {
+                if !SETTINGS_KEYS.contains(&key.as_str()) {
+                    self.warning(
+                        &["settings", key],
+                        format!("setting {key} is unknown and ignored"),
+                    );
+                }
+            }
+        }
+        let settings: Settings = match settings.clone().try_into() {
+            Ok(settings) => settings,
+            Err(e) => {
+                self.error(&["settings"], format!("cannot read the settings: {e}"));
+                return None;
+            }
+        };
+        if settings.network != "ingress" && settings.network != "egress" {
+            self.error(
+                &["settings", "network"],
+                format!(
+                    "network {} is unknown, can be \"ingress\" or \"egress\"",
+                    settings.network
+                ),
+            );
+        }
+        if let Some(runtime) = &settings.runtime {
+            if runtime != RUNTIME_DOCKER && runtime != RUNTIME_NATIVE {
+                self.error(
+                    &["settings", "runtime"],
+                    format!(
+                        "runtime {runtime} is unknown, can be \"{RUNTIME_DOCKER}\" or \"{RUNTIME_NATIVE}\""
+                    ),
+                );
+            }
+        }
+        self.check_name(&["settings", "instance"], "instance", &settings.instance);
+        if log::Level::from_str(&settings.log_level).is_err() {
+            self.error(
+                &["settings", "log_level"],
+                format!(
+                    "log_level {} is unknown, can be \"Error\", \"Warn\", \"Info\", \"Debug\" or \"Trace\"",
+                    settings.log_level
+                ),
+            );
+        }
+        if format!("{}:{}", settings.syslog_host, settings.syslog_port)
+            .parse::<SocketAddr>()
+            .is_err()
+        {
+            self.error(
+                &["settings", "syslog_host"],
+                format!(
+                    "syslog_host {} and syslog_port {} are not an IP address and a port",
+                    settings.syslog_host, settings.syslog_port
+                ),
+            );
+        }
+        if settings
+            .stats_multiplexer_listening_port
+            .parse::<u16>()
+            .is_err()
+        {
+            self.error(
+                &["settings", "stats_multiplexer_listening_port"],
+                format!(
+                    "stats_multiplexer_listening_port {} is not a port",
+                    settings.stats_multiplexer_listening_port
+                ),
+            );
+        }
+        for stats_server in &settings.stats_servers {
+            if stats_server.parse::<SocketAddr>().is_err() {
+                self.error(
+                    &["settings", "stats_servers"],
+                    format!("stats server {stats_server} is not an IP address and a port"),
+                );
+            }
+        }
+        if let Some(interval) = &settings.stats_flush_interval_sec {
+            if !matches!(interval.parse::<u64>(), Ok(seconds) if seconds > 0) {
+                self.error(
+                    &["settings", "stats_flush_interval_sec"],
+                    format!("stats_flush_interval_sec {interval} is not a number of seconds of at least 1"),
+                );
+            }
+        }
+        Some(settings)
+    }
+
+    fn validate_handler<'a>(
+        &mut self,
+        table: &'a str,
+        name: &'a str,
+        handler: &'a Value,
+        handler_type: HandlerType,
+        network: Option<&str>,
+    ) -> Option<ConfiguredHandler<'a>> {
+        let values = match handler.as_table() {
+            Some(values) => values,
+            None => {
+                self.error(&[table, name], format!("handler {name} must be a table"));
+                return None;
+            }
+        };
+        self.check_name(&[table, name], "handler", name);
+
+        let executable = values.get("type").and_then(Value::as_str);
+        let schema = executable.and_then(handler_schema);
+        match (executable, schema) {
+            (None, _) => self.error(
+                &[table, name],
+                format!("handler {name} has no type, or it is not a string"),
+            ),
+            (Some(executable), None) => self.error(
+                &[table, name, "type"],
+                format!(
+                    "handler {name} has unknown type {executable}, known types in [{table}] are {}",
+                    known_executables(handler_type).join(", ")
+                ),
+            ),
+            (Some(executable), Some(schema)) => {
+                if schema.handler_type != handler_type {
+                    let schema_table = HANDLER_TABLES
+                        .iter()
+                        .find(|(_, x)| *x == schema.handler_type)
+                        .map_or("", |(table, _)| *table);
+                    self.error(
+                        &[table, name, "type"],
+                        format!("handler {name} has type {executable}, which belongs in [{schema_table}]"),
+                    );
+                }
+                if let (Some(schema_network), Some(network)) = (schema.network, network) {
+                    if schema_network != network {
+                        self.error(
+                            &[table, name, "type"],
+                            format!("handler {name} has type {executable}, which runs in the {schema_network} network and not in the {network} network"),
+                        );
+                    }
+                }
+                for argument in schema.required_arguments() {
+                    if !values.contains_key(argument.name) {
+                        self.error(
+                            &[table, name],
+                            format!(
+                                "handler {name} of type {executable} needs {}",
+                                argument.name
+                            ),
+                        );
+                    }
+                }
+            }
+        }
+
+        let mut arguments = BTreeMap::new();
+        for (key, value) in values {
+            if value.is_table() {
+                self.error(
+                    &[table, name, key],
+                    format!("handler name \"{name}.{key}\" cannot contain '.'"),
+                );
+                continue;
+            }
+            match argument_value(schema, key, value) {
+                Ok(argument) => {
+                    arguments.insert(key.as_str(), argument);
+                }
+                Err(message) => self.error(
+                    &[table, name, key],
+                    format!("{key} of handler {name}: {message}"),
+                ),
+            }
+        }
+        Some(ConfiguredHandler {
+            table,
+            name,
+            handler_type,
+            schema: schema.filter(|schema| schema.handler_type == handler_type),
+            arguments,
+        })
+    }
+
+    /// Names are part of the names of the containers, sockets and statistics, `osdd.<instance>.<network>.<chain>.<type>.<handler>`.
+    fn check_name(&mut self, path: &[&str], what: &str, name: &str) {
+        let valid = name.starts_with(|c: char| c.is_ascii_alphanumeric())
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+        if !valid {
+            self.error(
+                path,
+                format!("{what} name \"{name}\" must start with a letter or digit and can only contain letters, digits, '_' and '-'"),
+            );
+        }
+    }
+
+    /// Checks the chains and returns the chain and the name of every handler used by a chain.
+    fn validate_chains(
+        &mut self,
+        chains: Option<&Value>,
+        handlers: &[ConfiguredHandler],
+        network: Option<&str>,
+    ) -> Vec<(String, String)> {
+        let mut uses = Vec::new();
+        let chains = match chains.map(Value::as_table) {
+            Some(Some(chains)) => chains,
+            Some(None) => {
+                self.error(&["chain"], "chain must be a table".to_string());
+                return uses;
+            }
+            None => {
+                self.warning(
+                    &[],
+                    "there are no chains, osdd starts no handlers".to_string(),
+                );
+                return uses;
+            }
+        };
+        for (chain_name, chain_value) in chains {
+            let path = ["chain", chain_name.as_str()];
+            self.check_name(&path, "chain", chain_name);
+            if let Some(table) = chain_value.as_table() {
+                for (key, value) in table {
+                    if value.is_table() {
+                        self.error(
+                            &["chain", chain_name, key],
+                            format!("chain name \"{chain_name}.{key}\" cannot contain '.'"),
+                        );
+                    } else if !CHAIN_KEYS.contains(&key.as_str()) {
+                        self.warning(
+                            &["chain", chain_name, key],
+                            format!("{key} of chain {chain_name} is unknown and ignored"),
+                        );
+                    }
+                }
+            }
+            let chain_toml: ChainToml = match chain_value.clone().try_into() {
+                Ok(chain_toml) => chain_toml,
+                Err(e) => {
+                    self.error(&path, format!("cannot read chain {chain_name}: {e}"));
+                    continue;
+                }
+            };
+            let chain = match read_chain(
+                chain_name.to_string(),
+                chain_toml,
+                network.unwrap_or("ingress"),
+            ) {
+                Ok(chain) => chain,
+                Err(e) => {
+                    self.error(&path, configuration_message(e));
+                    continue;
+                }
+            };
+
+            let mut missing: Vec<&String> = Vec::new();
+            for edge in &chain.edges {
+                for name in [&edge.from, &edge.to] {
+                    if uses.contains(&(chain_name.to_string(), name.to_string()))
+                        || missing.contains(&name)
+                    {
+                        continue;
+                    }
+                    if handlers.iter().any(|handler| handler.name == name) {
+                        uses.push((chain_name.to_string(), name.to_string()));
+                    } else {
+                        self.error(
+                            &path,
+                            format!(
+                                "chain {chain_name} uses handler {name}, which is not configured"
+                            ),
+                        );
+                        missing.push(name);
+                    }
+                }
+            }
+            let (names, links) = match assign_sockets(&chain, PATH_PREFIX_UNIX_SOCKETS_IN_DOCKER) {
+                Ok(names_and_links) => names_and_links,
+                Err(e) => {
+                    self.error(&path, configuration_message(e));
+                    continue;
+                }
+            };
+            if !missing.is_empty() {
+                continue;
+            }
+            for name in &names {
+                if let Some(handler) = handlers.iter().find(|handler| handler.name == name) {
+                    self.check_position(&path, handler, &links[name], network);
+                }
+            }
+        }
+        uses
+    }
+
+    /// Checks that a handler sends and receives as its type requires.
+    /// On ingress the data flows from the protocol handler to the transport handler, on egress the other way around.
+    fn check_position(
+        &mut self,
+        path: &[&str],
+        handler: &ConfiguredHandler,
+        links: &HandlerLinks,
+        network: Option<&str>,
+    ) {
+        let receives = !links.incoming.is_empty();
+        let sends = links.outgoing.is_some();
+        let chain_name = path[1];
+        let name = handler.name;
+        let problem = match (handler.handler_type, network) {
+            (HandlerType::Filter, _) if !(receives && sends) => Some(format!(
+                "filter {name} must both receive from and send to other handlers in chain {chain_name}"
+            )),
+            (HandlerType::Protocol, Some("ingress")) | (HandlerType::Transport, Some("egress"))
+                if receives =>
+            {
+                Some(format!(
+                    "{name} is where the data enters chain {chain_name} and cannot receive from other handlers"
+                ))
+            }
+            (HandlerType::Protocol, Some("egress")) | (HandlerType::Transport, Some("ingress"))
+                if sends =>
+            {
+                Some(format!(
+                    "{name} is where the data leaves chain {chain_name} and cannot send to other handlers"
+                ))
+            }
+            (HandlerType::Protocol, _) | (HandlerType::Transport, _) if receives && sends => {
+                Some(format!(
+                    "{name} is an end of chain {chain_name} and cannot both receive from and send to other handlers"
+                ))
+            }
+            _ => None,
+        };
+        if let Some(problem) = problem {
+            self.error(path, problem);
+        }
+    }
+
+    /// Checks that no two handlers use the same port on the proxy, and that no two transport handlers send to the same address.
+    /// A handler used by several chains runs once for every chain.
+    fn validate_ports(
+        &mut self,
+        settings: Option<&Settings>,
+        handlers: &[ConfiguredHandler],
+        uses: &[(String, String)],
+    ) {
+        let mut proxy_ports: HashMap<(&str, u16), String> = HashMap::new();
+        let mut destinations: HashMap<(&str, u16), String> = HashMap::new();
+        if let Some(port) =
+            settings.and_then(|settings| settings.stats_multiplexer_listening_port.parse().ok())
+        {
+            proxy_ports.insert(
+                ("udp", port),
+                "the stats multiplexer (stats_multiplexer_listening_port)".to_string(),
+            );
+        }
+        for (chain_name, name) in uses {
+            let handler = match handlers.iter().find(|handler| handler.name == name) {
+                Some(handler) => handler,
+                None => continue,
+            };
+            let mut ports = vec![("open_udp_port", "udp"), ("open_tcp_port", "tcp")];
+            if let Some(schema) = handler.schema {
+                ports.extend(schema.proxy_udp_ports.iter().map(|key| (*key, "udp")));
+            }
+            for (key, protocol) in ports {
+                let port = match handler
+                    .arguments
+                    .get(key)
+                    .and_then(|x| x.parse::<u16>().ok())
+                {
+                    Some(port) if port != 0 => port,
+                    _ => continue,
+                };
+                let user = format!("{key} of handler {name} in chain {chain_name}");
+                match proxy_ports.get(&(protocol, port)) {
+                    Some(other) => {
+                        let message =
+                            format!("{protocol} port {port} of {user} is already used by {other}");
+                        self.error(&[handler.table, name, key], message);
+                    }
+                    None => {
+                        proxy_ports.insert((protocol, port), user);
+                    }
+                }
+            }
+            if let Some((address_key, port_key)) =
+                handler.schema.and_then(|schema| schema.destination)
+            {
+                let destination = (
+                    handler.arguments.get(address_key),
+                    handler
+                        .arguments
+                        .get(port_key)
+                        .and_then(|x| x.parse::<u16>().ok()),
+                );
+                if let (Some(address), Some(port)) = destination {
+                    let user = format!("handler {name} in chain {chain_name}");
+                    match destinations.get(&(address.as_str(), port)) {
+                        Some(other) => {
+                            let message = format!(
+                                "{user} sends to {address}:{port}, as does {other}, the data of both chains is mixed"
+                            );
+                            self.error(&[handler.table, name, port_key], message);
+                        }
+                        None => {
+                            destinations.insert((address.as_str(), port), user);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Returns the message of a configuration error, without the prefix of its display.
+fn configuration_message(error: Error) -> String {
+    match error.kind() {
+        ConfigurationError(message) => message.to_string(),
+        _ => error.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A valid configuration, every test changes one line.
+    const CONFIG: &str = r#"[settings]
+path = "/home/osdd"
+stats_servers = ["127.0.0.1:7654"]
+syslog_host = "127.0.0.1"
+syslog_port = "8082"
+log_level = "Info"
+instance = "1"
+network = "ingress"
+stats_multiplexer_listening_port = "8125"
+
+[chain.TestTopic]
+protocol_handler = "udp_in"
+filter_handlers = ["secret_filter"]
+transport_handler = "udp1"
+
+[protocolhandler.udp_in]
+type = "ph_udp_ingress"
+listening_port = 7654
+bip_buffer_element_count = "2"
+
+[filterhandler.secret_filter]
+type = "filter"
+word_to_filter = "SECRET"
+
+[transporthandler.udp1]
+type = "transport_udp_send"
+receiver_address = "192.168.0.255"
+receiver_port = "1234"
+sender_address = "192.168.0.255"
+sender_port = "1234"
+"#;
+
+    /// Returns the issues of the configuration with `from` replaced by `to`, as severity, line and message.
+    fn issues(from: &str, to: &str) -> Vec<(Severity, Option<usize>, String)> {
+        assert!(CONFIG.contains(from), "{}", from);
+        validate_config(&CONFIG.replacen(from, to, 1))
+            .into_iter()
+            .map(|issue| (issue.severity, issue.line, issue.message))
+            .collect()
+    }
+
+    /// Asserts that the changed configuration has exactly one issue, on `line` and with a message containing `message`.
+    fn assert_issue(from: &str, to: &str, severity: Severity, line: usize, message: &str) {
+        let issues = issues(from, to);
+        assert_eq!(issues.len(), 1, "{:?}", issues);
+        assert_eq!(issues[0].0, severity, "{:?}", issues);
+        assert_eq!(issues[0].1, Some(line), "{:?}", issues);
+        assert!(issues[0].2.contains(message), "{:?}", issues);
+    }
+
+    #[test]
+    fn valid_config_test() {
+        assert!(validate_config(CONFIG).is_empty());
+    }
+
+    #[test]
+    fn syntax_error_test() {
+        assert_issue(
+            "log_level = \"Info\"",
+            "log_level = \"Info",
+            Severity::Error,
+            6,
+            "cannot read the configuration",
+        );
+    }
+
+    #[test]
+    fn settings_test() {
+        use Severity::*;
+        assert_issue(
+            "network = \"ingress\"",
+            "network = \"sideways\"",
+            Error,
+            8,
+            "network sideways is unknown",
+        );
+        assert_issue(
+            "log_level = \"Info\"",
+            "log_level = \"Loud\"",
+            Error,
+            6,
+            "log_level Loud is unknown",
+        );
+        assert_issue(
+            "instance = \"1\"",
+            "instance = \"a.b\"",
+            Error,
+            7,
+            "instance name \"a.b\"",
+        );
+        assert_issue(
+            "syslog_port = \"8082\"",
+            "syslog_port = \"x\"",
+            Error,
+            4,
+            "are not an IP address and a port",
+        );
+        assert_issue(
+            "\"127.0.0.1:7654\"",
+            "\"localhost\"",
+            Error,
+            3,
+            "stats server localhost",
+        );
+        assert_issue(
+            "[settings]",
+            "[settings]\nruntime = \"vm\"",
+            Error,
+            2,
+            "runtime vm is unknown",
+        );
+        assert_issue(
+            "[settings]",
+            "[settings]\nstats_flush_interval_sec = \"0\"",
+            Error,
+            2,
+            "at least 1",
+        );
+        assert_issue(
+            "[settings]",
+            "[settings]\ncolour = \"blue\"",
+            Warning,
+            2,
+            "setting colour is unknown",
+        );
+        assert_issue(
+            "path = \"/home/osdd\"\n",
+            "",
+            Error,
+            1,
+            "cannot read the settings",
+        );
+    }
+
+    #[test]
+    fn handler_test() {
+        use Severity::*;
+        assert_issue(
+            "type = \"filter\"",
+            "type = \"sieve\"",
+            Error,
+            22,
+            "unknown type sieve",
+        );
+        let wrong_type = issues("type = \"filter\"", "type = \"ph_udp_egress\"");
+        assert!(wrong_type.contains(&(
+            Error,
+            Some(22),
+            "handler secret_filter has type ph_udp_egress, which belongs in [protocolhandler]"
+                .to_string()
+        )));
+        assert!(wrong_type.contains(&(Error, Some(22), "handler secret_filter has type ph_udp_egress, which runs in the egress network and not in the ingress network".to_string())));
+        assert_issue(
+            "type = \"ph_udp_ingress\"",
+            "type = \"ph_udp_ingress\"\nlisten_port = 1",
+            Error,
+            18,
+            "ph_udp_ingress has no such argument",
+        );
+        assert_issue(
+            "listening_port = 7654",
+            "listening_port = 70000",
+            Error,
+            18,
+            "listening_port of handler udp_in",
+        );
+        assert_issue(
+            "word_to_filter = \"SECRET\"\n",
+            "",
+            Error,
+            21,
+            "needs word_to_filter",
+        );
+        assert_issue(
+            "[protocolhandler.udp_in]",
+            "[protocolhandler.udp_in]\nsocket_path = \"/tmp/a\"",
+            Error,
+            17,
+            "osdd sets it itself",
+        );
+        assert_issue(
+            "[filterhandler.secret_filter]",
+            "[other]\nkey = 1\n\n[filterhandler.secret_filter]",
+            Warning,
+            21,
+            "[other] is unknown",
+        );
+        let renamed = issues("[transporthandler.udp1]", "[transporthandler.udp2]");
+        assert!(renamed
+            .iter()
+            .any(|(severity, line, message)| *severity == Error
+                && *line == Some(11)
+                && message.contains("uses handler udp1, which is not configured")));
+        assert!(renamed
+            .iter()
+            .any(|(severity, line, message)| *severity == Warning
+                && *line == Some(25)
+                && message.contains("handler udp2 is not used by any chain")));
+        let cpu_limit = "bip_buffer_element_count = \"2\"\ncpu_limit_percent = 150";
+        assert!(issues("bip_buffer_element_count = \"2\"", cpu_limit).is_empty());
+        let native = CONFIG
+            .replacen("[settings]", "[settings]\nruntime = \"native\"", 1)
+            .replacen("bip_buffer_element_count = \"2\"", cpu_limit, 1);
+        let native_issues = validate_config(&native);
+        assert_eq!(native_issues.len(), 1, "{:?}", native_issues);
+        assert_eq!(native_issues[0].line, Some(21));
+        assert!(native_issues[0]
+            .message
+            .contains("the native runtime cannot limit the CPU"));
+    }
+
+    #[test]
+    fn chain_test() {
+        use Severity::*;
+        assert_issue(
+            "filter_handlers = [\"secret_filter\"]",
+            "filter_handlers = [\"secret_filter\"]\nlink = \"shm\"",
+            Warning,
+            14,
+            "link of chain TestTopic is unknown",
+        );
+        assert_issue(
+            "[chain.TestTopic]",
+            "[chain.\"Test.Topic\"]",
+            Error,
+            11,
+            "chain name \"Test.Topic\" must start with a letter",
+        );
+        //the handlers of a chain that cannot be built are not used
+        let pipe = issues(
+            "[chain.TestTopic]",
+            "[chain.TestTopic]\nlink_type = \"pipe\"",
+        );
+        assert_eq!(pipe.len(), 4, "{:?}", pipe);
+        assert_eq!(
+            pipe[0],
+            (
+                Error,
+                Some(11),
+                "Chain TestTopic has unknown link_type pipe, can be \"socket\" or \"shm\""
+                    .to_string()
+            )
+        );
+        assert_issue(
+            "[chain.TestTopic]",
+            "[chain.Test_Topic!]",
+            Error,
+            11,
+            "cannot read the configuration",
+        );
+    }
+
+    #[test]
+    fn ports_test() {
+        use Severity::*;
+        assert_issue(
+            "sender_port = \"1234\"",
+            "sender_port = \"8125\"",
+            Error,
+            30,
+            "udp port 8125 of sender_port of handler udp1",
+        );
+        let config = CONFIG.replacen(
+            "[chain.TestTopic]",
+            "[chain.Other]\nprotocol_handler = \"udp_in\"\nfilter_handlers = []\ntransport_handler = \"udp1\"\n\n[chain.TestTopic]",
+            1,
+        );
+        let issues = validate_config(&config);
+        //the handlers of both chains run twice, with the same ports and destination
+        assert!(issues
+            .iter()
+            .any(|issue| issue.message.contains("the data of both chains is mixed")));
+        assert!(issues.iter().all(|issue| issue.severity == Error));
+    }
+
+    #[test]
+    fn line_index_test() {
+        let lines = LineIndex::new("[a]\nb = 1\n# c = 2\n[d.'e']\n\"f\" = 3\n");
+        assert_eq!(lines.line(&["a"]), Some(1));
+        assert_eq!(lines.line(&["a", "b"]), Some(2));
+        assert_eq!(lines.line(&["a", "c"]), Some(1));
+        assert_eq!(lines.line(&["d"]), Some(4));
+        assert_eq!(lines.line(&["d", "e", "f"]), Some(5));
+        assert_eq!(lines.line(&["g"]), None);
+    }
+}
@@ -0,0 +1,56 @@
+// Copyright 2020 Ministerie van Defensie
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use structopt::StructOpt;
+
+///Commandline arguments used to run osdd-decode.
+#[derive(StructOpt)]
+pub struct OptDecode {
+    ///Read the transport traffic from this pcap file.
+    #[structopt(long = "pcap_file")]
+    pub pcap_file: Option<String>,
+
+    ///Listen for live transport traffic on this address, for example 0.0.0.0:1234.
+    #[structopt(long = "listen_address")]
+    pub listen_address: Option<String>,
+
+    ///Only decode UDP datagrams sent to this port when reading a pcap file. 0 decodes all ports.
+    #[structopt(long = "port", default_value = "0")]
+    pub port: u16,
+
+    ///How the data in reassembled messages is decoded, can be "raw" or "modbus".
+    ///The metadata of envelopes is always shown.
+    #[structopt(long = "decode", default_value = "raw")]
+    pub decode: String,
+
+    ///Output format, can be "text" or "json" (one JSON object per line).
+    #[structopt(long = "format", default_value = "text")]
+    pub format: String,
+
+    ///Write every reassembled payload to a separate file in this directory.
+    #[structopt(long = "export_dir")]
+    pub export_dir: Option<String>,
+
+    ///Do not print per-fragment headers, only gaps and reassembled messages.
+    #[structopt(long = "messages_only")]
+    pub messages_only: bool,
+
+    ///Stop after this amount of datagrams has been decoded. 0 means no limit.
+    #[structopt(long = "count", default_value = "0")]
+    pub count: usize,
+
+    ///The amount of payload bytes shown in the hex preview of a message.
+    #[structopt(long = "preview_bytes", default_value = "64")]
+    pub preview_bytes: usize,
+}
@@ -0,0 +1,248 @@
+// Copyright 2020 Ministerie van Defensie
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use osdd_decode::arguments::OptDecode;
+use osdd_decode::errors::ErrorKind::ArgumentError;
+use osdd_decode::errors::*;
+use osdd_decode::payload::*;
+use osdd_decode::pcap::PcapReader;
+use osdd_decode::reassembly::{DecodeEvent, Reassembler};
+use serde_json::json;
+use std::fs::File;
+use std::io::BufReader;
+use std::net::UdpSocket;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use structopt::StructOpt;
+
+///The maximum size of a UDP datagram sent by transport_udp_send.
+const MAX_DATAGRAM_SIZE: usize = 65535;
+
+fn main() {
+    env_logger::init();
+    decode().chain_unwrap();
+}
+
+///Prints (or exports) everything the Reassembler reports.
+struct Output {
+    json: bool,
+    messages_only: bool,
+    payload_format: PayloadFormat,
+    preview_bytes: usize,
+    export_dir: Option<PathBuf>,
+    datagrams: usize,
+    messages: usize,
+}
+
+impl Output {
+    fn datagram(&mut self, timestamp: Duration, source: &str, events: Vec<DecodeEvent>) -> Result<()> {
+        self.datagrams += 1;
+        let time = timestamp.as_secs_f64();
+        for event in events {
+            match event {
+                DecodeEvent::Fragment(header) => {
+                    if self.messages_only {
+                        continue;
+                    }
+                    if self.json {
+                        println!(
+                            "{}",
+                            json!({
+                                "event": "fragment",
+                                "time": time,
+                                "source": source,
+                                "message_type": format!("{:?}", header.message_type),
+                                "sequence_number": header.sequence_number,
+                                "payload_length": header.payload_length,
+                                "remaining_messages": header.remaining_messages,
+                            })
+                        );
+                    } else {
+                        println!(
+                            "{time:.6} {source} {:?} seq={} len={} remaining={}",
+                            header.message_type,
+                            header.sequence_number,
+                            header.payload_length,
+                            header.remaining_messages
+                        );
+                    }
+                }
+                DecodeEvent::Gap {
+                    expected,
+                    received,
+                    lost,
+                } => {
+                    if self.json {
+                        println!(
+                            "{}",
+                            json!({"event": "gap", "time": time, "expected": expected, "received": received, "lost": lost})
+                        );
+                    } else {
+                        println!("{time:.6} GAP expected seq={expected} received seq={received}, {lost} packets lost");
+                    }
+                }
+                DecodeEvent::OutOfOrder { expected, received } => {
+                    if self.json {
+                        println!(
+                            "{}",
+                            json!({"event": "out_of_order", "time": time, "expected": expected, "received": received})
+                        );
+                    } else {
+                        println!("{time:.6} OUT OF ORDER expected seq={expected} received seq={received}");
+                    }
+                }
+                DecodeEvent::Restarted { previous, received } => {
+                    if self.json {
+                        println!(
+                            "{}",
+                            json!({"event": "restarted", "time": time, "previous": previous, "received": received})
+                        );
+                    } else {
+                        println!("{time:.6} RESTARTED previous seq={previous} received seq={received}");
+                    }
+                }
+                DecodeEvent::Discarded { reason } => {
+                    if self.json {
+                        println!(
+                            "{}",
+                            json!({"event": "discarded", "time": time, "reason": reason})
+                        );
+                    } else {
+                        println!("{time:.6} DISCARDED {reason}");
+                    }
+                }
+                DecodeEvent::Message { fragments, payload } => {
+                    self.message(time, fragments, &payload)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn message(&mut self, time: f64, fragments: usize, payload: &[u8]) -> Result<()> {
+        self.messages += 1;
+        let decoded = decode_message(payload, self.payload_format, self.preview_bytes);
+        let mut exported = None;
+        if let Some(export_dir) = &self.export_dir {
+            let path = export_dir.join(format!("message_{:06}.bin", self.messages));
+            std::fs::write(&path, payload)
+                .chain_err(|| format!("Error exporting message to {}", path.display()))?;
+            exported = Some(path.display().to_string());
+        }
+        if self.json {
+            println!(
+                "{}",
+                json!({
+                    "event": "message",
+                    "time": time,
+                    "number": self.messages,
+                    "fragments": fragments,
+                    "length": payload.len(),
+                    "decoded": decoded,
+                    "exported_to": exported,
+                })
+            );
+        } else {
+            println!(
+                "{time:.6} MESSAGE #{} {} bytes in {fragments} fragments: {}",
+                self.messages,
+                payload.len(),
+                serde_json::to_string(&decoded)?
+            );
+            if let Some(path) = exported {
+                println!("    exported to {path}");
+            }
+        }
+        Ok(())
+    }
+}
+
+///Reads transport traffic from a pcap file or a live UDP port and decodes it.
+fn decode() -> Result<()> {
+    let opt = OptDecode::from_args();
+    if opt.pcap_file.is_some() == opt.listen_address.is_some() {
+        return Err(ArgumentError(
+            "exactly one of --pcap_file and --listen_address must be given".to_string(),
+        )
+        .into());
+    }
+    if opt.format != "text" && opt.format != "json" {
+        return Err(ArgumentError(format!("unknown output format {}", opt.format)).into());
+    }
+    let export_dir = opt.export_dir.as_ref().map(PathBuf::from);
+    if let Some(dir) = &export_dir {
+        std::fs::create_dir_all(dir)
+            .chain_err(|| format!("Error creating export directory {}", dir.display()))?;
+    }
+    let mut output = Output {
+        json: opt.format == "json",
+        messages_only: opt.messages_only,
+        payload_format: opt.decode.parse()?,
+        preview_bytes: opt.preview_bytes,
+        export_dir,
+        datagrams: 0,
+        messages: 0,
+    };
+    let mut reassembler = Reassembler::new();
+
+    if let Some(pcap_file) = &opt.pcap_file {
+        let file = File::open(pcap_file).chain_err(|| format!("Error opening {pcap_file}"))?;
+        let mut pcap_reader = PcapReader::new(BufReader::new(file))?;
+        while let Some(datagram) = pcap_reader.next_datagram()? {
+            if opt.port != 0 && datagram.destination.port() != opt.port {
+                continue;
+            }
+            let events = reassembler.push(&datagram.payload);
+            output.datagram(datagram.timestamp, &datagram.source.to_string(), events)?;
+            if opt.count != 0 && output.datagrams >= opt.count {
+                break;
+            }
+        }
+    } else if let Some(listen_address) = &opt.listen_address {
+        let socket = UdpSocket::bind(listen_address)
+            .chain_err(|| format!("Error binding udp socket on {listen_address}"))?;
+        let mut buffer = vec![0; MAX_DATAGRAM_SIZE];
+        loop {
+            let (length, source) = socket.recv_from(&mut buffer)?;
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            let events = reassembler.push(&buffer[..length]);
+            output.datagram(timestamp, &source.to_string(), events)?;
+            if opt.count != 0 && output.datagrams >= opt.count {
+                break;
+            }
+        }
+    }
+
+    if output.json {
+        println!(
+            "{}",
+            json!({
+                "event": "summary",
+                "datagrams": output.datagrams,
+                "messages": output.messages,
+                "lost_packets": reassembler.lost_packets(),
+            })
+        );
+    } else {
+        println!(
+            "{} datagrams, {} messages reassembled, {} packets lost",
+            output.datagrams,
+            output.messages,
+            reassembler.lost_packets()
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,24 @@
+// Copyright 2020 Ministerie van Defensie
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+///Commandline arguments for osdd-decode.
+pub mod arguments;
+///Error chain for osdd-decode.
+pub mod errors;
+///Decoding of reassembled payloads.
+pub mod payload;
+///Reader for pcap capture files.
+pub mod pcap;
+///Reassembly of transport_udp datagrams into messages.
+pub mod reassembly;
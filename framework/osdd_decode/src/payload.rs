@@ -0,0 +1,231 @@
+// Copyright 2020 Ministerie van Defensie
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::errors::ErrorKind::ArgumentError;
+use crate::errors::*;
+use bip_utils::frame::{FrameHeader, FrameKind};
+use framework_constants::FRAME_HEADER_LEN;
+use ph_modbus::data_packet::{DataPacket, DataType};
+use serde::Serialize;
+use socket_utils::envelope::Envelope;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+///How the data in a reassembled message should be interpreted.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PayloadFormat {
+    ///Show the payload as hex.
+    Raw,
+    ///Decode the payload as a DataPacket written by ph_modbus_ingress.
+    Modbus,
+}
+
+impl FromStr for PayloadFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<PayloadFormat> {
+        match s {
+            "raw" => Ok(PayloadFormat::Raw),
+            "modbus" => Ok(PayloadFormat::Modbus),
+            _ => Err(ArgumentError(format!("unknown payload format {s}")).into()),
+        }
+    }
+}
+
+///The decoded view of a reassembled payload.
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum DecodedPayload {
+    Raw {
+        length: usize,
+        preview: String,
+    },
+    Envelope {
+        source: String,
+        content_type: String,
+        trace_id: String,
+        ingest_timestamp_ns: u64,
+        headers: BTreeMap<String, String>,
+        payload: Box<DecodedPayload>,
+    },
+    Modbus {
+        datatype: String,
+        id: u16,
+        value: String,
+    },
+    Invalid {
+        reason: String,
+        length: usize,
+        preview: String,
+    },
+}
+
+///Decodes a reassembled message, a frame as written by the handlers.
+///The data of a FrameKind::Data frame or of an envelope is decoded with decode_payload.
+/// # Arguments
+/// * `message` - The reassembled message.
+/// * `format` - How the data in the message should be interpreted.
+/// * `preview_bytes` - The maximum amount of bytes shown in hex previews.
+pub fn decode_message(
+    message: &[u8],
+    format: PayloadFormat,
+    preview_bytes: usize,
+) -> DecodedPayload {
+    let invalid = |reason: String| DecodedPayload::Invalid {
+        reason,
+        length: message.len(),
+        preview: hex_preview(message, preview_bytes),
+    };
+    let header = match FrameHeader::from_bytes(message) {
+        Ok(header) => header,
+        Err(e) => return invalid(e.to_string()),
+    };
+    let payload = &message[FRAME_HEADER_LEN..];
+    if header.payload_length() != payload.len() {
+        return invalid(format!(
+            "frame header announces {} bytes of payload, but the message has {} bytes",
+            header.payload_length(),
+            payload.len()
+        ));
+    }
+    match header.kind {
+        FrameKind::Data => decode_payload(payload, format, preview_bytes),
+        FrameKind::Envelope => match Envelope::from_bytes(payload) {
+            Ok(envelope) => DecodedPayload::Envelope {
+                payload: Box::new(decode_payload(&envelope.payload, format, preview_bytes)),
+                source: envelope.source,
+                content_type: envelope.content_type,
+                trace_id: format!("{:016x}", envelope.trace_id),
+                ingest_timestamp_ns: envelope.ingest_timestamp_ns,
+                headers: envelope.headers,
+            },
+            Err(e) => invalid(e.to_string()),
+        },
+    }
+}
+
+///Decodes the data of a message.
+/// # Arguments
+/// * `payload` - The reassembled message.
+/// * `format` - How the message should be interpreted.
+/// * `preview_bytes` - The maximum amount of bytes shown in hex previews.
+pub fn decode_payload(
+    payload: &[u8],
+    format: PayloadFormat,
+    preview_bytes: usize,
+) -> DecodedPayload {
+    match format {
+        PayloadFormat::Raw => DecodedPayload::Raw {
+            length: payload.len(),
+            preview: hex_preview(payload, preview_bytes),
+        },
+        PayloadFormat::Modbus => {
+            let data_packet = DataPacket::from_bytes(payload.to_vec());
+            let datatype = match data_packet.datatype {
+                DataType::ModbusCommand => "modbus_command",
+                DataType::CoilValue => "coil_value",
+                DataType::InputValue => "input_value",
+                DataType::HoldingRegisterValue => "holding_register_value",
+                DataType::InputRegisterValue => "input_register_value",
+                DataType::Undefined => {
+                    return DecodedPayload::Invalid {
+                        reason: "not a Modbus DataPacket".to_string(),
+                        length: payload.len(),
+                        preview: hex_preview(payload, preview_bytes),
+                    }
+                }
+            };
+            DecodedPayload::Modbus {
+                datatype: datatype.to_string(),
+                id: data_packet.get_id(),
+                value: hex_preview(data_packet.get_value(), preview_bytes),
+            }
+        }
+    }
+}
+
+///Formats the first `max_bytes` bytes of `data` as space separated hex.
+pub fn hex_preview(data: &[u8], max_bytes: usize) -> String {
+    let mut preview = data
+        .iter()
+        .take(max_bytes)
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<Vec<String>>()
+        .join(" ");
+    if data.len() > max_bytes {
+        preview.push_str(" ..");
+    }
+    preview
+}
+
+#[cfg(test)]
+mod test {
+    use crate::payload::*;
+
+    use bip_utils::frame::{FrameHeader, FrameKind};
+
+    fn frame(kind: FrameKind, payload: &[u8]) -> Vec<u8> {
+        let mut frame = FrameHeader::new(kind, payload.len()).to_bytes().to_vec();
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    #[test]
+    fn decode_envelope_test() {
+        let mut envelope =
+            Envelope::new("ph_kafka_ingress", "application/vnd.osdd.kafka", b"payload")
+                .with_header("topic", "TestTopic");
+        envelope.trace_id = 42;
+        let bytes = envelope.to_bytes().expect("Error serializing Envelope");
+        let decoded = decode_message(&frame(FrameKind::Envelope, &bytes), PayloadFormat::Raw, 2);
+        assert_eq!(
+            decoded,
+            DecodedPayload::Envelope {
+                source: "ph_kafka_ingress".to_string(),
+                content_type: "application/vnd.osdd.kafka".to_string(),
+                trace_id: "000000000000002a".to_string(),
+                ingest_timestamp_ns: envelope.ingest_timestamp_ns,
+                headers: envelope.headers.clone(),
+                payload: Box::new(DecodedPayload::Raw {
+                    length: 7,
+                    preview: "70 61 ..".to_string(),
+                }),
+            }
+        );
+    }
+
+    #[test]
+    fn decode_invalid_frame_test() {
+        let decoded = decode_message(b"not a frame", PayloadFormat::Raw, 2);
+        assert!(matches!(decoded, DecodedPayload::Invalid { .. }));
+    }
+
+    #[test]
+    fn decode_modbus_data_packet_test() {
+        let data_packet = DataPacket::new(DataType::CoilValue, vec![0, 10, 1], 513);
+        let decoded = decode_message(
+            &frame(FrameKind::Data, &data_packet.to_bytes()),
+            PayloadFormat::Modbus,
+            64,
+        );
+        assert_eq!(
+            decoded,
+            DecodedPayload::Modbus {
+                datatype: "coil_value".to_string(),
+                id: 513,
+                value: "00 0a 01".to_string(),
+            }
+        );
+    }
+}
@@ -0,0 +1,340 @@
+// Copyright 2020 Ministerie van Defensie
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::errors::ErrorKind::PcapError;
+use crate::errors::*;
+use std::collections::HashMap;
+use std::io::Read;
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::time::Duration;
+
+const PCAP_MAGIC_MICROS: u32 = 0xa1b2_c3d4;
+const PCAP_MAGIC_NANOS: u32 = 0xa1b2_3c4d;
+const PCAPNG_MAGIC: u32 = 0x0a0d_0d0a;
+const PCAP_GLOBAL_HEADER_LEN: usize = 24;
+const PCAP_RECORD_HEADER_LEN: usize = 16;
+
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const LINKTYPE_IPV4: u32 = 228;
+const LINKTYPE_LINUX_SLL2: u32 = 276;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const IP_PROTOCOL_UDP: u8 = 17;
+const UDP_HEADER_LEN: usize = 8;
+
+///A UDP datagram read from a capture file.
+#[derive(Debug)]
+pub struct UdpDatagram {
+    ///Capture time of the (last fragment of the) datagram, relative to the unix epoch.
+    pub timestamp: Duration,
+    ///Source address of the datagram.
+    pub source: SocketAddrV4,
+    ///Destination address of the datagram.
+    pub destination: SocketAddrV4,
+    ///The UDP payload.
+    pub payload: Vec<u8>,
+}
+
+#[derive(Hash, PartialEq, Eq)]
+struct FragmentKey {
+    source: Ipv4Addr,
+    destination: Ipv4Addr,
+    identification: u16,
+}
+
+#[derive(Default)]
+struct IpFragments {
+    parts: Vec<(usize, Vec<u8>)>,
+    total_length: Option<usize>,
+}
+
+impl IpFragments {
+    ///Returns the reassembled IP payload when all fragments have been received.
+    fn try_assemble(&mut self) -> Option<Vec<u8>> {
+        let total_length = self.total_length?;
+        self.parts.sort_by_key(|part| part.0);
+        let mut assembled = Vec::with_capacity(total_length);
+        for (offset, data) in &self.parts {
+            if *offset > assembled.len() {
+                return None;
+            }
+            let skip = assembled.len() - offset;
+            if skip < data.len() {
+                assembled.extend_from_slice(&data[skip..]);
+            }
+        }
+        if assembled.len() >= total_length {
+            assembled.truncate(total_length);
+            Some(assembled)
+        } else {
+            None
+        }
+    }
+}
+
+///Reads UDP datagrams from a classic (libpcap) capture file.
+///IPv4 fragments are reassembled before the datagram is returned.
+pub struct PcapReader<R: Read> {
+    reader: R,
+    swapped: bool,
+    nanos: bool,
+    link_type: u32,
+    fragments: HashMap<FragmentKey, IpFragments>,
+}
+
+impl<R: Read> PcapReader<R> {
+    ///Creates a new PcapReader and reads the global header of the capture.
+    /// # Arguments
+    /// * `reader` - The source of the capture data.
+    pub fn new(mut reader: R) -> Result<PcapReader<R>> {
+        let mut header = [0; PCAP_GLOBAL_HEADER_LEN];
+        reader
+            .read_exact(&mut header)
+            .chain_err(|| PcapError("file is too short for a pcap header".to_string()))?;
+        let magic = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+        let (swapped, nanos) = match magic {
+            PCAP_MAGIC_MICROS => (false, false),
+            PCAP_MAGIC_NANOS => (false, true),
+            m if m.swap_bytes() == PCAP_MAGIC_MICROS => (true, false),
+            m if m.swap_bytes() == PCAP_MAGIC_NANOS => (true, true),
+            PCAPNG_MAGIC => {
+                return Err(PcapError(
+                    "pcapng is not supported, convert it with `editcap -F pcap`".to_string(),
+                )
+                .into())
+            }
+            m => return Err(PcapError(format!("unknown magic number {m:#010x}")).into()),
+        };
+        let mut pcap_reader = PcapReader {
+            reader,
+            swapped,
+            nanos,
+            link_type: 0,
+            fragments: HashMap::new(),
+        };
+        pcap_reader.link_type = pcap_reader.read_u32(&header[20..24]);
+        Ok(pcap_reader)
+    }
+
+    ///Returns the next UDP datagram in the capture.
+    ///Records that do not contain (the last fragment of) an IPv4 UDP datagram are skipped.
+    /// # Returns
+    /// `Option<UdpDatagram>` - None when the end of the capture is reached.
+    pub fn next_datagram(&mut self) -> Result<Option<UdpDatagram>> {
+        loop {
+            let mut record_header = [0; PCAP_RECORD_HEADER_LEN];
+            match self.reader.read_exact(&mut record_header) {
+                Ok(_) => (),
+                Err(ref e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+                Err(e) => return Err(Error::with_chain(e, "Error reading pcap record")),
+            }
+            let seconds = self.read_u32(&record_header[0..4]);
+            let fraction = self.read_u32(&record_header[4..8]);
+            let captured_length = self.read_u32(&record_header[8..12]) as usize;
+            let mut frame = vec![0; captured_length];
+            self.reader
+                .read_exact(&mut frame)
+                .chain_err(|| PcapError("capture ends in the middle of a record".to_string()))?;
+            let timestamp = if self.nanos {
+                Duration::new(seconds as u64, fraction)
+            } else {
+                Duration::new(seconds as u64, fraction.saturating_mul(1000))
+            };
+            if let Some(datagram) = self.parse_frame(&frame, timestamp) {
+                return Ok(Some(datagram));
+            }
+        }
+    }
+
+    fn read_u32(&self, bytes: &[u8]) -> u32 {
+        let value = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        if self.swapped {
+            value.swap_bytes()
+        } else {
+            value
+        }
+    }
+
+    ///Strips the link layer header and returns the IPv4 packet, if any.
+    fn ip_packet<'a>(&self, frame: &'a [u8]) -> Option<&'a [u8]> {
+        match self.link_type {
+            LINKTYPE_NULL => frame.get(4..),
+            LINKTYPE_RAW | LINKTYPE_IPV4 => Some(frame),
+            LINKTYPE_ETHERNET => {
+                let mut offset = 12;
+                let mut ether_type = read_u16_be(frame, offset)?;
+                while ether_type == ETHERTYPE_VLAN {
+                    offset += 4;
+                    ether_type = read_u16_be(frame, offset)?;
+                }
+                if ether_type == ETHERTYPE_IPV4 {
+                    frame.get(offset + 2..)
+                } else {
+                    None
+                }
+            }
+            LINKTYPE_LINUX_SLL if read_u16_be(frame, 14)? == ETHERTYPE_IPV4 => frame.get(16..),
+            LINKTYPE_LINUX_SLL2 if read_u16_be(frame, 0)? == ETHERTYPE_IPV4 => frame.get(20..),
+            _ => None,
+        }
+    }
+
+    fn parse_frame(&mut self, frame: &[u8], timestamp: Duration) -> Option<UdpDatagram> {
+        let packet = self.ip_packet(frame)?;
+        if packet.len() < 20 || packet[0] >> 4 != 4 || packet[9] != IP_PROTOCOL_UDP {
+            return None;
+        }
+        let header_length = ((packet[0] & 0x0f) as usize) * 4;
+        let total_length = (read_u16_be(packet, 2)? as usize).min(packet.len());
+        let identification = read_u16_be(packet, 4)?;
+        let flags_and_offset = read_u16_be(packet, 6)?;
+        let more_fragments = flags_and_offset & 0x2000 != 0;
+        let fragment_offset = ((flags_and_offset & 0x1fff) as usize) * 8;
+        let source = Ipv4Addr::new(packet[12], packet[13], packet[14], packet[15]);
+        let destination = Ipv4Addr::new(packet[16], packet[17], packet[18], packet[19]);
+        let ip_payload = packet.get(header_length..total_length)?;
+
+        let udp = if more_fragments || fragment_offset > 0 {
+            let key = FragmentKey {
+                source,
+                destination,
+                identification,
+            };
+            let fragments = self.fragments.entry(key).or_default();
+            fragments.parts.push((fragment_offset, ip_payload.to_vec()));
+            if !more_fragments {
+                fragments.total_length = Some(fragment_offset + ip_payload.len());
+            }
+            let assembled = fragments.try_assemble()?;
+            self.fragments.remove(&FragmentKey {
+                source,
+                destination,
+                identification,
+            });
+            assembled
+        } else {
+            ip_payload.to_vec()
+        };
+
+        if udp.len() < UDP_HEADER_LEN {
+            return None;
+        }
+        let source_port = read_u16_be(&udp, 0)?;
+        let destination_port = read_u16_be(&udp, 2)?;
+        let udp_length = (read_u16_be(&udp, 4)? as usize).clamp(UDP_HEADER_LEN, udp.len());
+        Some(UdpDatagram {
+            timestamp,
+            source: SocketAddrV4::new(source, source_port),
+            destination: SocketAddrV4::new(destination, destination_port),
+            payload: udp[UDP_HEADER_LEN..udp_length].to_vec(),
+        })
+    }
+}
+
+fn read_u16_be(bytes: &[u8], offset: usize) -> Option<u16> {
+    let field = bytes.get(offset..offset + 2)?;
+    Some(u16::from_be_bytes([field[0], field[1]]))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::pcap::*;
+
+    ///Builds a pcap file with an ethernet link type containing the given IPv4 packets.
+    fn ethernet_capture(packets: &[Vec<u8>]) -> Vec<u8> {
+        let mut capture = Vec::new();
+        capture.extend_from_slice(&PCAP_MAGIC_MICROS.to_le_bytes());
+        capture.extend_from_slice(&2u16.to_le_bytes());
+        capture.extend_from_slice(&4u16.to_le_bytes());
+        capture.extend_from_slice(&[0; 8]);
+        capture.extend_from_slice(&65535u32.to_le_bytes());
+        capture.extend_from_slice(&LINKTYPE_ETHERNET.to_le_bytes());
+        for (i, packet) in packets.iter().enumerate() {
+            let frame_length = (packet.len() + 14) as u32;
+            capture.extend_from_slice(&(i as u32).to_le_bytes());
+            capture.extend_from_slice(&500u32.to_le_bytes());
+            capture.extend_from_slice(&frame_length.to_le_bytes());
+            capture.extend_from_slice(&frame_length.to_le_bytes());
+            capture.extend_from_slice(&[0; 12]);
+            capture.extend_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+            capture.extend_from_slice(packet);
+        }
+        capture
+    }
+
+    ///Builds an IPv4 packet (or fragment) carrying `data` as protocol UDP.
+    fn ipv4_packet(data: &[u8], identification: u16, offset: usize, more: bool) -> Vec<u8> {
+        let mut packet = vec![0x45, 0];
+        packet.extend_from_slice(&((data.len() + 20) as u16).to_be_bytes());
+        packet.extend_from_slice(&identification.to_be_bytes());
+        let flags = ((offset / 8) as u16) | if more { 0x2000 } else { 0 };
+        packet.extend_from_slice(&flags.to_be_bytes());
+        packet.extend_from_slice(&[64, IP_PROTOCOL_UDP, 0, 0]);
+        packet.extend_from_slice(&[10, 0, 0, 1, 10, 0, 0, 2]);
+        packet.extend_from_slice(data);
+        packet
+    }
+
+    fn udp_datagram(payload: &[u8]) -> Vec<u8> {
+        let mut udp = Vec::new();
+        udp.extend_from_slice(&4000u16.to_be_bytes());
+        udp.extend_from_slice(&1234u16.to_be_bytes());
+        udp.extend_from_slice(&((payload.len() + UDP_HEADER_LEN) as u16).to_be_bytes());
+        udp.extend_from_slice(&[0, 0]);
+        udp.extend_from_slice(payload);
+        udp
+    }
+
+    #[test]
+    fn read_single_datagram_test() {
+        let packet = ipv4_packet(&udp_datagram(b"hello diode"), 1, 0, false);
+        let capture = ethernet_capture(&[packet]);
+        let mut reader = PcapReader::new(&capture[..]).expect("Error reading capture header");
+        let datagram = reader
+            .next_datagram()
+            .expect("Error reading datagram")
+            .expect("No datagram in capture");
+        assert_eq!(datagram.payload, b"hello diode");
+        assert_eq!(datagram.destination.port(), 1234);
+        assert_eq!(datagram.timestamp, Duration::new(0, 500_000));
+        assert!(reader.next_datagram().expect("Error reading").is_none());
+    }
+
+    #[test]
+    fn reassemble_ip_fragments_test() {
+        let payload: Vec<u8> = (0..=255).cycle().take(3000).collect();
+        let udp = udp_datagram(&payload);
+        let first = ipv4_packet(&udp[..1480], 7, 0, true);
+        let second = ipv4_packet(&udp[1480..2960], 7, 1480, true);
+        let last = ipv4_packet(&udp[2960..], 7, 2960, false);
+        //fragments may be captured out of order
+        let capture = ethernet_capture(&[second, first, last]);
+        let mut reader = PcapReader::new(&capture[..]).expect("Error reading capture header");
+        let datagram = reader
+            .next_datagram()
+            .expect("Error reading datagram")
+            .expect("No datagram in capture");
+        assert_eq!(datagram.payload, payload);
+    }
+
+    #[test]
+    fn reject_pcapng_test() {
+        let capture = [0x0a, 0x0d, 0x0d, 0x0a, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        assert!(PcapReader::new(&capture[..]).is_err());
+    }
+}
@@ -0,0 +1,339 @@
+// Copyright 2020 Ministerie van Defensie
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use framework_constants::*;
+use statistics_handler::StatsAllHandlers;
+use std::sync::Arc;
+use transport_udp::rx::inner_udp_receiver::State;
+use transport_udp::rx::inner_udp_receiver::State::*;
+use transport_udp::rx::{check_sequence_number, read_packet_header, PacketData, SequenceOrder};
+
+///An event produced while decoding transport_udp datagrams.
+#[derive(Debug)]
+pub enum DecodeEvent {
+    ///A datagram with a valid packet header was decoded.
+    Fragment(PacketData),
+    ///The sequence number jumped ahead, `lost` packets were never received.
+    Gap {
+        expected: u32,
+        received: u32,
+        lost: usize,
+    },
+    ///A packet arrived with a lower sequence number than expected.
+    OutOfOrder { expected: u32, received: u32 },
+    ///The sequence number went far back without StartUp packets, the sender restarted.
+    Restarted { previous: u32, received: u32 },
+    ///All fragments of a message were received and combined.
+    Message { fragments: usize, payload: Vec<u8> },
+    ///Data was thrown away, in the same situations the UdpReceiver would throw it away.
+    Discarded { reason: String },
+}
+
+///The Reassembler combines transport_udp datagrams into messages.
+///It follows the state machine of the InnerUdpReceiver, but reports every step as a DecodeEvent
+///instead of writing the result to a bip buffer.
+pub struct Reassembler {
+    fragments: Vec<Vec<u8>>,
+    current_sequence_number: u32,
+    state: State,
+    stats_data: Arc<StatsAllHandlers>,
+}
+
+impl Default for Reassembler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Reassembler {
+    ///Creates a new Reassembler in the WaitingForFirstData state.
+    pub fn new() -> Reassembler {
+        Reassembler {
+            fragments: Vec::new(),
+            current_sequence_number: 0,
+            state: WaitingForFirstData,
+            stats_data: Arc::new(StatsAllHandlers::default()),
+        }
+    }
+
+    ///Returns the total amount of packets lost so far.
+    pub fn lost_packets(&self) -> u64 {
+        self.stats_data.packetloss.load()
+    }
+
+    ///Decodes a single datagram.
+    /// # Arguments
+    /// * `datagram` - The UDP payload as sent by the UdpSender, including the packet header.
+    /// # Returns
+    /// `Vec<DecodeEvent>` - Everything that happened while handling this datagram, in order.
+    pub fn push(&mut self, datagram: &[u8]) -> Vec<DecodeEvent> {
+        let mut events = Vec::new();
+        if datagram.len() < HEADER_SIZE_BYTES {
+            events.push(DecodeEvent::Discarded {
+                reason: format!("datagram of {} bytes is shorter than the header", datagram.len()),
+            });
+            return events;
+        }
+        let packet_header = read_packet_header(datagram);
+        let payload_end = packet_header.payload_length as usize + HEADER_SIZE_BYTES;
+        if payload_end > datagram.len() {
+            events.push(DecodeEvent::Discarded {
+                reason: format!(
+                    "payload length {} exceeds datagram of {} bytes",
+                    packet_header.payload_length,
+                    datagram.len()
+                ),
+            });
+            return events;
+        }
+
+        //the same check as the UdpReceiver, a late packet is skipped before the loss check
+        let previous = self.current_sequence_number;
+        let received = packet_header.sequence_number;
+        let (order, lost) = check_sequence_number(
+            received,
+            &mut self.current_sequence_number,
+            self.stats_data.clone(),
+        );
+        match order {
+            SequenceOrder::Late => {
+                events.push(DecodeEvent::OutOfOrder {
+                    expected: previous.wrapping_add(1),
+                    received,
+                });
+                events.push(DecodeEvent::Fragment(packet_header));
+                events.push(DecodeEvent::Discarded {
+                    reason: format!("packet {received} received out of order"),
+                });
+                return events;
+            }
+            SequenceOrder::Restarted => {
+                events.push(DecodeEvent::Restarted { previous, received });
+                self.discard_partial_message(&mut events, "sender restarted");
+            }
+            SequenceOrder::InOrder => {}
+        }
+        if lost > 0 {
+            events.push(DecodeEvent::Gap {
+                expected: received.wrapping_sub(lost as u32),
+                received,
+                lost,
+            });
+            self.discard_partial_message(&mut events, "packetloss");
+        }
+
+        let payload = &datagram[HEADER_SIZE_BYTES..payload_end];
+        let message_type = packet_header.message_type;
+        let remaining_messages = packet_header.remaining_messages;
+        events.push(DecodeEvent::Fragment(packet_header));
+        self.state = match (self.state, message_type) {
+            (WaitingForData(total), MessageType::Data) => {
+                self.handle_data(&mut events, payload, remaining_messages, total)
+            }
+            (_, MessageType::Data) => {
+                events.push(DecodeEvent::Discarded {
+                    reason: "data fragment received without a DataFirst fragment".to_string(),
+                });
+                WaitingForFirstData
+            }
+            (_, MessageType::DataFirst) => {
+                self.discard_partial_message(&mut events, "new DataFirst fragment");
+                self.handle_data_first(&mut events, payload, remaining_messages)
+            }
+            (_, MessageType::StartUp) => {
+                self.discard_partial_message(&mut events, "startup message");
+                self.current_sequence_number = 0;
+                WaitingForFirstData
+            }
+            (state, MessageType::HeartBeat) => state,
+            (_, MessageType::Shutdown) => {
+                self.discard_partial_message(&mut events, "shutdown message");
+                WaitingForFirstData
+            }
+        };
+        events
+    }
+
+    fn handle_data_first(
+        &mut self,
+        events: &mut Vec<DecodeEvent>,
+        payload: &[u8],
+        remaining_messages: usize,
+    ) -> State {
+        if remaining_messages == 0 {
+            events.push(DecodeEvent::Message {
+                fragments: 1,
+                payload: payload.to_vec(),
+            });
+            return WaitingForFirstData;
+        }
+        //the first count of remaining messages + 1 = total amount of messages
+        let total = remaining_messages + 1;
+        self.fragments = vec![Vec::new(); total];
+        self.fragments[0] = payload.to_vec();
+        WaitingForData(total)
+    }
+
+    fn handle_data(
+        &mut self,
+        events: &mut Vec<DecodeEvent>,
+        payload: &[u8],
+        remaining_messages: usize,
+        total: usize,
+    ) -> State {
+        if remaining_messages + 1 >= total {
+            events.push(DecodeEvent::Discarded {
+                reason: format!(
+                    "data fragment claims {remaining_messages} remaining fragments of a {total} fragment message"
+                ),
+            });
+            return WaitingForFirstData;
+        }
+        self.fragments[total - remaining_messages - 1] = payload.to_vec();
+        if remaining_messages > 0 {
+            return WaitingForData(total);
+        }
+        let payload = self.fragments.concat();
+        self.fragments.clear();
+        events.push(DecodeEvent::Message {
+            fragments: total,
+            payload,
+        });
+        WaitingForFirstData
+    }
+
+    fn discard_partial_message(&mut self, events: &mut Vec<DecodeEvent>, reason: &str) {
+        if let WaitingForData(total) = self.state {
+            events.push(DecodeEvent::Discarded {
+                reason: format!("incomplete message of {total} fragments discarded: {reason}"),
+            });
+            self.fragments.clear();
+            self.state = WaitingForFirstData;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::reassembly::*;
+    use transport_udp::tx::write_packet_header;
+
+    ///Splits a message in datagrams the same way the UdpSender does.
+    fn split(message: &[u8], first_sequence_number: u32) -> Vec<Vec<u8>> {
+        let chunks: Vec<&[u8]> = message.chunks(MAX_PAYLOAD_SIZE_BYTES).collect();
+        let mut remaining = chunks.len() as u16;
+        let mut datagrams = Vec::new();
+        for (i, chunk) in chunks.iter().enumerate() {
+            let mut datagram = vec![0; chunk.len() + HEADER_SIZE_BYTES];
+            datagram[HEADER_SIZE_BYTES..].copy_from_slice(chunk);
+            let message_type = if i == 0 {
+                MessageType::DataFirst
+            } else {
+                MessageType::Data
+            };
+            write_packet_header(
+                &mut datagram,
+                first_sequence_number + i as u32,
+                message_type.as_u8(),
+                &mut remaining,
+            );
+            datagrams.push(datagram);
+        }
+        datagrams
+    }
+
+    fn messages(events: &[DecodeEvent]) -> Vec<&Vec<u8>> {
+        events
+            .iter()
+            .filter_map(|event| match event {
+                DecodeEvent::Message { payload, .. } => Some(payload),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reassemble_message_test() {
+        let message: Vec<u8> = (0..=255).cycle().take(MAX_PAYLOAD_SIZE_BYTES * 3 + 10).collect();
+        let mut reassembler = Reassembler::new();
+        let mut events = Vec::new();
+        for datagram in split(&message, 1) {
+            events.append(&mut reassembler.push(&datagram));
+        }
+        assert_eq!(messages(&events), vec![&message]);
+        assert_eq!(reassembler.lost_packets(), 0);
+    }
+
+    #[test]
+    fn gap_discards_message_test() {
+        let message: Vec<u8> = vec![7; MAX_PAYLOAD_SIZE_BYTES * 2 + 1];
+        let mut datagrams = split(&message, 1);
+        datagrams.remove(1);
+        let mut reassembler = Reassembler::new();
+        let mut events = Vec::new();
+        for datagram in datagrams {
+            events.append(&mut reassembler.push(&datagram));
+        }
+        assert!(messages(&events).is_empty());
+        assert!(events
+            .iter()
+            .any(|event| matches!(event, DecodeEvent::Gap { lost: 1, .. })));
+        assert_eq!(reassembler.lost_packets(), 1);
+    }
+
+    #[test]
+    fn out_of_order_test() {
+        let message: Vec<u8> = vec![7; MAX_PAYLOAD_SIZE_BYTES + 1];
+        let mut datagrams = split(&message, 1);
+        datagrams.swap(0, 1);
+        datagrams.extend(split(&message, 3));
+        let mut reassembler = Reassembler::new();
+        let mut events = Vec::new();
+        for datagram in datagrams {
+            events.append(&mut reassembler.push(&datagram));
+        }
+        //like the UdpReceiver the late packet does not move the sequence number back,
+        //the message after it follows without a gap
+        assert_eq!(messages(&events), vec![&message]);
+        assert!(events
+            .iter()
+            .any(|event| matches!(event, DecodeEvent::OutOfOrder { expected: 3, received: 1 })));
+        assert_eq!(reassembler.lost_packets(), 1);
+    }
+
+    #[test]
+    fn restarted_sender_test() {
+        let message: Vec<u8> = vec![7; MAX_PAYLOAD_SIZE_BYTES + 1];
+        let mut reassembler = Reassembler::new();
+        let mut events = Vec::new();
+        for datagram in split(&message, 5000).into_iter().chain(split(&message, 1)) {
+            events.append(&mut reassembler.push(&datagram));
+        }
+        assert_eq!(messages(&events), vec![&message, &message]);
+        assert!(events.iter().any(|event| matches!(
+            event,
+            DecodeEvent::Restarted {
+                previous: 5001,
+                received: 1
+            }
+        )));
+    }
+
+    #[test]
+    fn truncated_datagram_test() {
+        let mut reassembler = Reassembler::new();
+        let events = reassembler.push(&[3, 1, 0]);
+        assert!(matches!(events[0], DecodeEvent::Discarded { .. }));
+    }
+}
@@ -0,0 +1,273 @@
+// Copyright 2020 Ministerie van Defensie
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::errors::*;
+use bip_utils::async_bip::reserve_frame;
+use bip_utils::frame::FrameHeader;
+use bip_utils::BipBufferWriter;
+use framework_constants::*;
+use statistics_handler::StatsAllHandlers;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::Poll;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+use tokio::time::{Instant, Sleep};
+
+const RECONNECT_BACKOFF_MIN: Duration = Duration::from_millis(100);
+const RECONNECT_BACKOFF_MAX: Duration = Duration::from_secs(5);
+
+///A socket the reader receives from, with the state needed to reconnect it.
+struct Upstream {
+    path: String,
+    stream: Option<UnixStream>,
+    backoff: Duration,
+    next_attempt: Instant,
+}
+
+///The async version of the BufferedSocketReader, for handlers that run on tokio.
+pub struct AsyncBufferedSocketReader {
+    upstreams: Vec<Upstream>,
+    next_upstream: usize,
+    writer: BipBufferWriter,
+    stats_data: Option<Arc<StatsAllHandlers>>,
+}
+
+impl AsyncBufferedSocketReader {
+    ///Creates a new instance of AsyncBufferedSocketReader.
+    ///Completes when the socket has been created by a SocketWriter and the connection is accepted.
+    /// # Arguments
+    /// * `path` - The path of the socket the reader should connect to.
+    /// * `writer` - The BipBufferWriter used to send the received data to a bip_buffer.
+    pub async fn new(path: &str, writer: BipBufferWriter) -> Result<AsyncBufferedSocketReader> {
+        AsyncBufferedSocketReader::new_fan_in(&[path], writer).await
+    }
+
+    ///Creates a new instance of AsyncBufferedSocketReader that merges the elements of several sockets into one bip_buffer.
+    ///Completes when all sockets have been created by a SocketWriter and the connections are accepted.
+    /// # Arguments
+    /// * `paths` - The paths of the sockets the reader should connect to.
+    /// * `writer` - The BipBufferWriter used to send the received data to a bip_buffer.
+    pub async fn new_fan_in(
+        paths: &[&str],
+        writer: BipBufferWriter,
+    ) -> Result<AsyncBufferedSocketReader> {
+        let mut upstreams = Vec::with_capacity(paths.len());
+        for path in paths {
+            upstreams.push(Upstream {
+                path: path.to_string(),
+                stream: Some(wait_for_stream(path).await),
+                backoff: RECONNECT_BACKOFF_MIN,
+                next_attempt: Instant::now(),
+            });
+        }
+        Ok(AsyncBufferedSocketReader {
+            upstreams,
+            next_upstream: 0,
+            writer,
+            stats_data: None,
+        })
+    }
+
+    ///Reports the reconnects of this reader in the `reconnects` statistic.
+    /// # Arguments
+    /// * `stats_data` - The statistics of the handler.
+    pub fn with_stats(mut self, stats_data: Arc<StatsAllHandlers>) -> AsyncBufferedSocketReader {
+        self.stats_data = Some(stats_data);
+        self
+    }
+
+    ///Receives the next element and writes it to the bip_buffer, see `BufferedSocketReader::receive_data`.
+    pub async fn receive_data(&mut self) -> Result<usize> {
+        loop {
+            self.reconnect_due().await;
+            let upstream_index = match self.wait_for_readable().await {
+                Some(upstream_index) => upstream_index,
+                None => continue,
+            };
+            let upstream = &mut self.upstreams[upstream_index];
+            let received = match &mut upstream.stream {
+                Some(stream) => receive_frame(stream, &mut self.writer, &upstream.path).await?,
+                None => None,
+            };
+            match received {
+                Some(element_length) => {
+                    self.next_upstream = (upstream_index + 1) % self.upstreams.len();
+                    return Ok(element_length);
+                }
+                None => {
+                    upstream.stream = None;
+                    upstream.backoff = RECONNECT_BACKOFF_MIN;
+                    upstream.next_attempt = Instant::now() + upstream.backoff;
+                }
+            }
+        }
+    }
+
+    ///Waits until one of the connected sockets can be read, or until the next reconnect attempt is due.
+    ///Returns the index of the socket that can be read.
+    async fn wait_for_readable(&self) -> Option<usize> {
+        let mut reconnect_timer: Option<Pin<Box<Sleep>>> = self
+            .upstreams
+            .iter()
+            .filter(|upstream| upstream.stream.is_none())
+            .map(|upstream| upstream.next_attempt)
+            .min()
+            .map(|next_attempt| Box::pin(tokio::time::sleep_until(next_attempt)));
+        std::future::poll_fn(|cx| {
+            //start with the socket after the one that was read last, so a busy socket cannot starve the others
+            for offset in 0..self.upstreams.len() {
+                let upstream_index = (self.next_upstream + offset) % self.upstreams.len();
+                if let Some(stream) = &self.upstreams[upstream_index].stream {
+                    //an error is returned by the read that follows
+                    if stream.poll_read_ready(cx).is_ready() {
+                        return Poll::Ready(Some(upstream_index));
+                    }
+                }
+            }
+            match &mut reconnect_timer {
+                Some(timer) => timer.as_mut().poll(cx).map(|_| None),
+                None => Poll::Pending,
+            }
+        })
+        .await
+    }
+
+    ///Connects the sockets that lost their connection and are due for a new attempt, waiting longer after every failed attempt.
+    async fn reconnect_due(&mut self) {
+        let now = Instant::now();
+        for upstream in self
+            .upstreams
+            .iter_mut()
+            .filter(|upstream| upstream.stream.is_none() && upstream.next_attempt <= now)
+        {
+            match UnixStream::connect(&upstream.path).await {
+                Ok(stream) => {
+                    upstream.stream = Some(stream);
+                    if let Some(stats_data) = &self.stats_data {
+                        stats_data.reconnects.add(1);
+                    }
+                    log::info!("AsyncBufferedSocketReader reconnected to {}", upstream.path);
+                }
+                Err(e) => {
+                    upstream.backoff = std::cmp::min(upstream.backoff * 2, RECONNECT_BACKOFF_MAX);
+                    upstream.next_attempt = now + upstream.backoff;
+                    log::warn!(
+                        "AsyncBufferedSocketReader could not reconnect to {}, retrying in {:?}: {}",
+                        upstream.path,
+                        upstream.backoff,
+                        e
+                    );
+                }
+            }
+        }
+    }
+
+    ///Stops the AsyncBufferedSocketReader. Shuts down the underlying streams.
+    pub async fn stop(&mut self) -> Result<()> {
+        for stream in self
+            .upstreams
+            .iter_mut()
+            .filter_map(|upstream| upstream.stream.as_mut())
+        {
+            stream
+                .shutdown()
+                .await
+                .chain_err(|| "Error shutting down socket for AsyncBufferedSocketReader")?;
+        }
+        log::info!("AsyncBufferedSocketReader has been shutdown");
+        Ok(())
+    }
+}
+
+///Receives one frame, returns None when the connection was lost.
+async fn receive_frame(
+    stream: &mut UnixStream,
+    writer: &mut BipBufferWriter,
+    path: &str,
+) -> Result<Option<usize>> {
+    //receive frame header
+    let mut header_buffer = [0; FRAME_HEADER_LEN];
+    if let Err(e) = stream.read_exact(&mut header_buffer).await {
+        log::warn!(
+            "AsyncBufferedSocketReader lost its connection to {}: {}",
+            path,
+            e
+        );
+        return Ok(None);
+    }
+    let element_length = FrameHeader::from_bytes(&header_buffer)
+        .chain_err(|| "AsyncBufferedSocketReader received a malformed frame")?
+        .payload_length();
+
+    //reserve total buffer space
+    let frame_length = element_length + FRAME_HEADER_LEN;
+    let received_directly = match reserve_frame(writer, frame_length).await? {
+        Some(mut reservation) => {
+            reservation[..FRAME_HEADER_LEN].copy_from_slice(&header_buffer);
+            //receive data packet
+            let received = stream
+                .read_exact(&mut reservation[FRAME_HEADER_LEN..frame_length])
+                .await;
+            if received.is_err() {
+                //dropping the reservation would commit the partial element, forgetting it leaves the bip_buffer unchanged
+                std::mem::forget(reservation);
+            }
+            Some(received)
+        }
+        None => None,
+    };
+    let received = match received_directly {
+        Some(received) => received,
+        None => {
+            //the element does not fit, receive it anyway and leave it to the overflow policy of the writer
+            let mut frame = vec![0; frame_length];
+            frame[..FRAME_HEADER_LEN].copy_from_slice(&header_buffer);
+            let received = stream.read_exact(&mut frame[FRAME_HEADER_LEN..]).await;
+            if received.is_ok() {
+                writer.write_frame(&[&frame])?;
+            }
+            received
+        }
+    };
+    if let Err(e) = received {
+        log::warn!(
+            "AsyncBufferedSocketReader lost its connection to {}: {}",
+            path,
+            e
+        );
+        return Ok(None);
+    }
+    Ok(Some(element_length))
+}
+
+///Waits until the socket exists and a SocketWriter accepts the connection.
+async fn wait_for_stream(path: &str) -> UnixStream {
+    //wait for socket to exist
+    while !std::path::Path::new(path).exists() {
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        log::warn!("AsyncBufferedSocketReader: socketfile does not yet exist.");
+    }
+    //wait for accept() to be called on socket
+    loop {
+        if let Ok(stream) = UnixStream::connect(path).await {
+            return stream;
+        } else {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            log::warn!("AsyncBufferedSocketReader: accept has not yet been called on this socket");
+        }
+    }
+}
@@ -0,0 +1,232 @@
+// Copyright 2020 Ministerie van Defensie
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::buffered_socket_writer::{reader_gone, FanOutMode};
+use crate::errors::ErrorKind::UnixDomainSocketError;
+use crate::errors::*;
+use bip_utils::async_bip::{peek_frame_header, wait_for_data};
+use bip_utils::BipBufferReader;
+use framework_constants::FRAME_HEADER_LEN;
+use statistics_handler::StatsAllHandlers;
+use std::sync::Arc;
+use std::task::Poll;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{UnixListener, UnixStream};
+
+///The async version of the BufferedSocketWriter, for handlers that run on tokio.
+pub struct AsyncBufferedSocketWriter {
+    listener: UnixListener,
+    ///The connections to the readers, None for a reader that has gone away and has not reconnected yet.
+    streams: Vec<Option<UnixStream>>,
+    mode: FanOutMode,
+    next_stream: usize,
+    path: String,
+    stats_data: Option<Arc<StatsAllHandlers>>,
+}
+
+impl AsyncBufferedSocketWriter {
+    ///Creates a new instance of the AsyncBufferedSocketWriter and starts accepting connections to the socket.
+    ///Completes when a reader has connected.
+    /// # Arguments
+    /// * `path` - The path the socket is created on.
+    pub async fn start_listening(path: &str) -> Result<AsyncBufferedSocketWriter> {
+        AsyncBufferedSocketWriter::start_listening_fan_out(path, 1, FanOutMode::Broadcast).await
+    }
+
+    ///Creates a new instance of the AsyncBufferedSocketWriter that sends to several readers on the same socket.
+    ///Completes when `readers` readers have connected.
+    /// # Arguments
+    /// * `path` - The path the socket is created on.
+    /// * `readers` - The number of readers that connect to the socket.
+    /// * `mode` - Whether every element goes to all readers or to one of them.
+    pub async fn start_listening_fan_out(
+        path: &str,
+        readers: usize,
+        mode: FanOutMode,
+    ) -> Result<AsyncBufferedSocketWriter> {
+        if readers == 0 {
+            return Err(UnixDomainSocketError(format!(
+                "AsyncBufferedSocketWriter on {path} needs at least one reader"
+            ))
+            .into());
+        }
+        if let Err(e) = std::fs::remove_file(path) {
+            log::error!("Error removing socket file at {}: {}", path, e);
+        };
+        let listener =
+            UnixListener::bind(path).chain_err(|| "Error while binding unix domain socket path")?;
+        let mut streams = Vec::with_capacity(readers);
+        for _ in 0..readers {
+            streams.push(Some(accept_stream(&listener).await?));
+        }
+        Ok(AsyncBufferedSocketWriter {
+            listener,
+            streams,
+            mode,
+            next_stream: 0,
+            path: path.to_string(),
+            stats_data: None,
+        })
+    }
+
+    ///Reports the reconnects of this writer in the `reconnects` statistic.
+    /// # Arguments
+    /// * `stats_data` - The statistics of the handler.
+    pub fn with_stats(mut self, stats_data: Arc<StatsAllHandlers>) -> AsyncBufferedSocketWriter {
+        self.stats_data = Some(stats_data);
+        self
+    }
+
+    ///Sends the next element of the bip_buffer to the socket, see `BufferedSocketWriter::send_data`.
+    /// # Arguments
+    /// * `reader` - The BipBufferReader used to get data from the bip_buffer.
+    pub async fn send_data(&mut self, reader: &mut BipBufferReader) -> Result<usize> {
+        let frame_length = FRAME_HEADER_LEN + peek_frame_header(reader).await.payload_length();
+        wait_for_data(reader, frame_length).await;
+        self.accept_waiting_readers().await?;
+        let frame = &reader.valid()[..frame_length];
+        loop {
+            let sent = match self.mode {
+                FanOutMode::Broadcast => {
+                    let mut sent = false;
+                    for stream_index in 0..self.streams.len() {
+                        sent |= self.write_to(stream_index, frame).await;
+                    }
+                    sent
+                }
+                FanOutMode::RoundRobin => {
+                    let mut sent = false;
+                    for _ in 0..self.streams.len() {
+                        let stream_index = self.next_stream;
+                        self.next_stream = (stream_index + 1) % self.streams.len();
+                        if self.write_to(stream_index, frame).await {
+                            sent = true;
+                            break;
+                        }
+                    }
+                    sent
+                }
+            };
+            if sent {
+                break;
+            }
+            log::warn!(
+                "AsyncBufferedSocketWriter lost all readers on {}. Waiting for a new connection.",
+                self.path
+            );
+            let stream = accept_stream(&self.listener).await?;
+            self.add_reader(stream);
+        }
+        reader.consume(frame_length);
+        Ok(frame_length)
+    }
+
+    ///Writes the frame to one reader, a reader that has gone away is marked as such.
+    /// # Returns
+    /// * `bool` - true when the reader received the frame.
+    async fn write_to(&mut self, stream_index: usize, frame: &[u8]) -> bool {
+        let stream = match &mut self.streams[stream_index] {
+            Some(stream) => stream,
+            None => return false,
+        };
+        match stream.write_all(frame).await {
+            Ok(()) => true,
+            Err(e) => {
+                log::warn!(
+                    "AsyncBufferedSocketWriter lost reader {} on {}: {}. The other readers are served until it reconnects.",
+                    stream_index,
+                    self.path,
+                    e
+                );
+                self.streams[stream_index] = None;
+                false
+            }
+        }
+    }
+
+    ///Accepts the readers that have connected since the last element, without waiting.
+    async fn accept_waiting_readers(&mut self) -> Result<()> {
+        loop {
+            let accepted =
+                std::future::poll_fn(|cx| Poll::Ready(self.listener.poll_accept(cx))).await;
+            match accepted {
+                Poll::Ready(Ok((stream, address))) => {
+                    log::info!("Client connected from: {:?}", address);
+                    self.add_reader(stream);
+                }
+                Poll::Ready(Err(e)) => {
+                    return Err(Error::with_chain(e, "Failed to accept incoming connection"))
+                }
+                Poll::Pending => return Ok(()),
+            }
+        }
+    }
+
+    ///Replaces the stream of a reader that has gone away with a new connection, see `BufferedSocketWriter`.
+    ///A connection beyond the number of readers is closed.
+    fn add_reader(&mut self, stream: UnixStream) {
+        if !self.streams.iter().any(Option::is_none) {
+            for stream_slot in &mut self.streams {
+                if stream_slot.as_ref().is_some_and(reader_gone) {
+                    *stream_slot = None;
+                }
+            }
+        }
+        match self.streams.iter().position(Option::is_none) {
+            Some(stream_index) => {
+                self.streams[stream_index] = Some(stream);
+                if let Some(stats_data) = &self.stats_data {
+                    stats_data.reconnects.add(1);
+                }
+                log::info!(
+                    "AsyncBufferedSocketWriter reconnected reader {} on {}",
+                    stream_index,
+                    self.path
+                );
+            }
+            None => log::warn!(
+                "AsyncBufferedSocketWriter on {} already has {} readers, closing the new connection",
+                self.path,
+                self.streams.len()
+            ),
+        }
+    }
+
+    ///Shuts down the connections and removes the socket file.
+    pub async fn stop(&mut self) {
+        for stream in self.streams.iter_mut().flatten() {
+            if let Err(e) = stream.shutdown().await {
+                log::warn!("{:?}", e);
+            }
+        }
+        match std::fs::remove_file(&self.path) {
+            Ok(_) => {
+                log::info!("Cleanup succesfull. Shutdown complete.");
+            }
+            Err(e) => {
+                log::warn!("Error while cleaning up: {}. Shutdown complete.", e);
+            }
+        };
+    }
+}
+
+async fn accept_stream(listener: &UnixListener) -> Result<UnixStream> {
+    match listener.accept().await {
+        Ok((stream, address)) => {
+            log::info!("Client connected from: {:?}", address);
+            Ok(stream)
+        }
+        Err(e) => Err(Error::with_chain(e, "Failed to accept incoming connection")),
+    }
+}
@@ -13,15 +13,33 @@
 // limitations under the License.
 
 use crate::errors::*;
+use bip_utils::frame::FrameHeader;
+use bip_utils::BipBufferWriter;
 use framework_constants::*;
-use spsc_bip_buffer::BipBufferWriter;
+use statistics_handler::StatsAllHandlers;
 use std::io::Read;
 use std::net::Shutdown;
+use std::os::unix::io::AsRawFd;
 use std::os::unix::net::UnixStream;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const RECONNECT_BACKOFF_MIN: Duration = Duration::from_millis(100);
+const RECONNECT_BACKOFF_MAX: Duration = Duration::from_secs(5);
+
+///A socket the reader receives from, with the state needed to reconnect it.
+struct Upstream {
+    path: String,
+    stream: Option<UnixStream>,
+    backoff: Duration,
+    next_attempt: Instant,
+}
 
 pub struct BufferedSocketReader {
-    stream: UnixStream,
+    upstreams: Vec<Upstream>,
+    next_upstream: usize,
     writer: BipBufferWriter,
+    stats_data: Option<Arc<StatsAllHandlers>>,
 }
 
 impl BufferedSocketReader {
@@ -31,66 +49,262 @@ impl BufferedSocketReader {
     /// * `path` - The path of the socket the reader should connect to.
     /// * `writer` - The BipBufferWriter used to send the received data to a bip_buffer.
     pub fn new(path: &str, writer: BipBufferWriter) -> Result<BufferedSocketReader> {
-        //wait for socket to exist
-        while !std::path::Path::new(path).exists() {
-            std::thread::sleep(std::time::Duration::from_secs(2));
-            log::warn!("BufferedSocketReader: socketfile does not yet exist.");
-        }
-        //wait for accept() to be called on socket
-        loop {
-            if let Ok(stream) = UnixStream::connect(path) {
-                stream
-                    .set_nonblocking(false)
-                    .chain_err(|| "non blocking for BufferedSocketReader could not be set")?;
-                stream
-                    .set_write_timeout(None)
-                    .chain_err(|| "write timeout for BufferedSocketReader could not be set!")?;
-                return Ok(BufferedSocketReader { stream, writer });
-            } else {
-                std::thread::sleep(std::time::Duration::from_millis(200));
-                log::warn!("BufferedSocketReader: accept has not yet been called on this socket");
-            }
-        }
+        BufferedSocketReader::new_fan_in(&[path], writer)
+    }
+
+    ///Creates a new instance of BufferedSocketReader that merges the elements of several sockets into one bip_buffer.
+    ///This function will block until all sockets have been created by a SocketWriter.
+    /// # Arguments
+    /// * `paths` - The paths of the sockets the reader should connect to.
+    /// * `writer` - The BipBufferWriter used to send the received data to a bip_buffer.
+    pub fn new_fan_in(paths: &[&str], writer: BipBufferWriter) -> Result<BufferedSocketReader> {
+        let upstreams = paths
+            .iter()
+            .map(|path| Upstream {
+                path: path.to_string(),
+                stream: Some(wait_for_stream(path)),
+                backoff: RECONNECT_BACKOFF_MIN,
+                next_attempt: Instant::now(),
+            })
+            .collect();
+        Ok(BufferedSocketReader {
+            upstreams,
+            next_upstream: 0,
+            writer,
+            stats_data: None,
+        })
+    }
+
+    ///Reports the reconnects of this reader in the `reconnects` statistic.
+    /// # Arguments
+    /// * `stats_data` - The statistics of the handler.
+    pub fn with_stats(mut self, stats_data: Arc<StatsAllHandlers>) -> BufferedSocketReader {
+        self.stats_data = Some(stats_data);
+        self
     }
 
     ///This function fetches data from the socket.
     ///This data is then sent to the bip_buffer using the bipBufferWriter.
     ///This function will block until space is available in the bip_buffer.
+    ///When the connection is lost the reader reconnects with backoff, an element that was only partly received is discarded.
+    ///With several sockets the next element is taken from the first socket that has data, the sockets take turns.
+    ///A socket that lost its connection does not hold up the others.
+    ///A malformed frame header results in an error, nothing is reserved for it.
     pub fn receive_data(&mut self) -> Result<usize> {
-        //receive length field
-        let mut exact_length_buffer = [0; BIP_BUFFER_LEN_FIELD_LEN];
-        self.stream
-            .read_exact(&mut exact_length_buffer)
-            .chain_err(|| "Error reading exact when reading element length from stream")?;
-        let element_length = usize::from_le_bytes(exact_length_buffer);
-
-        //reserve total buffer space
-        let mut reservation = loop {
-            match self
-                .writer
-                .reserve(element_length + BIP_BUFFER_LEN_FIELD_LEN)
-            {
-                None => std::thread::sleep(std::time::Duration::from_millis(20)),
-                Some(reservation) => break reservation,
+        loop {
+            self.reconnect_due();
+            let upstream_index = match self.wait_for_readable()? {
+                Some(upstream_index) => upstream_index,
+                None => continue,
+            };
+            let upstream = &mut self.upstreams[upstream_index];
+            let received = match &mut upstream.stream {
+                Some(stream) => receive_frame(stream, &mut self.writer, &upstream.path)?,
+                None => None,
+            };
+            match received {
+                Some(element_length) => {
+                    self.next_upstream = (upstream_index + 1) % self.upstreams.len();
+                    return Ok(element_length);
+                }
+                None => {
+                    upstream.stream = None;
+                    upstream.backoff = RECONNECT_BACKOFF_MIN;
+                    upstream.next_attempt = Instant::now() + upstream.backoff;
+                }
             }
+        }
+    }
+
+    ///Waits until one of the connected sockets can be read, or until the next reconnect attempt is due.
+    ///Returns the index of the socket that can be read.
+    fn wait_for_readable(&self) -> Result<Option<usize>> {
+        let connected = self
+            .upstreams
+            .iter()
+            .enumerate()
+            .filter_map(|(index, upstream)| upstream.stream.as_ref().map(|stream| (index, stream)))
+            .collect::<Vec<(usize, &UnixStream)>>();
+        let mut poll_fds = connected
+            .iter()
+            .map(|(_, stream)| libc::pollfd {
+                fd: stream.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            })
+            .collect::<Vec<libc::pollfd>>();
+        //block without a timeout when there is nothing to reconnect
+        let timeout_ms = self
+            .upstreams
+            .iter()
+            .filter(|upstream| upstream.stream.is_none())
+            .map(|upstream| {
+                upstream
+                    .next_attempt
+                    .saturating_duration_since(Instant::now())
+                    .as_millis() as libc::c_int
+            })
+            .min()
+            .unwrap_or(-1);
+        //safety: poll_fds holds poll_fds.len() initialized pollfd structs
+        let ready = unsafe {
+            libc::poll(
+                poll_fds.as_mut_ptr(),
+                poll_fds.len() as libc::nfds_t,
+                timeout_ms,
+            )
         };
+        if ready < 0 {
+            let error = std::io::Error::last_os_error();
+            if error.kind() == std::io::ErrorKind::Interrupted {
+                return Ok(None);
+            }
+            return Err(Error::with_chain(
+                error,
+                "BufferedSocketReader could not wait for its sockets",
+            ));
+        }
+        //start with the socket after the one that was read last, so a busy socket cannot starve the others
+        Ok((0..self.upstreams.len())
+            .map(|offset| (self.next_upstream + offset) % self.upstreams.len())
+            .find(|upstream_index| {
+                connected
+                    .iter()
+                    .zip(&poll_fds)
+                    .any(|((index, _), poll_fd)| index == upstream_index && poll_fd.revents != 0)
+            }))
+    }
 
-        reservation[..BIP_BUFFER_LEN_FIELD_LEN].copy_from_slice(&exact_length_buffer);
-        let element_reservation =
-            &mut reservation[BIP_BUFFER_LEN_FIELD_LEN..element_length + BIP_BUFFER_LEN_FIELD_LEN];
-        //receive data packet
-        self.stream
-            .read_exact(element_reservation)
-            .chain_err(|| "Error reading exact when reading element from stream")?;
-        Ok(element_length)
+    ///Connects the sockets that lost their connection and are due for a new attempt, waiting longer after every failed attempt.
+    fn reconnect_due(&mut self) {
+        let now = Instant::now();
+        for upstream in self
+            .upstreams
+            .iter_mut()
+            .filter(|upstream| upstream.stream.is_none() && upstream.next_attempt <= now)
+        {
+            match connect_stream(&upstream.path) {
+                Ok(stream) => {
+                    upstream.stream = Some(stream);
+                    if let Some(stats_data) = &self.stats_data {
+                        stats_data.reconnects.add(1);
+                    }
+                    log::info!("BufferedSocketReader reconnected to {}", upstream.path);
+                }
+                Err(e) => {
+                    upstream.backoff = std::cmp::min(upstream.backoff * 2, RECONNECT_BACKOFF_MAX);
+                    upstream.next_attempt = now + upstream.backoff;
+                    log::warn!(
+                        "BufferedSocketReader could not reconnect to {}, retrying in {:?}: {}",
+                        upstream.path,
+                        upstream.backoff,
+                        e
+                    );
+                }
+            }
+        }
     }
-    ///Stops the BufferedSocketReader. Calls Shutdown::Both on the underlying stream.
+
+    ///Stops the BufferedSocketReader. Calls Shutdown::Both on the underlying streams.
     pub fn stop(&self) -> Result<()> {
-        log::warn!("Error shutting down socket for BufferedSocketReader");
-        self.stream
-            .shutdown(Shutdown::Both)
-            .chain_err(|| "Error shutting down socket for BufferedSocketReader")?;
+        for stream in self
+            .upstreams
+            .iter()
+            .filter_map(|upstream| upstream.stream.as_ref())
+        {
+            stream
+                .shutdown(Shutdown::Both)
+                .chain_err(|| "Error shutting down socket for BufferedSocketReader")?;
+        }
         log::info!("BufferedSocketReader has been shutdown");
         Ok(())
     }
 }
+
+///Receives one frame, returns None when the connection was lost.
+fn receive_frame(
+    stream: &mut UnixStream,
+    writer: &mut BipBufferWriter,
+    path: &str,
+) -> Result<Option<usize>> {
+    //receive frame header
+    let mut header_buffer = [0; FRAME_HEADER_LEN];
+    if let Err(e) = stream.read_exact(&mut header_buffer) {
+        log::warn!(
+            "BufferedSocketReader lost its connection to {}: {}",
+            path,
+            e
+        );
+        return Ok(None);
+    }
+    let element_length = FrameHeader::from_bytes(&header_buffer)
+        .chain_err(|| "BufferedSocketReader received a malformed frame")?
+        .payload_length();
+
+    //reserve total buffer space
+    let frame_length = element_length + FRAME_HEADER_LEN;
+    let received_directly = match writer.reserve_frame(frame_length)? {
+        Some(mut reservation) => {
+            reservation[..FRAME_HEADER_LEN].copy_from_slice(&header_buffer);
+            //receive data packet
+            let received = stream.read_exact(&mut reservation[FRAME_HEADER_LEN..frame_length]);
+            if received.is_err() {
+                //dropping the reservation would commit the partial element, forgetting it leaves the bip_buffer unchanged
+                std::mem::forget(reservation);
+            }
+            Some(received)
+        }
+        None => None,
+    };
+    let received = match received_directly {
+        Some(received) => received,
+        None => {
+            //the element does not fit, receive it anyway and leave it to the overflow policy of the writer
+            let mut frame = vec![0; frame_length];
+            frame[..FRAME_HEADER_LEN].copy_from_slice(&header_buffer);
+            let received = stream.read_exact(&mut frame[FRAME_HEADER_LEN..]);
+            if received.is_ok() {
+                writer.write_frame(&[&frame])?;
+            }
+            received
+        }
+    };
+    if let Err(e) = received {
+        log::warn!(
+            "BufferedSocketReader lost its connection to {}: {}",
+            path,
+            e
+        );
+        return Ok(None);
+    }
+    Ok(Some(element_length))
+}
+
+///Waits until the socket exists and a SocketWriter accepts the connection.
+fn wait_for_stream(path: &str) -> UnixStream {
+    //wait for socket to exist
+    while !std::path::Path::new(path).exists() {
+        std::thread::sleep(std::time::Duration::from_secs(2));
+        log::warn!("BufferedSocketReader: socketfile does not yet exist.");
+    }
+    //wait for accept() to be called on socket
+    loop {
+        if let Ok(stream) = connect_stream(path) {
+            return stream;
+        } else {
+            std::thread::sleep(std::time::Duration::from_millis(200));
+            log::warn!("BufferedSocketReader: accept has not yet been called on this socket");
+        }
+    }
+}
+
+fn connect_stream(path: &str) -> Result<UnixStream> {
+    let stream = UnixStream::connect(path).chain_err(|| "Failed to connect to socket")?;
+    stream
+        .set_nonblocking(false)
+        .chain_err(|| "non blocking for BufferedSocketReader could not be set")?;
+    stream
+        .set_write_timeout(None)
+        .chain_err(|| "write timeout for BufferedSocketReader could not be set!")?;
+    Ok(stream)
+}
@@ -12,60 +12,264 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::errors::ErrorKind::UnixDomainSocketError;
 use crate::errors::*;
-use bip_utils::get_element_length;
+use bip_utils::peek_frame_header;
 use bip_utils::wait_for_data;
-use framework_constants::BIP_BUFFER_LEN_FIELD_LEN;
-use spsc_bip_buffer::BipBufferReader;
+use bip_utils::BipBufferReader;
+use framework_constants::FRAME_HEADER_LEN;
+use statistics_handler::StatsAllHandlers;
 use std::io::Write;
 use std::net::Shutdown;
+use std::os::unix::io::AsRawFd;
 use std::os::unix::net::{UnixListener, UnixStream};
+use std::str::FromStr;
+use std::sync::Arc;
+
+///How a BufferedSocketWriter with several readers distributes the elements.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FanOutMode {
+    ///Every reader receives every element.
+    Broadcast,
+    ///Every element is sent to one reader, the readers take turns.
+    RoundRobin,
+}
+
+impl FromStr for FanOutMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<FanOutMode> {
+        match s {
+            "broadcast" => Ok(FanOutMode::Broadcast),
+            "round_robin" => Ok(FanOutMode::RoundRobin),
+            _ => Err(UnixDomainSocketError(format!(
+                "unknown fan out mode {s}, can be \"broadcast\" or \"round_robin\""
+            ))
+            .into()),
+        }
+    }
+}
 
 pub struct BufferedSocketWriter {
-    stream: UnixStream,
+    listener: UnixListener,
+    ///The connections to the readers, None for a reader that has gone away and has not reconnected yet.
+    streams: Vec<Option<UnixStream>>,
+    mode: FanOutMode,
+    next_stream: usize,
     path: String,
+    stats_data: Option<Arc<StatsAllHandlers>>,
 }
 
 impl BufferedSocketWriter {
     ///Creates a new instance of the SocketWriter and starts accepting connections to the socket.
+    ///This function will block until a reader has connected.
     /// # Arguments
     /// * `path` - The path the socket is created on.
     pub fn start_listening(path: &str) -> Result<BufferedSocketWriter> {
+        BufferedSocketWriter::start_listening_fan_out(path, 1, FanOutMode::Broadcast)
+    }
+
+    ///Creates a new instance of the SocketWriter that sends to several readers on the same socket.
+    ///This function will block until `readers` readers have connected.
+    /// # Arguments
+    /// * `path` - The path the socket is created on.
+    /// * `readers` - The number of readers that connect to the socket.
+    /// * `mode` - Whether every element goes to all readers or to one of them.
+    pub fn start_listening_fan_out(
+        path: &str,
+        readers: usize,
+        mode: FanOutMode,
+    ) -> Result<BufferedSocketWriter> {
+        if readers == 0 {
+            return Err(UnixDomainSocketError(format!(
+                "BufferedSocketWriter on {path} needs at least one reader"
+            ))
+            .into());
+        }
         if let Err(e) = std::fs::remove_file(path) {
             log::error!("Error removing socket file at {}: {}", path, e);
         };
+        let listener =
+            UnixListener::bind(path).chain_err(|| "Error while binding unix domain socket path")?;
+        let streams = (0..readers)
+            .map(|_| accept_stream(&listener).map(Some))
+            .collect::<Result<Vec<Option<UnixStream>>>>()?;
+        //readers that reconnect later are accepted while sending, without waiting for them
+        listener
+            .set_nonblocking(true)
+            .chain_err(|| "non blocking for the BufferedSocketWriter listener could not be set!")?;
         Ok(BufferedSocketWriter {
-            stream: create_stream(path)?,
+            listener,
+            streams,
+            mode,
+            next_stream: 0,
             path: path.to_string(),
+            stats_data: None,
         })
     }
+
+    ///Reports the reconnects of this writer in the `reconnects` statistic.
+    /// # Arguments
+    /// * `stats_data` - The statistics of the handler.
+    pub fn with_stats(mut self, stats_data: Arc<StatsAllHandlers>) -> BufferedSocketWriter {
+        self.stats_data = Some(stats_data);
+        self
+    }
+
     ///Used to send data to the socket. The data that is sent is read using `reader`.
+    ///With several readers the element is written to all of them or to the next one, depending on the FanOutMode.
+    ///A reader that has gone away is skipped and the other readers are served, until it connects again.
+    ///When every reader has gone away, this function blocks until one connects and the element stays in the bip_buffer until then.
     /// # Arguments
     /// * `reader` - The BipBufferReader used to get data from the bip_buffer.
-    #[allow(clippy::unused_io_amount)]
     pub fn send_data(&mut self, reader: &mut BipBufferReader) -> Result<usize> {
-        //read a usize from the buffer
-        let element_length: usize = get_element_length(reader);
+        //read the frame header from the buffer, it is consumed together with the element
+        let frame_length = FRAME_HEADER_LEN + peek_frame_header(reader).payload_length();
         //read data from the buffer
-        wait_for_data(reader, element_length);
-        let incoming = reader.valid();
-        self.stream
-            .write(&element_length.to_le_bytes())
-            .chain_err(|| "Buffered Socket Writer could not send to socket")?;
-        self.stream
-            .write(&incoming[..element_length])
-            .chain_err(|| "Buffered Socket Writer could not send to socket")?;
-        reader.consume(element_length);
-        Ok(element_length + BIP_BUFFER_LEN_FIELD_LEN)
+        wait_for_data(reader, frame_length);
+        self.accept_waiting_readers()?;
+        let frame = &reader.valid()[..frame_length];
+        loop {
+            let sent = match self.mode {
+                FanOutMode::Broadcast => self.broadcast(frame),
+                FanOutMode::RoundRobin => self.send_to_next(frame),
+            };
+            if sent {
+                break;
+            }
+            log::warn!(
+                "BufferedSocketWriter lost all readers on {}. Waiting for a new connection.",
+                self.path
+            );
+            self.wait_for_reader()?;
+        }
+        reader.consume(frame_length);
+        Ok(frame_length)
     }
 
-    pub fn stop(&self) {
-        match self.stream.shutdown(Shutdown::Both) {
-            Ok(_) => {
-                log::info!("BufferedSocketWriter has been shutdown");
+    ///Writes the frame to every connected reader.
+    /// # Returns
+    /// * `bool` - true when at least one reader received the frame.
+    fn broadcast(&mut self, frame: &[u8]) -> bool {
+        let mut sent = false;
+        for stream_index in 0..self.streams.len() {
+            sent |= self.write_to(stream_index, frame);
+        }
+        sent
+    }
+
+    ///Writes the frame to the next connected reader.
+    /// # Returns
+    /// * `bool` - true when a reader received the frame.
+    fn send_to_next(&mut self, frame: &[u8]) -> bool {
+        for _ in 0..self.streams.len() {
+            let stream_index = self.next_stream;
+            self.next_stream = (stream_index + 1) % self.streams.len();
+            if self.write_to(stream_index, frame) {
+                return true;
             }
+        }
+        false
+    }
+
+    ///Writes the frame to one reader, a reader that has gone away is marked as such.
+    /// # Returns
+    /// * `bool` - true when the reader received the frame.
+    fn write_to(&mut self, stream_index: usize, frame: &[u8]) -> bool {
+        let stream = match &mut self.streams[stream_index] {
+            Some(stream) => stream,
+            None => return false,
+        };
+        match stream.write_all(frame) {
+            Ok(()) => true,
             Err(e) => {
-                log::warn!("{:?}", e);
+                log::warn!(
+                    "BufferedSocketWriter lost reader {} on {}: {}. The other readers are served until it reconnects.",
+                    stream_index,
+                    self.path,
+                    e
+                );
+                if let Err(e) = stream.shutdown(Shutdown::Both) {
+                    log::debug!("{:?}", e);
+                }
+                self.streams[stream_index] = None;
+                false
+            }
+        }
+    }
+
+    ///Accepts the readers that have connected since the last element, without waiting.
+    fn accept_waiting_readers(&mut self) -> Result<()> {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, address)) => {
+                    log::info!("Client connected from: {:?}", address);
+                    self.add_reader(prepare_stream(stream)?);
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(Error::with_chain(e, "Failed to accept incoming connection")),
+            }
+        }
+    }
+
+    ///Waits until a reader connects, used when every reader has gone away.
+    fn wait_for_reader(&mut self) -> Result<()> {
+        self.listener
+            .set_nonblocking(false)
+            .chain_err(|| "blocking for the BufferedSocketWriter listener could not be set!")?;
+        let stream = accept_stream(&self.listener);
+        self.listener
+            .set_nonblocking(true)
+            .chain_err(|| "non blocking for the BufferedSocketWriter listener could not be set!")?;
+        self.add_reader(stream?);
+        Ok(())
+    }
+
+    ///Replaces the stream of a reader that has gone away with a new connection.
+    ///A reader that reconnects before the writer noticed it had gone away takes the place of its closed stream.
+    ///A connection beyond the number of readers is closed.
+    fn add_reader(&mut self, stream: UnixStream) {
+        if !self.streams.iter().any(Option::is_none) {
+            for stream_slot in &mut self.streams {
+                if stream_slot.as_ref().is_some_and(reader_gone) {
+                    *stream_slot = None;
+                }
+            }
+        }
+        match self.streams.iter().position(Option::is_none) {
+            Some(stream_index) => {
+                self.streams[stream_index] = Some(stream);
+                if let Some(stats_data) = &self.stats_data {
+                    stats_data.reconnects.add(1);
+                }
+                log::info!(
+                    "BufferedSocketWriter reconnected reader {} on {}",
+                    stream_index,
+                    self.path
+                );
+            }
+            None => {
+                log::warn!(
+                    "BufferedSocketWriter on {} already has {} readers, closing the new connection",
+                    self.path,
+                    self.streams.len()
+                );
+                if let Err(e) = stream.shutdown(Shutdown::Both) {
+                    log::debug!("{:?}", e);
+                }
+            }
+        }
+    }
+
+    pub fn stop(&self) {
+        for stream in self.streams.iter().flatten() {
+            match stream.shutdown(Shutdown::Both) {
+                Ok(_) => {
+                    log::info!("BufferedSocketWriter has been shutdown");
+                }
+                Err(e) => {
+                    log::warn!("{:?}", e);
+                }
             }
         }
         match std::fs::remove_file(&self.path) {
@@ -79,24 +283,38 @@ impl BufferedSocketWriter {
     }
 }
 
-fn create_stream(path: &str) -> Result<UnixStream> {
-    match UnixListener::bind(path) {
-        Ok(listener) => match listener.accept() {
-            Ok((stream, address)) => {
-                log::info!("Client connected from: {:?}", address);
-                stream
-                    .set_nonblocking(false)
-                    .chain_err(|| "non blocking for BufferedSocketWriter could not be set!")?;
-                stream
-                    .set_read_timeout(None)
-                    .chain_err(|| "read timeout for BufferedSocketWriter could not be set!")?;
-                Ok(stream)
-            }
-            Err(e) => Err(Error::with_chain(e, "Failed to accept incoming connection")),
-        },
-        Err(e) => Err(Error::with_chain(
-            e,
-            "Error while binding unix domain socket path",
-        )),
+fn accept_stream(listener: &UnixListener) -> Result<UnixStream> {
+    match listener.accept() {
+        Ok((stream, address)) => {
+            log::info!("Client connected from: {:?}", address);
+            prepare_stream(stream)
+        }
+        Err(e) => Err(Error::with_chain(e, "Failed to accept incoming connection")),
     }
 }
+
+///Returns true when the reader has closed the stream. Readers never write to the socket, so only the end of the stream can be read.
+pub(crate) fn reader_gone(stream: &impl AsRawFd) -> bool {
+    let mut byte = 0u8;
+    let received = unsafe {
+        libc::recv(
+            stream.as_raw_fd(),
+            &mut byte as *mut u8 as *mut libc::c_void,
+            1,
+            libc::MSG_PEEK | libc::MSG_DONTWAIT,
+        )
+    };
+    received == 0
+        || (received < 0
+            && std::io::Error::last_os_error().kind() != std::io::ErrorKind::WouldBlock)
+}
+
+fn prepare_stream(stream: UnixStream) -> Result<UnixStream> {
+    stream
+        .set_nonblocking(false)
+        .chain_err(|| "non blocking for BufferedSocketWriter could not be set!")?;
+    stream
+        .set_read_timeout(None)
+        .chain_err(|| "read timeout for BufferedSocketWriter could not be set!")?;
+    Ok(stream)
+}
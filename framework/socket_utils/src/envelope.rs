@@ -0,0 +1,223 @@
+// Copyright 2020 Ministerie van Defensie
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::errors::*;
+use bip_utils::frame::{FrameHeader, FrameKind};
+use bip_utils::{read_frame_header, wait_for_data, write_frame_to_bip_buffer};
+use bip_utils::{BipBufferReader, BipBufferWriter};
+use serde::{Deserialize, Serialize};
+use statistics_handler::{Counter, Histogram, StatsAllHandlers};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+///The content type of data without a more specific type.
+pub const CONTENT_TYPE_OCTET_STREAM: &str = "application/octet-stream";
+
+///The Envelope carries the data of a message together with its metadata.
+///Protocol handlers create an envelope when data enters the OSDD, filters and the transport pass it on,
+///so every handler in a chain can use the metadata without knowing the format of the data.
+///
+///Envelopes are sent as frames of kind FrameKind::Envelope.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct Envelope {
+    ///The moment the data entered the OSDD, in nanoseconds since the unix epoch.
+    pub ingest_timestamp_ns: u64,
+    ///The name of the handler that created the envelope.
+    pub source: String,
+    ///The type of the data, for example "application/octet-stream".
+    pub content_type: String,
+    ///A random ID that identifies this message in logging along the chain.
+    pub trace_id: u64,
+    ///Free-form metadata, for example the Kafka topic of the data.
+    pub headers: BTreeMap<String, String>,
+    ///The data itself.
+    pub payload: Vec<u8>,
+}
+
+impl Envelope {
+    ///Creates a new envelope, stamped with the current time and a new trace ID.
+    /// # Arguments
+    /// * `source` - The name of the handler creating the envelope.
+    /// * `content_type` - The type of the data.
+    /// * `payload` - The data.
+    pub fn new(source: &str, content_type: &str, payload: &[u8]) -> Envelope {
+        Envelope {
+            ingest_timestamp_ns: now_ns(),
+            source: source.to_string(),
+            content_type: content_type.to_string(),
+            trace_id: rand::random(),
+            headers: BTreeMap::new(),
+            payload: payload.to_vec(),
+        }
+    }
+
+    ///Adds a header to the envelope.
+    pub fn with_header(mut self, key: &str, value: &str) -> Envelope {
+        self.headers.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    ///Returns the value of a header, if it is present.
+    pub fn header(&self, key: &str) -> Option<&str> {
+        self.headers.get(key).map(String::as_str)
+    }
+
+    ///Returns the time since the data entered the OSDD, measured with the clock of this host.
+    ///None when the envelope has no ingest timestamp, or when the timestamp lies in the future
+    ///because the clocks of the proxies differ.
+    pub fn age(&self) -> Option<Duration> {
+        now_ns()
+            .checked_sub(self.ingest_timestamp_ns)
+            .filter(|_| self.ingest_timestamp_ns != 0)
+            .map(Duration::from_nanos)
+    }
+
+    ///Serializes the envelope.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        bincode::serialize(self).chain_err(|| "Failed serializing Envelope")
+    }
+
+    ///Deserializes an envelope.
+    pub fn from_bytes(buffer: &[u8]) -> Result<Envelope> {
+        bincode::deserialize(buffer).chain_err(|| "Failed deserializing Envelope")
+    }
+}
+
+///Returns the current time in nanoseconds since the unix epoch.
+pub fn now_ns() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_nanos() as u64)
+        .unwrap_or_default()
+}
+
+///Records the end-to-end latency of the envelopes an egress handler delivers, the time since the data entered the OSDD.
+///The latency is the timer `latency` in the statistics of the handler, so there is one histogram per chain.
+///Envelopes stamped in the future, because the clocks of the proxies differ, are counted in `latency.clock_skew`.
+pub struct LatencyRecorder {
+    latency: Arc<Histogram>,
+    clock_skew: Arc<Counter>,
+}
+
+impl LatencyRecorder {
+    ///Registers the latency statistics of a handler.
+    pub fn new(stats_data: &StatsAllHandlers) -> LatencyRecorder {
+        LatencyRecorder {
+            latency: stats_data.register_timer("latency"),
+            clock_skew: stats_data.registry.counter("latency.clock_skew", &[]),
+        }
+    }
+
+    ///Records the age of a delivered envelope. Data without an ingest timestamp is not recorded.
+    pub fn record(&self, envelope: &Envelope) {
+        if envelope.ingest_timestamp_ns == 0 {
+            return;
+        }
+        match envelope.age() {
+            Some(age) => self.latency.record_duration(age),
+            None => self.clock_skew.add(1),
+        }
+    }
+}
+
+///This function is used to write an envelope to the bip_buffer as a frame of kind FrameKind::Envelope.
+/// # Arguments
+/// * `writer` - The bipBufferWriter used to write to the bip_buffer.
+/// * `envelope` - The envelope to write.
+/// # Returns
+/// * `usize` - The size of the serialized envelope in bytes.
+pub fn write_envelope_to_bip_buffer(
+    writer: &mut BipBufferWriter,
+    envelope: &Envelope,
+) -> Result<usize> {
+    let bytes = envelope.to_bytes()?;
+    if bytes.len() > framework_constants::MAX_FRAME_PAYLOAD_LEN {
+        return Err(format!(
+            "Envelope of {} bytes exceeds the maximum frame size",
+            bytes.len()
+        )
+        .into());
+    }
+    write_frame_to_bip_buffer(
+        writer,
+        FrameHeader::new(FrameKind::Envelope, bytes.len()),
+        &bytes,
+    )?;
+    Ok(bytes.len())
+}
+
+///This function is used to read an envelope from the bip_buffer.
+///A frame of kind FrameKind::Data, written by a handler that does not use envelopes,
+///is returned as an envelope without metadata and content type CONTENT_TYPE_OCTET_STREAM.
+/// # Arguments
+/// * `reader` - The bipBufferReader used to read from the bip_buffer.
+/// # Returns
+/// * `Envelope` - The envelope, an error is returned when the frame can not be deserialized.
+pub fn read_envelope_from_bip_buffer(reader: &mut BipBufferReader) -> Result<Envelope> {
+    let header = read_frame_header(reader);
+    let element_length = header.payload_length();
+    wait_for_data(reader, element_length);
+    let element = &reader.valid()[..element_length];
+    let envelope = match header.kind {
+        FrameKind::Envelope => Envelope::from_bytes(element),
+        FrameKind::Data => Ok(Envelope {
+            content_type: CONTENT_TYPE_OCTET_STREAM.to_string(),
+            payload: element.to_vec(),
+            ..Default::default()
+        }),
+    };
+    reader.consume(element_length);
+    envelope
+}
+
+#[cfg(test)]
+mod test {
+    use crate::envelope::*;
+    use bip_utils::write_to_bip_buffer;
+    use framework_constants::*;
+
+    #[test]
+    fn envelope_through_bip_buffer_test() {
+        let (mut writer, mut reader) = bip_utils::bip_buffer_with_len(MAX_BIP_BUFFER_MESSAGE_SIZE);
+        let envelope = Envelope::new("ph_test_ingress", "text/plain", b"hello")
+            .with_header("topic", "TestTopic");
+        write_envelope_to_bip_buffer(&mut writer, &envelope).expect("Can't write envelope");
+        let received = read_envelope_from_bip_buffer(&mut reader).expect("Can't read envelope");
+        assert_eq!(received, envelope);
+        assert_eq!(received.header("topic"), Some("TestTopic"));
+        assert_ne!(received.ingest_timestamp_ns, 0);
+    }
+
+    #[test]
+    fn data_frame_as_envelope_test() {
+        let (mut writer, mut reader) = bip_utils::bip_buffer_with_len(MAX_BIP_BUFFER_MESSAGE_SIZE);
+        write_to_bip_buffer(&mut writer, b"plain data").expect("Can't write to bip buffer");
+        let received = read_envelope_from_bip_buffer(&mut reader).expect("Can't read envelope");
+        assert_eq!(received.payload, b"plain data");
+        assert_eq!(received.content_type, CONTENT_TYPE_OCTET_STREAM);
+        assert!(received.headers.is_empty());
+        assert_eq!(received.age(), None);
+    }
+
+    #[test]
+    fn envelope_age_test() {
+        let mut envelope = Envelope::new("ph_test_ingress", "text/plain", b"hello");
+        envelope.ingest_timestamp_ns -= 2_000_000_000;
+        let age = envelope.age().expect("Envelope has no age");
+        assert!(age >= Duration::from_secs(2));
+        envelope.ingest_timestamp_ns = now_ns() + 60_000_000_000;
+        assert_eq!(envelope.age(), None);
+    }
+}
@@ -19,10 +19,14 @@ error_chain! {
     types {
         Error, ErrorKind, ResultExt, Result;
     }
+    links {
+        Frame(bip_utils::errors::Error, bip_utils::errors::ErrorKind);
+    }
     foreign_links {
         ConfigError(::std::num::ParseIntError);
         Io(::std::io::Error);
         SocketParse(::std::net::AddrParseError);
+        Bincode(::bincode::Error);
     }
 
     errors {
@@ -34,5 +38,9 @@ error_chain! {
             description("Unix domain Socket error")
             display("Unix Domain Socket error: '{}'", t)
         }
+        SharedMemoryError(t: String){
+            description("Shared memory error")
+            display("Shared memory error: '{}'", t)
+        }
     }
 }
@@ -12,9 +12,22 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+///Async versions of the BufferedSocketReader and BufferedSocketWriter, for handlers that run on tokio.
+#[cfg(feature = "tokio")]
+pub mod async_buffered_socket_reader;
+#[cfg(feature = "tokio")]
+pub mod async_buffered_socket_writer;
 pub mod buffered_socket_reader;
 pub mod buffered_socket_writer;
+///The metadata envelope that travels with every message through a chain.
+pub mod envelope;
 pub mod errors;
+///Selects a Unix socket or a shared-memory ring as link between two handlers.
+pub mod link;
+///A single producer, single consumer ring in shared memory.
+pub mod shm_ring;
+pub mod shm_ring_reader;
+pub mod shm_ring_writer;
 pub mod socket_reader;
 pub mod socket_writer;
 
@@ -58,19 +71,23 @@ mod test {
     }
     mod buffered {
         use crate::buffered_socket_reader::BufferedSocketReader;
-        use crate::buffered_socket_writer::BufferedSocketWriter;
+        use crate::buffered_socket_writer::{BufferedSocketWriter, FanOutMode};
         use bip_utils::read_from_bip_buffer;
         use bip_utils::write_to_bip_buffer;
         use framework_constants::*;
+        use statistics_handler::StatsAllHandlers;
+        use std::io::Write;
+        use std::os::unix::net::UnixListener;
+        use std::sync::Arc;
         #[test]
         fn read_write_single_element_test() {
             let path = "/tmp/read_write_single_element_buffered";
 
             let (mut in_writer, mut in_reader) =
-                spsc_bip_buffer::bip_buffer_with_len(MAX_BIP_BUFFER_MESSAGE_SIZE * 10);
+                bip_utils::bip_buffer_with_len(MAX_BIP_BUFFER_MESSAGE_SIZE * 10);
             //add data to bip_buffer
             let buffer = vec![2; MAX_BUFFER_SIZE_BYTES];
-            write_to_bip_buffer(&mut in_writer, &buffer);
+            write_to_bip_buffer(&mut in_writer, &buffer).expect("Can't write to bip buffer");
             //start socket writer and socket reader
             std::thread::spawn(move || {
                 let mut socket_writer = BufferedSocketWriter::start_listening(path)
@@ -83,7 +100,7 @@ mod test {
             });
 
             let (out_writer, mut out_reader) =
-                spsc_bip_buffer::bip_buffer_with_len(MAX_BIP_BUFFER_MESSAGE_SIZE * 10);
+                bip_utils::bip_buffer_with_len(MAX_BIP_BUFFER_MESSAGE_SIZE * 10);
 
             let mut socket_reader =
                 BufferedSocketReader::new(path, out_writer).expect("Can't create socket reader");
@@ -96,5 +113,549 @@ mod test {
             assert_eq!(&buffer[..], &received_buffer[..]);
             assert_eq!(&buffer[..].len(), &received_buffer[..].len());
         }
+
+        #[test]
+        fn reject_malformed_frame_test() {
+            let path = "/tmp/reject_malformed_frame_buffered";
+            if let Err(e) = std::fs::remove_file(path) {
+                log::debug!("{}", e);
+            }
+            let listener = UnixListener::bind(path).expect("can't bind socket");
+            std::thread::spawn(move || {
+                let (mut stream, _) = listener.accept().expect("can't accept connection");
+                //an unframed native length field announcing an element that does not fit anywhere
+                stream
+                    .write_all(&usize::MAX.to_le_bytes())
+                    .expect("can't write to socket");
+                stream.write_all(&[0; 8]).expect("can't write to socket");
+                std::thread::sleep(std::time::Duration::from_secs(2));
+            });
+
+            let (out_writer, mut out_reader) =
+                bip_utils::bip_buffer_with_len(MAX_BIP_BUFFER_MESSAGE_SIZE);
+            let mut socket_reader =
+                BufferedSocketReader::new(path, out_writer).expect("Can't create socket reader");
+            assert!(socket_reader.receive_data().is_err());
+            assert!(out_reader.valid().is_empty());
+        }
+
+        #[test]
+        fn reader_reconnects_test() {
+            let path = "/tmp/reader_reconnects_buffered";
+            let (mut in_writer, mut in_reader) =
+                bip_utils::bip_buffer_with_len(MAX_BIP_BUFFER_MESSAGE_SIZE * 10);
+            write_to_bip_buffer(&mut in_writer, &[1; 100]).expect("Can't write to bip buffer");
+            write_to_bip_buffer(&mut in_writer, &[2; 100]).expect("Can't write to bip buffer");
+            //the writer restarts after sending the first element
+            std::thread::spawn(move || {
+                let mut socket_writer = BufferedSocketWriter::start_listening(path)
+                    .expect("can't create socket writer");
+                socket_writer
+                    .send_data(&mut in_reader)
+                    .expect("Cant send data");
+                socket_writer.stop();
+                std::thread::sleep(std::time::Duration::from_millis(300));
+                let mut socket_writer = BufferedSocketWriter::start_listening(path)
+                    .expect("can't create socket writer");
+                socket_writer
+                    .send_data(&mut in_reader)
+                    .expect("Cant send data");
+                std::thread::sleep(std::time::Duration::from_secs(2));
+            });
+
+            let stats_data = Arc::new(StatsAllHandlers::default());
+            let (out_writer, mut out_reader) =
+                bip_utils::bip_buffer_with_len(MAX_BIP_BUFFER_MESSAGE_SIZE * 10);
+            let mut socket_reader = BufferedSocketReader::new(path, out_writer)
+                .expect("Can't create socket reader")
+                .with_stats(stats_data.clone());
+            socket_reader.receive_data().expect("can't receive data");
+            socket_reader.receive_data().expect("can't receive data");
+
+            let mut received_buffer = [0; 100];
+            read_from_bip_buffer(&mut out_reader, &mut received_buffer);
+            assert_eq!(received_buffer, [1; 100]);
+            read_from_bip_buffer(&mut out_reader, &mut received_buffer);
+            assert_eq!(received_buffer, [2; 100]);
+            assert_eq!(stats_data.reconnects.load(), 1);
+        }
+
+        #[test]
+        fn writer_reaccepts_test() {
+            let path = "/tmp/writer_reaccepts_buffered";
+            let (mut in_writer, mut in_reader) =
+                bip_utils::bip_buffer_with_len(MAX_BIP_BUFFER_MESSAGE_SIZE * 10);
+            let stats_data = Arc::new(StatsAllHandlers::default());
+            let writer_stats_data = stats_data.clone();
+            std::thread::spawn(move || {
+                let mut socket_writer = BufferedSocketWriter::start_listening(path)
+                    .expect("can't create socket writer")
+                    .with_stats(writer_stats_data);
+                loop {
+                    socket_writer
+                        .send_data(&mut in_reader)
+                        .expect("Cant send data");
+                }
+            });
+
+            //the first reader goes away after receiving one element
+            let (out_writer, mut out_reader) =
+                bip_utils::bip_buffer_with_len(MAX_BIP_BUFFER_MESSAGE_SIZE * 10);
+            let mut socket_reader =
+                BufferedSocketReader::new(path, out_writer).expect("Can't create socket reader");
+            write_to_bip_buffer(&mut in_writer, &[1; 100]).expect("Can't write to bip buffer");
+            socket_reader.receive_data().expect("can't receive data");
+            drop(socket_reader);
+
+            //the element written while no reader is connected is kept until the next reader connects
+            write_to_bip_buffer(&mut in_writer, &[2; 100]).expect("Can't write to bip buffer");
+            let (out_writer_second, mut out_reader_second) =
+                bip_utils::bip_buffer_with_len(MAX_BIP_BUFFER_MESSAGE_SIZE * 10);
+            let mut socket_reader = BufferedSocketReader::new(path, out_writer_second)
+                .expect("Can't create socket reader");
+            socket_reader.receive_data().expect("can't receive data");
+
+            let mut received_buffer = [0; 100];
+            read_from_bip_buffer(&mut out_reader, &mut received_buffer);
+            assert_eq!(received_buffer, [1; 100]);
+            read_from_bip_buffer(&mut out_reader_second, &mut received_buffer);
+            assert_eq!(received_buffer, [2; 100]);
+            assert_eq!(stats_data.reconnects.load(), 1);
+        }
+
+        #[test]
+        fn fan_out_broadcast_test() {
+            let path = "/tmp/fan_out_broadcast_buffered";
+            let (mut in_writer, mut in_reader) =
+                bip_utils::bip_buffer_with_len(MAX_BIP_BUFFER_MESSAGE_SIZE * 10);
+            write_to_bip_buffer(&mut in_writer, &[1; 100]).expect("Can't write to bip buffer");
+            write_to_bip_buffer(&mut in_writer, &[2; 100]).expect("Can't write to bip buffer");
+            std::thread::spawn(move || {
+                let mut socket_writer =
+                    BufferedSocketWriter::start_listening_fan_out(path, 2, FanOutMode::Broadcast)
+                        .expect("can't create socket writer");
+                socket_writer
+                    .send_data(&mut in_reader)
+                    .expect("Cant send data");
+                socket_writer
+                    .send_data(&mut in_reader)
+                    .expect("Cant send data");
+                std::thread::sleep(std::time::Duration::from_secs(2));
+            });
+
+            //both readers receive both elements
+            let mut received_buffer = [0; 100];
+            let mut socket_readers = Vec::new();
+            for _ in 0..2 {
+                let (out_writer, out_reader) =
+                    bip_utils::bip_buffer_with_len(MAX_BIP_BUFFER_MESSAGE_SIZE * 10);
+                let socket_reader = BufferedSocketReader::new(path, out_writer)
+                    .expect("Can't create socket reader");
+                socket_readers.push((socket_reader, out_reader));
+            }
+            for (socket_reader, out_reader) in &mut socket_readers {
+                socket_reader.receive_data().expect("can't receive data");
+                socket_reader.receive_data().expect("can't receive data");
+                read_from_bip_buffer(out_reader, &mut received_buffer);
+                assert_eq!(received_buffer, [1; 100]);
+                read_from_bip_buffer(out_reader, &mut received_buffer);
+                assert_eq!(received_buffer, [2; 100]);
+            }
+        }
+
+        #[test]
+        fn fan_out_round_robin_test() {
+            let path = "/tmp/fan_out_round_robin_buffered";
+            let (mut in_writer, mut in_reader) =
+                bip_utils::bip_buffer_with_len(MAX_BIP_BUFFER_MESSAGE_SIZE * 10);
+            for element in 1..=4 {
+                write_to_bip_buffer(&mut in_writer, &[element; 100])
+                    .expect("Can't write to bip buffer");
+            }
+            std::thread::spawn(move || {
+                let mut socket_writer =
+                    BufferedSocketWriter::start_listening_fan_out(path, 2, FanOutMode::RoundRobin)
+                        .expect("can't create socket writer");
+                for _ in 0..4 {
+                    socket_writer
+                        .send_data(&mut in_reader)
+                        .expect("Cant send data");
+                }
+                std::thread::sleep(std::time::Duration::from_secs(2));
+            });
+
+            //every reader receives every other element
+            let mut received = Vec::new();
+            let mut socket_readers = Vec::new();
+            for _ in 0..2 {
+                let (out_writer, out_reader) =
+                    bip_utils::bip_buffer_with_len(MAX_BIP_BUFFER_MESSAGE_SIZE * 10);
+                let socket_reader = BufferedSocketReader::new(path, out_writer)
+                    .expect("Can't create socket reader");
+                socket_readers.push((socket_reader, out_reader));
+            }
+            for (socket_reader, out_reader) in &mut socket_readers {
+                let mut received_buffer = [0; 100];
+                socket_reader.receive_data().expect("can't receive data");
+                socket_reader.receive_data().expect("can't receive data");
+                read_from_bip_buffer(out_reader, &mut received_buffer);
+                let first = received_buffer[0];
+                read_from_bip_buffer(out_reader, &mut received_buffer);
+                assert_eq!(received_buffer[0], first + 2);
+                received.push(first);
+            }
+            received.sort_unstable();
+            assert_eq!(received, vec![1, 2]);
+        }
+
+        #[test]
+        fn fan_out_lost_reader_test() {
+            let path = "/tmp/fan_out_lost_reader_buffered";
+            let (mut in_writer, mut in_reader) =
+                bip_utils::bip_buffer_with_len(MAX_BIP_BUFFER_MESSAGE_SIZE * 10);
+            let stats_data = Arc::new(StatsAllHandlers::default());
+            let writer_stats_data = stats_data.clone();
+            std::thread::spawn(move || {
+                let mut socket_writer =
+                    BufferedSocketWriter::start_listening_fan_out(path, 2, FanOutMode::Broadcast)
+                        .expect("can't create socket writer")
+                        .with_stats(writer_stats_data);
+                loop {
+                    socket_writer
+                        .send_data(&mut in_reader)
+                        .expect("Cant send data");
+                }
+            });
+
+            let mut socket_readers = Vec::new();
+            for _ in 0..2 {
+                let (out_writer, out_reader) =
+                    bip_utils::bip_buffer_with_len(MAX_BIP_BUFFER_MESSAGE_SIZE * 10);
+                let socket_reader = BufferedSocketReader::new(path, out_writer)
+                    .expect("Can't create socket reader");
+                socket_readers.push((socket_reader, out_reader));
+            }
+            write_to_bip_buffer(&mut in_writer, &[1; 100]).expect("Can't write to bip buffer");
+            for (socket_reader, _) in &mut socket_readers {
+                socket_reader.receive_data().expect("can't receive data");
+            }
+
+            //the second reader goes away, the first keeps receiving
+            let (second_reader, _) = socket_readers.pop().expect("no second reader");
+            drop(second_reader);
+            let (first_reader, first_out_reader) = &mut socket_readers[0];
+            for element in 2..=3 {
+                write_to_bip_buffer(&mut in_writer, &[element; 100])
+                    .expect("Can't write to bip buffer");
+                first_reader.receive_data().expect("can't receive data");
+            }
+
+            //a new reader takes its place
+            let (out_writer, mut new_out_reader) =
+                bip_utils::bip_buffer_with_len(MAX_BIP_BUFFER_MESSAGE_SIZE * 10);
+            let mut new_reader =
+                BufferedSocketReader::new(path, out_writer).expect("Can't create socket reader");
+            write_to_bip_buffer(&mut in_writer, &[4; 100]).expect("Can't write to bip buffer");
+            first_reader.receive_data().expect("can't receive data");
+            new_reader.receive_data().expect("can't receive data");
+
+            let mut received_buffer = [0; 100];
+            for element in 1..=4 {
+                read_from_bip_buffer(first_out_reader, &mut received_buffer);
+                assert_eq!(received_buffer, [element; 100]);
+            }
+            read_from_bip_buffer(&mut new_out_reader, &mut received_buffer);
+            assert_eq!(received_buffer, [4; 100]);
+            assert_eq!(stats_data.reconnects.load(), 1);
+        }
+
+        #[test]
+        fn fan_in_test() {
+            let paths = ["/tmp/fan_in_first_buffered", "/tmp/fan_in_second_buffered"];
+            for (index, &path) in paths.iter().enumerate() {
+                let (mut in_writer, mut in_reader) =
+                    bip_utils::bip_buffer_with_len(MAX_BIP_BUFFER_MESSAGE_SIZE * 10);
+                write_to_bip_buffer(&mut in_writer, &[index as u8 + 1; 100])
+                    .expect("Can't write to bip buffer");
+                std::thread::spawn(move || {
+                    let mut socket_writer = BufferedSocketWriter::start_listening(path)
+                        .expect("can't create socket writer");
+                    socket_writer
+                        .send_data(&mut in_reader)
+                        .expect("Cant send data");
+                    std::thread::sleep(std::time::Duration::from_secs(2));
+                });
+            }
+
+            //the elements of both writers end up in the same bip_buffer
+            let (out_writer, mut out_reader) =
+                bip_utils::bip_buffer_with_len(MAX_BIP_BUFFER_MESSAGE_SIZE * 10);
+            let mut socket_reader = BufferedSocketReader::new_fan_in(&paths, out_writer)
+                .expect("Can't create socket reader");
+            socket_reader.receive_data().expect("can't receive data");
+            socket_reader.receive_data().expect("can't receive data");
+
+            let mut received = Vec::new();
+            let mut received_buffer = [0; 100];
+            for _ in 0..2 {
+                read_from_bip_buffer(&mut out_reader, &mut received_buffer);
+                received.push(received_buffer[0]);
+            }
+            received.sort_unstable();
+            assert_eq!(received, vec![1, 2]);
+        }
+    }
+    mod shm {
+        use crate::link::*;
+        use crate::shm_ring_reader::ShmRingReader;
+        use crate::shm_ring_writer::ShmRingWriter;
+        use bip_utils::read_from_bip_buffer;
+        use bip_utils::write_to_bip_buffer;
+        use framework_constants::*;
+        use statistics_handler::StatsAllHandlers;
+        use std::sync::Arc;
+
+        #[test]
+        fn read_write_wrapping_ring_test() {
+            let path = "/tmp/read_write_wrapping_ring_shm";
+            let (mut in_writer, mut in_reader) =
+                bip_utils::bip_buffer_with_len(MAX_BIP_BUFFER_MESSAGE_SIZE * 2);
+            let mut ring_writer = ShmRingWriter::with_capacity(path, MAX_BIP_BUFFER_MESSAGE_SIZE)
+                .expect("can't create ring writer");
+            //enough elements to wrap around the ring several times
+            let elements = (0..40u8)
+                .map(|i| vec![i; 100_000 + i as usize])
+                .collect::<Vec<Vec<u8>>>();
+            let sent = elements.clone();
+            std::thread::spawn(move || {
+                for element in &sent {
+                    write_to_bip_buffer(&mut in_writer, element)
+                        .expect("Can't write to bip buffer");
+                    ring_writer
+                        .send_data(&mut in_reader)
+                        .expect("Cant send data");
+                }
+            });
+
+            let (out_writer, mut out_reader) =
+                bip_utils::bip_buffer_with_len(MAX_BIP_BUFFER_MESSAGE_SIZE * 2);
+            let mut ring_reader =
+                ShmRingReader::new(path, out_writer).expect("Can't create ring reader");
+            let mut received_buffer = vec![0; MAX_BIP_BUFFER_MESSAGE_SIZE];
+            for element in &elements {
+                let length = ring_reader.receive_data().expect("can't receive data");
+                assert_eq!(length, element.len());
+                let length = read_from_bip_buffer(&mut out_reader, &mut received_buffer);
+                assert_eq!(&received_buffer[..length], &element[..]);
+            }
+        }
+
+        #[test]
+        fn reader_follows_new_ring_test() {
+            let path = "/tmp/reader_follows_new_ring_shm";
+            let (mut in_writer, mut in_reader) =
+                bip_utils::bip_buffer_with_len(MAX_BIP_BUFFER_MESSAGE_SIZE * 10);
+            write_to_bip_buffer(&mut in_writer, &[1; 100]).expect("Can't write to bip buffer");
+            write_to_bip_buffer(&mut in_writer, &[2; 100]).expect("Can't write to bip buffer");
+            let mut ring_writer =
+                LinkWriter::start_listening(LinkType::Shm, path).expect("can't create ring writer");
+            ring_writer
+                .send_data(&mut in_reader)
+                .expect("Cant send data");
+
+            let stats_data = Arc::new(StatsAllHandlers::default());
+            let (out_writer, mut out_reader) =
+                bip_utils::bip_buffer_with_len(MAX_BIP_BUFFER_MESSAGE_SIZE * 10);
+            let mut ring_reader = LinkReader::new(LinkType::Shm, path, out_writer)
+                .expect("Can't create ring reader")
+                .with_stats(stats_data.clone());
+
+            //the writer restarts and creates a new ring, the frame in the old ring is still read
+            let mut ring_writer =
+                LinkWriter::start_listening(LinkType::Shm, path).expect("can't create ring writer");
+            ring_writer
+                .send_data(&mut in_reader)
+                .expect("Cant send data");
+            ring_reader.receive_data().expect("can't receive data");
+            ring_reader.receive_data().expect("can't receive data");
+            ring_writer.stop();
+
+            let mut received_buffer = [0; 100];
+            read_from_bip_buffer(&mut out_reader, &mut received_buffer);
+            assert_eq!(received_buffer, [1; 100]);
+            read_from_bip_buffer(&mut out_reader, &mut received_buffer);
+            assert_eq!(received_buffer, [2; 100]);
+            assert_eq!(stats_data.reconnects.load(), 1);
+        }
+
+        #[test]
+        fn ring_writer_stats_test() {
+            let path = "/tmp/ring_writer_stats_shm";
+            let (mut in_writer, mut in_reader) =
+                bip_utils::bip_buffer_with_len(MAX_BIP_BUFFER_MESSAGE_SIZE);
+            let stats_data = Arc::new(StatsAllHandlers::default());
+            let mut ring_writer = ShmRingWriter::start_listening(path)
+                .expect("can't create ring writer")
+                .with_stats(stats_data.clone());
+            write_to_bip_buffer(&mut in_writer, &[1; 100]).expect("Can't write to bip buffer");
+            ring_writer
+                .send_data(&mut in_reader)
+                .expect("Cant send data");
+            ring_writer.stop();
+
+            let buffers = stats_data.buffers.lock().unwrap();
+            let (name, buffer_stats) = &buffers[0];
+            assert_eq!(name, "shm");
+            assert_eq!(buffer_stats.fill(), (FRAME_HEADER_LEN + 100) as u64);
+        }
+
+        #[test]
+        fn corrupt_ring_positions_test() {
+            use std::os::unix::fs::FileExt;
+            let path = "/tmp/corrupt_ring_positions_shm";
+            let ring_writer = ShmRingWriter::with_capacity(path, MAX_BIP_BUFFER_MESSAGE_SIZE)
+                .expect("can't create ring writer");
+            //the write position lies in the first page of the file, after the magic, version, capacity and padding
+            let file = std::fs::OpenOptions::new()
+                .write(true)
+                .open(path)
+                .expect("can't open ring");
+            file.write_at(&(MAX_BIP_BUFFER_MESSAGE_SIZE as u64 * 3).to_ne_bytes(), 64)
+                .expect("can't corrupt ring");
+
+            let (out_writer, _out_reader) =
+                bip_utils::bip_buffer_with_len(MAX_BIP_BUFFER_MESSAGE_SIZE);
+            let mut ring_reader =
+                ShmRingReader::new(path, out_writer).expect("Can't create ring reader");
+            assert!(ring_reader.receive_data().is_err());
+            ring_writer.stop();
+        }
+
+        #[test]
+        fn link_type_from_str_test() {
+            assert_eq!("socket".parse::<LinkType>().ok(), Some(LinkType::Socket));
+            assert_eq!("shm".parse::<LinkType>().ok(), Some(LinkType::Shm));
+            assert!("tcp".parse::<LinkType>().is_err());
+        }
+    }
+    #[cfg(feature = "tokio")]
+    mod tokio {
+        use crate::async_buffered_socket_reader::AsyncBufferedSocketReader;
+        use crate::async_buffered_socket_writer::AsyncBufferedSocketWriter;
+        use bip_utils::async_bip::{read_from_bip_buffer, write_to_bip_buffer};
+        use framework_constants::*;
+
+        #[test]
+        fn read_write_async_test() {
+            let paths = [
+                "/tmp/read_write_first_async",
+                "/tmp/read_write_second_async",
+            ];
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .expect("can't create runtime");
+            runtime.block_on(async {
+                //two writers on one runtime, merged by one reader
+                for &path in &paths {
+                    let (mut in_writer, mut in_reader) =
+                        bip_utils::bip_buffer_with_len(MAX_BIP_BUFFER_MESSAGE_SIZE * 2);
+                    tokio::spawn(async move {
+                        let mut socket_writer = AsyncBufferedSocketWriter::start_listening(path)
+                            .await
+                            .expect("can't create socket writer");
+                        for element in 0..10u8 {
+                            write_to_bip_buffer(&mut in_writer, &[element; 100_000])
+                                .await
+                                .expect("Can't write to bip buffer");
+                            socket_writer
+                                .send_data(&mut in_reader)
+                                .await
+                                .expect("Cant send data");
+                        }
+                        tokio::time::sleep(std::time::Duration::from_secs(2)).await;
+                        socket_writer.stop().await;
+                    });
+                }
+
+                let (out_writer, mut out_reader) =
+                    bip_utils::bip_buffer_with_len(MAX_BIP_BUFFER_MESSAGE_SIZE * 2);
+                let mut socket_reader = AsyncBufferedSocketReader::new_fan_in(&paths, out_writer)
+                    .await
+                    .expect("Can't create socket reader");
+                //the bip_buffer is smaller than all elements together, so receiving and reading take turns
+                let reading = tokio::spawn(async move {
+                    let mut received = vec![0; 10];
+                    let mut received_buffer = vec![0; MAX_BIP_BUFFER_MESSAGE_SIZE];
+                    for _ in 0..20 {
+                        let length =
+                            read_from_bip_buffer(&mut out_reader, &mut received_buffer).await;
+                        assert_eq!(length, 100_000);
+                        received[received_buffer[0] as usize] += 1;
+                    }
+                    received
+                });
+                for _ in 0..20 {
+                    socket_reader
+                        .receive_data()
+                        .await
+                        .expect("can't receive data");
+                }
+                assert_eq!(reading.await.expect("reading failed"), vec![2; 10]);
+            });
+        }
+
+        #[test]
+        fn fan_out_lost_reader_async_test() {
+            use crate::buffered_socket_reader::BufferedSocketReader;
+            use crate::buffered_socket_writer::FanOutMode;
+            let path = "/tmp/fan_out_lost_reader_async";
+            let (mut in_writer, mut in_reader) =
+                bip_utils::bip_buffer_with_len(MAX_BIP_BUFFER_MESSAGE_SIZE * 10);
+            std::thread::spawn(move || {
+                let runtime = tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                    .expect("can't create runtime");
+                runtime.block_on(async {
+                    let mut socket_writer = AsyncBufferedSocketWriter::start_listening_fan_out(
+                        path,
+                        2,
+                        FanOutMode::RoundRobin,
+                    )
+                    .await
+                    .expect("can't create socket writer");
+                    loop {
+                        socket_writer
+                            .send_data(&mut in_reader)
+                            .await
+                            .expect("Cant send data");
+                    }
+                });
+            });
+
+            let mut socket_readers = Vec::new();
+            for _ in 0..2 {
+                let (out_writer, out_reader) =
+                    bip_utils::bip_buffer_with_len(MAX_BIP_BUFFER_MESSAGE_SIZE * 10);
+                let socket_reader = BufferedSocketReader::new(path, out_writer)
+                    .expect("Can't create socket reader");
+                socket_readers.push((socket_reader, out_reader));
+            }
+            //the second reader goes away, its turns go to the first reader
+            let (second_reader, _) = socket_readers.pop().expect("no second reader");
+            drop(second_reader);
+            let (first_reader, first_out_reader) = &mut socket_readers[0];
+            for element in 1..=3 {
+                bip_utils::write_to_bip_buffer(&mut in_writer, &[element; 100])
+                    .expect("Can't write to bip buffer");
+                first_reader.receive_data().expect("can't receive data");
+            }
+            let mut received_buffer = [0; 100];
+            for element in 1..=3 {
+                bip_utils::read_from_bip_buffer(first_out_reader, &mut received_buffer);
+                assert_eq!(received_buffer, [element; 100]);
+            }
+        }
     }
 }
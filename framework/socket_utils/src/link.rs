@@ -0,0 +1,168 @@
+// Copyright 2020 Ministerie van Defensie
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::buffered_socket_reader::BufferedSocketReader;
+use crate::buffered_socket_writer::{BufferedSocketWriter, FanOutMode};
+use crate::errors::ErrorKind::{SharedMemoryError, UnixDomainSocketError};
+use crate::errors::*;
+use crate::shm_ring_reader::ShmRingReader;
+use crate::shm_ring_writer::ShmRingWriter;
+use bip_utils::{BipBufferReader, BipBufferWriter};
+use statistics_handler::StatsAllHandlers;
+use std::str::FromStr;
+use std::sync::Arc;
+
+///How two handlers in a chain are linked.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LinkType {
+    ///A Unix domain socket, the default.
+    Socket,
+    ///A shared-memory ring, for handlers on the same machine.
+    Shm,
+}
+
+impl FromStr for LinkType {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<LinkType> {
+        match s {
+            "socket" => Ok(LinkType::Socket),
+            "shm" => Ok(LinkType::Shm),
+            _ => Err(UnixDomainSocketError(format!(
+                "unknown link type {s}, can be \"socket\" or \"shm\""
+            ))
+            .into()),
+        }
+    }
+}
+
+///The sending end of a link, a BufferedSocketWriter or a ShmRingWriter.
+pub enum LinkWriter {
+    Socket(BufferedSocketWriter),
+    Shm(ShmRingWriter),
+}
+
+impl LinkWriter {
+    ///Creates the sending end of a link of the given type.
+    /// # Arguments
+    /// * `link_type` - The type of the link.
+    /// * `path` - The path of the socket or ring.
+    pub fn start_listening(link_type: LinkType, path: &str) -> Result<LinkWriter> {
+        Ok(match link_type {
+            LinkType::Socket => LinkWriter::Socket(BufferedSocketWriter::start_listening(path)?),
+            LinkType::Shm => LinkWriter::Shm(ShmRingWriter::start_listening(path)?),
+        })
+    }
+
+    ///Creates the sending end of a link that several handlers read from.
+    ///Only a socket can have more than one reader.
+    /// # Arguments
+    /// * `link_type` - The type of the link.
+    /// * `path` - The path of the socket or ring.
+    /// * `readers` - The number of handlers that read from the link.
+    /// * `mode` - Whether every element goes to all readers or to one of them.
+    pub fn start_listening_fan_out(
+        link_type: LinkType,
+        path: &str,
+        readers: usize,
+        mode: FanOutMode,
+    ) -> Result<LinkWriter> {
+        match link_type {
+            LinkType::Socket => Ok(LinkWriter::Socket(
+                BufferedSocketWriter::start_listening_fan_out(path, readers, mode)?,
+            )),
+            LinkType::Shm if readers == 1 => LinkWriter::start_listening(link_type, path),
+            LinkType::Shm => Err(SharedMemoryError(format!(
+                "a shared-memory ring has one reader, {path} has {readers}"
+            ))
+            .into()),
+        }
+    }
+
+    ///Reports the reconnects of this writer in the `reconnects` statistic.
+    pub fn with_stats(self, stats_data: Arc<StatsAllHandlers>) -> LinkWriter {
+        match self {
+            LinkWriter::Socket(writer) => LinkWriter::Socket(writer.with_stats(stats_data)),
+            LinkWriter::Shm(writer) => LinkWriter::Shm(writer.with_stats(stats_data)),
+        }
+    }
+
+    ///Sends the next element of the bip_buffer over the link.
+    pub fn send_data(&mut self, reader: &mut BipBufferReader) -> Result<usize> {
+        match self {
+            LinkWriter::Socket(writer) => writer.send_data(reader),
+            LinkWriter::Shm(writer) => writer.send_data(reader),
+        }
+    }
+
+    pub fn stop(&self) {
+        match self {
+            LinkWriter::Socket(writer) => writer.stop(),
+            LinkWriter::Shm(writer) => writer.stop(),
+        }
+    }
+}
+
+///The receiving end of a link, a BufferedSocketReader or a ShmRingReader.
+pub enum LinkReader {
+    Socket(BufferedSocketReader),
+    Shm(ShmRingReader),
+}
+
+impl LinkReader {
+    ///Creates the receiving end of a link of the given type.
+    ///Several socket paths separated by a comma are merged into one bip_buffer.
+    /// # Arguments
+    /// * `link_type` - The type of the link.
+    /// * `path` - The path of the socket or ring, or a comma separated list of socket paths.
+    /// * `writer` - The BipBufferWriter used to send the received data to a bip_buffer.
+    pub fn new(link_type: LinkType, path: &str, writer: BipBufferWriter) -> Result<LinkReader> {
+        let paths = path.split(',').collect::<Vec<&str>>();
+        Ok(match link_type {
+            LinkType::Socket => {
+                LinkReader::Socket(BufferedSocketReader::new_fan_in(&paths, writer)?)
+            }
+            LinkType::Shm if paths.len() == 1 => LinkReader::Shm(ShmRingReader::new(path, writer)?),
+            LinkType::Shm => {
+                return Err(SharedMemoryError(format!(
+                    "a shared-memory ring has one writer, {path} are several rings"
+                ))
+                .into())
+            }
+        })
+    }
+
+    ///Reports the reconnects of this reader in the `reconnects` statistic.
+    pub fn with_stats(self, stats_data: Arc<StatsAllHandlers>) -> LinkReader {
+        match self {
+            LinkReader::Socket(reader) => LinkReader::Socket(reader.with_stats(stats_data)),
+            LinkReader::Shm(reader) => LinkReader::Shm(reader.with_stats(stats_data)),
+        }
+    }
+
+    ///Receives the next element from the link and writes it to the bip_buffer.
+    pub fn receive_data(&mut self) -> Result<usize> {
+        match self {
+            LinkReader::Socket(reader) => reader.receive_data(),
+            LinkReader::Shm(reader) => reader.receive_data(),
+        }
+    }
+
+    pub fn stop(&self) -> Result<()> {
+        match self {
+            LinkReader::Socket(reader) => reader.stop(),
+            LinkReader::Shm(reader) => reader.stop(),
+        }
+    }
+}
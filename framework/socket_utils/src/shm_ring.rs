@@ -0,0 +1,325 @@
+// Copyright 2020 Ministerie van Defensie
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::errors::ErrorKind::SharedMemoryError;
+use crate::errors::*;
+use std::fs::{File, OpenOptions};
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::time::Duration;
+
+///Identifies a file as an OSDD shared-memory ring ("OSDR").
+const RING_MAGIC: u32 = 0x5244_534F;
+///The version of the ring layout.
+const RING_VERSION: u32 = 1;
+///The data of the ring starts after the first page, the header lives in the first page.
+const RING_HEADER_LEN: usize = 4096;
+///How long a wait on the futex of the ring may take before the caller gets control back.
+pub const RING_WAIT_TIMEOUT: Duration = Duration::from_millis(100);
+
+///The header at the start of a ring file.
+///The write and read positions only ever increase, the position in the data area is the position modulo the capacity.
+///The positions are kept on separate cache lines, so the writer and reader do not slow each other down.
+#[repr(C)]
+struct RingHeader {
+    magic: AtomicU32,
+    version: u32,
+    capacity: u64,
+    _padding_1: [u8; 48],
+    ///The amount of bytes written to the ring.
+    write_position: AtomicU64,
+    ///Incremented by the writer after every write, the reader waits on it.
+    data_signal: AtomicU32,
+    _padding_2: [u8; 52],
+    ///The amount of bytes read from the ring.
+    read_position: AtomicU64,
+    ///Incremented by the reader after every read, the writer waits on it.
+    space_signal: AtomicU32,
+    _padding_3: [u8; 52],
+}
+
+///A single producer, single consumer byte ring in a memory mapped file.
+///The ring is shared between two processes, one writing and one reading.
+///Waiting for data or space is done with a futex on the mapped memory, so no system calls are needed while data flows.
+pub struct ShmRing {
+    memory: *mut u8,
+    mapped_length: usize,
+    capacity: usize,
+    inode: u64,
+    //the file stays open while it is mapped
+    _file: File,
+}
+
+//The mapped memory is only accessed through the atomics in the header and the positions they guard.
+unsafe impl Send for ShmRing {}
+
+impl ShmRing {
+    ///Creates a new ring at `path`, replacing an existing ring.
+    ///The ring is prepared in a temporary file and then renamed, so a reader never sees a half-initialized ring.
+    /// # Arguments
+    /// * `path` - The path of the ring file, for example in /dev/shm.
+    /// * `capacity` - The size of the data area in bytes.
+    pub fn create(path: &str, capacity: usize) -> Result<ShmRing> {
+        let temporary_path = format!("{}.{}.tmp", path, std::process::id());
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&temporary_path)
+            .chain_err(|| format!("Error creating shared memory ring at {temporary_path}"))?;
+        file.set_len((RING_HEADER_LEN + capacity) as u64)
+            .chain_err(|| "Error sizing shared memory ring")?;
+        let ring = ShmRing::map(file, capacity)?;
+        //the ring is not visible to a reader yet, so the plain fields can be written
+        let header = ring.memory as *mut RingHeader;
+        unsafe {
+            (*header).version = RING_VERSION;
+            (*header).capacity = capacity as u64;
+        }
+        ring.header().magic.store(RING_MAGIC, Ordering::Release);
+        std::fs::rename(&temporary_path, path)
+            .chain_err(|| format!("Error moving shared memory ring to {path}"))?;
+        Ok(ring)
+    }
+
+    ///Opens an existing ring created by ShmRing::create.
+    /// # Arguments
+    /// * `path` - The path of the ring file.
+    pub fn open(path: &str) -> Result<ShmRing> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .chain_err(|| format!("Error opening shared memory ring at {path}"))?;
+        let file_length = file
+            .metadata()
+            .chain_err(|| "Error reading shared memory ring metadata")?
+            .len() as usize;
+        if file_length <= RING_HEADER_LEN {
+            return Err(SharedMemoryError(format!("{path} is too small to be a ring")).into());
+        }
+        let ring = ShmRing::map(file, file_length - RING_HEADER_LEN)?;
+        let header = ring.header();
+        if header.magic.load(Ordering::Acquire) != RING_MAGIC
+            || header.version != RING_VERSION
+            || header.capacity as usize != ring.capacity
+        {
+            return Err(SharedMemoryError(format!("{path} is not a valid ring")).into());
+        }
+        Ok(ring)
+    }
+
+    fn map(file: File, capacity: usize) -> Result<ShmRing> {
+        let mapped_length = RING_HEADER_LEN + capacity;
+        let inode = file
+            .metadata()
+            .chain_err(|| "Error reading shared memory ring metadata")?
+            .ino();
+        let memory = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                mapped_length,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if memory == libc::MAP_FAILED {
+            return Err(Error::with_chain(
+                std::io::Error::last_os_error(),
+                "Error mapping shared memory ring",
+            ));
+        }
+        Ok(ShmRing {
+            memory: memory as *mut u8,
+            mapped_length,
+            capacity,
+            inode,
+            _file: file,
+        })
+    }
+
+    fn header(&self) -> &RingHeader {
+        unsafe { &*(self.memory as *const RingHeader) }
+    }
+
+    ///The size of the data area in bytes.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    ///The inode of the ring file, used to detect that the file has been replaced by a new ring.
+    pub fn inode(&self) -> u64 {
+        self.inode
+    }
+
+    ///The amount of bytes that can be read.
+    ///The positions are written by the other process, positions that do not fit the capacity result in an error.
+    pub fn available(&self) -> Result<usize> {
+        let header = self.header();
+        let available = header
+            .write_position
+            .load(Ordering::Acquire)
+            .saturating_sub(header.read_position.load(Ordering::Acquire));
+        if available > self.capacity as u64 {
+            return Err(SharedMemoryError(format!(
+                "The ring holds {} bytes according to its positions, but its capacity is {} bytes",
+                available, self.capacity
+            ))
+            .into());
+        }
+        Ok(available as usize)
+    }
+
+    ///The amount of bytes that can be written.
+    pub fn free_space(&self) -> Result<usize> {
+        Ok(self.capacity - self.available()?)
+    }
+
+    ///The amount of bytes read from the ring since it was created.
+    pub fn read_position(&self) -> u64 {
+        self.header().read_position.load(Ordering::Acquire)
+    }
+
+    ///Writes `data` to the ring and wakes up the reader.
+    ///The caller must make sure there is enough free space.
+    pub fn write(&self, data: &[u8]) {
+        debug_assert!(self
+            .free_space()
+            .is_ok_and(|free_space| data.len() <= free_space));
+        let header = self.header();
+        let write_position = header.write_position.load(Ordering::Relaxed);
+        self.copy_in(write_position, data);
+        header
+            .write_position
+            .store(write_position + data.len() as u64, Ordering::Release);
+        header.data_signal.fetch_add(1, Ordering::Release);
+        futex_wake(&header.data_signal);
+    }
+
+    ///Copies bytes from the ring, starting `offset` bytes after the read position, without consuming them.
+    ///The caller must make sure the bytes are available.
+    pub fn peek(&self, offset: usize, buffer: &mut [u8]) {
+        debug_assert!(self
+            .available()
+            .is_ok_and(|available| offset + buffer.len() <= available));
+        let read_position = self.header().read_position.load(Ordering::Relaxed);
+        self.copy_out(read_position + offset as u64, buffer);
+    }
+
+    ///Marks `length` bytes as read and wakes up the writer.
+    pub fn consume(&self, length: usize) {
+        let header = self.header();
+        header
+            .read_position
+            .fetch_add(length as u64, Ordering::Release);
+        header.space_signal.fetch_add(1, Ordering::Release);
+        futex_wake(&header.space_signal);
+    }
+
+    ///Waits until at least `length` bytes can be read, or until RING_WAIT_TIMEOUT has passed.
+    /// # Returns
+    /// * `bool` - true when the bytes are available.
+    pub fn wait_for_data(&self, length: usize) -> Result<bool> {
+        let signal = self.header().data_signal.load(Ordering::Acquire);
+        if self.available()? >= length {
+            return Ok(true);
+        }
+        futex_wait(&self.header().data_signal, signal, RING_WAIT_TIMEOUT);
+        Ok(self.available()? >= length)
+    }
+
+    ///Waits until at least `length` bytes can be written, or until RING_WAIT_TIMEOUT has passed.
+    /// # Returns
+    /// * `bool` - true when the space is available.
+    pub fn wait_for_space(&self, length: usize) -> Result<bool> {
+        let signal = self.header().space_signal.load(Ordering::Acquire);
+        if self.free_space()? >= length {
+            return Ok(true);
+        }
+        futex_wait(&self.header().space_signal, signal, RING_WAIT_TIMEOUT);
+        Ok(self.free_space()? >= length)
+    }
+
+    fn data(&self) -> *mut u8 {
+        unsafe { self.memory.add(RING_HEADER_LEN) }
+    }
+
+    fn copy_in(&self, position: u64, data: &[u8]) {
+        let start = (position % self.capacity as u64) as usize;
+        let first_part = std::cmp::min(data.len(), self.capacity - start);
+        unsafe {
+            std::ptr::copy_nonoverlapping(data.as_ptr(), self.data().add(start), first_part);
+            std::ptr::copy_nonoverlapping(
+                data.as_ptr().add(first_part),
+                self.data(),
+                data.len() - first_part,
+            );
+        }
+    }
+
+    fn copy_out(&self, position: u64, buffer: &mut [u8]) {
+        let start = (position % self.capacity as u64) as usize;
+        let first_part = std::cmp::min(buffer.len(), self.capacity - start);
+        unsafe {
+            std::ptr::copy_nonoverlapping(self.data().add(start), buffer.as_mut_ptr(), first_part);
+            std::ptr::copy_nonoverlapping(
+                self.data(),
+                buffer.as_mut_ptr().add(first_part),
+                buffer.len() - first_part,
+            );
+        }
+    }
+}
+
+impl Drop for ShmRing {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.memory as *mut libc::c_void, self.mapped_length);
+        }
+    }
+}
+
+///Waits until `word` no longer contains `expected`, is woken up or `timeout` has passed.
+///The futex is shared between processes, so FUTEX_PRIVATE_FLAG is not used.
+fn futex_wait(word: &AtomicU32, expected: u32, timeout: Duration) {
+    let timeout = libc::timespec {
+        tv_sec: timeout.as_secs() as libc::time_t,
+        tv_nsec: timeout.subsec_nanos() as libc::c_long,
+    };
+    unsafe {
+        libc::syscall(
+            libc::SYS_futex,
+            word as *const AtomicU32,
+            libc::FUTEX_WAIT,
+            expected,
+            &timeout as *const libc::timespec,
+        );
+    }
+}
+
+///Wakes up all processes waiting on `word`.
+fn futex_wake(word: &AtomicU32) {
+    unsafe {
+        libc::syscall(
+            libc::SYS_futex,
+            word as *const AtomicU32,
+            libc::FUTEX_WAKE,
+            i32::MAX,
+        );
+    }
+}
@@ -0,0 +1,143 @@
+// Copyright 2020 Ministerie van Defensie
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::errors::*;
+use crate::shm_ring::ShmRing;
+use bip_utils::frame::FrameHeader;
+use bip_utils::BipBufferWriter;
+use framework_constants::*;
+use statistics_handler::StatsAllHandlers;
+use std::os::unix::fs::MetadataExt;
+use std::sync::Arc;
+
+///Receives frames from a shared-memory ring, written by a ShmRingWriter in another process.
+///It has the same API as the BufferedSocketReader.
+pub struct ShmRingReader {
+    ring: ShmRing,
+    path: String,
+    writer: BipBufferWriter,
+    stats_data: Option<Arc<StatsAllHandlers>>,
+}
+
+impl ShmRingReader {
+    ///Creates a new instance of ShmRingReader.
+    ///This function will block until the ring has been created by a ShmRingWriter.
+    /// # Arguments
+    /// * `path` - The path of the ring the reader should open.
+    /// * `writer` - The BipBufferWriter used to send the received data to a bip_buffer.
+    pub fn new(path: &str, writer: BipBufferWriter) -> Result<ShmRingReader> {
+        loop {
+            match ShmRing::open(path) {
+                Ok(ring) => {
+                    return Ok(ShmRingReader {
+                        ring,
+                        path: path.to_string(),
+                        writer,
+                        stats_data: None,
+                    })
+                }
+                Err(e) => {
+                    log::warn!(
+                        "ShmRingReader: ring at {} can not be opened yet: {}",
+                        path,
+                        e
+                    );
+                    std::thread::sleep(std::time::Duration::from_millis(200));
+                }
+            }
+        }
+    }
+
+    ///Reports the reconnects of this reader in the `reconnects` statistic.
+    /// # Arguments
+    /// * `stats_data` - The statistics of the handler.
+    pub fn with_stats(mut self, stats_data: Arc<StatsAllHandlers>) -> ShmRingReader {
+        self.stats_data = Some(stats_data);
+        self
+    }
+
+    ///This function fetches a frame from the ring.
+    ///This data is then sent to the bip_buffer using the bipBufferWriter.
+    ///This function will block until space is available in the bip_buffer.
+    ///When the writer has created a new ring, the reader switches to it after reading the remaining frames of the old ring.
+    ///A malformed frame header results in an error, nothing is reserved for it.
+    pub fn receive_data(&mut self) -> Result<usize> {
+        while !self.ring.wait_for_data(FRAME_HEADER_LEN)? {
+            if self.ring_replaced() {
+                self.reconnect();
+            }
+        }
+        let mut header_buffer = [0; FRAME_HEADER_LEN];
+        self.ring.peek(0, &mut header_buffer);
+        let element_length = FrameHeader::from_bytes(&header_buffer)
+            .chain_err(|| "ShmRingReader received a malformed frame")?
+            .payload_length();
+        let frame_length = element_length + FRAME_HEADER_LEN;
+        //the writer only publishes complete frames
+        if self.ring.available()? < frame_length {
+            return Err(
+                format!("ShmRingReader found an incomplete frame of {frame_length} bytes").into(),
+            );
+        }
+
+        //reserve total buffer space
+        let reserved = match self.writer.reserve_frame(frame_length)? {
+            Some(mut reservation) => {
+                self.ring.peek(0, &mut reservation);
+                reservation.send();
+                true
+            }
+            None => false,
+        };
+        if !reserved {
+            //the frame does not fit, leave it to the overflow policy of the writer
+            let mut frame = vec![0; frame_length];
+            self.ring.peek(0, &mut frame);
+            self.writer.write_frame(&[&frame])?;
+        }
+        self.ring.consume(frame_length);
+        Ok(element_length)
+    }
+
+    ///Returns true when the ring file has been replaced and the old ring has been read completely.
+    fn ring_replaced(&self) -> bool {
+        if !matches!(self.ring.available(), Ok(0)) {
+            return false;
+        }
+        match std::fs::metadata(&self.path) {
+            Ok(metadata) => metadata.ino() != self.ring.inode(),
+            Err(_) => false,
+        }
+    }
+
+    ///Opens the new ring created by the writer.
+    fn reconnect(&mut self) {
+        match ShmRing::open(&self.path) {
+            Ok(ring) => {
+                self.ring = ring;
+                if let Some(stats_data) = &self.stats_data {
+                    stats_data.reconnects.add(1);
+                }
+                log::info!("ShmRingReader reconnected to {}", self.path);
+            }
+            Err(e) => log::warn!("ShmRingReader could not open the new ring: {}", e),
+        }
+    }
+
+    ///Stops the ShmRingReader. The ring is unmapped when the reader is dropped.
+    pub fn stop(&self) -> Result<()> {
+        log::info!("ShmRingReader has been shutdown");
+        Ok(())
+    }
+}
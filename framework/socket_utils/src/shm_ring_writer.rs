@@ -0,0 +1,110 @@
+// Copyright 2020 Ministerie van Defensie
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::errors::ErrorKind::SharedMemoryError;
+use crate::errors::*;
+use crate::shm_ring::ShmRing;
+use bip_utils::peek_frame_header;
+use bip_utils::wait_for_data;
+use bip_utils::BipBufferReader;
+use framework_constants::*;
+use statistics_handler::{BufferStatistics, StatsAllHandlers};
+use std::sync::Arc;
+use std::time::Instant;
+
+///The default size of the data area of a ring, it can hold 10 elements of the maximum size.
+pub const SHM_RING_CAPACITY: usize = MAX_BIP_BUFFER_MESSAGE_SIZE * 10;
+
+///Sends frames from a bip_buffer to a shared-memory ring, read by a ShmRingReader in another process.
+///It has the same API as the BufferedSocketWriter.
+pub struct ShmRingWriter {
+    ring: ShmRing,
+    path: String,
+    buffer_stats: Option<Arc<BufferStatistics>>,
+    //the read position of the ring the last time the statistics were updated
+    reported_read_position: u64,
+}
+
+impl ShmRingWriter {
+    ///Creates a new ring at `path` with a capacity of SHM_RING_CAPACITY bytes.
+    ///A ring left behind by a previous writer is replaced, a reader still using it first reads the remaining frames.
+    /// # Arguments
+    /// * `path` - The path the ring is created on, for example in /dev/shm.
+    pub fn start_listening(path: &str) -> Result<ShmRingWriter> {
+        ShmRingWriter::with_capacity(path, SHM_RING_CAPACITY)
+    }
+
+    ///Creates a new ring at `path` with the given capacity.
+    /// # Arguments
+    /// * `path` - The path the ring is created on, for example in /dev/shm.
+    /// * `capacity` - The size of the data area in bytes, at least one element of the maximum size.
+    pub fn with_capacity(path: &str, capacity: usize) -> Result<ShmRingWriter> {
+        if capacity < MAX_BIP_BUFFER_MESSAGE_SIZE {
+            return Err(SharedMemoryError(format!(
+                "A ring of {capacity} bytes can not hold an element of the maximum size"
+            ))
+            .into());
+        }
+        Ok(ShmRingWriter {
+            ring: ShmRing::create(path, capacity)?,
+            path: path.to_string(),
+            buffer_stats: None,
+            reported_read_position: 0,
+        })
+    }
+
+    ///Reports the ring as the buffer `shm` in the statistics: its fill level and the time the writer waited for the reader.
+    ///The ring does not lose its reader, the reader reopens the ring itself, so there are no reconnects to report.
+    /// # Arguments
+    /// * `stats_data` - The statistics of the handler.
+    pub fn with_stats(mut self, stats_data: Arc<StatsAllHandlers>) -> ShmRingWriter {
+        self.buffer_stats = Some(stats_data.register_buffer("shm", self.ring.capacity()));
+        self
+    }
+
+    ///Used to send data to the ring. The data that is sent is read using `reader`.
+    ///This function will block until space is available in the ring.
+    /// # Arguments
+    /// * `reader` - The BipBufferReader used to get data from the bip_buffer.
+    pub fn send_data(&mut self, reader: &mut BipBufferReader) -> Result<usize> {
+        let frame_length = FRAME_HEADER_LEN + peek_frame_header(reader).payload_length();
+        wait_for_data(reader, frame_length);
+        let start = Instant::now();
+        while !self.ring.wait_for_space(frame_length)? {}
+        self.ring.write(&reader.valid()[..frame_length]);
+        reader.consume(frame_length);
+        if let Some(buffer_stats) = &self.buffer_stats {
+            let read_position = self.ring.read_position();
+            buffer_stats
+                .add_consumed(read_position.saturating_sub(self.reported_read_position) as usize);
+            self.reported_read_position = read_position;
+            buffer_stats.add_blocked(start.elapsed());
+            buffer_stats.add_written(frame_length);
+        }
+        Ok(frame_length)
+    }
+
+    ///Stops the ShmRingWriter and removes the ring file.
+    ///A reader that still has the ring open can read the remaining frames.
+    pub fn stop(&self) {
+        match std::fs::remove_file(&self.path) {
+            Ok(_) => {
+                log::info!("Cleanup succesfull. Shutdown complete.");
+            }
+            Err(e) => {
+                log::warn!("Error while cleaning up: {}. Shutdown complete.", e);
+            }
+        };
+    }
+}
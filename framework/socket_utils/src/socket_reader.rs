@@ -14,6 +14,7 @@
 
 use crate::errors::ErrorKind::UnixDomainSocketError;
 use crate::errors::*;
+use bip_utils::frame::FrameHeader;
 use framework_constants::*;
 use std::io::Read;
 use std::net::Shutdown;
@@ -52,12 +53,15 @@ impl SocketReader {
     }
     ///This function fetches data from the socket.
     ///This data is then copied to the supplied buffer.
+    ///A malformed frame header results in an error.
     pub fn receive_data(&mut self, buffer: &mut [u8]) -> Result<usize> {
-        let mut element_length_bytes: [u8; BIP_BUFFER_LEN_FIELD_LEN] = Default::default();
+        let mut header_bytes = [0; FRAME_HEADER_LEN];
         self.stream
-            .read_exact(&mut element_length_bytes)
-            .chain_err(|| "Error reading exact when reading element length from stream")?;
-        let element_length = usize::from_le_bytes(element_length_bytes);
+            .read_exact(&mut header_bytes)
+            .chain_err(|| "Error reading exact when reading frame header from stream")?;
+        let element_length = FrameHeader::from_bytes(&header_bytes)
+            .chain_err(|| "SocketReader received a malformed frame")?
+            .payload_length();
         if element_length > buffer.len() {
             return Err(UnixDomainSocketError(
                 "Element length received by socket reader > buffer size".to_string(),
@@ -13,6 +13,7 @@
 // limitations under the License.
 
 use crate::errors::*;
+use bip_utils::frame::{FrameHeader, FrameKind};
 use framework_constants::*;
 use std::io::Write;
 use std::net::Shutdown;
@@ -59,17 +60,15 @@ impl SocketWriter {
         })
     }
 
-    ///Sends data from `buffer` to the socket.
-    #[allow(clippy::unused_io_amount)]
+    ///Sends data from `buffer` to the socket, framed as FrameKind::Data.
     pub fn send_data(&mut self, buffer: &mut [u8]) -> Result<()> {
-        let mut stream_buffer = vec![0; buffer.len() + BIP_BUFFER_LEN_FIELD_LEN];
+        let mut stream_buffer = vec![0; buffer.len() + FRAME_HEADER_LEN];
 
-        let byte_len = buffer.len().to_le_bytes();
-        stream_buffer[..BIP_BUFFER_LEN_FIELD_LEN].copy_from_slice(&byte_len);
-        stream_buffer[BIP_BUFFER_LEN_FIELD_LEN..buffer.len() + BIP_BUFFER_LEN_FIELD_LEN]
-            .copy_from_slice(buffer);
+        let header = FrameHeader::new(FrameKind::Data, buffer.len());
+        stream_buffer[..FRAME_HEADER_LEN].copy_from_slice(&header.to_bytes());
+        stream_buffer[FRAME_HEADER_LEN..buffer.len() + FRAME_HEADER_LEN].copy_from_slice(buffer);
         self.stream
-            .write(&stream_buffer)
+            .write_all(&stream_buffer)
             .chain_err(|| "Socket writer could not write to socket")?;
         Ok(())
     }
@@ -14,8 +14,8 @@
 
 use framework_constants::*;
 use logging::set_syslog;
-use socket_utils::buffered_socket_writer::BufferedSocketWriter;
-use spsc_bip_buffer::bip_buffer_with_len;
+use socket_utils::link::*;
+use bip_utils::bip_buffer_with_len;
 use statistics_handler::*;
 use std::process;
 use std::process::Command;
@@ -80,9 +80,22 @@ fn udp_receive() -> Result<()> {
     let stats_addr = format!("{}:{}", opt.host_stats_server, opt.port_stats_server);
 
     let statistics_client = StatsdClient::<StatsAllHandlers>::new_standard();
+    let statistics_client = statistics_client
+        .with_metrics_format(opt.metrics_format)
+        .with_interval(std::time::Duration::from_millis(opt.stats_interval_ms));
+    let statistics_client = match &opt.metrics_address {
+        Some(metrics_address) => statistics_client
+            .with_metrics_endpoint(metrics_address, &opt.handler_name)
+            .chain_err(|| "Error while serving metrics")?,
+        None => statistics_client,
+    };
     statistics_client
         .run(stats_addr, opt.handler_name)
         .chain_err(|| "Error while running statitics")?;
+    let writer = writer
+        .with_overflow_policy(opt.overflow_policy, &opt.spill_directory, opt.spill_max_bytes)
+        .with_stats("bip", statistics_client.get_data_clone());
+    let stats_data = statistics_client.get_data_clone();
     //build the udp_receiver thread.
     let receiver_thread_builder = std::thread::Builder::new().name("udp_receiver_thread".into());
     let receiver_handle = receiver_thread_builder.spawn(move || {
@@ -98,8 +111,10 @@ fn udp_receive() -> Result<()> {
     //build the socket_writer thread.
     let socket_writer_thread_builder =
         std::thread::Builder::new().name("socket_writer_thread".into());
-    let mut buffered_socket_writer = BufferedSocketWriter::start_listening(&path)
-        .chain_err(|| "Error creating buffered socket writer")?;
+    let mut buffered_socket_writer =
+        LinkWriter::start_listening_fan_out(opt.link_type, &path, opt.fan_out, opt.fan_out_mode)
+            .chain_err(|| "Error creating buffered socket writer")?
+            .with_stats(stats_data);
     let socket_writer_handle = socket_writer_thread_builder.spawn(move || loop {
         clean_unwrap(
             buffered_socket_writer
@@ -14,8 +14,8 @@
 
 use framework_constants::*;
 use logging::set_syslog;
-use socket_utils::buffered_socket_reader::BufferedSocketReader;
-use spsc_bip_buffer::bip_buffer_with_len;
+use socket_utils::link::*;
+use bip_utils::bip_buffer_with_len;
 use statistics_handler::*;
 use std::process;
 use std::process::Command;
@@ -68,20 +68,33 @@ fn udp_send() -> Result<()> {
     //create statistics client
     let stats_addr = format!("{}:{}", opt.host_stats_server, opt.port_stats_server);
     let statistics_client = StatsdClient::<StatsAllHandlers>::new_standard();
+    let statistics_client = statistics_client
+        .with_metrics_format(opt.metrics_format)
+        .with_interval(std::time::Duration::from_millis(opt.stats_interval_ms));
+    let statistics_client = match &opt.metrics_address {
+        Some(metrics_address) => statistics_client
+            .with_metrics_endpoint(metrics_address, &opt.handler_name)
+            .chain_err(|| "Error while serving metrics")?,
+        None => statistics_client,
+    };
     statistics_client
         .run(stats_addr, opt.handler_name)
         .chain_err(|| "Error while running statitics")?;
+    let writer = writer
+        .with_overflow_policy(opt.overflow_policy, &opt.spill_directory, opt.spill_max_bytes)
+        .with_stats("bip", statistics_client.get_data_clone());
     let stats_data = statistics_client.data;
 
     let sender = UdpSender::new(
         &format!("{}:{}", opt.sender_addr, opt.sender_port),
         reader,
         opt.send_delay_ms,
-        stats_data,
+        stats_data.clone(),
     )?;
-    let mut unix_socket_reader: BufferedSocketReader =
-        BufferedSocketReader::new(&opt.socket_path, writer)
-            .chain_err(|| "Error creating buffered socket reader")?;
+    let mut unix_socket_reader: LinkReader =
+        LinkReader::new(opt.link_type, &opt.socket_path, writer)
+            .chain_err(|| "Error creating buffered socket reader")?
+            .with_stats(stats_data);
     Command::new("renice")
         .args(["-n", "-10", "-p", &process::id().to_string()])
         .spawn()
@@ -13,12 +13,12 @@
 // limitations under the License.
 
 use bip_utils::write_to_bip_buffer;
-use framework_constants::BIP_BUFFER_LEN_FIELD_LEN;
+use framework_constants::FRAME_HEADER_LEN;
 use framework_constants::MAX_BUFFER_SIZE_BYTES;
 use framework_constants::MAX_PAYLOAD_SIZE_BYTES;
 use logging::set_syslog;
-use spsc_bip_buffer::bip_buffer_with_len;
-use spsc_bip_buffer::BipBufferWriter;
+use bip_utils::bip_buffer_with_len;
+use bip_utils::BipBufferWriter;
 use statistics_handler::*;
 use std::thread::JoinHandle;
 use structopt::*;
@@ -38,7 +38,7 @@ fn main() {
     )
     .expect("error setting syslog");
     let (writer, reader) =
-        bip_buffer_with_len((MAX_BUFFER_SIZE_BYTES + BIP_BUFFER_LEN_FIELD_LEN) * 10); //stores 10 elements
+        bip_buffer_with_len((MAX_BUFFER_SIZE_BYTES + FRAME_HEADER_LEN) * 10); //stores 10 elements
     log::info!("Starting sender at {}:{}", opt.sender_addr, opt.sender_port);
     let statistics_client = StatsdClient::<StatsAllHandlers>::new_standard();
     let stats_data = statistics_client.data;
@@ -70,9 +70,9 @@ fn _send_small_messages(writer: &mut BipBufferWriter) {
     for (i, byte) in buffer.iter_mut().enumerate() {
         *byte = i as u8;
     }
-    write_to_bip_buffer(writer, &buffer);
-    write_to_bip_buffer(writer, &buffer[..20]);
-    write_to_bip_buffer(writer, &buffer[..10]);
+    write_to_bip_buffer(writer, &buffer).expect("Can't write to bip buffer");
+    write_to_bip_buffer(writer, &buffer[..20]).expect("Can't write to bip buffer");
+    write_to_bip_buffer(writer, &buffer[..10]).expect("Can't write to bip buffer");
 }
 
 fn send_single_large_message(writer: &mut BipBufferWriter) {
@@ -80,5 +80,5 @@ fn send_single_large_message(writer: &mut BipBufferWriter) {
     for (i, byte) in buffer.iter_mut().enumerate() {
         *byte = i as u8;
     }
-    write_to_bip_buffer(writer, &buffer);
+    write_to_bip_buffer(writer, &buffer).expect("Can't write to bip buffer");
 }
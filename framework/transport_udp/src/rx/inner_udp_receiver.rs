@@ -13,10 +13,12 @@
 // limitations under the License.
 
 use crate::rx::*;
-use bip_utils::write_to_bip_buffer;
-use statistics_handler::StatsAllHandlers;
+use bip_utils::frame::FrameHeader;
+use bip_utils::write_frame_bytes_to_bip_buffer;
+use statistics_handler::{Histogram, StatsAllHandlers};
 use std::net::UdpSocket;
 use std::sync::Arc;
+use std::time::Instant;
 use MessageType::*;
 use State::*;
 
@@ -40,6 +42,12 @@ pub struct InnerUdpReceiver {
     current_sequence_number: u32,
     state: State,
     stats_data: Arc<StatsAllHandlers>,
+    ///The sizes of the frames written to the bip_buffer.
+    message_size: Arc<Histogram>,
+    ///The time from the first to the last packet of a frame that is split over several packets.
+    reassembly_time: Arc<Histogram>,
+    ///When the first packet of the current frame was received.
+    first_packet_at: Instant,
 }
 
 impl InnerUdpReceiver {
@@ -66,6 +74,9 @@ impl InnerUdpReceiver {
             combined_buffer,
             current_sequence_number,
             state: State::WaitingForFirstData,
+            message_size: stats_data.register_histogram("message.size"),
+            reassembly_time: stats_data.register_timer("reassembly.time"),
+            first_packet_at: Instant::now(),
             stats_data,
         }
     }
@@ -78,12 +89,35 @@ impl InnerUdpReceiver {
         loop {
             receive_packet(&self.socket, &mut self.packet_buffer);
             let packet_header = read_packet_header(&self.packet_buffer);
-            let lost_packets = check_for_packetloss(
+            let previous_sequence_number = self.current_sequence_number;
+            let (order, lost_packets) = check_sequence_number(
                 packet_header.sequence_number,
                 &mut self.current_sequence_number,
                 self.stats_data.clone(),
             );
             self.update_in_stats(&packet_header);
+            match order {
+                //A packet received out of order (or twice) belongs to data that was already
+                //handled or discarded. Combining it with the current data would corrupt it.
+                //It is skipped without moving the sequence number back.
+                SequenceOrder::Late => {
+                    log::warn!(
+                        "Packet with number: {} discarded because it was received out of order",
+                        packet_header.sequence_number
+                    );
+                    continue;
+                }
+                //The StartUp packets of a restarted sender were lost, start again from its first packet.
+                SequenceOrder::Restarted => {
+                    log::warn!(
+                        "Packet with number: {} is far behind number {}, the sender restarted. Sequence number reset",
+                        packet_header.sequence_number,
+                        previous_sequence_number
+                    );
+                    self.state = WaitingForFirstData;
+                }
+                SequenceOrder::InOrder => {}
+            }
             //When packetloss occurs the current data can not be used anymore.
             //State is set to WaitingForFirstData
             if lost_packets > 0 {
@@ -141,6 +175,7 @@ impl InnerUdpReceiver {
     ///This function is used to handle a message that has the DataFirst MessageType.
     fn handle_data_first_message(&mut self, packet_header: &PacketData) -> State {
         if packet_header.remaining_messages > 0 {
+            self.first_packet_at = Instant::now();
             self.combined_buffer[0].clear();
             self.combined_buffer[0].extend_from_slice(
                 &self.packet_buffer
@@ -149,16 +184,31 @@ impl InnerUdpReceiver {
             //the first count of remaining messages + 1 = total amount of messages
             WaitingForData(packet_header.remaining_messages + 1)
         } else {
-            //datafirst is the only message
-            write_to_bip_buffer(
+            //datafirst is the only message, it contains a complete frame.
+            match write_frame_bytes_to_bip_buffer(
                 &mut self.bip_writer,
                 &self.packet_buffer
                     [HEADER_SIZE_BYTES..packet_header.payload_length as usize + HEADER_SIZE_BYTES],
-            );
-            //update bytes out statistic
-            self.stats_data
-                .out_bytes
-                .add((packet_header.payload_length as usize + BIP_BUFFER_LEN_FIELD_LEN) as u64);
+            ) {
+                //update bytes out statistic
+                Ok(true) => {
+                    self.stats_data
+                        .out_bytes
+                        .add(packet_header.payload_length as u64);
+                    self.message_size
+                        .record(packet_header.payload_length as u64);
+                }
+                //dropped by the overflow policy, counted by the bip_buffer
+                Ok(false) => log::warn!(
+                    "Data dropped when writing to bip_buffer in receiver: No space in buffer!"
+                ),
+                Err(e) => {
+                    self.stats_data
+                        .dropped_bytes
+                        .add(packet_header.payload_length as u64);
+                    log::warn!("Data dropped in receiver: {}", e);
+                }
+            }
             WaitingForFirstData
         }
     }
@@ -202,34 +252,52 @@ impl InnerUdpReceiver {
     }
 
     ///This function is used to combine all packets that belong to one set of data.
-    ///The combined messages are written to the BipBuffer.
+    ///The combined messages form one frame, which is validated and written to the BipBuffer.
     fn combine_and_write_to_bip(&mut self, packet_header: &PacketData, total_messages: usize) {
-        let total_bytes = ((total_messages - 1) * MAX_PAYLOAD_SIZE_BYTES)
-            + packet_header.payload_length as usize
-            + BIP_BUFFER_LEN_FIELD_LEN;
-        if let Some(mut reservation) = self.bip_writer.reserve(total_bytes) {
-            //write length field
-            let element_bytes = (total_bytes - BIP_BUFFER_LEN_FIELD_LEN).to_le_bytes();
-            reservation[..BIP_BUFFER_LEN_FIELD_LEN].copy_from_slice(&element_bytes);
-            //write parts
-            let mut start_index = BIP_BUFFER_LEN_FIELD_LEN;
-            let mut end_index = MAX_PAYLOAD_SIZE_BYTES + start_index;
-            for message in 0..total_messages - 1 {
-                reservation[start_index..end_index].copy_from_slice(&self.combined_buffer[message]);
-                start_index = end_index;
-                end_index += MAX_PAYLOAD_SIZE_BYTES;
+        let total_bytes =
+            ((total_messages - 1) * MAX_PAYLOAD_SIZE_BYTES) + packet_header.payload_length as usize;
+        //the frame header is at the start of the first message.
+        match FrameHeader::from_bytes(&self.combined_buffer[0]) {
+            Ok(header) if header.payload_length() + FRAME_HEADER_LEN == total_bytes => {}
+            Ok(header) => {
+                self.stats_data.dropped_bytes.add(total_bytes as u64);
+                log::warn!(
+                    "Data dropped in receiver: frame of {} bytes announces {} bytes of payload",
+                    total_bytes,
+                    header.payload_length()
+                );
+                return;
             }
-            //write last, possibly < MAX_PAYLOAD_SIZE_BYTE, part.
-            reservation[start_index..].copy_from_slice(
-                &self.combined_buffer[total_messages - 1][..packet_header.payload_length as usize],
-            );
-
+            Err(e) => {
+                self.stats_data.dropped_bytes.add(total_bytes as u64);
+                log::warn!("Data dropped in receiver: {}", e);
+                return;
+            }
+        }
+        self.reassembly_time
+            .record_duration(self.first_packet_at.elapsed());
+        //the parts of the frame, the last part is possibly < MAX_PAYLOAD_SIZE_BYTES
+        let mut parts: Vec<&[u8]> = self.combined_buffer[..total_messages - 1]
+            .iter()
+            .map(|part| &part[..])
+            .collect();
+        parts.push(
+            &self.combined_buffer[total_messages - 1][..packet_header.payload_length as usize],
+        );
+        match self.bip_writer.write_frame(&parts) {
             //update bytes out statistic
-            self.stats_data.out_bytes.add(total_bytes as u64);
-            reservation.send();
-        } else {
-            self.stats_data.dropped_bytes.add(total_bytes as u64);
-            log::warn!("Data dropped when writing to bip_buffer in receiver: No space in buffer!");
+            Ok(true) => {
+                self.stats_data.out_bytes.add(total_bytes as u64);
+                self.message_size.record(total_bytes as u64);
+            }
+            //dropped by the overflow policy, counted by the bip_buffer
+            Ok(false) => {
+                log::warn!("Data dropped when writing to bip_buffer in receiver: No space in buffer!")
+            }
+            Err(e) => {
+                self.stats_data.dropped_bytes.add(total_bytes as u64);
+                log::warn!("Data dropped in receiver: {}", e);
+            }
         }
     }
 
@@ -256,7 +324,7 @@ mod test {
                 .expect("Error binding port for test");
             let statistics_client = StatsdClient::<StatsAllHandlers>::new_standard();
             let stats_data = statistics_client.data;
-            let (writer, _) = spsc_bip_buffer::bip_buffer_with_len(MAX_BIP_BUFFER_MESSAGE_SIZE);
+            let (writer, _) = bip_utils::bip_buffer_with_len(MAX_BIP_BUFFER_MESSAGE_SIZE);
             let packet_header = PacketData {
                 message_type: MessageType::Data,
                 payload_length: 0,
@@ -280,7 +348,7 @@ mod test {
             let statistics_client = StatsdClient::<StatsAllHandlers>::new_standard();
             let stats_data = statistics_client.data;
             let (writer, _) =
-                spsc_bip_buffer::bip_buffer_with_len(MAX_BIP_BUFFER_MESSAGE_SIZE * 10);
+                bip_utils::bip_buffer_with_len(MAX_BIP_BUFFER_MESSAGE_SIZE * 10);
             let mut packet_header = PacketData {
                 message_type: MessageType::DataFirst,
                 payload_length: MAX_PAYLOAD_SIZE_BYTES as u16,
@@ -312,7 +380,7 @@ mod test {
             let statistics_client = StatsdClient::<StatsAllHandlers>::new_standard();
             let stats_data = statistics_client.data;
             let (writer, _) =
-                spsc_bip_buffer::bip_buffer_with_len(MAX_BIP_BUFFER_MESSAGE_SIZE * 10);
+                bip_utils::bip_buffer_with_len(MAX_BIP_BUFFER_MESSAGE_SIZE * 10);
             let mut packet_header = PacketData {
                 message_type: MessageType::DataFirst,
                 payload_length: MAX_PAYLOAD_SIZE_BYTES as u16,
@@ -13,7 +13,7 @@
 // limitations under the License.
 
 use framework_constants::*;
-use spsc_bip_buffer::BipBufferWriter;
+use bip_utils::BipBufferWriter;
 use statistics_handler::StatsAllHandlers;
 use std::net::UdpSocket;
 use std::sync::Arc;
@@ -70,6 +70,40 @@ pub fn read_packet_header(buffer: &[u8]) -> PacketData {
     }
 }
 
+///The number of packets a sequence number can go back and still be a late packet of the current sender.
+///A sequence number further back means the sender restarted and its StartUp packets were lost.
+pub const SEQUENCE_RESYNC_WINDOW: u32 = 1024;
+
+///How the sequence number of a received packet relates to the currently known sequence number.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SequenceOrder {
+    ///The packet follows the current packet, possibly after lost packets.
+    InOrder,
+    ///The packet was received out of order or twice, it belongs to data that was already handled or discarded.
+    Late,
+    ///The sequence number went back more than SEQUENCE_RESYNC_WINDOW packets, the sender restarted.
+    Restarted,
+}
+
+///Compares the sequence number of a received packet with the currently known sequence number.
+///The sender wraps the sequence number around, a number up to half the range ahead of the current one follows it.
+///Sequence number 0 is used by the special messages and follows every number.
+/// # Arguments
+/// * `incoming` - The sequence number of the incoming packet.
+/// * `current` - The currenctly known sequence number.
+/// # Returns
+/// `SequenceOrder` - Whether the packet follows the current one, is late or comes from a restarted sender.
+pub fn sequence_order(incoming: u32, current: u32) -> SequenceOrder {
+    let behind = current.wrapping_sub(incoming);
+    if incoming == 0 || behind > u32::MAX / 2 {
+        SequenceOrder::InOrder
+    } else if behind <= SEQUENCE_RESYNC_WINDOW {
+        SequenceOrder::Late
+    } else {
+        SequenceOrder::Restarted
+    }
+}
+
 ///Packetloss is checked using the sequence number of the incoming packet.
 ///This sequence number should match the expected sequence number.
 ///If it doesn't packetloss has occured.
@@ -84,7 +118,7 @@ pub fn check_for_packetloss(
     current: &mut u32,
     stats_data: Arc<StatsAllHandlers>,
 ) -> usize {
-    let expected_sequence_number = *current + 1;
+    let expected_sequence_number = current.wrapping_add(1);
     let mut lost_packets: usize = 0;
     match incoming {
         //special case
@@ -94,9 +128,9 @@ pub fn check_for_packetloss(
         incoming if incoming == expected_sequence_number => {
             //do nothing, no packets were lost
         }
-        //if packetloss has occurred
-        incoming if incoming > expected_sequence_number => {
-            let packetloss = incoming - expected_sequence_number;
+        //if packetloss has occurred, also when the sequence number wrapped around
+        incoming if sequence_order(incoming, *current) == SequenceOrder::InOrder => {
+            let packetloss = incoming.wrapping_sub(expected_sequence_number);
             stats_data.packetloss.add(packetloss as u64);
             log::error!("Lost {} packets this iteration!", packetloss);
             lost_packets = packetloss as usize;
@@ -113,20 +147,54 @@ pub fn check_for_packetloss(
     lost_packets
 }
 
+///Checks the sequence number of a received packet and updates the currently known sequence number.
+///A late packet must be skipped, it does not move the sequence number back and is not counted as packetloss.
+///When the sender restarted the sequence number starts again from 0, the packets of the new sender
+///before the incoming one are counted as lost.
+/// # Arguments
+/// * `incoming` - The sequence number of the incoming packet.
+/// * `current` - The currenctly known sequence number.
+/// * `stats_data` - The struct used to store statistics data.
+/// # Returns
+/// `(SequenceOrder, usize)` - How the packet relates to the current one and the amount of packets lost before it.
+pub fn check_sequence_number(
+    incoming: u32,
+    current: &mut u32,
+    stats_data: Arc<StatsAllHandlers>,
+) -> (SequenceOrder, usize) {
+    let order = sequence_order(incoming, *current);
+    match order {
+        SequenceOrder::Late => (order, 0),
+        SequenceOrder::Restarted => {
+            *current = 0;
+            (order, check_for_packetloss(incoming, current, stats_data))
+        }
+        SequenceOrder::InOrder => (order, check_for_packetloss(incoming, current, stats_data)),
+    }
+}
+
 ///This struct is used to store all header information of a UDP packet.
 #[derive(Debug)]
 pub struct PacketData {
-    message_type: MessageType,
-    sequence_number: u32,
-    payload_length: u16,
-    remaining_messages: usize,
+    ///The MessageType of the packet.
+    pub message_type: MessageType,
+    ///The sequence number of the packet.
+    pub sequence_number: u32,
+    ///The length of the payload following the packet header.
+    pub payload_length: u16,
+    ///The amount of packets that still follow for the current message.
+    pub remaining_messages: usize,
 }
 
 #[cfg(test)]
 mod test {
+    use crate::rx::check_for_packetloss;
     use crate::rx::read_packet_header;
+    use crate::rx::{check_sequence_number, sequence_order, SequenceOrder, SEQUENCE_RESYNC_WINDOW};
     use crate::tx::write_packet_header;
     use framework_constants::*;
+    use statistics_handler::StatsAllHandlers;
+    use std::sync::Arc;
 
     #[test]
     fn read_writer_packet_header_test() {
@@ -150,39 +218,95 @@ mod test {
     }
 
     #[test]
-    #[ignore] //TODO: enable this again
     fn packetloss_test() {
-        //TODO: create statistics struct
-
-        // //check against 0
-        // data.sequence_number_counter.store(0, Ordering::SeqCst);
-        // data.packetloss_counter.store(0, Ordering::SeqCst);
-        // check_for_packetloss(0, &mut 0, Arc::clone(&data));
-        // assert_eq!(data.get_packetloss(), 0);
-
-        // //check an expected increase in sequence number
-        // data.sequence_number_counter.store(0, Ordering::SeqCst);
-        // data.packetloss_counter.store(0, Ordering::SeqCst);
-        // check_for_packetloss(2, &mut 0, Arc::clone(&data));
-        // assert_eq!(data.get_packetloss(), 1);
-
-        // //check for packetloss over single iteration
-        // data.sequence_number_counter.store(0, Ordering::SeqCst);
-        // data.packetloss_counter.store(0, Ordering::SeqCst);
-        // check_for_packetloss(3, &mut 0, Arc::clone(&data));
-        // assert_eq!(data.get_packetloss(), 2);
-
-        // //check for packetloss over multiple iterations
-        // data.sequence_number_counter.store(0, Ordering::SeqCst);
-        // data.packetloss_counter.store(0, Ordering::SeqCst);
-        // check_for_packetloss(1, &mut 0, Arc::clone(&data));
-        // assert_eq!(data.get_packetloss(), 0);
-        // data.sequence_number_counter.store(1, Ordering::SeqCst);
-        // check_for_packetloss(4, &mut 1, Arc::clone(&data));
-        // assert_eq!(data.get_packetloss(), 2);
-        // data.sequence_number_counter.store(4, Ordering::SeqCst);
-        // check_for_packetloss(20, &mut 4, Arc::clone(&data));
-        // //expect 15 lost + 2 previously lost packets.
-        // assert_eq!(data.get_packetloss(), 17);
+        let stats_data = Arc::new(StatsAllHandlers::default());
+
+        //check against 0
+        assert_eq!(check_for_packetloss(0, &mut 0, Arc::clone(&stats_data)), 0);
+        assert_eq!(stats_data.packetloss.load(), 0);
+
+        //check an expected increase in sequence number
+        assert_eq!(check_for_packetloss(1, &mut 0, Arc::clone(&stats_data)), 0);
+        assert_eq!(stats_data.packetloss.load(), 0);
+
+        //check for packetloss over single iteration
+        assert_eq!(check_for_packetloss(2, &mut 0, Arc::clone(&stats_data)), 1);
+        assert_eq!(stats_data.packetloss.load(), 1);
+        assert_eq!(check_for_packetloss(3, &mut 0, Arc::clone(&stats_data)), 2);
+        assert_eq!(stats_data.packetloss.load(), 3);
+
+        //check for packetloss over multiple iterations
+        let mut current = 1;
+        assert_eq!(
+            check_for_packetloss(4, &mut current, Arc::clone(&stats_data)),
+            2
+        );
+        assert_eq!(current, 4);
+        assert_eq!(
+            check_for_packetloss(20, &mut current, Arc::clone(&stats_data)),
+            15
+        );
+        //expect 15 lost + 2 + 3 previously lost packets.
+        assert_eq!(stats_data.packetloss.load(), 20);
+
+        //a packet received out of order is not counted, but resets the current sequence number
+        assert_eq!(
+            check_for_packetloss(7, &mut current, Arc::clone(&stats_data)),
+            0
+        );
+        assert_eq!(current, 7);
+        assert_eq!(stats_data.packetloss.load(), 20);
+
+        //the sequence number wraps around
+        let mut current = u32::MAX - 1;
+        assert_eq!(
+            check_for_packetloss(2, &mut current, Arc::clone(&stats_data)),
+            3
+        );
+        assert_eq!(current, 2);
+        assert_eq!(stats_data.packetloss.load(), 23);
+    }
+
+    #[test]
+    fn sequence_order_test() {
+        use SequenceOrder::*;
+        assert_eq!(sequence_order(1, 0), InOrder);
+        assert_eq!(sequence_order(20, 4), InOrder);
+        assert_eq!(sequence_order(0, 4), InOrder);
+        assert_eq!(sequence_order(3, u32::MAX), InOrder);
+        //duplicated and reordered packets
+        assert_eq!(sequence_order(4, 4), Late);
+        assert_eq!(sequence_order(3, 4), Late);
+        assert_eq!(sequence_order(u32::MAX, 3), Late);
+        assert_eq!(sequence_order(1, SEQUENCE_RESYNC_WINDOW + 1), Late);
+        //a restarted sender whose StartUp packets were lost
+        assert_eq!(sequence_order(1, SEQUENCE_RESYNC_WINDOW + 2), Restarted);
+        assert_eq!(sequence_order(1, 5000), Restarted);
+    }
+
+    #[test]
+    fn check_sequence_number_test() {
+        use SequenceOrder::*;
+        let stats_data = Arc::new(StatsAllHandlers::default());
+        let mut current = 10;
+        assert_eq!(
+            check_sequence_number(13, &mut current, Arc::clone(&stats_data)),
+            (InOrder, 2)
+        );
+        assert_eq!(current, 13);
+        //a late packet does not move the sequence number back
+        assert_eq!(
+            check_sequence_number(12, &mut current, Arc::clone(&stats_data)),
+            (Late, 0)
+        );
+        assert_eq!(current, 13);
+        //the packets of the restarted sender before the incoming one were lost
+        current = 5000;
+        assert_eq!(
+            check_sequence_number(3, &mut current, Arc::clone(&stats_data)),
+            (Restarted, 2)
+        );
+        assert_eq!(current, 3);
+        assert_eq!(stats_data.packetloss.load(), 4);
     }
 }
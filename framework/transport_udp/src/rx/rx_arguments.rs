@@ -12,6 +12,10 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use bip_utils::OverflowPolicy;
+use socket_utils::buffered_socket_writer::FanOutMode;
+use socket_utils::link::LinkType;
+use statistics_handler::MetricsFormat;
 use structopt::StructOpt;
 
 ///This struct contains all structopt definitions used by the UdpReceiver.
@@ -25,6 +29,18 @@ pub struct OptReceiver {
     ///The path used for the unix domain socket.
     pub socket_path: String,
 
+    ///The type of the links to the neighbouring handlers, can be "socket" or "shm".
+    #[structopt(long = "link_type", default_value = "socket")]
+    pub link_type: LinkType,
+
+    ///The number of handlers that read from the outgoing socket.
+    #[structopt(long = "fan_out", default_value = "1")]
+    pub fan_out: usize,
+
+    ///How the elements are divided over the handlers that read from the outgoing socket, can be "broadcast" or "round_robin".
+    #[structopt(long = "fan_out_mode", default_value = "broadcast")]
+    pub fan_out_mode: FanOutMode,
+
     #[structopt(
         long = "receiver_address",
         default_value = "192.168.0.1",
@@ -49,11 +65,35 @@ pub struct OptReceiver {
     ///The port of the stats server.
     pub port_stats_server: u16,
 
+    ///Socket address of the Prometheus /metrics endpoint, for example "0.0.0.0:9100", no endpoint when not given.
+    #[structopt(long = "metrics_address")]
+    pub metrics_address: Option<String>,
+
+    ///The format of the statistics, can be "statsd", "dogstatsd" (statsd with tags) or "influx" (InfluxDB line protocol).
+    #[structopt(long = "metrics_format", default_value = "statsd")]
+    pub metrics_format: MetricsFormat,
+
+    ///The time between two reports of the statistics, in milliseconds.
+    #[structopt(long = "stats_interval_ms", default_value = "1000")]
+    pub stats_interval_ms: u64,
+
     ///The maximum amount of elements the bip buffer can store.
     ///The size of a single element is 1Mb.
     #[structopt(long = "bip_buffer_element_count", default_value = "10")]
     pub bip_buffer_element_count: usize,
 
+    ///What happens with an element when a bip buffer is full, can be "block", "drop_newest", "drop_oldest" or "spill".
+    #[structopt(long = "overflow_policy", default_value = "drop_newest")]
+    pub overflow_policy: OverflowPolicy,
+
+    ///The directory elements are spilled to with the "spill" overflow policy.
+    #[structopt(long = "spill_directory", default_value = "/tmp")]
+    pub spill_directory: String,
+
+    ///The maximum size of the spill file in bytes, elements that would exceed it are dropped.
+    #[structopt(long = "spill_max_bytes", default_value = "1073741824")]
+    pub spill_max_bytes: u64,
+
     ///From syslog server host
     #[structopt(long = "from_host_sys_log", default_value = "0.0.0.0")]
     pub from_host_sys_log: String,
@@ -97,6 +137,7 @@ impl OptReceiver {
             format!("{}:{}", &self.from_host_sys_log, &self.from_port_sys_log)
         );
         log::info!("Log level is {}", &self.log_level);
+        log::info!("Overflow policy of the bip buffer is {:?}", &self.overflow_policy);
         log::info!("---------------------------------------\r\n\r\n");
     }
 }
@@ -28,6 +28,11 @@ impl UdpReceiver {
             socket: UdpSocket::bind(host)?,
         })
     }
+    ///Returns the address the UdpReceiver is listening on.
+    pub fn local_addr(&self) -> Result<std::net::SocketAddr> {
+        Ok(self.socket.local_addr()?)
+    }
+
     ///This function is used to start the UdpReceiver.
     ///It will create and start a InnerUdpReceiver struct.
     ///The joinhandle to this struct is returned by the run function.
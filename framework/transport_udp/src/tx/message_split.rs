@@ -14,15 +14,16 @@
 
 use crate::tx::send_data;
 use crate::tx::write_packet_header;
-use bip_utils::get_element_length;
+use bip_utils::peek_frame_header;
 use bip_utils::wait_for_data;
 use framework_constants::*;
-use spsc_bip_buffer::BipBufferReader;
+use bip_utils::BipBufferReader;
 use statistics_handler::*;
 use std::net::UdpSocket;
 use std::sync::Arc;
 
 ///This function is used to split the data read from a bip_buffer.
+///The complete frame, header included, is sent so the frame kind and flags survive the diode.
 ///The data is split into packets that can be sent over UDP(payload < 65507 bytes)
 pub fn split_and_send_data(
     socket: &UdpSocket,
@@ -31,7 +32,7 @@ pub fn split_and_send_data(
     send_delay_ms: u64,
     stats_data: Arc<StatsAllHandlers>,
 ) {
-    let element_length = get_element_length(reader);
+    let element_length = FRAME_HEADER_LEN + peek_frame_header(reader).payload_length();
     stats_data.in_bytes.add(element_length as u64);
     wait_for_data(reader, element_length);
     let element_buffer = &mut reader.valid()[..element_length];
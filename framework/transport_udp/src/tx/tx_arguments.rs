@@ -12,6 +12,9 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use bip_utils::OverflowPolicy;
+use socket_utils::link::LinkType;
+use statistics_handler::MetricsFormat;
 use structopt::StructOpt;
 
 ///This struct contains all structopt definitions used by the UdpSender.
@@ -25,6 +28,10 @@ pub struct OptSender {
     ///The path used for the unix domain socket.
     pub socket_path: String,
 
+    ///The type of the links to the neighbouring handlers, can be "socket" or "shm".
+    #[structopt(long = "link_type", default_value = "socket")]
+    pub link_type: LinkType,
+
     #[structopt(
         long = "receiver_address",
         default_value = "192.168.0.2",
@@ -65,11 +72,35 @@ pub struct OptSender {
     ///The port of the stats server.
     pub port_stats_server: u16,
 
+    ///Socket address of the Prometheus /metrics endpoint, for example "0.0.0.0:9100", no endpoint when not given.
+    #[structopt(long = "metrics_address")]
+    pub metrics_address: Option<String>,
+
+    ///The format of the statistics, can be "statsd", "dogstatsd" (statsd with tags) or "influx" (InfluxDB line protocol).
+    #[structopt(long = "metrics_format", default_value = "statsd")]
+    pub metrics_format: MetricsFormat,
+
+    ///The time between two reports of the statistics, in milliseconds.
+    #[structopt(long = "stats_interval_ms", default_value = "1000")]
+    pub stats_interval_ms: u64,
+
     #[structopt(long = "bip_buffer_element_count", default_value = "10")]
     ///The maximum amount of elements the bip buffer can store.
     ///The size of a single element is 1Mb.
     pub bip_buffer_element_count: usize,
 
+    #[structopt(long = "overflow_policy", default_value = "block")]
+    ///What happens with an element when a bip buffer is full, can be "block", "drop_newest", "drop_oldest" or "spill".
+    pub overflow_policy: OverflowPolicy,
+
+    #[structopt(long = "spill_directory", default_value = "/tmp")]
+    ///The directory elements are spilled to with the "spill" overflow policy.
+    pub spill_directory: String,
+
+    #[structopt(long = "spill_max_bytes", default_value = "1073741824")]
+    ///The maximum size of the spill file in bytes, elements that would exceed it are dropped.
+    pub spill_max_bytes: u64,
+
     #[structopt(long = "send_delay_ms", default_value = "5")]
     ///Send delay in milliseconds used for every UDP message.
     pub send_delay_ms: u64,
@@ -117,6 +148,7 @@ impl OptSender {
             format!("{}:{}", &self.from_host_sys_log, &self.from_port_sys_log)
         );
         log::info!("Log level is {}", &self.log_level);
+        log::info!("Overflow policy of the bip buffer is {:?}", &self.overflow_policy);
         log::info!("---------------------------------------\r\n\r\n");
     }
 }
@@ -16,7 +16,7 @@ use crate::errors::ErrorKind::UdpSocketError;
 use crate::errors::*;
 use crate::tx::message_split::split_and_send_data;
 use crate::tx::special_message::*;
-use spsc_bip_buffer::BipBufferReader;
+use bip_utils::BipBufferReader;
 use statistics_handler::*;
 use std::net::UdpSocket;
 use std::sync::atomic::AtomicBool;
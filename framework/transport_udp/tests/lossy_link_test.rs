@@ -0,0 +1,321 @@
+// Copyright 2020 Ministerie van Defensie
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use bip_utils::read_from_bip_buffer;
+use bip_utils::write_to_bip_buffer;
+use framework_constants::*;
+use link_simulator::model::LinkStats;
+use link_simulator::profile::*;
+use link_simulator::simulator::LinkSimulator;
+use bip_utils::bip_buffer_with_len;
+use statistics_handler::*;
+use std::net::UdpSocket;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use transport_udp::rx::udp_receiver::*;
+use transport_udp::rx::{read_packet_header, SEQUENCE_RESYNC_WINDOW};
+use transport_udp::tx::udp_sender::*;
+
+///Every message is split into two datagrams by the UdpSender.
+const MESSAGE_SIZE: usize = 100_000;
+const MESSAGE_COUNT: usize = 40;
+
+///The result of sending messages from a UdpSender to a UdpReceiver over a simulated link.
+struct LinkRun {
+    sent: Vec<Vec<u8>>,
+    received: Vec<Vec<u8>>,
+    receiver_stats: Arc<StatsAllHandlers>,
+    link_stats: LinkStats,
+}
+
+///Sends MESSAGE_COUNT messages through a LinkSimulator with the given profile and
+///collects everything the UdpReceiver wrote to its bip_buffer.
+fn run_over_link(profile: LinkProfile) -> LinkRun {
+    let sent: Vec<Vec<u8>> = (0..MESSAGE_COUNT)
+        .map(|i| {
+            (0..MESSAGE_SIZE)
+                .map(|j| (i * 7 + j) as u8)
+                .collect::<Vec<u8>>()
+        })
+        .collect();
+
+    //receiver
+    let receiver = UdpReceiver::new("127.0.0.1:0").expect("Error creating receiver");
+    let receiver_addr = receiver
+        .local_addr()
+        .expect("Error getting receiver address")
+        .to_string();
+    let (receiver_writer, mut receiver_reader) =
+        bip_buffer_with_len(MAX_BIP_BUFFER_MESSAGE_SIZE * 10);
+    let receiver_stats = Arc::new(StatsAllHandlers::default());
+    let stats_data = receiver_stats.clone();
+    let receiver_handle = std::thread::spawn(move || {
+        receiver
+            .run(receiver_writer, stats_data)
+            .expect("error while running receiver");
+    });
+
+    //simulated link
+    let simulator = LinkSimulator::new("127.0.0.1:0", &receiver_addr, profile)
+        .expect("Error creating link simulator");
+    let simulator_addr = simulator
+        .local_addr()
+        .expect("Error getting simulator address")
+        .to_string();
+    let simulator_handle = simulator.run().expect("Error running link simulator");
+
+    //sender
+    let (mut sender_writer, sender_reader) = bip_buffer_with_len(MAX_BIP_BUFFER_MESSAGE_SIZE * 10);
+    let sender_stats = Arc::new(StatsAllHandlers::default());
+    let sender = UdpSender::new("127.0.0.1:0", sender_reader, 1, sender_stats.clone())
+        .expect("cant create udp sender");
+    sender.run(&simulator_addr).expect("error running sender");
+    //let the burst of startup messages pass first, so it does not compete with the data for socket buffer space.
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while receiver_stats.in_packets.load() == 0 {
+        assert!(Instant::now() < deadline, "receiver did not receive startup messages");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    std::thread::sleep(Duration::from_millis(100));
+    for message in &sent {
+        write_to_bip_buffer(&mut sender_writer, message).expect("Can't write to bip buffer");
+    }
+
+    //wait until all datagrams are sent, then shut the receiver down.
+    let expected_packets =
+        (MESSAGE_COUNT * (MESSAGE_SIZE + FRAME_HEADER_LEN).div_ceil(MAX_PAYLOAD_SIZE_BYTES)) as u64;
+    let deadline = Instant::now() + Duration::from_secs(30);
+    while sender_stats.out_packets.load() < expected_packets {
+        assert!(Instant::now() < deadline, "sender did not send all packets");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    sender.stop();
+    receiver_handle.join().expect("Error joining receiver");
+    simulator.stop();
+    simulator_handle.join().expect("Error joining simulator");
+
+    let mut received = Vec::new();
+    let mut buffer = vec![0; MAX_BIP_BUFFER_MESSAGE_SIZE];
+    while receiver_reader.valid().len() >= FRAME_HEADER_LEN {
+        let length = read_from_bip_buffer(&mut receiver_reader, &mut buffer);
+        received.push(buffer[..length].to_vec());
+    }
+    LinkRun {
+        sent,
+        received,
+        receiver_stats,
+        link_stats: simulator.stats(),
+    }
+}
+
+///Asserts that every received message is a complete sent message, in the order they were sent.
+fn assert_ordered_subset(run: &LinkRun) {
+    let mut sent = run.sent.iter();
+    for message in &run.received {
+        assert!(
+            sent.any(|sent_message| sent_message == message),
+            "received a message that was not sent or not in order"
+        );
+    }
+}
+
+#[test]
+fn perfect_link_test() {
+    let run = run_over_link(LinkProfile::default());
+    assert_eq!(run.received, run.sent);
+    assert_eq!(run.receiver_stats.packetloss.load(), 0);
+    assert_eq!(run.link_stats.dropped, 0);
+}
+
+#[test]
+fn delayed_link_test() {
+    let run = run_over_link(LinkProfile {
+        delay_ms: 3,
+        ..Default::default()
+    });
+    assert_eq!(run.received, run.sent);
+    assert_eq!(run.receiver_stats.packetloss.load(), 0);
+}
+
+#[test]
+fn dropped_datagrams_discard_messages_test() {
+    let run = run_over_link(LinkProfile {
+        seed: 7,
+        drop_probability: 0.05,
+        ..Default::default()
+    });
+    assert!(run.link_stats.dropped > 0);
+    assert_ordered_subset(&run);
+    assert!(run.received.len() < run.sent.len());
+    //only gaps in the data sequence are counted, lost special messages are not.
+    let packetloss = run.receiver_stats.packetloss.load();
+    assert!(packetloss > 0);
+    assert!(packetloss <= run.link_stats.dropped);
+}
+
+#[test]
+fn burst_loss_test() {
+    let run = run_over_link(LinkProfile {
+        seed: 11,
+        gilbert_elliott: Some(GilbertElliott {
+            good_to_bad: 0.05,
+            bad_to_good: 0.3,
+            loss_good: 0.0,
+            loss_bad: 1.0,
+        }),
+        ..Default::default()
+    });
+    assert!(run.link_stats.dropped_in_burst > 0);
+    assert_ordered_subset(&run);
+    assert!(run.received.len() < run.sent.len());
+    assert!(run.receiver_stats.packetloss.load() > 0);
+}
+
+#[test]
+fn duplicated_datagrams_are_ignored_test() {
+    let run = run_over_link(LinkProfile {
+        seed: 3,
+        duplicate_probability: 0.2,
+        ..Default::default()
+    });
+    assert!(run.link_stats.duplicated > 0);
+    assert_eq!(run.received, run.sent);
+    assert_eq!(run.receiver_stats.packetloss.load(), 0);
+}
+
+#[test]
+fn reordered_datagrams_discard_messages_test() {
+    let run = run_over_link(LinkProfile {
+        seed: 5,
+        reorder_probability: 0.1,
+        ..Default::default()
+    });
+    assert!(run.link_stats.reordered > 0);
+    //the receiver does not buffer out of order datagrams, a swap is handled as packetloss.
+    assert_ordered_subset(&run);
+    assert!(run.received.len() < run.sent.len());
+    assert!(run.receiver_stats.packetloss.load() > 0);
+    //a late datagram is discarded without rewinding the sequence number, every swap is at most one loss
+    assert!(run.receiver_stats.packetloss.load() <= run.link_stats.reordered);
+}
+
+#[test]
+fn corrupted_payload_is_passed_on_test() {
+    let run = run_over_link(LinkProfile {
+        seed: 13,
+        corrupt_probability: 0.2,
+        corrupt_offset: HEADER_SIZE_BYTES,
+        ..Default::default()
+    });
+    //the transport has no checksum: corrupted payloads are delivered as complete messages.
+    assert_eq!(run.received.len(), run.sent.len());
+    assert_eq!(run.receiver_stats.packetloss.load(), 0);
+    let corrupted_messages = run
+        .received
+        .iter()
+        .zip(&run.sent)
+        .filter(|(received, sent)| received != sent)
+        .count() as u64;
+    assert!(corrupted_messages > 0);
+    assert!(corrupted_messages <= run.link_stats.corrupted);
+}
+
+#[test]
+fn restarted_sender_with_lost_startup_test() {
+    //more packets than the resync window, so the first packet of the restarted sender is far behind
+    let first_count = SEQUENCE_RESYNC_WINDOW as usize + 500;
+    let second_count = 100;
+    let first: Vec<Vec<u8>> = (0..first_count).map(|i| i.to_le_bytes().to_vec()).collect();
+    let second: Vec<Vec<u8>> = (0..second_count).map(|i| vec![i as u8; 100]).collect();
+
+    //receiver
+    let receiver = UdpReceiver::new("127.0.0.1:0").expect("Error creating receiver");
+    let receiver_addr = receiver
+        .local_addr()
+        .expect("Error getting receiver address");
+    let (receiver_writer, mut receiver_reader) =
+        bip_buffer_with_len(MAX_BIP_BUFFER_MESSAGE_SIZE * 10);
+    let receiver_stats = Arc::new(StatsAllHandlers::default());
+    let stats_data = receiver_stats.clone();
+    let receiver_handle = std::thread::spawn(move || {
+        receiver
+            .run(receiver_writer, stats_data)
+            .expect("error while running receiver");
+    });
+
+    //a link that loses the ShutDown packets of the first sender, and the StartUp packets and
+    //the first data packet of the second sender: all packets of the second sender with number 0
+    let link = UdpSocket::bind("127.0.0.1:0").expect("Error binding link");
+    let link_addr = link
+        .local_addr()
+        .expect("Error getting link address")
+        .to_string();
+    let link_handle = std::thread::spawn(move || {
+        let mut buffer = vec![0; MAX_BUFFER_SIZE_BYTES];
+        let mut first_sender = None;
+        loop {
+            let (length, from) = link
+                .recv_from(&mut buffer)
+                .expect("Error receiving on link");
+            let first_sender = *first_sender.get_or_insert(from);
+            let packet_header = read_packet_header(&buffer);
+            let shutdown = matches!(packet_header.message_type, MessageType::Shutdown);
+            if from == first_sender && shutdown
+                || from != first_sender && packet_header.sequence_number == 0 && !shutdown
+            {
+                continue;
+            }
+            link.send_to(&buffer[..length], receiver_addr)
+                .expect("Error sending on link");
+            if shutdown {
+                break;
+            }
+        }
+    });
+
+    //sends the messages and waits until they are sent
+    let send = |messages: &[Vec<u8>]| {
+        let (mut sender_writer, sender_reader) =
+            bip_buffer_with_len(MAX_BIP_BUFFER_MESSAGE_SIZE * 10);
+        let sender_stats = Arc::new(StatsAllHandlers::default());
+        let sender = UdpSender::new("127.0.0.1:0", sender_reader, 1, sender_stats.clone())
+            .expect("cant create udp sender");
+        sender.run(&link_addr).expect("error running sender");
+        std::thread::sleep(Duration::from_millis(100));
+        for message in messages {
+            write_to_bip_buffer(&mut sender_writer, message).expect("Can't write to bip buffer");
+        }
+        let deadline = Instant::now() + Duration::from_secs(30);
+        while sender_stats.out_packets.load() < messages.len() as u64 {
+            assert!(Instant::now() < deadline, "sender did not send all packets");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        std::thread::sleep(Duration::from_millis(100));
+        sender.stop();
+    };
+    send(&first);
+    send(&second);
+    receiver_handle.join().expect("Error joining receiver");
+    link_handle.join().expect("Error joining link");
+
+    let mut received = Vec::new();
+    let mut buffer = vec![0; MAX_BIP_BUFFER_MESSAGE_SIZE];
+    while receiver_reader.valid().len() >= FRAME_HEADER_LEN {
+        let length = read_from_bip_buffer(&mut receiver_reader, &mut buffer);
+        received.push(buffer[..length].to_vec());
+    }
+    //the receiver resyncs on the second packet of the restarted sender, the first message is lost
+    assert_eq!(received[..first_count], first[..]);
+    assert_eq!(received[first_count..], second[1..]);
+}
@@ -25,7 +25,7 @@ fn send_message() {
     let sender_ip: &str = "0.0.0.0:9541";
     let receiver = UdpReceiver::new(receiver_ip).expect("Error creating receiver");
     let (receiver_writer, mut receiver_reader) =
-        spsc_bip_buffer::bip_buffer_with_len(MAX_BIP_BUFFER_MESSAGE_SIZE * 10);
+        bip_utils::bip_buffer_with_len(MAX_BIP_BUFFER_MESSAGE_SIZE * 10);
 
     //create statistics handler
     let statistics_client = StatsdClient::<StatsAllHandlers>::new_standard();
@@ -40,14 +40,14 @@ fn send_message() {
     });
     //send over udp
     let (mut sender_writer, sender_reader) =
-        spsc_bip_buffer::bip_buffer_with_len(MAX_BIP_BUFFER_MESSAGE_SIZE * 10);
+        bip_utils::bip_buffer_with_len(MAX_BIP_BUFFER_MESSAGE_SIZE * 10);
     let sender = UdpSender::new(sender_ip, sender_reader, 5, stats_data)
         .expect("cant create udp sender");
     sender.run(receiver_ip).expect("error");
 
     //add data to the sender_bip_buffer
     let mut send_buffer = create_send_buffer();
-    write_to_bip_buffer(&mut sender_writer, &mut send_buffer);
+    write_to_bip_buffer(&mut sender_writer, &mut send_buffer).expect("Can't write to bip buffer");
     //assert on data
     let mut receive_buffer = vec![0; MAX_BIP_BUFFER_MESSAGE_SIZE * 10];
     let message_size = read_from_bip_buffer(&mut receiver_reader, &mut receive_buffer);
@@ -12,6 +12,10 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use bip_utils::OverflowPolicy;
+use socket_utils::buffered_socket_writer::FanOutMode;
+use socket_utils::link::LinkType;
+use statistics_handler::MetricsFormat;
 use structopt::StructOpt;
 ///Commandline arguments used to run ph_kafka_ingress.
 #[derive(StructOpt)]
@@ -23,6 +27,18 @@ pub struct OptIngress {
     )]
     pub socket_path: String,
 
+    ///The type of the links to the neighbouring handlers, can be "socket" or "shm".
+    #[structopt(long = "link_type", default_value = "socket")]
+    pub link_type: LinkType,
+
+    ///The number of handlers that read from the outgoing socket.
+    #[structopt(long = "fan_out", default_value = "1")]
+    pub fan_out: usize,
+
+    ///How the elements are divided over the handlers that read from the outgoing socket, can be "broadcast" or "round_robin".
+    #[structopt(long = "fan_out_mode", default_value = "broadcast")]
+    pub fan_out_mode: FanOutMode,
+
     ///Max bytes per message settings for consumer
     #[structopt(long = "max_bytes_per_partition", default_value = "1000000")]
     pub max_bytes_per_partition: usize,
@@ -35,6 +51,18 @@ pub struct OptIngress {
     #[structopt(long = "stats_server_port", default_value = "8125")]
     pub port_stats_server: u16,
 
+    ///Socket address of the Prometheus /metrics endpoint, for example "0.0.0.0:9100", no endpoint when not given.
+    #[structopt(long = "metrics_address")]
+    pub metrics_address: Option<String>,
+
+    ///The format of the statistics, can be "statsd", "dogstatsd" (statsd with tags) or "influx" (InfluxDB line protocol).
+    #[structopt(long = "metrics_format", default_value = "statsd")]
+    pub metrics_format: MetricsFormat,
+
+    ///The time between two reports of the statistics, in milliseconds.
+    #[structopt(long = "stats_interval_ms", default_value = "1000")]
+    pub stats_interval_ms: u64,
+
     ///Topic to read from the kafka server
     #[structopt(short, long = "topic_name", default_value = "TestTopic")]
     pub topic_name: String,
@@ -75,6 +103,18 @@ pub struct OptIngress {
     ///The size of a single element is 1Mb.
     #[structopt(long = "bip_buffer_element_count", default_value = "2")]
     pub bip_buffer_element_count: usize,
+
+    ///What happens with an element when a bip buffer is full, can be "block", "drop_newest", "drop_oldest" or "spill".
+    #[structopt(long = "overflow_policy", default_value = "block")]
+    pub overflow_policy: OverflowPolicy,
+
+    ///The directory elements are spilled to with the "spill" overflow policy.
+    #[structopt(long = "spill_directory", default_value = "/tmp")]
+    pub spill_directory: String,
+
+    ///The maximum size of the spill file in bytes, elements that would exceed it are dropped.
+    #[structopt(long = "spill_max_bytes", default_value = "1073741824")]
+    pub spill_max_bytes: u64,
 }
 
 ///Commandline arguments used to run ph_kafka_egress.
@@ -87,6 +127,10 @@ pub struct OptEgress {
     )]
     pub socket_path: String,
 
+    ///The type of the links to the neighbouring handlers, can be "socket" or "shm".
+    #[structopt(long = "link_type", default_value = "socket")]
+    pub link_type: LinkType,
+
     ///StatsD server host.
     #[structopt(long = "stats_server_address", default_value = "localhost")]
     pub host_stats_server: String,
@@ -95,6 +139,18 @@ pub struct OptEgress {
     #[structopt(long = "stats_server_port", default_value = "8125")]
     pub port_stats_server: u16,
 
+    ///Socket address of the Prometheus /metrics endpoint, for example "0.0.0.0:9100", no endpoint when not given.
+    #[structopt(long = "metrics_address")]
+    pub metrics_address: Option<String>,
+
+    ///The format of the statistics, can be "statsd", "dogstatsd" (statsd with tags) or "influx" (InfluxDB line protocol).
+    #[structopt(long = "metrics_format", default_value = "statsd")]
+    pub metrics_format: MetricsFormat,
+
+    ///The time between two reports of the statistics, in milliseconds.
+    #[structopt(long = "stats_interval_ms", default_value = "1000")]
+    pub stats_interval_ms: u64,
+
     ///kafka server host
     #[structopt(short, long = "host_kafka_server", default_value = "10.0.0.2")]
     pub host_kafka_server: String,
@@ -108,6 +164,27 @@ pub struct OptEgress {
     #[structopt(long = "bip_buffer_element_count", default_value = "10")]
     pub bip_buffer_element_count: usize,
 
+    ///What happens with an element when a bip buffer is full, can be "block", "drop_newest", "drop_oldest" or "spill".
+    #[structopt(long = "overflow_policy", default_value = "block")]
+    pub overflow_policy: OverflowPolicy,
+
+    ///The directory elements are spilled to with the "spill" overflow policy.
+    #[structopt(long = "spill_directory", default_value = "/tmp")]
+    pub spill_directory: String,
+
+    ///The maximum size of the spill file in bytes, elements that would exceed it are dropped.
+    #[structopt(long = "spill_max_bytes", default_value = "1073741824")]
+    pub spill_max_bytes: u64,
+
+    ///The directory of the spool that keeps messages while the Kafka server cannot be reached.
+    ///Use a persistent volume to keep the messages when the container is restarted.
+    #[structopt(long = "spool_directory", default_value = "/tmp/osdd_spool")]
+    pub spool_directory: String,
+
+    ///The maximum size of the spool in bytes, 0 disables the spool.
+    #[structopt(long = "spool_max_bytes", default_value = "104857600")]
+    pub spool_max_bytes: u64,
+
     ///Topic to replace
     #[structopt(short, long = "in_replacement", default_value = "TestTopic")]
     //Use this command to replace a specific topic name. This is the inputlist
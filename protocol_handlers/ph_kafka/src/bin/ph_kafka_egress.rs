@@ -17,10 +17,9 @@ use ph_kafka::errors::Result;
 use ph_kafka::errors::*;
 use ph_kafka::producer::EgressProducer;
 use ph_kafka::*;
-use socket_utils::buffered_socket_reader::*;
-use spsc_bip_buffer::*;
+use socket_utils::link::*;
+use bip_utils::{bip_buffer_with_len, Spool};
 use statistics_handler::*;
-use std::process::Command;
 use std::thread;
 use structopt::StructOpt;
 
@@ -58,26 +57,31 @@ fn kafka_egress() -> Result<()> {
     .chain_err(|| "Error initializing syslog")?;
     log::info!("start {}", &opt.handler_name);
 
-    //[OSDD-46]
-    Command::new("/bin/sh")
-        .args(["-c"])
-        .args(&[format!(
-            "echo '{} kafka-server-rx' >> /etc/hosts",
-            opt.host_kafka_server
-        )])
-        .spawn()?;
-
     let stats_addr = format!("{}:{}", opt.host_stats_server, opt.port_stats_server);
     let (bip_writer, mut bip_reader) =
         bip_buffer_with_len(opt.bip_buffer_element_count * MAX_BIP_BUFFER_MESSAGE_SIZE);
-    let mut socket_reader = BufferedSocketReader::new(&opt.socket_path, bip_writer)
-        .chain_err(|| "Error while create socket reader")?;
 
     //Start stats thread
     let stats: StatsdClient<StatsAllHandlers> = StatsdClient::<StatsAllHandlers>::new_standard();
+    let stats = stats
+        .with_metrics_format(opt.metrics_format)
+        .with_interval(std::time::Duration::from_millis(opt.stats_interval_ms));
+    let stats = match &opt.metrics_address {
+        Some(metrics_address) => stats
+            .with_metrics_endpoint(metrics_address, &opt.handler_name)
+            .chain_err(|| "Error while serving metrics")?,
+        None => stats,
+    };
     stats
         .run(stats_addr, opt.handler_name)
         .chain_err(|| "Error while running statitics")?;
+    let bip_writer = bip_writer
+        .with_overflow_policy(opt.overflow_policy, &opt.spill_directory, opt.spill_max_bytes)
+        .with_stats("bip", stats.get_data_clone());
+
+    let mut socket_reader = LinkReader::new(opt.link_type, &opt.socket_path, bip_writer)
+        .chain_err(|| "Error while create socket reader")?
+        .with_stats(stats.get_data_clone());
 
     //Create EgressProducer
     let mut producer = EgressProducer::new(
@@ -85,8 +89,14 @@ fn kafka_egress() -> Result<()> {
         opt.port_kafka_server,
         opt.in_replacement,
         opt.out_replacement,
-        stats.data,
-    )?;
+        stats.get_data_clone(),
+    );
+    if opt.spool_max_bytes > 0 {
+        let spool = Spool::open(&opt.spool_directory, opt.spool_max_bytes)
+            .chain_err(|| "Error while opening spool")?
+            .with_stats(stats.data);
+        producer = producer.with_spool(spool);
+    }
 
     // {UNIX_DOMAIN_SOCKET} <-- get_data_from_socket_send_to_bip_buffer --> {BIPBUFFER} <-- bipreader_send_to_kafka --> {KAFKA_SERVER}
 
@@ -19,10 +19,9 @@ use ph_kafka::consumer::IngressConsumer;
 use ph_kafka::errors::Result;
 use ph_kafka::errors::*;
 use ph_kafka::*;
-use socket_utils::buffered_socket_writer::*;
-use spsc_bip_buffer::bip_buffer_with_len;
+use socket_utils::link::*;
+use bip_utils::bip_buffer_with_len;
 use statistics_handler::*;
-use std::process::Command;
 use std::thread;
 use structopt::StructOpt;
 
@@ -57,15 +56,6 @@ fn outer_kafka_ingress() -> Result<()> {
     .chain_err(|| "Error initializing syslog")?;
     log::info!("start {}", &opt.handler_name);
 
-    //[OSDD-46]
-    Command::new("/bin/sh")
-        .args(["-c"])
-        .args(&[format!(
-            "echo '{} kafka-server-tx' >> /etc/hosts",
-            opt.host_kafka_server
-        )])
-        .spawn()?;
-
     loop {
         match inner_kafka_ingress() {
             Ok(_) => (),
@@ -80,24 +70,47 @@ fn outer_kafka_ingress() -> Result<()> {
 
 fn inner_kafka_ingress() -> Result<()> {
     let opt = arguments::OptIngress::from_args();
-    let mut socket_writer = BufferedSocketWriter::start_listening(&opt.socket_path)
-        .chain_err(|| "Error creating socket writer")?;
 
-    let (mut bip_writer_first, mut bip_reader_first) =
+    let (bip_writer_first, mut bip_reader_first) =
         bip_buffer_with_len(MAX_BIP_BUFFER_MESSAGE_SIZE * opt.bip_buffer_element_count);
-    let (mut bip_writer_second, mut bip_reader_second) =
+    let (bip_writer_second, mut bip_reader_second) =
         bip_buffer_with_len(MAX_BIP_BUFFER_MESSAGE_SIZE * opt.bip_buffer_element_count);
 
     //Start stats thread
     let stats_addr = format!("{}:{}", opt.host_stats_server, opt.port_stats_server);
     let stats: StatsdClient<StatsAllHandlers> =
         StatsdClient::<StatsAllHandlers>::new_with_custom_fields(None, Some("messages_behind"));
+    let stats = stats
+        .with_metrics_format(opt.metrics_format)
+        .with_interval(std::time::Duration::from_millis(opt.stats_interval_ms));
+    let stats = match &opt.metrics_address {
+        Some(metrics_address) => stats
+            .with_metrics_endpoint(metrics_address, &opt.handler_name)
+            .chain_err(|| "Error while serving metrics")?,
+        None => stats,
+    };
     stats
-        .run(stats_addr, opt.handler_name)
+        .run(stats_addr, opt.handler_name.clone())
         .chain_err(|| "Error while running statitics")?;
+    let mut bip_writer_first = bip_writer_first
+        .with_overflow_policy(opt.overflow_policy, &opt.spill_directory, opt.spill_max_bytes)
+        .with_stats("bip_first", stats.get_data_clone());
+    let mut bip_writer_second = bip_writer_second
+        .with_overflow_policy(opt.overflow_policy, &opt.spill_directory, opt.spill_max_bytes)
+        .with_stats("bip_second", stats.get_data_clone());
+
+    let mut socket_writer = LinkWriter::start_listening_fan_out(
+        opt.link_type,
+        &opt.socket_path,
+        opt.fan_out,
+        opt.fan_out_mode,
+    )
+    .chain_err(|| "Error creating socket writer")?
+    .with_stats(stats.get_data_clone());
 
     //Create ingress_consumer
     let topicname = opt.topic_name;
+    let handler_name = opt.handler_name;
     let mut ingress_consumer = IngressConsumer::new(
         &topicname,
         &opt.host_kafka_server,
@@ -131,6 +144,7 @@ fn inner_kafka_ingress() -> Result<()> {
         .name("serialize_packet".into())
         .spawn(move || loop {
             serialize_between_bip_buffers(
+                &handler_name,
                 &topicname,
                 &mut bip_reader_first,
                 &mut bip_writer_second,
@@ -14,21 +14,19 @@
 
 use crate::errors::ErrorKind::SendToKafka;
 use crate::errors::*;
-use crate::MAX_BIP_BUFFER_MESSAGE_SIZE;
+use crate::*;
 use bip_utils::read_from_bip_buffer;
 use bip_utils::write_to_bip_buffer;
-use error_chain::*;
 use kafka::consumer::{Consumer, FetchOffset};
 use log::trace;
-use spsc_bip_buffer::BipBufferReader;
-use spsc_bip_buffer::BipBufferWriter;
+use bip_utils::BipBufferReader;
+use bip_utils::BipBufferWriter;
+use socket_utils::envelope::*;
 use statistics_handler::*;
 use std::sync::Arc;
 
 use std::str;
 
-use crate::KafkaMessage;
-
 const OFFSET_HEADER: usize = 8;
 
 /// A struct with a Kafka consumer and settings read form the command line arguments
@@ -119,7 +117,8 @@ impl IngressConsumer {
                         buf[OFFSET_HEADER..message_length + OFFSET_HEADER]
                             .clone_from_slice(message.value);
                         //send message to bipbuffer
-                        write_to_bip_buffer(bip_writer, &buf[..message_length + OFFSET_HEADER]);
+                        write_to_bip_buffer(bip_writer, &buf[..message_length + OFFSET_HEADER])
+                            .chain_err(|| "Error writing kafka message to bip buffer")?;
 
                         match self
                             .consumer
@@ -140,11 +139,15 @@ impl IngressConsumer {
     }
 }
 
-///Read data from the bipbuffer, serialize_packet and send it to another bipbuffer
+///Read data from the bipbuffer, wrap it in an envelope and send it to another bipbuffer.
+///The topic and offset of the message are stored as headers of the envelope.
 /// # Arguments
+/// * `source` - The name of the handler, stored as the source of the envelope.
+/// * `topic` - The topic name of where the message came from.
 /// * `bip_reader` - The BipBufferWriter used to get data from the BipBuffer.
 /// * `bip_writer` - The BipBufferWriter used to send data to the BipBuffer.
 pub fn serialize_between_bip_buffers(
+    source: &str,
     topic: &str,
     bip_reader: &mut BipBufferReader,
     bip_writer: &mut BipBufferWriter,
@@ -152,18 +155,13 @@ pub fn serialize_between_bip_buffers(
     let mut buf: [u8; MAX_BIP_BUFFER_MESSAGE_SIZE] = [0; MAX_BIP_BUFFER_MESSAGE_SIZE];
     let length = read_from_bip_buffer(bip_reader, &mut buf);
     let mut offset_bytes: [u8; OFFSET_HEADER] = [0; OFFSET_HEADER];
-    for byte in offset_bytes.iter_mut() {
-        *byte = buf[*byte as usize];
-    }
-    let kafka_msg = KafkaMessage::new(
-        &buf[OFFSET_HEADER..length],
-        i64::from_be_bytes(offset_bytes),
-        topic,
-    );
-
-    match kafka_msg.serialize_packet(&mut buf) {
-        Ok(length) => write_to_bip_buffer(bip_writer, &buf[..length as usize]),
-        Err(e) => bail!(e),
-    };
+    offset_bytes.copy_from_slice(&buf[..OFFSET_HEADER]);
+    let envelope = Envelope::new(source, CONTENT_TYPE_KAFKA, &buf[OFFSET_HEADER..length])
+        .with_header(HEADER_TOPIC, topic)
+        .with_header(
+            HEADER_OFFSET,
+            &i64::from_be_bytes(offset_bytes).to_string(),
+        );
+    write_envelope_to_bip_buffer(bip_writer, &envelope)?;
     Ok(())
 }
@@ -32,6 +32,10 @@ error_chain! {
     types {
         Error, ErrorKind, ResultExt, Result;
     }
+    links {
+        SocketUtils(socket_utils::errors::Error, socket_utils::errors::ErrorKind);
+        BipUtils(bip_utils::errors::Error, bip_utils::errors::ErrorKind);
+    }
     foreign_links {
         KafkaError(kafka::Error);
         Io(::std::io::Error);
@@ -14,46 +14,71 @@
 
 use crate::errors::ErrorKind::*;
 use crate::errors::*;
-use crate::KafkaMessage;
-use bip_utils::read_from_bip_buffer;
+use crate::HEADER_TOPIC;
+use kafka::error::{ErrorKind as KafkaErrorKind, KafkaCode};
 use kafka::producer::{Producer, Record};
 use log::{info, warn};
-use spsc_bip_buffer::BipBufferReader;
+use socket_utils::envelope::*;
+use bip_utils::{BipBufferReader, Spool};
+use framework_constants::FRAME_HEADER_LEN;
 use statistics_handler::*;
 use std::str;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-const BUFFER_SIZE_BYTES: usize = 1_050_000;
+///How long the producer waits before it tries to send the messages in the spool again after a failure.
+const SPOOL_RETRY_INTERVAL: Duration = Duration::from_secs(1);
 
 /// A struct with a Kafka producer and settings read form the command line arguments
 pub struct EgressProducer {
-    producer: Producer,
+    ///The address of the Kafka server.
+    host_port: String,
+    ///Created when Kafka is first reached, so the handler can spool messages while Kafka is down at startup.
+    producer: Option<Producer>,
     stats_data: Arc<StatsAllHandlers>,
+    ///The time it takes to send a message to Kafka.
+    produce_time: Arc<Histogram>,
+    ///The time since the messages entered the OSDD, when they are sent to Kafka.
+    latency: LatencyRecorder,
     in_replacement: String,
     out_replacement: String,
+    ///Keeps the messages that could not be sent, until Kafka can be reached again.
+    spool: Option<Spool>,
+    ///The spool is not replayed before this time, after a failure.
+    next_replay: Instant,
 }
 
 impl EgressProducer {
+    ///Creates the producer, when Kafka cannot be reached yet it connects when the first message is sent.
     pub fn new(
         host: &str,
         port: u16,
         in_replacement: String,
         out_replacement: String,
         stats_data: Arc<StatsAllHandlers>,
-    ) -> Result<EgressProducer> {
-        let host_port = format!("{host}:{port}");
-        info!("Try to connect with kafka server: {}", host_port);
-        let producer = Producer::from_hosts(vec![host_port]).create();
-
-        match producer {
-            Ok(producer) => Ok(EgressProducer {
-                producer,
-                stats_data,
-                in_replacement,
-                out_replacement,
-            }),
-            Err(e) => Err(Error::with_chain(e, "Failed creating Kafka producer")),
+    ) -> EgressProducer {
+        let mut egress_producer = EgressProducer {
+            host_port: format!("{host}:{port}"),
+            producer: None,
+            produce_time: stats_data.register_timer("kafka.produce_time"),
+            latency: LatencyRecorder::new(&stats_data),
+            stats_data,
+            in_replacement,
+            out_replacement,
+            spool: None,
+            next_replay: Instant::now(),
+        };
+        if let Err(e) = egress_producer.producer() {
+            warn!("Failed creating Kafka producer, retrying when data arrives: {}", e);
         }
+        egress_producer
+    }
+
+    ///Puts the messages that cannot be sent to Kafka in `spool`, instead of returning an error.
+    ///Messages left in the spool by a previous run are sent first.
+    pub fn with_spool(mut self, spool: Spool) -> EgressProducer {
+        self.spool = Some(spool);
+        self
     }
 
     ///Reads data from the bipbuffer and send it to kafka
@@ -63,46 +88,162 @@ impl EgressProducer {
         &mut self,
         bip_reader: &mut BipBufferReader,
     ) -> Result<()> {
-        let mut buffer = [0; BUFFER_SIZE_BYTES];
-
         loop {
-            let element_length = read_from_bip_buffer(bip_reader, &mut buffer);
-            self.deserialize_incoming_data_and_send_to_kafka(&buffer[..element_length])?;
+            if self.spooling() {
+                if Instant::now() >= self.next_replay {
+                    self.replay_spool()?;
+                }
+                //keep retrying the spool while no new data arrives
+                if self.spooling()
+                    && !bip_reader.wait_for_data_until(FRAME_HEADER_LEN, self.next_replay)
+                {
+                    continue;
+                }
+            }
+            match read_envelope_from_bip_buffer(bip_reader) {
+                Ok(envelope) => self.send_envelope_to_kafka(envelope)?,
+                Err(e) => {
+                    warn!("invalid envelope received: {}", e);
+                    self.stats_data.in_packets.add(1);
+                    self.stats_data.dropped_packets.add(1);
+                }
+            }
         }
     }
 
-    /// Give a u8 array, deserialize and send it to kafka
+    /// Send the payload of an envelope to the kafka topic in its topic header
     /// # Arguments
-    /// * `bip_reader` - The BipBufferReader used to get data from the bip_buffer.
-    fn deserialize_incoming_data_and_send_to_kafka(&mut self, incoming_data: &[u8]) -> Result<()> {
-        self.stats_data.in_bytes.add(incoming_data.len() as u64);
+    /// * `envelope` - The envelope read from the bip_buffer.
+    fn send_envelope_to_kafka(&mut self, envelope: Envelope) -> Result<()> {
+        self.stats_data
+            .in_bytes
+            .add(envelope.payload.len() as u64);
         self.stats_data.in_packets.add(1);
 
-        if let Ok(kafka_message) = KafkaMessage::deserialize_packet(incoming_data) {
-            let topic = self.replace_topic(kafka_message.topic.to_string());
-            let kafka_message_length = kafka_message.payload.len();
-            match self
-                .producer
-                .send(&Record::from_value(&topic, kafka_message.payload))
-            {
-                Ok(_) => {
-                    self.stats_data.out_bytes.add(kafka_message_length as u64);
-                    self.stats_data.out_packets.add(1);
+        if let Some(topic) = envelope.header(HEADER_TOPIC) {
+            if self.spooling() {
+                //keep the order, the messages in the spool go first
+                return self.spool_envelope(&envelope);
+            }
+            let topic = self.replace_topic(topic.to_string());
+            if let Err(e) = self.send(&topic, &envelope) {
+                if is_permanent(&e) {
+                    warn!(
+                        "Kafka rejected envelope {:x}, dropping it: {}",
+                        envelope.trace_id, e
+                    );
+                    self.stats_data.dropped_packets.add(1);
+                    return Ok(());
                 }
-                Err(e) => {
-                    warn!("Error while sending data to kafka: {}", e);
+                warn!("Error while sending data to kafka: {}", e);
+                if self.spool.is_none() {
                     self.stats_data.dropped_packets.add(1);
                     return Err(SendToKafka(e.to_string()).into());
                 }
+                self.next_replay = Instant::now() + SPOOL_RETRY_INTERVAL;
+                self.spool_envelope(&envelope)?;
             }
         } else {
-            warn!("invalid KafkaMessage received");
+            warn!(
+                "envelope {:x} without a {} header received",
+                envelope.trace_id, HEADER_TOPIC
+            );
+            self.stats_data.dropped_packets.add(1);
+        }
+
+        Ok(())
+    }
+
+    ///Returns the Kafka producer, it is created when Kafka could not be reached before.
+    fn producer(&mut self) -> kafka::Result<&mut Producer> {
+        if self.producer.is_none() {
+            info!("Try to connect with kafka server: {}", self.host_port);
+            self.producer = Some(Producer::from_hosts(vec![self.host_port.clone()]).create()?);
+        }
+        Ok(self.producer.as_mut().expect("producer was created"))
+    }
+
+    ///Sends the payload of an envelope to a topic and counts it when it was sent.
+    /// # Arguments
+    /// * `topic` - The topic, after replace_topic.
+    /// * `envelope` - The envelope to send.
+    fn send(&mut self, topic: &str, envelope: &Envelope) -> kafka::Result<()> {
+        let start = Instant::now();
+        let record = Record::from_value(topic, &envelope.payload[..]);
+        let result = self.producer().and_then(|producer| producer.send(&record));
+        self.produce_time.record_duration(start.elapsed());
+        if result.is_ok() {
+            self.stats_data.out_bytes.add(envelope.payload.len() as u64);
+            self.stats_data.out_packets.add(1);
+            count_topic(&self.stats_data, topic, envelope.payload.len());
+            self.latency.record(envelope);
+        }
+        result
+    }
+
+    ///Returns true when there are messages in the spool, new messages must then be added to the spool.
+    fn spooling(&self) -> bool {
+        self.spool.as_ref().is_some_and(|spool| !spool.is_empty())
+    }
+
+    ///Adds an envelope to the spool, it is dropped when the spool is full.
+    /// # Arguments
+    /// * `envelope` - The envelope that could not be sent to Kafka.
+    fn spool_envelope(&mut self, envelope: &Envelope) -> Result<()> {
+        let spool = self.spool.as_mut().expect("spool_envelope without spool");
+        if spool.is_empty() {
+            info!("Kafka server cannot be reached, spooling messages");
+        }
+        if !spool.push(&envelope.to_bytes()?)? {
+            warn!("spool is full, dropping envelope {:x}", envelope.trace_id);
             self.stats_data.dropped_packets.add(1);
         }
+        Ok(())
+    }
 
+    ///Sends the messages in the spool to Kafka in order, until the spool is empty or sending fails.
+    ///Messages that can never be sent, because they are invalid or rejected by Kafka, are dropped so they do not block the spool.
+    fn replay_spool(&mut self) -> Result<()> {
+        while let Some(message) = self.spool()?.peek()? {
+            let envelope = match Envelope::from_bytes(&message) {
+                Ok(envelope) => envelope,
+                Err(e) => {
+                    warn!("Dropping invalid envelope from the spool: {}", e);
+                    self.stats_data.dropped_packets.add(1);
+                    self.spool()?.pop()?;
+                    continue;
+                }
+            };
+            let topic = envelope.header(HEADER_TOPIC).unwrap_or_default().to_string();
+            let topic = self.replace_topic(topic);
+            match self.send(&topic, &envelope) {
+                Ok(()) => self.spool()?.pop()?,
+                Err(e) if is_permanent(&e) => {
+                    warn!(
+                        "Kafka rejected spooled envelope {:x}, dropping it: {}",
+                        envelope.trace_id, e
+                    );
+                    self.stats_data.dropped_packets.add(1);
+                    self.spool()?.pop()?;
+                }
+                Err(e) => {
+                    warn!("Error while sending spooled data to kafka: {}", e);
+                    self.next_replay = Instant::now() + SPOOL_RETRY_INTERVAL;
+                    return Ok(());
+                }
+            }
+        }
+        info!("Kafka server reached again, spool is empty");
         Ok(())
     }
 
+    ///Returns the spool, replay_spool is only called when there is one.
+    fn spool(&mut self) -> Result<&mut Spool> {
+        self.spool
+            .as_mut()
+            .ok_or_else(|| "replay_spool without spool".into())
+    }
+
     ///Replace the topic as it is given in command args
     /// # Arguments
     /// * `topic` - input topic.
@@ -115,3 +256,36 @@ impl EgressProducer {
         topic
     }
 }
+
+///Returns true when sending a message again cannot succeed, because Kafka rejected the message itself.
+///Errors of the connection or of the leader of a partition are temporary, the message is sent again later.
+///A topic the producer does not know is permanent as well, the producer only learns the topics when it is created.
+fn is_permanent(error: &kafka::Error) -> bool {
+    let code = match error.kind() {
+        KafkaErrorKind::Kafka(code) => code,
+        KafkaErrorKind::TopicPartitionError(_, _, code) => code,
+        _ => return false,
+    };
+    matches!(
+        code,
+        KafkaCode::CorruptMessage
+            | KafkaCode::UnknownTopicOrPartition
+            | KafkaCode::InvalidMessageSize
+            | KafkaCode::MessageSizeTooLarge
+            | KafkaCode::InvalidTopic
+            | KafkaCode::RecordListTooLarge
+            | KafkaCode::InvalidRequiredAcks
+            | KafkaCode::TopicAuthorizationFailed
+            | KafkaCode::ClusterAuthorizationFailed
+            | KafkaCode::InvalidTimestamp
+            | KafkaCode::UnsupportedVersion
+    )
+}
+
+///Counts a message sent to a topic in the metrics of that topic.
+fn count_topic(stats_data: &StatsAllHandlers, topic: &str, length: usize) {
+    let labels = [("topic", topic)];
+    let registry = &stats_data.registry;
+    registry.counter("topic.out.bytes", &labels).add(length as u64);
+    registry.counter("topic.out.packets", &labels).add(1);
+}
@@ -12,6 +12,8 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use socket_utils::buffered_socket_writer::FanOutMode;
+use socket_utils::link::LinkType;
 use structopt::StructOpt;
 ///Commandline arguments used to run the mock ingress.
 #[derive(StructOpt)]
@@ -23,6 +25,18 @@ pub struct OptIngress {
     )]
     pub socket_path: String,
 
+    ///The type of the links to the neighbouring handlers, can be "socket" or "shm".
+    #[structopt(long = "link_type", default_value = "socket")]
+    pub link_type: LinkType,
+
+    ///The number of handlers that read from the outgoing socket.
+    #[structopt(long = "fan_out", default_value = "1")]
+    pub fan_out: usize,
+
+    ///How the elements are divided over the handlers that read from the outgoing socket, can be "broadcast" or "round_robin".
+    #[structopt(long = "fan_out_mode", default_value = "broadcast")]
+    pub fan_out_mode: FanOutMode,
+
     #[structopt(long = "stats_server_address", default_value = "10.0.0.2")]
     pub host_stats_server: String,
 
@@ -59,6 +73,10 @@ pub struct OptEgress {
     )]
     pub socket_path: String,
 
+    ///The type of the links to the neighbouring handlers, can be "socket" or "shm".
+    #[structopt(long = "link_type", default_value = "socket")]
+    pub link_type: LinkType,
+
     #[structopt(long = "stats_server_address", default_value = "10.0.0.2")]
     pub host_stats_server: String,
 
@@ -12,45 +12,69 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use bip_utils::bip_buffer_with_len;
+use framework_constants::MAX_BIP_BUFFER_MESSAGE_SIZE;
 use ph_mock_handler::set_syslog;
 use ph_mock_handler::*;
-use socket_utils::socket_reader::SocketReader;
+use socket_utils::envelope::{read_envelope_from_bip_buffer, LatencyRecorder};
+use socket_utils::link::*;
+use statistics_handler::*;
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::Ordering;
+use std::sync::Arc;
 use std::thread::JoinHandle;
 use structopt::StructOpt;
 
 pub struct MockHandlerEgress {
     path: String,
+    link_type: LinkType,
+    stats_data: Arc<StatsAllHandlers>,
     should_stop: AtomicBool,
 }
 
 impl MockHandlerEgress {
-    pub fn new(path: &str) -> MockHandlerEgress {
+    pub fn new(path: &str, stats_data: Arc<StatsAllHandlers>) -> MockHandlerEgress {
         MockHandlerEgress {
             path: path.to_string(),
+            link_type: LinkType::Socket,
+            stats_data,
             should_stop: AtomicBool::new(false),
         }
     }
+
+    ///Receives the data over a link of `link_type`.
+    pub fn with_link_type(mut self, link_type: LinkType) -> MockHandlerEgress {
+        self.link_type = link_type;
+        self
+    }
     #[allow(clippy::while_immutable_condition)]
     pub fn run(&self) -> JoinHandle<()> {
         log::info!("Mock Handler Egress started");
-        let mut reader = SocketReader::new(&self.path).expect("Can't create socket reader");
+        let (bip_writer, mut bip_reader) =
+            bip_buffer_with_len(MAX_BIP_BUFFER_MESSAGE_SIZE * MOCK_BIP_BUFFER_ELEMENT_COUNT);
+        let mut reader = LinkReader::new(self.link_type, &self.path, bip_writer)
+            .expect("Can't create socket reader")
+            .with_stats(self.stats_data.clone());
         let should_stop = self.should_stop.load(Ordering::SeqCst);
         std::thread::spawn(move || {
-            let mut buffer: [u8; 65508] = [0; 65508]; //max payload size + 8 length bytes
+            while !should_stop {
+                reader.receive_data().expect("Error while receiving data");
+            }
+            reader.stop().expect("Cant stop reader");
+        });
+        let latency = LatencyRecorder::new(&self.stats_data);
+        std::thread::spawn(move || {
             let mut print_counter = 0;
             while !should_stop {
-                reader
-                    .receive_data(&mut buffer)
-                    .expect("Error while receiving data");
+                let envelope = read_envelope_from_bip_buffer(&mut bip_reader)
+                    .expect("Error while reading data");
+                latency.record(&envelope);
                 if print_counter > 10_000 {
                     log::info!("Data received by Mock Handler Egress");
                     print_counter = 0;
                 }
                 print_counter += 1;
             }
-            reader.stop().expect("Cant stop reader");
         })
     }
     pub fn stop(&self) {
@@ -68,7 +92,16 @@ fn main() {
         opt.to_host_sys_log,
         opt.to_port_sys_log.to_string(),
     );
-    let egress = MockHandlerEgress::new(&opt.socket_path);
+    let stats: StatsdClient<StatsAllHandlers> =
+        StatsdClient::<StatsAllHandlers>::new_with_custom_fields(None, None);
+    stats
+        .run(
+            format!("{}:{}", opt.host_stats_server, opt.port_stats_server),
+            opt.handler_name,
+        )
+        .expect("Can't run statistics");
+    let egress = MockHandlerEgress::new(&opt.socket_path, stats.get_data_clone())
+        .with_link_type(opt.link_type);
     let egress_handle = egress.run();
     egress_handle.join().expect("Error joining thread!");
 }
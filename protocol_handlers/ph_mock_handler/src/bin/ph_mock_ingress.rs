@@ -12,8 +12,12 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use bip_utils::bip_buffer_with_len;
+use framework_constants::MAX_BIP_BUFFER_MESSAGE_SIZE;
 use ph_mock_handler::*;
-use socket_utils::socket_writer::SocketWriter;
+use socket_utils::buffered_socket_writer::FanOutMode;
+use socket_utils::envelope::*;
+use socket_utils::link::*;
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::Ordering;
 use std::thread::JoinHandle;
@@ -21,29 +25,66 @@ use structopt::StructOpt;
 
 pub struct MockHandlerIngress {
     path: String,
+    handler_name: String,
+    link_type: LinkType,
+    fan_out: usize,
+    fan_out_mode: FanOutMode,
     should_stop: AtomicBool,
 }
 
 impl MockHandlerIngress {
-    pub fn new(path: &str) -> MockHandlerIngress {
+    pub fn new(path: &str, handler_name: &str) -> MockHandlerIngress {
         MockHandlerIngress {
             path: path.to_string(),
+            handler_name: handler_name.to_string(),
+            link_type: LinkType::Socket,
+            fan_out: 1,
+            fan_out_mode: FanOutMode::Broadcast,
             should_stop: AtomicBool::new(false),
         }
     }
 
+    ///Sends the data over a link of `link_type` to `fan_out` readers.
+    pub fn with_link(
+        mut self,
+        link_type: LinkType,
+        fan_out: usize,
+        fan_out_mode: FanOutMode,
+    ) -> MockHandlerIngress {
+        self.link_type = link_type;
+        self.fan_out = fan_out;
+        self.fan_out_mode = fan_out_mode;
+        self
+    }
+
     #[allow(clippy::while_immutable_condition)]
     pub fn run(&self) -> JoinHandle<()> {
         log::info!("Mock Handler Ingress started");
-        let mut writer =
-            SocketWriter::start_listening(&self.path).expect("cant create socket writer");
+        let (mut bip_writer, mut bip_reader) =
+            bip_buffer_with_len(MAX_BIP_BUFFER_MESSAGE_SIZE * MOCK_BIP_BUFFER_ELEMENT_COUNT);
+        let mut writer = LinkWriter::start_listening_fan_out(
+            self.link_type,
+            &self.path,
+            self.fan_out,
+            self.fan_out_mode,
+        )
+        .expect("cant create socket writer");
         let should_stop = self.should_stop.load(Ordering::SeqCst);
+        let handler_name = self.handler_name.clone();
+        std::thread::spawn(move || {
+            while !should_stop {
+                //write data with max payload size
+                let envelope = Envelope::new(&handler_name, CONTENT_TYPE_OCTET_STREAM, &[0; 65500]);
+                write_envelope_to_bip_buffer(&mut bip_writer, &envelope)
+                    .expect("Error while writing data");
+            }
+        });
         std::thread::spawn(move || {
             let mut print_counter = 0;
             while !should_stop {
                 writer
-                    .send_data(&mut [0; 65500])
-                    .expect("Error while sending data"); //write data with max payload size
+                    .send_data(&mut bip_reader)
+                    .expect("Error while sending data");
                 if print_counter > 10_000 {
                     log::info!("Data sent by Mock Handler Ingress");
                     print_counter = 0;
@@ -70,7 +111,11 @@ fn main() {
         opt.to_host_sys_log,
         opt.to_port_sys_log.to_string(),
     );
-    let ingress = MockHandlerIngress::new(&opt.socket_path);
+    let ingress = MockHandlerIngress::new(&opt.socket_path, &opt.handler_name).with_link(
+        opt.link_type,
+        opt.fan_out,
+        opt.fan_out_mode,
+    );
     let ingress_handle = ingress.run();
     ingress_handle.join().expect("Error joining thread!");
 }
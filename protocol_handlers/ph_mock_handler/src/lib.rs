@@ -15,6 +15,9 @@
 use std::net::SocketAddr;
 use syslog::Facility;
 
+///The number of elements the bip buffers of the mock handlers can store.
+pub const MOCK_BIP_BUFFER_ELEMENT_COUNT: usize = 10;
+
 pub fn set_syslog(
     from_host_sys_log: String,
     from_port_sys_log: String,
@@ -12,6 +12,9 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use bip_utils::OverflowPolicy;
+use socket_utils::buffered_socket_writer::FanOutMode;
+use socket_utils::link::LinkType;
 use structopt::StructOpt;
 ///Commandline arguments used to run ph_modbus_ingress.
 #[derive(StructOpt)]
@@ -23,11 +26,35 @@ pub struct OptIngress {
     )]
     pub socket_path: String,
 
+    ///The type of the links to the neighbouring handlers, can be "socket" or "shm".
+    #[structopt(long = "link_type", default_value = "socket")]
+    pub link_type: LinkType,
+
+    ///The number of handlers that read from the outgoing socket.
+    #[structopt(long = "fan_out", default_value = "1")]
+    pub fan_out: usize,
+
+    ///How the elements are divided over the handlers that read from the outgoing socket, can be "broadcast" or "round_robin".
+    #[structopt(long = "fan_out_mode", default_value = "broadcast")]
+    pub fan_out_mode: FanOutMode,
+
     ///The maximum amount of elements the bip buffer can store.
     ///The size of a single element is 1Mb.
     #[structopt(long = "bip_buffer_element_count", default_value = "10")]
     pub bip_buffer_element_count: usize,
 
+    ///What happens with an element when a bip buffer is full, can be "block", "drop_newest", "drop_oldest" or "spill".
+    #[structopt(long = "overflow_policy", default_value = "block")]
+    pub overflow_policy: OverflowPolicy,
+
+    ///The directory elements are spilled to with the "spill" overflow policy.
+    #[structopt(long = "spill_directory", default_value = "/tmp")]
+    pub spill_directory: String,
+
+    ///The maximum size of the spill file in bytes, elements that would exceed it are dropped.
+    #[structopt(long = "spill_max_bytes", default_value = "1073741824")]
+    pub spill_max_bytes: u64,
+
     ///Port the stats handler is listening on.
     #[structopt(long = "listening_port", default_value = "1235")]
     pub listening_port: u16,
@@ -131,6 +158,10 @@ pub struct OptEgress {
     )]
     pub socket_path: String,
 
+    ///The type of the links to the neighbouring handlers, can be "socket" or "shm".
+    #[structopt(long = "link_type", default_value = "socket")]
+    pub link_type: LinkType,
+
     ///Port the stats handler is listening on.
     #[structopt(long = "listening_port", default_value = "1235")]
     pub listening_port: u16,
@@ -164,6 +195,18 @@ pub struct OptEgress {
     #[structopt(long = "bip_buffer_element_count", default_value = "100")]
     pub bip_buffer_element_count: usize,
 
+    ///What happens with an element when a bip buffer is full, can be "block", "drop_newest", "drop_oldest" or "spill".
+    #[structopt(long = "overflow_policy", default_value = "block")]
+    pub overflow_policy: OverflowPolicy,
+
+    ///The directory elements are spilled to with the "spill" overflow policy.
+    #[structopt(long = "spill_directory", default_value = "/tmp")]
+    pub spill_directory: String,
+
+    ///The maximum size of the spill file in bytes, elements that would exceed it are dropped.
+    #[structopt(long = "spill_max_bytes", default_value = "1073741824")]
+    pub spill_max_bytes: u64,
+
     ///Log level for logging
     #[structopt(long = "log_level", default_value = "Warn")]
     pub log_level: String,
@@ -190,6 +233,11 @@ pub struct OptEgress {
     #[structopt(long = "modbus_port", default_value = "502")]
     pub modbus_port: u16,
 
+    // Write mode: Not used.
+    // Read mode:  Responses of the virtual Modbus server are delayed by the given amount of ms.
+    #[structopt(long = "modbus_delay_ms", default_value = "0")]
+    pub modbus_delay_ms: u64,
+
     // How long should a TCP connection attempt take? (in milliseconds)
     #[structopt(long = "modbus_tcp_timeout", default_value = "250")]
     pub modbus_tcp_timeout: u64,
@@ -7,16 +7,16 @@ use rand::Rng;
 
 mod request_handler;
 pub use request_handler::{RequestHandler, Frame, ModbusDatabank};
-mod data_packet;
-pub use data_packet::{DataPacket, DataType};
-use spsc_bip_buffer::BipBufferWriter;
-use bip_utils::write_to_bip_buffer;
+pub use ph_modbus::data_packet::{DataPacket, DataType};
+use bip_utils::BipBufferWriter;
 
 pub struct ModbusServer {
     pub host: String,
     pub handler: Arc<Mutex<RequestHandler>>,
     pub write_buffer: Option<Arc<Mutex<BipBufferWriter>>>,
     pub response_delay: u64,
+    pub handler_name: String,
+    pub fec_resend_count: u8,
 }
 
 #[allow(dead_code)]
@@ -35,6 +35,8 @@ impl ModbusServer {
         let handler = self.handler.clone();
         let buffer = self.write_buffer.clone();
         let delay = self.response_delay;
+        let handler_name = self.handler_name.clone();
+        let copies = self.fec_resend_count as usize + 1;
         thread::spawn(move||{
             let mut data = [0u8; 4096];
             loop {
@@ -53,7 +55,9 @@ impl ModbusServer {
                                     // Wrap the request in a DataPacket so the receiver knows what type of data it is
                                     let udp_data = DataPacket::new(DataType::ModbusCommand, input_frame.to_bytes(), id);
                                     // Add the data packet to buffer
-                                    write_to_bip_buffer(&mut buffer.as_ref().expect("User somehow managed to write to the egress proxy while in read mode.").lock().unwrap(), &udp_data.to_bytes());
+                                    if let Err(e) = udp_data.write_envelope(&mut buffer.as_ref().expect("User somehow managed to write to the egress proxy while in read mode.").lock().unwrap(), &handler_name, copies) {
+                                        log::warn!("Could not write modbus command to bip buffer: {}", e);
+                                    }
                                 }
                             }
                         }
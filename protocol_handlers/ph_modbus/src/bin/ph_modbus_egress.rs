@@ -12,12 +12,13 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use bip_utils::read_from_bip_buffer;
 use logging::*;
 use ph_modbus::errors::*;
 use ph_modbus::*;
-use socket_utils::buffered_socket_reader::BufferedSocketReader;
-use spsc_bip_buffer::bip_buffer_with_len;
+use socket_utils::link::LinkReader;
+use socket_utils::envelope::{read_envelope_from_bip_buffer, Envelope, LatencyRecorder};
+use bip_utils::{bip_buffer_with_len, BipBufferReader};
+use statistics_handler::*;
 use std::thread;
 use structopt::StructOpt;
 
@@ -30,7 +31,7 @@ use modbus_server::{RequestHandler, ModbusDatabank, ModbusServer, DataType, Data
 use std::sync::{Arc, Mutex};
 
 fn main() {
-    let opt = arguments::OptIngress::from_args();
+    let opt = arguments::OptEgress::from_args();
     set_syslog(
         opt.from_host_sys_log.as_str(),
         opt.from_port_sys_log.to_string().as_str(),
@@ -40,11 +41,17 @@ fn main() {
         opt.handler_name.as_str(),
     ).expect("Could not set syslog");
 
+    // Start the stats thread, the latency of the delivered data packets is reported in the timer `latency`
+    let stats: StatsdClient<StatsAllHandlers> = StatsdClient::<StatsAllHandlers>::new_with_custom_fields(None, None);
+    stats.run(format!("{}:{}", opt.host_stats_server, opt.port_stats_server), opt.handler_name.clone()).expect("Could not run statistics");
+    let latency = LatencyRecorder::new(&stats.get_data_clone());
+
     // Create a shared buffer
     let (bip_writer, bip_reader) = bip_buffer_with_len(MAX_BIP_BUFFER_MESSAGE_SIZE * opt.bip_buffer_element_count as usize);
+    let bip_writer = bip_writer.with_overflow_policy(opt.overflow_policy, &opt.spill_directory, opt.spill_max_bytes).with_stats("bip", stats.get_data_clone());
     // Store any incoming data into the shared buffer
     let bip_reader_guard = Arc::new(Mutex::new(bip_reader));
-    let mut reader = BufferedSocketReader::new(&opt.socket_path, bip_writer).expect("Failed to create socket_reader");
+    let mut reader = LinkReader::new(opt.link_type, &opt.socket_path, bip_writer).expect("Failed to create socket_reader").with_stats(stats.get_data_clone());
     thread::spawn(move||{
         loop {
             reader.receive_data().chain_err(|| "Failed to read socket data").chain_unwrap();
@@ -64,13 +71,9 @@ fn main() {
             // Forward the data to the right modbus device
             loop {
                 // Read from buffer
-                let mut buffer = [0u8;300];
                 let _bip_reader = bip_reader_guard.clone();
-                let length = read_from_bip_buffer(&mut _bip_reader.lock().unwrap(), &mut buffer);
-                let data = &buffer[..length];
-                if length > 0 {
-                    // Create DataPacket from data
-                    let data_packet = DataPacket::from_bytes(data.to_vec());
+                let received = read_data_packet(&mut _bip_reader.lock().unwrap());
+                if let Some((envelope, data_packet)) = received {
                     if data_packet.get_id() != latest_packet_id {
                         if data_packet.datatype == DataType::ModbusCommand {
                             // Create a modbus frame
@@ -80,6 +83,7 @@ fn main() {
                                 print!("Sending to Modbus device: ");
                                 modbus_frame.print();
                                 modbus_client.send_raw(modbus_frame.to_bytes());
+                                latency.record(&envelope);
                             } else {
                                 log::info!("Not handling invalid Modbus frame:");
                             }
@@ -102,6 +106,8 @@ fn main() {
                 handler: modbus_request_handler,
                 response_delay: opt.modbus_delay_ms,
                 write_buffer: None,
+                handler_name: opt.handler_name,
+                fec_resend_count: 0,
             };
             let mut last_coil_packet_id: u16 = 0;
             let mut last_input_packet_id: u16 = 0;
@@ -112,10 +118,10 @@ fn main() {
             let receive_thread = thread::spawn(move||{
                 log::info!("Accepting incoming data.");
                 loop {
-                    let mut buffer = [0u8;300];
-                    let length = read_from_bip_buffer(&mut _bip_reader.lock().unwrap(), &mut buffer);
-                    let data = &buffer[..length];
-                    let data_packet = DataPacket::from_bytes(data.to_vec());
+                    let (envelope, data_packet) = match read_data_packet(&mut _bip_reader.lock().unwrap()) {
+                        Some(received) => received,
+                        None => continue,
+                    };
                     let mut __modbus_databank = _modbus_databank.clone();
                     match data_packet.datatype {
                         DataType::ModbusCommand => {log::info!("Ignoring Modbus command, we're in read mode.");},
@@ -127,6 +133,7 @@ fn main() {
                                     address, [status].to_vec()
                                 );
                                 last_coil_packet_id = data_packet.get_id();
+                                latency.record(&envelope);
                             }
                         },
                         DataType::InputValue => {
@@ -137,6 +144,7 @@ fn main() {
                                     address, [status].to_vec()
                                 );
                                 last_input_packet_id = data_packet.get_id();
+                                latency.record(&envelope);
                             }
                         },
                         DataType::HoldingRegisterValue => {
@@ -147,6 +155,7 @@ fn main() {
                                     address, [value].to_vec()
                                 );
                                 last_holding_register_packet_id = data_packet.get_id();
+                                latency.record(&envelope);
                             }
                         },
                         DataType::InputRegisterValue => {
@@ -157,6 +166,7 @@ fn main() {
                                     address, [value].to_vec()
                                 );
                                 last_input_register_packet_id = data_packet.get_id();
+                                latency.record(&envelope);
                             }
                         },
                         _ => {log::info!("Ignoring wrong type of data packet")}
@@ -175,3 +185,17 @@ fn main() {
 
     panic!("Modbus egress proxy stopped working.");
 }
+
+// Reads the next envelope from the bip buffer and takes the DataPacket out of it
+fn read_data_packet(bip_reader: &mut BipBufferReader) -> Option<(Envelope, DataPacket)> {
+    match read_envelope_from_bip_buffer(bip_reader) {
+        Ok(mut envelope) => {
+            let data_packet = DataPacket::from_bytes(std::mem::take(&mut envelope.payload));
+            Some((envelope, data_packet))
+        }
+        Err(e) => {
+            log::warn!("Could not read envelope from bip buffer: {}", e);
+            None
+        }
+    }
+}
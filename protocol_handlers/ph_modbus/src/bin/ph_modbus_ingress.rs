@@ -12,21 +12,19 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use bip_utils::write_to_bip_buffer;
 use logging::*;
 use ph_modbus::*;
-use spsc_bip_buffer::bip_buffer_with_len;
+use bip_utils::bip_buffer_with_len;
 use std::thread;
 use structopt::StructOpt;
 use rand::Rng;
 
 use std::sync::{Arc, Mutex};
-use socket_utils::socket_writer::SocketWriter;
+use socket_utils::link::LinkWriter;
 use std::time::Duration;
 use std::thread::JoinHandle;
 use std::str::FromStr;
-use bip_utils::read_from_bip_buffer;
-use spsc_bip_buffer::BipBufferWriter;
+use bip_utils::BipBufferWriter;
 
 #[path = "./modbus/modbus_server.rs"]
 mod modbus_server;
@@ -52,26 +50,23 @@ fn main() {
     ).expect("Could not set syslog");
 
     // Create a shared bip buffer
-    let (bip_writer, bip_reader) = bip_buffer_with_len(MAX_BIP_BUFFER_MESSAGE_SIZE * opt.bip_buffer_element_count as usize);
-    let bip_reader_guard = Arc::new(Mutex::new(bip_reader));
+    let (bip_writer, mut bip_reader) = bip_buffer_with_len(MAX_BIP_BUFFER_MESSAGE_SIZE * opt.bip_buffer_element_count as usize);
+    let bip_writer = bip_writer.with_overflow_policy(opt.overflow_policy, &opt.spill_directory, opt.spill_max_bytes);
     let bip_writer_guard = Arc::new(Mutex::new(bip_writer));
 
     let mut threads: Vec<JoinHandle<()>> = vec![];
 
+    // Every data packet is written fec_resend_count+1 times, the egress proxy skips the copies
+    let copies = opt.fec_resend_count as usize + 1;
+    let handler_name = opt.handler_name.clone();
+
     // Start a thread that forwards data to the other side of the data diode
-    let fec_resend_count = opt.fec_resend_count;
+    let socket_path = opt.socket_path.clone();
+    let (link_type, fan_out, fan_out_mode) = (opt.link_type, opt.fan_out, opt.fan_out_mode);
     let forwarding_thread = thread::spawn(move || {
-        let mut _bip_reader = bip_reader_guard.clone();
-        let mut socket_writer = SocketWriter::start_listening(&opt.socket_path).expect("Failed to create socket_writer");
+        let mut socket_writer = LinkWriter::start_listening_fan_out(link_type, &socket_path, fan_out, fan_out_mode).expect("Failed to create socket_writer");
         loop {
-            let mut buffer = vec![0u8; MAX_BIP_BUFFER_MESSAGE_SIZE];
-            let length = read_from_bip_buffer(&mut _bip_reader.lock().unwrap(), &mut buffer);
-            if length > 0 {
-                for _ in 0..fec_resend_count+1 {
-                    log::info!("SEND: {:?}", &buffer[..length]);
-                    socket_writer.send_data(&mut buffer[..length]).expect("Failed to send data");
-                }
-            }
+            socket_writer.send_data(&mut bip_reader).expect("Failed to send data");
         }
     });
     threads.push(forwarding_thread);
@@ -87,43 +82,50 @@ fn main() {
                 host: format!("{}:{}", opt.modbus_address, opt.modbus_port),
                 write_buffer: Some(bip_writer_guard),
                 response_delay: opt.modbus_delay_ms,
+                handler_name,
+                fec_resend_count: opt.fec_resend_count,
             };
             // Run the modbus server
             modbus_server.run();
         },
         "read" => {
+            let packet_writer = PacketWriter {
+                bip_writer: bip_writer_guard,
+                handler_name,
+                copies,
+            };
             // Start a thread in which coils are read
             let host = opt.modbus_address.clone();
-            let mut _bip_writer = bip_writer_guard.clone();
+            let _packet_writer = packet_writer.clone();
             let coil_read_thread = thread::spawn(move||{
-                read_coils(&opt.modbus_coil_addresses_to_read, _bip_writer, &host, opt.modbus_port, opt.modbus_tcp_timeout, opt.modbus_delay_ms);
+                read_coils(&opt.modbus_coil_addresses_to_read, _packet_writer, &host, opt.modbus_port, opt.modbus_tcp_timeout, opt.modbus_delay_ms);
             });
             threads.push(coil_read_thread);
 
             // Start a thread in which inputs are read
             thread::sleep(Duration::from_millis(opt.modbus_delay_ms/4));
             let host = opt.modbus_address.clone();
-            let mut _bip_writer = bip_writer_guard.clone();
+            let _packet_writer = packet_writer.clone();
             let input_read_thread = thread::spawn(move||{
-                read_inputs(&opt.modbus_input_addresses_to_read, _bip_writer, &host, opt.modbus_port, opt.modbus_tcp_timeout, opt.modbus_delay_ms);
+                read_inputs(&opt.modbus_input_addresses_to_read, _packet_writer, &host, opt.modbus_port, opt.modbus_tcp_timeout, opt.modbus_delay_ms);
             });
             threads.push(input_read_thread);
 
             // Start a thread in which holding registers are read
             thread::sleep(Duration::from_millis(opt.modbus_delay_ms/4));
             let host = opt.modbus_address.clone();
-            let mut _bip_writer = bip_writer_guard.clone();
+            let _packet_writer = packet_writer.clone();
             let holding_register_read_thread = thread::spawn(move||{
-                read_holding_registers(&opt.modbus_holding_register_addresses_to_read, _bip_writer, &host, opt.modbus_port, opt.modbus_tcp_timeout, opt.modbus_delay_ms);
+                read_holding_registers(&opt.modbus_holding_register_addresses_to_read, _packet_writer, &host, opt.modbus_port, opt.modbus_tcp_timeout, opt.modbus_delay_ms);
             });
             threads.push(holding_register_read_thread);
 
             // Start a thread in which input registers are read
             thread::sleep(Duration::from_millis(opt.modbus_delay_ms/4));
             let host = opt.modbus_address.clone();
-            let mut _bip_writer = bip_writer_guard;
+            let _packet_writer = packet_writer.clone();
             let input_register_read_thread = thread::spawn(move||{
-                read_input_registers(&opt.modbus_input_register_addresses_to_read, _bip_writer, &host, opt.modbus_port, opt.modbus_tcp_timeout, opt.modbus_delay_ms);
+                read_input_registers(&opt.modbus_input_register_addresses_to_read, _packet_writer, &host, opt.modbus_port, opt.modbus_tcp_timeout, opt.modbus_delay_ms);
             });
             threads.push(input_register_read_thread);
         }
@@ -137,7 +139,23 @@ fn main() {
     }
 }
 
-fn read_coils(modbus_coil_addresses_to_read: &String, bip_writer: Arc<Mutex<BipBufferWriter>>, modbus_address: &String, modbus_port: u16, modbus_tcp_timeout: u64, modbus_delay_ms: u64) {
+// Writes the data packets that are read from the Modbus device to the bip buffer
+#[derive(Clone)]
+struct PacketWriter {
+    bip_writer: Arc<Mutex<BipBufferWriter>>,
+    handler_name: String,
+    copies: usize,
+}
+
+impl PacketWriter {
+    fn write(&self, data_packet: &DataPacket) {
+        if let Err(e) = data_packet.write_envelope(&mut self.bip_writer.lock().unwrap(), &self.handler_name, self.copies) {
+            log::warn!("Could not write data packet to bip buffer: {}", e);
+        }
+    }
+}
+
+fn read_coils(modbus_coil_addresses_to_read: &String, packet_writer: PacketWriter, modbus_address: &String, modbus_port: u16, modbus_tcp_timeout: u64, modbus_delay_ms: u64) {
     // Read the given coil addresses and send them over
     let tcp_client = TcpClient::new(modbus_address.to_string(), modbus_port, modbus_tcp_timeout, true);
     let mut modbus_client = ModbusClient {
@@ -175,7 +193,7 @@ fn read_coils(modbus_coil_addresses_to_read: &String, bip_writer: Arc<Mutex<BipB
                             *status as u8                               // coil status
                             ].to_vec()
                         };
-                        write_to_bip_buffer(&mut bip_writer.lock().unwrap(), &data_packet.to_bytes());
+                        packet_writer.write(&data_packet);
                     }
                 } else {
                     log::info!("Could not read coil value(s) for {:?}", item);
@@ -190,7 +208,7 @@ fn read_coils(modbus_coil_addresses_to_read: &String, bip_writer: Arc<Mutex<BipB
     }
 }
 
-fn read_inputs(modbus_input_addresses_to_read: &String, bip_writer: Arc<Mutex<BipBufferWriter>>, modbus_address: &String, modbus_port: u16, modbus_tcp_timeout: u64, modbus_delay_ms: u64) {
+fn read_inputs(modbus_input_addresses_to_read: &String, packet_writer: PacketWriter, modbus_address: &String, modbus_port: u16, modbus_tcp_timeout: u64, modbus_delay_ms: u64) {
     // Read the given input addresses and send them over
     let tcp_client = TcpClient::new(modbus_address.to_string(), modbus_port, modbus_tcp_timeout, true);
     let mut modbus_client = ModbusClient {
@@ -228,7 +246,7 @@ fn read_inputs(modbus_input_addresses_to_read: &String, bip_writer: Arc<Mutex<Bi
                                 *status as u8                               // input status
                             ].to_vec()
                         };
-                        write_to_bip_buffer(&mut bip_writer.lock().unwrap(), &data_packet.to_bytes());
+                        packet_writer.write(&data_packet);
                     }
                 } else {
                     log::info!("Could not read input value(s) for {:?}", item);
@@ -243,7 +261,7 @@ fn read_inputs(modbus_input_addresses_to_read: &String, bip_writer: Arc<Mutex<Bi
     }
 }
 
-fn read_holding_registers(modbus_holding_register_addresses_to_read: &String, bip_writer: Arc<Mutex<BipBufferWriter>>, modbus_address: &String, modbus_port: u16, modbus_tcp_timeout: u64, modbus_delay_ms: u64) {
+fn read_holding_registers(modbus_holding_register_addresses_to_read: &String, packet_writer: PacketWriter, modbus_address: &String, modbus_port: u16, modbus_tcp_timeout: u64, modbus_delay_ms: u64) {
     // Read the given input addresses and send them over
     let tcp_client = TcpClient::new(modbus_address.to_string(), modbus_port, modbus_tcp_timeout, true);
     let mut modbus_client = ModbusClient {
@@ -282,7 +300,7 @@ fn read_holding_registers(modbus_holding_register_addresses_to_read: &String, bi
                                 Utils::u16_to_u8(*status)[1]                // register status
                             ].to_vec()
                         };
-                        write_to_bip_buffer(&mut bip_writer.lock().unwrap(), &data_packet.to_bytes());
+                        packet_writer.write(&data_packet);
                     }
                 } else {
                     log::info!("Could not read holding register value(s) for {:?}", item);
@@ -297,7 +315,7 @@ fn read_holding_registers(modbus_holding_register_addresses_to_read: &String, bi
     }
 }
 
-fn read_input_registers(modbus_input_register_addresses_to_read: &String, bip_writer: Arc<Mutex<BipBufferWriter>>, modbus_address: &String, modbus_port: u16, modbus_tcp_timeout: u64, modbus_delay_ms: u64) {
+fn read_input_registers(modbus_input_register_addresses_to_read: &String, packet_writer: PacketWriter, modbus_address: &String, modbus_port: u16, modbus_tcp_timeout: u64, modbus_delay_ms: u64) {
     // Read the given input addresses and send them over
     let tcp_client = TcpClient::new(modbus_address.to_string(), modbus_port, modbus_tcp_timeout, true);
     let mut modbus_client = ModbusClient {
@@ -336,7 +354,7 @@ fn read_input_registers(modbus_input_register_addresses_to_read: &String, bip_wr
                                 Utils::u16_to_u8(*status)[1]                // register status
                             ].to_vec()
                         };
-                        write_to_bip_buffer(&mut bip_writer.lock().unwrap(), &data_packet.to_bytes());
+                        packet_writer.write(&data_packet);
                     }
                 } else {
                     log::info!("Could not read input register value(s) for {:?}", item);
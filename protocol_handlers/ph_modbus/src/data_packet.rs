@@ -1,6 +1,22 @@
-#[path = "./utils.rs"]
-mod utils;
-pub use utils::Utils;
+// Copyright 2020 Ministerie van Defensie
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use bip_utils::BipBufferWriter;
+use socket_utils::envelope::{write_envelope_to_bip_buffer, Envelope};
+
+// The content type of the envelopes that carry a DataPacket
+pub const CONTENT_TYPE_DATA_PACKET: &str = "application/x-modbus-data-packet";
 
 #[derive(Copy, Clone, PartialEq, Eq)]
 pub enum DataType {
@@ -56,7 +72,7 @@ impl DataPacket {
             4 => DataType::InputRegisterValue,
             _ => DataType::Undefined
         };
-        let id = Utils::u8_to_u16(data[1], data[2]);
+        let id = u16::from_be_bytes([data[1], data[2]]);
         let value = data[3..].to_vec();
 
         Self {
@@ -68,9 +84,16 @@ impl DataPacket {
     pub fn to_bytes(&self) -> Vec<u8> {
         let mut out: Vec<u8> = vec![];
         out.push(self.datatype as u8);
-        out.push(Utils::u16_to_u8(self.id)[0]);
-        out.push(Utils::u16_to_u8(self.id)[1]);
+        out.extend(self.id.to_be_bytes());
         out.extend(&self.value);
         out
     }
+    // Writes the DataPacket to the bip buffer as an envelope, `copies` times so the egress proxy can recover from packet loss
+    pub fn write_envelope(&self, bip_writer: &mut BipBufferWriter, source: &str, copies: usize) -> socket_utils::errors::Result<()> {
+        let envelope = Envelope::new(source, CONTENT_TYPE_DATA_PACKET, &self.to_bytes());
+        for _ in 0..copies {
+            write_envelope_to_bip_buffer(bip_writer, &envelope)?;
+        }
+        Ok(())
+    }
 }
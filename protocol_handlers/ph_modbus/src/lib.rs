@@ -14,6 +14,8 @@
 
 /// Arguments for ph_udp
 pub mod arguments;
+/// The DataPacket format sent between ph_modbus_ingress and ph_modbus_egress
+pub mod data_packet;
 pub mod errors;
 
 ///	The maximum size in bytes of a single bipbuffer message.
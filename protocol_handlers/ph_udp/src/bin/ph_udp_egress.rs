@@ -12,12 +12,12 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use bip_utils::read_from_bip_buffer;
 use logging::*;
 use ph_udp::errors::*;
 use ph_udp::*;
-use socket_utils::buffered_socket_reader::BufferedSocketReader;
-use spsc_bip_buffer::bip_buffer_with_len;
+use socket_utils::envelope::{read_envelope_from_bip_buffer, LatencyRecorder};
+use socket_utils::link::*;
+use bip_utils::bip_buffer_with_len;
 use statistics_handler::*;
 use std::net::UdpSocket;
 use std::thread;
@@ -56,16 +56,30 @@ fn outer_udp_egress() -> Result<()> {
 
     let (bip_writer, mut bip_reader) =
         bip_buffer_with_len(opt.bip_buffer_element_count * MAX_BIP_BUFFER_MESSAGE_SIZE);
-    let mut socket_reader = BufferedSocketReader::new(&opt.socket_path, bip_writer)
-        .chain_err(|| "Error while creating socket reader")?;
 
     //Start stats thread
     let stats_addr = format!("{}:{}", opt.host_stats_server, opt.port_stats_server);
     let stats: StatsdClient<StatsAllHandlers> =
         StatsdClient::<StatsAllHandlers>::new_with_custom_fields(None, None);
+    let stats = stats
+        .with_metrics_format(opt.metrics_format)
+        .with_interval(std::time::Duration::from_millis(opt.stats_interval_ms));
+    let stats = match &opt.metrics_address {
+        Some(metrics_address) => stats
+            .with_metrics_endpoint(metrics_address, &opt.handler_name)
+            .chain_err(|| "Error while serving metrics")?,
+        None => stats,
+    };
     stats
         .run(stats_addr, opt.handler_name)
         .chain_err(|| "Error while running statitics")?;
+    let bip_writer = bip_writer
+        .with_overflow_policy(opt.overflow_policy, &opt.spill_directory, opt.spill_max_bytes)
+        .with_stats("bip", stats.get_data_clone());
+
+    let mut socket_reader = LinkReader::new(opt.link_type, &opt.socket_path, bip_writer)
+        .chain_err(|| "Error while creating socket reader")?
+        .with_stats(stats.get_data_clone());
 
     let udp_receiver_server = format!("{}:{}", opt.udp_receiver_host, opt.udp_receiver_port);
 
@@ -94,19 +108,27 @@ fn outer_udp_egress() -> Result<()> {
         .chain_err(|| "error while parsing udp socket")?;
 
     let stats_data = stats.get_data_clone();
-    //65535 is max udp pakket size
-    let mut buffer = [0; MAX_UDP_SIZE];
     let stats_server: std::net::SocketAddr = udp_receiver_server
         .parse()
         .chain_err(|| "Cannot parse stats server and host to socket address")?;
+    let latency = LatencyRecorder::new(&stats_data);
     let udp_sender = thread::Builder::new()
         .name("udp_sender".into())
         .spawn(move || loop {
-            let element_length = read_from_bip_buffer(&mut bip_reader, &mut buffer);
-            match socket.send_to(&buffer[0..element_length], stats_server) {
+            let envelope = match read_envelope_from_bip_buffer(&mut bip_reader) {
+                Ok(envelope) => envelope,
+                Err(e) => {
+                    stats_data.dropped_packets.add(1);
+                    log::warn!("Couldn't read envelope from bip buffer: {}", e);
+                    continue;
+                }
+            };
+            let element_length = envelope.payload.len();
+            match socket.send_to(&envelope.payload, stats_server) {
                 Ok(_) => {
                     stats_data.out_bytes.add(element_length as u64);
                     stats_data.out_packets.add(1);
+                    latency.record(&envelope);
                 }
                 Err(e) => {
                     stats_data.dropped_packets.add(1);
@@ -12,13 +12,13 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use bip_utils::write_to_bip_buffer;
 use error_chain::*;
 use logging::*;
 use ph_udp::errors::*;
 use ph_udp::*;
-use socket_utils::buffered_socket_writer::BufferedSocketWriter;
-use spsc_bip_buffer::bip_buffer_with_len;
+use socket_utils::envelope::*;
+use socket_utils::link::*;
+use bip_utils::bip_buffer_with_len;
 use statistics_handler::*;
 use std::net::UdpSocket;
 use std::thread;
@@ -72,19 +72,37 @@ fn outer_udp_ingress() -> Result<()> {
 fn inner_udp_ingress() -> Result<()> {
     let opt = arguments::OptIngress::from_args();
 
-    let mut socket_writer = BufferedSocketWriter::start_listening(&opt.socket_path)
-        .chain_err(|| "Error creating socket writer")?;
-
-    let (mut bip_writer, mut bip_reader) =
+    let (bip_writer, mut bip_reader) =
         bip_buffer_with_len(MAX_BIP_BUFFER_MESSAGE_SIZE * opt.bip_buffer_element_count);
 
     //Start stats thread
     let stats_addr = format!("{}:{}", opt.host_stats_server, opt.port_stats_server);
     let stats: StatsdClient<StatsAllHandlers> =
         StatsdClient::<StatsAllHandlers>::new_with_custom_fields(None, None);
+    let stats = stats
+        .with_metrics_format(opt.metrics_format)
+        .with_interval(std::time::Duration::from_millis(opt.stats_interval_ms));
+    let stats = match &opt.metrics_address {
+        Some(metrics_address) => stats
+            .with_metrics_endpoint(metrics_address, &opt.handler_name)
+            .chain_err(|| "Error while serving metrics")?,
+        None => stats,
+    };
     stats
-        .run(stats_addr, opt.handler_name)
+        .run(stats_addr, opt.handler_name.clone())
         .chain_err(|| "Error while running statitics")?;
+    let mut bip_writer = bip_writer
+        .with_overflow_policy(opt.overflow_policy, &opt.spill_directory, opt.spill_max_bytes)
+        .with_stats("bip", stats.get_data_clone());
+
+    let mut socket_writer = LinkWriter::start_listening_fan_out(
+        opt.link_type,
+        &opt.socket_path,
+        opt.fan_out,
+        opt.fan_out_mode,
+    )
+    .chain_err(|| "Error creating socket writer")?
+    .with_stats(stats.get_data_clone());
 
     //2 threads:
     //- udp_receiver,
@@ -95,6 +113,7 @@ fn inner_udp_ingress() -> Result<()> {
     let socket = UdpSocket::bind(format!("0.0.0.0:{}", &opt.listening_port.to_string()))?;
 
     let stats_data = stats.get_data_clone();
+    let handler_name = opt.handler_name;
 
     let udp_receiver = thread::Builder::new()
         .name("udp_receiver".into())
@@ -102,12 +121,23 @@ fn inner_udp_ingress() -> Result<()> {
             let mut buf = [0; MAX_UDP_SIZE];
             loop {
                 match socket.recv_from(&mut buf) {
-                    Ok((length, _)) => {
+                    Ok((length, source_address)) => {
                         stats_data.in_packets.add(1);
                         stats_data.in_bytes.add(length as u64);
                         log::trace!("Received packet with size {}", length);
                         if length != 0 {
-                            write_to_bip_buffer(&mut bip_writer, &buf[..length]);
+                            let envelope = Envelope::new(
+                                &handler_name,
+                                CONTENT_TYPE_OCTET_STREAM,
+                                &buf[..length],
+                            )
+                            .with_header(HEADER_SOURCE_ADDRESS, &source_address.to_string());
+                            if let Err(e) = write_envelope_to_bip_buffer(&mut bip_writer, &envelope)
+                            {
+                                stats_data.dropped_packets.add(1);
+                                stats_data.dropped_bytes.add(length as u64);
+                                log::warn!("Couldn't write udp packet to bip buffer: {}", e);
+                            }
                         }
                     }
                     Err(e) => {
@@ -28,5 +28,9 @@ error_chain! {
             description("Custom field Error")
             display("Custom field Error: {}", t)
         }
+        InvalidMetricsFormat(t: String){
+            description("Invalid metrics format")
+            display("Invalid metrics format: {}", t)
+        }
     }
 }
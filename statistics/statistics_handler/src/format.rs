@@ -0,0 +1,364 @@
+// Copyright 2020 Ministerie van Defensie
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::errors::ErrorKind::*;
+use crate::errors::*;
+use crate::metrics::{statsd_labels, statsd_values, MetricValue, Metrics};
+use std::net::{ToSocketAddrs, UdpSocket};
+use std::str::FromStr;
+
+///The name of every tagged metric starts with this prefix, the handler is in the tags.
+const TAGGED_PREFIX: &str = "osdd";
+///The maximum size of a datagram with metrics, it fits in an ethernet frame.
+pub const MAX_DATAGRAM_LEN: usize = 1432;
+
+///How the statistics are sent to the statistics server.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum MetricsFormat {
+    ///Plain statsd, the handler name is the prefix of every metric name.
+    #[default]
+    Statsd,
+    ///Statsd with DogStatsD tags: `osdd.in.bytes:12|c|#instance:1,network:ingress,...`.
+    Dogstatsd,
+    ///InfluxDB line protocol: `osdd.in.bytes,instance=1,network=ingress,... value=12`.
+    Influx,
+}
+
+impl FromStr for MetricsFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<MetricsFormat> {
+        match s {
+            "statsd" => Ok(MetricsFormat::Statsd),
+            "dogstatsd" => Ok(MetricsFormat::Dogstatsd),
+            "influx" => Ok(MetricsFormat::Influx),
+            _ => Err(InvalidMetricsFormat(format!(
+                "unknown metrics format {s}, can be \"statsd\", \"dogstatsd\" or \"influx\""
+            ))
+            .into()),
+        }
+    }
+}
+
+///Returns the labels of a handler, derived from its name.
+///A name given by osdd, `osdd.<instance>.<network>.<chain>.<type>.<name>`, is split into the labels
+///`instance`, `network`, `chain`, `handler_type` and `handler`. Any other name becomes the `handler` label.
+pub(crate) fn handler_labels(handler_name: &str) -> Vec<(String, String)> {
+    let parts: Vec<&str> = handler_name.split('.').collect();
+    let labels: Vec<(&str, &str)> = match parts.as_slice() {
+        ["osdd", instance, network, chain, handler_type, name] => vec![
+            ("instance", instance),
+            ("network", network),
+            ("chain", chain),
+            ("handler_type", handler_type),
+            ("handler", name),
+        ],
+        _ => vec![("handler", handler_name)],
+    };
+    labels
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect()
+}
+
+///Sends the metrics of every interval to the statistics server, in the configured format.
+pub(crate) struct MetricsSender {
+    socket: UdpSocket,
+    format: MetricsFormat,
+    ///The prefix of every metric name in plain statsd.
+    prefix: String,
+    ///The tags of the handler, added to every metric with tags.
+    tags: Vec<(String, String)>,
+}
+
+impl MetricsSender {
+    ///Creates a sender to the statistics server at `addr`.
+    /// # Arguments
+    /// * `addr` - The address of the statistics server.
+    /// * `prefix` - The name of the handler, the statsd prefix or the source of the tags.
+    /// * `format` - The format of the metrics.
+    pub(crate) fn new(addr: &str, prefix: &str, format: MetricsFormat) -> Result<MetricsSender> {
+        let server_address = addr
+            .to_socket_addrs()
+            .chain_err(|| format!("Failed to resolve statistics server {}", addr))?
+            .next()
+            .ok_or_else(|| Error::from(format!("No address for statistics server {}", addr)))?;
+        let local_address = if server_address.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
+        let socket =
+            UdpSocket::bind(local_address).chain_err(|| "Failed to bind metrics socket")?;
+        socket
+            .connect(server_address)
+            .chain_err(|| format!("Failed to connect metrics socket to {}", addr))?;
+        Ok(MetricsSender {
+            socket,
+            format,
+            prefix: prefix.to_string(),
+            tags: handler_labels(prefix),
+        })
+    }
+
+    ///Returns the error the server answered a previous datagram with, for example an ICMP port unreachable.
+    pub(crate) fn take_error(&self) -> std::io::Result<()> {
+        match self.socket.take_error()? {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    ///Sends the metrics of one interval.
+    ///An error is returned when a datagram could not be sent, for example because the server is unreachable.
+    pub(crate) fn send(&self, metrics: &Metrics) -> std::io::Result<()> {
+        let lines = match self.format {
+            MetricsFormat::Statsd => statsd_lines(metrics, &self.prefix),
+            MetricsFormat::Dogstatsd => dogstatsd_lines(metrics, &self.tags),
+            MetricsFormat::Influx => influx_lines(metrics, &self.tags),
+        };
+        for datagram in datagrams(&lines) {
+            self.socket.send(datagram.as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+fn statsd_lines(metrics: &Metrics, prefix: &str) -> Vec<String> {
+    let mut lines = Vec::new();
+    for metric in metrics.iter() {
+        let labels = statsd_labels(metric);
+        for (name, value, kind) in statsd_values(metric) {
+            if prefix.is_empty() {
+                lines.push(format!("{name}{labels}:{value}|{kind}"));
+            } else {
+                lines.push(format!("{prefix}.{name}{labels}:{value}|{kind}"));
+            }
+        }
+    }
+    lines
+}
+
+fn dogstatsd_lines(metrics: &Metrics, tags: &[(String, String)]) -> Vec<String> {
+    let mut lines = Vec::new();
+    for metric in metrics.iter() {
+        let tags = tags
+            .iter()
+            .chain(&metric.labels)
+            .map(|(key, value)| format!("{}:{}", escape_dogstatsd(key), escape_dogstatsd(value)))
+            .collect::<Vec<String>>()
+            .join(",");
+        for (name, value, kind) in statsd_values(metric) {
+            lines.push(format!(
+                "{TAGGED_PREFIX}.{}:{value}|{kind}|#{tags}",
+                escape_dogstatsd(&name)
+            ));
+        }
+    }
+    lines
+}
+
+fn influx_lines(metrics: &Metrics, tags: &[(String, String)]) -> Vec<String> {
+    let mut lines = Vec::new();
+    for metric in metrics.iter() {
+        let mut line = format!("{TAGGED_PREFIX}.{}", escape_influx(&metric.name, false));
+        for (key, value) in tags.iter().chain(&metric.labels) {
+            line.push_str(&format!(
+                ",{}={}",
+                escape_influx(key, true),
+                escape_influx(value, true)
+            ));
+        }
+        let fields = match &metric.value {
+            MetricValue::Count(value) | MetricValue::Gauge(value) => format!("value={value}"),
+            MetricValue::Histogram(snapshot) => {
+                let scale = if snapshot.is_timer() { 1000.0 } else { 1.0 };
+                format!(
+                    "count={}i,p50={},p90={},p99={},max={}",
+                    snapshot.count(),
+                    snapshot.value_at_quantile(0.5) as f64 / scale,
+                    snapshot.value_at_quantile(0.9) as f64 / scale,
+                    snapshot.value_at_quantile(0.99) as f64 / scale,
+                    snapshot.max() as f64 / scale
+                )
+            }
+        };
+        lines.push(format!("{line} {fields}"));
+    }
+    lines
+}
+
+///Joins lines into datagrams of at most `MAX_DATAGRAM_LEN` bytes, a longer line gets a datagram of its own.
+pub fn datagrams(lines: &[String]) -> Vec<String> {
+    let mut datagrams: Vec<String> = Vec::new();
+    for line in lines {
+        match datagrams.last_mut() {
+            Some(datagram) if datagram.len() + 1 + line.len() <= MAX_DATAGRAM_LEN => {
+                datagram.push('\n');
+                datagram.push_str(line);
+            }
+            _ => datagrams.push(line.clone()),
+        }
+    }
+    datagrams
+}
+
+///Replaces the characters DogStatsD uses as separators by an underscore.
+fn escape_dogstatsd(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            ':' | '|' | '@' | '#' | ',' | '\n' => '_',
+            c => c,
+        })
+        .collect()
+}
+
+///Escapes the characters the InfluxDB line protocol uses as separators, `=` only in tags.
+fn escape_influx(text: &str, tag: bool) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars().map(|c| if c == '\n' { ' ' } else { c }) {
+        if c == ',' || c == ' ' || (tag && c == '=') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::histogram::Histogram;
+
+    const HANDLER_NAME: &str = "osdd.1.ingress.TestTopic.ph.kafka";
+
+    fn metrics() -> Metrics {
+        let mut metrics = Metrics::default();
+        metrics.count("in.packets", 12.0);
+        let labels = [("topic".to_string(), "a,b c=d:e|f".to_string())];
+        metrics.add("topic.out.bytes", &labels, MetricValue::Count(3.0));
+        metrics
+    }
+
+    #[test]
+    fn metrics_format_test() {
+        assert_eq!("statsd".parse::<MetricsFormat>().unwrap(), MetricsFormat::Statsd);
+        assert_eq!("dogstatsd".parse::<MetricsFormat>().unwrap(), MetricsFormat::Dogstatsd);
+        assert_eq!("influx".parse::<MetricsFormat>().unwrap(), MetricsFormat::Influx);
+        assert!("prometheus".parse::<MetricsFormat>().is_err());
+    }
+
+    #[test]
+    fn handler_labels_test() {
+        let pair = |key: &str, value: &str| (key.to_string(), value.to_string());
+        assert_eq!(
+            handler_labels(HANDLER_NAME),
+            vec![
+                pair("instance", "1"),
+                pair("network", "ingress"),
+                pair("chain", "TestTopic"),
+                pair("handler_type", "ph"),
+                pair("handler", "kafka"),
+            ]
+        );
+        //a name not given by osdd is the handler label
+        assert_eq!(handler_labels("ph_kafka"), vec![pair("handler", "ph_kafka")]);
+        assert_eq!(
+            handler_labels("osdd.1.ingress.ph.kafka"),
+            vec![pair("handler", "osdd.1.ingress.ph.kafka")]
+        );
+    }
+
+    #[test]
+    fn statsd_lines_test() {
+        assert_eq!(
+            statsd_lines(&metrics(), "handler"),
+            vec![
+                "handler.in.packets:12|c",
+                "handler.topic.out.bytes.a_b_c=d_e_f:3|c"
+            ]
+        );
+        assert_eq!(statsd_lines(&metrics(), "")[0], "in.packets:12|c");
+    }
+
+    #[test]
+    fn dogstatsd_lines_test() {
+        let lines = dogstatsd_lines(&metrics(), &handler_labels(HANDLER_NAME));
+        let tags = "instance:1,network:ingress,chain:TestTopic,handler_type:ph,handler:kafka";
+        assert_eq!(lines[0], format!("osdd.in.packets:12|c|#{tags}"));
+        //the separators of DogStatsD are replaced in tags
+        assert_eq!(
+            lines[1],
+            format!("osdd.topic.out.bytes:3|c|#{tags},topic:a_b c=d_e_f")
+        );
+        assert_eq!(escape_dogstatsd("a:b|c@d#e,f\ng"), "a_b_c_d_e_f_g");
+    }
+
+    #[test]
+    fn influx_lines_test() {
+        let mut metrics = metrics();
+        let timer = Histogram::new_timer();
+        timer.record(2_000);
+        metrics.histogram("latency", timer.take());
+        let lines = influx_lines(&metrics, &handler_labels(HANDLER_NAME));
+        let tags = "instance=1,network=ingress,chain=TestTopic,handler_type=ph,handler=kafka";
+        assert_eq!(lines[0], format!("osdd.in.packets,{tags} value=12"));
+        //commas, spaces and in tags equal signs are escaped
+        assert_eq!(
+            lines[1],
+            format!("osdd.topic.out.bytes,{tags},topic=a\\,b\\ c\\=d:e|f value=3")
+        );
+        //a timer is sent in milliseconds
+        assert_eq!(
+            lines[2],
+            format!("osdd.latency,{tags} count=1i,p50=2,p90=2,p99=2,max=2")
+        );
+        assert_eq!(escape_influx("a=b c,d\ne", false), "a=b\\ c\\,d\\ e");
+    }
+
+    #[test]
+    fn datagrams_test() {
+        let line = |length: usize| "a".repeat(length);
+        let lines = vec![line(700), line(700), line(40), line(1500), line(10)];
+        assert_eq!(
+            datagrams(&lines),
+            vec![
+                format!("{}\n{}", line(700), line(700)),
+                line(40),
+                line(1500),
+                line(10)
+            ]
+        );
+        //lines are joined up to exactly MAX_DATAGRAM_LEN
+        let lines = vec![line(716), line(MAX_DATAGRAM_LEN - 717)];
+        assert_eq!(datagrams(&lines).len(), 1);
+        let lines = vec![line(716), line(MAX_DATAGRAM_LEN - 716)];
+        assert_eq!(datagrams(&lines).len(), 2);
+        assert!(datagrams(&[]).is_empty());
+    }
+
+    #[test]
+    fn metrics_sender_test() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap().to_string();
+        let sender = MetricsSender::new(&addr, HANDLER_NAME, MetricsFormat::Dogstatsd).unwrap();
+        sender.send(&metrics()).unwrap();
+        let mut buffer = [0; MAX_DATAGRAM_LEN];
+        let length = server.recv(&mut buffer).unwrap();
+        let datagram = String::from_utf8_lossy(&buffer[..length]).to_string();
+        assert_eq!(datagram.lines().count(), 2);
+        assert!(datagram.starts_with("osdd.in.packets:12|c|#instance:1,"));
+        assert!(MetricsSender::new("127.0.0.1", "", MetricsFormat::Statsd).is_err());
+    }
+}
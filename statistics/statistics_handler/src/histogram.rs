@@ -0,0 +1,173 @@
+// Copyright 2020 Ministerie van Defensie
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+///The precision of the recorded values, 3 significant figures is an error of at most 0.1%.
+const SIGNIFICANT_FIGURES: u8 = 3;
+///Larger values are recorded as this value, it is an hour for a timer.
+const HIGHEST_VALUE: u64 = 3_600_000_000;
+
+///The distribution of values recorded since the last report, in an HDR histogram.
+///A timer records durations in microseconds, they are reported in milliseconds to statsd and in seconds to Prometheus.
+pub struct Histogram {
+    histogram: Mutex<hdrhistogram::Histogram<u64>>,
+    timer: bool,
+}
+
+impl Default for Histogram {
+    fn default() -> Histogram {
+        Histogram::new()
+    }
+}
+
+impl Histogram {
+    ///Creates a histogram of values, for example message sizes in bytes.
+    pub fn new() -> Histogram {
+        Histogram {
+            histogram: Mutex::new(new_hdr_histogram()),
+            timer: false,
+        }
+    }
+
+    ///Creates a histogram of durations.
+    pub fn new_timer() -> Histogram {
+        Histogram {
+            timer: true,
+            ..Histogram::new()
+        }
+    }
+
+    ///Records a value, a timer records the value as microseconds.
+    pub fn record(&self, value: u64) {
+        self.histogram
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .saturating_record(value);
+    }
+
+    ///Records a duration in microseconds.
+    pub fn record_duration(&self, duration: Duration) {
+        self.record(duration.as_micros() as u64);
+    }
+
+    ///Returns the values recorded since the previous call, and starts over.
+    pub fn take(&self) -> HistogramSnapshot {
+        let mut histogram = self
+            .histogram
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        HistogramSnapshot {
+            histogram: std::mem::replace(&mut *histogram, new_hdr_histogram()),
+            timer: self.timer,
+        }
+    }
+}
+
+fn new_hdr_histogram() -> hdrhistogram::Histogram<u64> {
+    hdrhistogram::Histogram::new_with_max(HIGHEST_VALUE, SIGNIFICANT_FIGURES)
+        .expect("valid histogram bounds")
+}
+
+///The values recorded by a histogram in one interval.
+///All values are in the recorded unit, microseconds for a timer.
+#[derive(Clone)]
+pub struct HistogramSnapshot {
+    histogram: hdrhistogram::Histogram<u64>,
+    timer: bool,
+}
+
+impl std::fmt::Debug for HistogramSnapshot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HistogramSnapshot")
+            .field("count", &self.count())
+            .field("timer", &self.timer)
+            .finish()
+    }
+}
+
+impl PartialEq for HistogramSnapshot {
+    fn eq(&self, other: &HistogramSnapshot) -> bool {
+        self.timer == other.timer && self.histogram == other.histogram
+    }
+}
+
+impl HistogramSnapshot {
+    ///Returns true when the values are durations in microseconds.
+    pub fn is_timer(&self) -> bool {
+        self.timer
+    }
+
+    ///Returns the number of recorded values.
+    pub fn count(&self) -> u64 {
+        self.histogram.len()
+    }
+
+    ///Returns the sum of the recorded values.
+    pub fn sum(&self) -> f64 {
+        self.histogram.mean() * self.histogram.len() as f64
+    }
+
+    ///Returns the value below which the `quantile` (0.0 to 1.0) of the recorded values fall.
+    pub fn value_at_quantile(&self, quantile: f64) -> u64 {
+        self.histogram.value_at_quantile(quantile)
+    }
+
+    pub fn max(&self) -> u64 {
+        self.histogram.max()
+    }
+
+    ///Returns the number of recorded values that are at most `value`.
+    pub fn count_at_most(&self, value: u64) -> u64 {
+        self.histogram.count_between(0, value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_take_test() {
+        let histogram = Histogram::new();
+        for value in 1..=100 {
+            histogram.record(value);
+        }
+        let snapshot = histogram.take();
+        assert!(!snapshot.is_timer());
+        assert_eq!(snapshot.count(), 100);
+        assert_eq!(snapshot.sum().round(), 5050.0);
+        assert_eq!(snapshot.value_at_quantile(0.5), 50);
+        assert_eq!(snapshot.value_at_quantile(0.99), 99);
+        assert_eq!(snapshot.max(), 100);
+        assert_eq!(snapshot.count_at_most(10), 10);
+        //the histogram starts over
+        assert_eq!(histogram.take().count(), 0);
+    }
+
+    #[test]
+    fn timer_test() {
+        let timer = Histogram::new_timer();
+        timer.record_duration(Duration::from_millis(3));
+        //a duration of more than an hour is recorded as an hour
+        timer.record_duration(Duration::from_secs(7200));
+        let snapshot = timer.take();
+        assert!(snapshot.is_timer());
+        assert_eq!(snapshot.count(), 2);
+        assert_eq!(snapshot.count_at_most(3_000), 1);
+        assert!(snapshot.max() >= HIGHEST_VALUE);
+        assert!(snapshot.max() < HIGHEST_VALUE + HIGHEST_VALUE / 1000);
+    }
+}
@@ -14,17 +14,39 @@
 
 use crate::errors::ErrorKind::*;
 use crate::errors::*;
-use statsd::client::Pipeline;
-use statsd::Client;
-use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
-use std::sync::Arc;
+use crate::format::MetricsSender;
+use error_chain::ChainedError;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
 use std::thread;
 use std::thread::JoinHandle;
+use std::time::Duration;
 
 pub mod errors;
+///The formats the statistics can be sent in, plain statsd or with tags.
+pub mod format;
+///Histograms and timers of the values recorded in one interval.
+pub mod histogram;
+///The metrics collected in one interval.
+pub mod metrics;
+///The HTTP endpoint that serves the statistics to Prometheus.
+pub mod prometheus;
+///Metrics with a name and labels that handlers create while they run.
+pub mod registry;
 
-///Delay used in the run loop of the statistics handler thread.
-const STATS_DELAY_SEC: u64 = 1;
+pub use format::MetricsFormat;
+pub use histogram::{Histogram, HistogramSnapshot};
+pub use metrics::{Metric, MetricValue, Metrics};
+pub use prometheus::PrometheusExporter;
+pub use registry::MetricsRegistry;
+
+///The default time between two reports of the statistics handler thread.
+const DEFAULT_STATS_INTERVAL: Duration = Duration::from_secs(1);
+///The number of reports kept while the statistics server cannot be reached, older reports are dropped.
+const MAX_UNSENT_REPORTS: usize = 300;
+///The longest time `stop` waits for the final report.
+const STOP_TIMEOUT: Duration = Duration::from_secs(2);
 
 #[derive(Default)]
 pub struct Counter(AtomicU64);
@@ -36,7 +58,7 @@ impl Counter {
     pub fn load(&self) -> u64 {
         self.0.load(Ordering::Relaxed)
     }
-    fn get_and_reset(&self) -> f64 {
+    pub(crate) fn get_and_reset(&self) -> f64 {
         self.0.swap(0, Ordering::Relaxed) as f64
     }
 }
@@ -48,11 +70,81 @@ impl Gauge {
     pub fn set(&self, value: u64) {
         self.0.swap(value, Ordering::Relaxed);
     }
-    fn get(&self) -> f64 {
+    pub(crate) fn get(&self) -> f64 {
         self.0.load(Ordering::Relaxed) as f64
     }
 }
 
+///Statistics of a buffer between the threads of a handler, reported as gauges named after the buffer.
+#[derive(Default)]
+pub struct BufferStatistics {
+    capacity: u64,
+    written: AtomicU64,
+    consumed: AtomicU64,
+    ///The highest fill level in bytes since the last report.
+    high_water: AtomicU64,
+    ///The time the writer waited for space since the last report, in microseconds.
+    blocked_us: AtomicU64,
+}
+
+impl BufferStatistics {
+    ///Creates the statistics of a buffer of `capacity` bytes.
+    pub fn new(capacity: usize) -> BufferStatistics {
+        BufferStatistics {
+            capacity: capacity as u64,
+            ..Default::default()
+        }
+    }
+
+    ///Registers `bytes` written to the buffer.
+    pub fn add_written(&self, bytes: usize) {
+        let written = self.written.fetch_add(bytes as u64, Ordering::Relaxed) + bytes as u64;
+        self.high_water.fetch_max(
+            written.saturating_sub(self.consumed.load(Ordering::Relaxed)),
+            Ordering::Relaxed,
+        );
+    }
+
+    ///Registers `bytes` consumed from the buffer.
+    pub fn add_consumed(&self, bytes: usize) {
+        self.consumed.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    ///Registers time the writer spent waiting for space in the buffer.
+    pub fn add_blocked(&self, duration: std::time::Duration) {
+        self.blocked_us
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    ///Returns the number of bytes in the buffer.
+    pub fn fill(&self) -> u64 {
+        //load consumed first, it never passes written
+        let consumed = self.consumed.load(Ordering::Relaxed);
+        self.written
+            .load(Ordering::Relaxed)
+            .saturating_sub(consumed)
+    }
+
+    ///Adds the fill level and high-water mark in percent of the capacity, and the time blocked in milliseconds.
+    fn fill_metrics(&self, name: &str, metrics: &mut Metrics) {
+        let fill = self.fill();
+        let high_water = self.high_water.swap(fill, Ordering::Relaxed).max(fill);
+        metrics.gauge(&format!("{name}.fill"), self.percentage(fill));
+        metrics.gauge(&format!("{name}.high_water"), self.percentage(high_water));
+        metrics.count(
+            &format!("{name}.blocked_ms"),
+            self.blocked_us.swap(0, Ordering::Relaxed) as f64 / 1000.0,
+        );
+    }
+
+    fn percentage(&self, bytes: u64) -> f64 {
+        if self.capacity == 0 {
+            return 0.0;
+        }
+        bytes as f64 * 100.0 / self.capacity as f64
+    }
+}
+
 #[derive(Default)]
 pub struct StatsAllHandlers {
     pub in_bytes: Counter,
@@ -62,29 +154,76 @@ pub struct StatsAllHandlers {
     pub dropped_bytes: Counter,
     pub dropped_packets: Counter,
     pub packetloss: Counter,
-    pub custom_counter: Option<(Counter, String)>,
-    pub custom_gauge: Option<(Gauge, String)>,
+    ///The number of times a Unix socket link to a neighbouring handler was re-established.
+    pub reconnects: Counter,
+    ///The counter set by `add_custom_counter`, it is kept in the registry.
+    pub custom_counter: Option<Arc<Counter>>,
+    ///The gauge set by `set_custom_gauge`, it is kept in the registry.
+    pub custom_gauge: Option<Arc<Gauge>>,
+    ///The buffers between the threads of the handler, by name.
+    pub buffers: Mutex<Vec<(String, Arc<BufferStatistics>)>>,
+    ///The counters, gauges and histograms created by the handler, by name and labels.
+    pub registry: MetricsRegistry,
+}
+
+impl StatsAllHandlers {
+    ///Adds a buffer of `capacity` bytes to the statistics, its gauges are named after `name`.
+    /// # Returns
+    /// * `Arc<BufferStatistics>` - The statistics the buffer updates.
+    pub fn register_buffer(&self, name: &str, capacity: usize) -> Arc<BufferStatistics> {
+        let buffer = Arc::new(BufferStatistics::new(capacity));
+        self.buffers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push((name.to_string(), buffer.clone()));
+        buffer
+    }
+
+    ///Adds a gauge named `name` to the statistics, see `MetricsRegistry::gauge` for labelled gauges.
+    /// # Returns
+    /// * `Arc<Gauge>` - The gauge to set.
+    pub fn register_gauge(&self, name: &str) -> Arc<Gauge> {
+        self.registry.gauge(name, &[])
+    }
+
+    ///Adds a histogram of values named `name` to the statistics, for example of message sizes.
+    /// # Returns
+    /// * `Arc<Histogram>` - The histogram to record values in.
+    pub fn register_histogram(&self, name: &str) -> Arc<Histogram> {
+        self.registry.histogram(name, &[])
+    }
+
+    ///Adds a timer named `name` to the statistics, a histogram of durations.
+    /// # Returns
+    /// * `Arc<Histogram>` - The histogram to record durations in.
+    pub fn register_timer(&self, name: &str) -> Arc<Histogram> {
+        self.registry.timer(name, &[])
+    }
 }
 
 impl StatisticData for StatsAllHandlers {
-    fn fill_pipeline(&self, pipeline: &mut Pipeline) {
-        pipeline.count("in.bytes", self.in_bytes.get_and_reset());
-        pipeline.count("in.packets", self.in_packets.get_and_reset());
-        pipeline.count("out.bytes", self.out_bytes.get_and_reset());
-        pipeline.count("out.packets", self.out_packets.get_and_reset());
-        pipeline.count("dropped.bytes", self.dropped_bytes.get_and_reset());
-        pipeline.count("dropped.packets", self.dropped_packets.get_and_reset());
-        pipeline.count("packetloss", self.packetloss.get_and_reset());
-        if let Some(x) = &self.custom_counter {
-            pipeline.count(&x.1, x.0.get_and_reset());
-        }
-        if let Some(x) = &self.custom_gauge {
-            pipeline.gauge(&x.1, x.0.get());
+    fn fill_metrics(&self, metrics: &mut Metrics) {
+        metrics.count("in.bytes", self.in_bytes.get_and_reset());
+        metrics.count("in.packets", self.in_packets.get_and_reset());
+        metrics.count("out.bytes", self.out_bytes.get_and_reset());
+        metrics.count("out.packets", self.out_packets.get_and_reset());
+        metrics.count("dropped.bytes", self.dropped_bytes.get_and_reset());
+        metrics.count("dropped.packets", self.dropped_packets.get_and_reset());
+        metrics.count("packetloss", self.packetloss.get_and_reset());
+        metrics.count("reconnects", self.reconnects.get_and_reset());
+        for (name, buffer) in self
+            .buffers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+        {
+            buffer.fill_metrics(name, metrics);
         }
+        self.registry.fill_metrics(metrics);
     }
     fn set_custom_gauge(&self, number: u64) -> Result<()> {
         match self.custom_gauge.as_ref() {
-            Some(v) => v.0.set(number),
+            Some(v) => v.set(number),
             None => return Err(CustomField("Custom field not set correct".to_string()).into()),
         }
         Ok(())
@@ -92,7 +231,7 @@ impl StatisticData for StatsAllHandlers {
 
     fn add_custom_counter(&self, number: u64) -> Result<()> {
         match self.custom_counter.as_ref() {
-            Some(v) => v.0.add(number),
+            Some(v) => v.add(number),
             None => return Err(CustomField("Custom field not set correct".to_string()).into()),
         }
         Ok(())
@@ -101,15 +240,56 @@ impl StatisticData for StatsAllHandlers {
 
 ///This trait is used to signal that a data struct can be passed to the StatsdClient
 pub trait StatisticData {
-    fn fill_pipeline(&self, pipeline: &mut Pipeline);
+    fn fill_metrics(&self, metrics: &mut Metrics);
     fn set_custom_gauge(&self, number: u64) -> Result<()>;
     fn add_custom_counter(&self, number: u64) -> Result<()>;
 }
 
+///The state of the statistics handler thread.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+enum RunState {
+    #[default]
+    Idle,
+    Running,
+    ///`stop` was called, the thread sends its final report.
+    Stopping,
+    Stopped,
+}
+
+///Wakes the statistics handler thread when it has to stop, and tells `stop` when the final report is sent.
+#[derive(Default)]
+struct RunControl {
+    state: Mutex<RunState>,
+    changed: Condvar,
+}
+
+impl RunControl {
+    fn lock(&self) -> MutexGuard<'_, RunState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn set(&self, state: RunState) {
+        *self.lock() = state;
+        self.changed.notify_all();
+    }
+
+    ///Waits `interval`, or less when the thread has to stop. Returns false when the thread has to stop.
+    fn wait(&self, interval: Duration) -> bool {
+        let (state, _) = self
+            .changed
+            .wait_timeout_while(self.lock(), interval, |state| *state == RunState::Running)
+            .unwrap_or_else(PoisonError::into_inner);
+        *state == RunState::Running
+    }
+}
+
 ///The statsdClient is used to send statistics data to the specified statsd server
 pub struct StatsdClient<T: StatisticData + Send + Sync + 'static> {
     pub data: Arc<T>,
-    is_running: Arc<AtomicBool>,
+    control: Arc<RunControl>,
+    exporter: Option<Arc<PrometheusExporter>>,
+    format: MetricsFormat,
+    interval: Duration,
 }
 
 impl<T> StatsdClient<T>
@@ -120,7 +300,10 @@ where
     pub fn new_standard() -> StatsdClient<StatsAllHandlers> {
         StatsdClient {
             data: Arc::new(StatsAllHandlers::default()),
-            is_running: Arc::new(AtomicBool::default()),
+            control: Arc::default(),
+            exporter: None,
+            format: MetricsFormat::default(),
+            interval: DEFAULT_STATS_INTERVAL,
         }
     }
 
@@ -133,14 +316,9 @@ where
         custom_counter: Option<&str>,
         custom_gauge: Option<&str>,
     ) -> StatsdClient<StatsAllHandlers> {
-        let mut counter_option: Option<(Counter, String)> = None;
-        if let Some(x) = custom_counter {
-            counter_option = Some((Counter::default(), x.to_string()));
-        }
-        let mut gauge_option: Option<(Gauge, String)> = None;
-        if let Some(x) = custom_gauge {
-            gauge_option = Some((Gauge::default(), x.to_string()));
-        }
+        let registry = MetricsRegistry::default();
+        let counter_option = custom_counter.map(|name| registry.counter(name, &[]));
+        let gauge_option = custom_gauge.map(|name| registry.gauge(name, &[]));
         StatsdClient {
             data: Arc::new(StatsAllHandlers {
                 in_bytes: Counter::default(),
@@ -152,52 +330,257 @@ where
                 custom_counter: counter_option,
                 custom_gauge: gauge_option,
                 packetloss: Counter::default(),
+                reconnects: Counter::default(),
+                buffers: Mutex::default(),
+                registry,
             }),
-            is_running: Arc::new(AtomicBool::default()),
+            control: Arc::default(),
+            exporter: None,
+            format: MetricsFormat::default(),
+            interval: DEFAULT_STATS_INTERVAL,
         }
     }
 
+    ///Serves the statistics on an HTTP `/metrics` endpoint for Prometheus, next to sending them to statsd.
+    ///The endpoint is updated by the thread started with `run`.
+    /// # Arguments
+    /// * `addr` - The socket address to listen on, for example `0.0.0.0:9100`.
+    /// * `handler_name` - The name of the handler, the labels of the metrics are derived from it.
+    pub fn with_metrics_endpoint(
+        mut self,
+        addr: &str,
+        handler_name: &str,
+    ) -> std::io::Result<StatsdClient<T>> {
+        self.exporter = Some(PrometheusExporter::serve(addr, handler_name)?);
+        Ok(self)
+    }
+
+    ///Sets the format the statistics are sent in, plain statsd by default.
+    ///With tags, the handler name is not the prefix of the metric names but split into the tags
+    ///`instance`, `network`, `chain`, `handler_type` and `handler`.
+    pub fn with_metrics_format(mut self, format: MetricsFormat) -> StatsdClient<T> {
+        self.format = format;
+        self
+    }
+
+    ///Sets the time between two reports to the statistics server, 1 second by default.
+    pub fn with_interval(mut self, interval: Duration) -> StatsdClient<T> {
+        self.interval = interval;
+        self
+    }
+
     ///This function is used to start the statsdClient.
     ///When this function is called the statsdClient starts sending statistics to the specified statsd server.
+    ///When the server cannot be reached, the statistics are kept and sent when it can be reached again.
     /// # Arguments
     /// * `addr` - The address of the statsd server the statistics should be sent to.
     /// * `prefix` - The prefix that is used by statsd to name variables.
     /// # Returns
     /// * `JoinHandle` - The joinhandle of the thread that is created to run this function.
     pub fn run(&self, addr: String, prefix: String) -> std::io::Result<JoinHandle<()>> {
-        let is_running = Arc::clone(&self.is_running);
-        let data = Arc::clone(&self.data);
+        let reporter = Reporter {
+            addr,
+            prefix,
+            data: self.data.clone(),
+            exporter: self.exporter.clone(),
+            format: self.format,
+        };
+        let control = Arc::clone(&self.control);
+        let interval = self.interval;
+        control.set(RunState::Running);
         thread::Builder::new()
             .name("statistics_handler_thread".into())
-            .spawn(move || {
-                statistics_inner_thread(is_running, addr, prefix, data)
-                    .expect("Error in statitics thread");
-            })
+            .spawn(move || statistics_inner_thread(reporter, &control, interval))
     }
+
     ///Stops the run loop of the statsdClient.
+    ///The statistics counted since the last report are sent first, this waits at most `STOP_TIMEOUT`.
     pub fn stop(&self) {
         log::info!("Statistics Handler stopping.");
-        self.is_running.store(false, Ordering::SeqCst);
+        let mut state = self.control.lock();
+        if *state != RunState::Running {
+            return;
+        }
+        *state = RunState::Stopping;
+        self.control.changed.notify_all();
+        let _ = self
+            .control
+            .changed
+            .wait_timeout_while(state, STOP_TIMEOUT, |state| *state == RunState::Stopping);
     }
 }
 
-pub fn statistics_inner_thread(
-    is_running: Arc<AtomicBool>,
+///Collects the statistics of a handler and sends them to the statistics server.
+struct Reporter {
+    ///The address of the statistics server.
     addr: String,
+    ///The name of the handler, the prefix of the metric names in plain statsd or split into tags.
     prefix: String,
     data: Arc<dyn StatisticData + Send + Sync>,
-) -> Result<()> {
-    is_running.store(true, Ordering::SeqCst);
-    match Client::new(addr, &prefix) {
-        Ok(client) => {
-            while is_running.load(Ordering::SeqCst) {
-                let mut pipeline = client.pipeline();
-                data.fill_pipeline(&mut pipeline);
-                pipeline.send(&client);
-                std::thread::sleep(std::time::Duration::from_secs(STATS_DELAY_SEC));
+    exporter: Option<Arc<PrometheusExporter>>,
+    format: MetricsFormat,
+}
+
+///Reports the statistics every `interval` until the statsdClient is stopped, and once more after that.
+///A report is kept until the next interval confirms that the server did not answer it with an error,
+///at most `MAX_UNSENT_REPORTS` are kept. While the server cannot be reached one report is sent per interval,
+///when it is confirmed the other reports follow in order.
+fn statistics_inner_thread(reporter: Reporter, control: &RunControl, interval: Duration) {
+    let mut sender: Option<MetricsSender> = None;
+    let mut unsent: VecDeque<Metrics> = VecDeque::new();
+    //the number of reports at the front of `unsent` that are sent but not confirmed
+    let mut sent: usize = 0;
+    let mut reachable = true;
+    loop {
+        let running = control.wait(interval);
+        let mut metrics = Metrics::default();
+        reporter.data.fill_metrics(&mut metrics);
+        if let Some(exporter) = &reporter.exporter {
+            exporter.record(&metrics);
+        }
+        if unsent.len() == MAX_UNSENT_REPORTS {
+            unsent.pop_front();
+            sent = sent.saturating_sub(1);
+        }
+        unsent.push_back(metrics);
+        if sender.is_none() {
+            match MetricsSender::new(&reporter.addr, &reporter.prefix, reporter.format) {
+                Ok(new_sender) => sender = Some(new_sender),
+                Err(e) => {
+                    if reachable {
+                        log::warn!(
+                            "Couldn't create statistics sender, keeping the statistics until it can be created. {}",
+                            e.display_chain()
+                        );
+                        reachable = false;
+                    }
+                }
+            }
+        }
+        if let Some(sender) = &sender {
+            match send_reports(sender, &mut unsent, &mut sent, !reachable) {
+                Ok(()) if !reachable && sent == 0 => {
+                    log::info!("Statistics server {} reached again", reporter.addr);
+                    reachable = true;
+                }
+                Ok(()) => (),
+                Err(e) => {
+                    if reachable {
+                        log::warn!(
+                            "Couldn't send statistics to {}, keeping them until it can be reached. {}",
+                            reporter.addr,
+                            e
+                        );
+                        reachable = false;
+                    }
+                }
             }
         }
-        Err(e) => return Err(Error::with_chain(e, "Failed to create StatsD Client")),
-    };
+        if !running {
+            control.set(RunState::Stopped);
+            return;
+        }
+    }
+}
+
+///Confirms the reports sent in the previous interval, and sends the unsent reports in order.
+/// # Arguments
+/// * `sent` - The number of reports at the front of `unsent` sent in the previous interval, it is updated.
+/// * `probe` - Sends only the first report, to find out whether the server can be reached again.
+fn send_reports(
+    sender: &MetricsSender,
+    unsent: &mut VecDeque<Metrics>,
+    sent: &mut usize,
+    probe: bool,
+) -> std::io::Result<()> {
+    let confirmed = sender.take_error();
+    if confirmed.is_ok() {
+        unsent.drain(..*sent);
+        if probe && *sent > 0 {
+            //the probe is confirmed, the other reports are sent in the next interval
+            *sent = 0;
+            return Ok(());
+        }
+    }
+    *sent = 0;
+    confirmed?;
+    let count = if probe { 1 } else { unsent.len() };
+    for metrics in unsent.iter().take(count) {
+        sender.send(metrics)?;
+        *sent += 1;
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::UdpSocket;
+
+    fn receive(server: &UdpSocket) -> String {
+        let mut buffer = [0; 2048];
+        let length = server.recv(&mut buffer).expect("No statistics received");
+        String::from_utf8_lossy(&buffer[..length]).to_string()
+    }
+
+    fn metrics(name: &str) -> Metrics {
+        let mut metrics = Metrics::default();
+        metrics.count(name, 1.0);
+        metrics
+    }
+
+    #[test]
+    fn stop_sends_final_report_test() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let stats = StatsdClient::<StatsAllHandlers>::new_standard()
+            .with_interval(Duration::from_secs(3600));
+        let handle = stats
+            .run(server.local_addr().unwrap().to_string(), "handler".to_string())
+            .unwrap();
+        stats.data.in_packets.add(5);
+        stats.stop();
+        //the interval has not passed, the report is sent because of stop
+        assert!(receive(&server).contains("handler.in.packets:5|c"));
+        handle.join().unwrap();
+        //stopping again does not wait
+        stats.stop();
+    }
+
+    #[test]
+    fn send_reports_test() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        drop(server);
+        let sender = MetricsSender::new(&addr.to_string(), "", MetricsFormat::Statsd).unwrap();
+        let mut unsent = VecDeque::from(vec![metrics("first")]);
+        let mut sent = 0;
+        send_reports(&sender, &mut unsent, &mut sent, false).unwrap();
+        assert_eq!(sent, 1);
+
+        //the server answered the first report with port unreachable, it is kept
+        thread::sleep(Duration::from_millis(100));
+        unsent.push_back(metrics("second"));
+        assert!(send_reports(&sender, &mut unsent, &mut sent, false).is_err());
+        assert_eq!((unsent.len(), sent), (2, 0));
+
+        //while the server cannot be reached, only the first report is sent
+        let server = UdpSocket::bind(addr).unwrap();
+        server
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        send_reports(&sender, &mut unsent, &mut sent, true).unwrap();
+        assert_eq!((unsent.len(), sent), (2, 1));
+        assert_eq!(receive(&server), "first:1|c");
+
+        //the probe is confirmed, the other reports follow in the next interval
+        send_reports(&sender, &mut unsent, &mut sent, true).unwrap();
+        assert_eq!((unsent.len(), sent), (1, 0));
+        send_reports(&sender, &mut unsent, &mut sent, false).unwrap();
+        assert_eq!(receive(&server), "second:1|c");
+        send_reports(&sender, &mut unsent, &mut sent, false).unwrap();
+        assert_eq!((unsent.len(), sent), (0, 0));
+    }
+}
@@ -0,0 +1,181 @@
+// Copyright 2020 Ministerie van Defensie
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::histogram::HistogramSnapshot;
+use statsd::client::Pipeline;
+
+///The quantiles of a histogram that are sent to statsd, with the suffix of their name.
+const STATSD_QUANTILES: [(f64, &str); 3] = [(0.5, "p50"), (0.9, "p90"), (0.99, "p99")];
+
+///The value of a metric in one interval, the kind decides how statsd and Prometheus show it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MetricValue {
+    ///The amount since the previous interval.
+    Count(f64),
+    ///The current value.
+    Gauge(f64),
+    ///The values recorded since the previous interval.
+    Histogram(HistogramSnapshot),
+}
+
+///A single value of a metric.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metric {
+    pub name: String,
+    ///The labels that tell metrics with the same name apart, for example the topic.
+    pub labels: Vec<(String, String)>,
+    pub value: MetricValue,
+}
+
+///The metrics collected from the statistic data in one interval.
+///They are sent to the statsd server and added to the Prometheus endpoint.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    metrics: Vec<Metric>,
+}
+
+impl Metrics {
+    ///Adds the amount counted since the previous interval.
+    pub fn count(&mut self, name: &str, value: f64) {
+        self.push(name, MetricValue::Count(value));
+    }
+
+    ///Adds the current value of a gauge.
+    pub fn gauge(&mut self, name: &str, value: f64) {
+        self.push(name, MetricValue::Gauge(value));
+    }
+
+    ///Adds the values a histogram recorded since the previous interval.
+    pub fn histogram(&mut self, name: &str, snapshot: HistogramSnapshot) {
+        self.push(name, MetricValue::Histogram(snapshot));
+    }
+
+    ///Adds the value of a metric with labels.
+    pub fn add(&mut self, name: &str, labels: &[(String, String)], value: MetricValue) {
+        self.metrics.push(Metric {
+            name: name.to_string(),
+            labels: labels.to_vec(),
+            value,
+        });
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Metric> {
+        self.metrics.iter()
+    }
+
+    ///Adds all metrics to a statsd pipeline.
+    ///Statsd has no labels, the value of every label is appended to the name: `<name>.<value>`.
+    ///A histogram is sent as the count `<name>.count` and the gauges `<name>.p50`, `<name>.p90`, `<name>.p99`
+    ///and `<name>.max`, in milliseconds for a timer.
+    pub fn fill_pipeline(&self, pipeline: &mut Pipeline) {
+        for metric in &self.metrics {
+            let labels = statsd_labels(metric);
+            for (name, value, kind) in statsd_values(metric) {
+                let name = format!("{name}{labels}");
+                match kind {
+                    "c" => pipeline.count(&name, value),
+                    _ => pipeline.gauge(&name, value),
+                }
+            }
+        }
+    }
+
+    fn push(&mut self, name: &str, value: MetricValue) {
+        self.add(name, &[], value);
+    }
+}
+
+///Returns the statsd values of a metric as name, value and statsd type, `c` for a count and `g` for a gauge.
+///A histogram has the count `<name>.count` and the gauges `<name>.p50`, `<name>.p90`, `<name>.p99` and `<name>.max`.
+pub fn statsd_values(metric: &Metric) -> Vec<(String, f64, &'static str)> {
+    let name = &metric.name;
+    match &metric.value {
+        MetricValue::Count(value) => vec![(name.clone(), *value, "c")],
+        MetricValue::Gauge(value) => vec![(name.clone(), *value, "g")],
+        MetricValue::Histogram(snapshot) => {
+            let mut values = vec![(format!("{name}.count"), snapshot.count() as f64, "c")];
+            if snapshot.count() == 0 {
+                return values;
+            }
+            let scale = if snapshot.is_timer() { 1000.0 } else { 1.0 };
+            for (quantile, suffix) in STATSD_QUANTILES {
+                values.push((
+                    format!("{name}.{suffix}"),
+                    snapshot.value_at_quantile(quantile) as f64 / scale,
+                    "g",
+                ));
+            }
+            values.push((format!("{name}.max"), snapshot.max() as f64 / scale, "g"));
+            values
+        }
+    }
+}
+
+///Returns the values of the labels of a metric, each after a dot, to append to its name.
+///Characters statsd uses as separators are replaced by an underscore.
+pub(crate) fn statsd_labels(metric: &Metric) -> String {
+    let mut labels = String::new();
+    for (_, value) in &metric.labels {
+        labels.push('.');
+        labels.extend(value.chars().map(|c| match c {
+            '.' | ':' | '|' | '@' | '#' | ',' | ' ' | '\n' => '_',
+            c => c,
+        }));
+    }
+    labels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::histogram::Histogram;
+
+    #[test]
+    fn statsd_values_test() {
+        let mut metrics = Metrics::default();
+        metrics.count("in.packets", 3.0);
+        metrics.gauge("bip.fill", 50.0);
+        let timer = Histogram::new_timer();
+        timer.record(1_000);
+        timer.record(2_000);
+        metrics.histogram("latency", timer.take());
+        metrics.histogram("message_size", Histogram::new().take());
+        let values: Vec<(String, f64, &str)> = metrics.iter().flat_map(statsd_values).collect();
+        let value = |name: &str| {
+            values
+                .iter()
+                .find(|(value_name, _, _)| value_name == name)
+                .map(|(_, value, kind)| (*value, *kind))
+        };
+        assert_eq!(value("in.packets"), Some((3.0, "c")));
+        assert_eq!(value("bip.fill"), Some((50.0, "g")));
+        //a timer is sent in milliseconds
+        assert_eq!(value("latency.count"), Some((2.0, "c")));
+        assert_eq!(value("latency.p50"), Some((1.0, "g")));
+        assert_eq!(value("latency.max"), Some((2.0, "g")));
+        //an empty histogram only has its count
+        assert_eq!(value("message_size.count"), Some((0.0, "c")));
+        assert_eq!(value("message_size.p50"), None);
+    }
+
+    #[test]
+    fn statsd_labels_test() {
+        let metric = Metric {
+            name: "topic.out.packets".to_string(),
+            labels: vec![("topic".to_string(), "a.b:c|d e".to_string())],
+            value: MetricValue::Count(1.0),
+        };
+        assert_eq!(statsd_labels(&metric), ".a_b_c_d_e");
+    }
+}
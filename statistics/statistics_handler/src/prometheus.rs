@@ -0,0 +1,441 @@
+// Copyright 2020 Ministerie van Defensie
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::format::handler_labels;
+use crate::metrics::{MetricValue, Metrics};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+///Every metric name on the endpoint starts with this prefix.
+const METRIC_PREFIX: &str = "osdd_";
+///The maximum size of a request, larger requests are refused.
+const MAX_REQUEST_LEN: usize = 8192;
+///A client that does not send its request in time is disconnected.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+///The endpoints served by this process, by address.
+///A handler that restarts within its process keeps the endpoint, and its counters, of the previous run.
+static ENDPOINTS: Mutex<Vec<(String, Arc<PrometheusExporter>)>> = Mutex::new(Vec::new());
+///The upper bounds of the buckets of a timer, in seconds.
+const TIMER_BUCKETS: [f64; 16] = [
+    0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5,
+    5.0, 10.0,
+];
+///The upper bounds of the buckets of a histogram of values, sized for message sizes in bytes.
+const VALUE_BUCKETS: [f64; 10] = [
+    64.0, 256.0, 1024.0, 4096.0, 16384.0, 65536.0, 262144.0, 1048576.0, 4194304.0, 16777216.0,
+];
+///A timer records microseconds, Prometheus shows seconds.
+const MICROS_PER_SECOND: f64 = 1_000_000.0;
+
+///The value of a metric since the handler started.
+enum Total {
+    Counter(f64),
+    Gauge(f64),
+    Histogram {
+        timer: bool,
+        ///The number of values in each bucket and all smaller buckets.
+        buckets: Vec<u64>,
+        count: u64,
+        sum: f64,
+    },
+}
+
+impl Total {
+    fn new(value: &MetricValue) -> Total {
+        match value {
+            MetricValue::Count(_) => Total::Counter(0.0),
+            MetricValue::Gauge(_) => Total::Gauge(0.0),
+            MetricValue::Histogram(snapshot) => Total::Histogram {
+                timer: snapshot.is_timer(),
+                buckets: vec![0; bucket_bounds(snapshot.is_timer()).len()],
+                count: 0,
+                sum: 0.0,
+            },
+        }
+    }
+
+    fn add(&mut self, value: &MetricValue) {
+        match (self, value) {
+            (Total::Counter(total), MetricValue::Count(value)) => *total += value,
+            (Total::Gauge(total), MetricValue::Gauge(value)) => *total = *value,
+            (
+                Total::Histogram {
+                    timer,
+                    buckets,
+                    count,
+                    sum,
+                },
+                MetricValue::Histogram(snapshot),
+            ) => {
+                for (bucket, bound) in buckets.iter_mut().zip(bucket_bounds(*timer)) {
+                    *bucket += snapshot.count_at_most(recorded_value(bound, *timer));
+                }
+                *count += snapshot.count();
+                *sum += snapshot.sum() / unit(*timer);
+            }
+            _ => log::warn!("metric changed its kind, value ignored"),
+        }
+    }
+}
+
+///Serves the statistics of a handler on an HTTP `/metrics` endpoint in the Prometheus text format.
+///Counts are added up to Prometheus counters, gauges keep their last value.
+pub struct PrometheusExporter {
+    ///The labels of every metric, derived from the handler name.
+    labels: String,
+    ///The totals by metric name and by the labels of the metric.
+    metrics: Mutex<BTreeMap<String, BTreeMap<String, Total>>>,
+}
+
+impl PrometheusExporter {
+    ///Creates an exporter for the handler with the given name.
+    ///The labels of the handler are derived from its name, see `handler_labels`.
+    /// # Arguments
+    /// * `handler_name` - The name of the handler, the same as the statsd prefix.
+    pub fn new(handler_name: &str) -> PrometheusExporter {
+        let labels = handler_labels(handler_name)
+            .iter()
+            .map(|(key, value)| format!("{}=\"{}\"", key, escape_label_value(value)))
+            .collect::<Vec<String>>()
+            .join(",");
+        PrometheusExporter {
+            labels,
+            metrics: Mutex::default(),
+        }
+    }
+
+    ///Adds the metrics of one interval.
+    pub fn record(&self, metrics: &Metrics) {
+        let mut totals = self.metrics.lock().unwrap_or_else(PoisonError::into_inner);
+        for metric in metrics.iter() {
+            let mut labels = self.labels.clone();
+            for (key, value) in &metric.labels {
+                let _ = write!(
+                    labels,
+                    ",{}=\"{}\"",
+                    sanitize(key),
+                    escape_label_value(value)
+                );
+            }
+            totals
+                .entry(metric_name(&metric.name, &metric.value))
+                .or_default()
+                .entry(labels)
+                .or_insert_with(|| Total::new(&metric.value))
+                .add(&metric.value);
+        }
+    }
+
+    ///Returns all metrics in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let mut text = String::new();
+        for (name, totals) in self
+            .metrics
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+        {
+            let kind = match totals.values().next() {
+                Some(Total::Counter(_)) => "counter",
+                Some(Total::Gauge(_)) => "gauge",
+                Some(Total::Histogram { .. }) => "histogram",
+                None => continue,
+            };
+            let _ = writeln!(text, "# TYPE {name} {kind}");
+            for (labels, total) in totals {
+                match total {
+                    Total::Counter(value) | Total::Gauge(value) => {
+                        let _ = writeln!(text, "{name}{{{labels}}} {value}");
+                    }
+                    Total::Histogram {
+                        timer,
+                        buckets,
+                        count,
+                        sum,
+                    } => {
+                        for (bucket, bound) in buckets.iter().zip(bucket_bounds(*timer)) {
+                            let _ =
+                                writeln!(text, "{name}_bucket{{{labels},le=\"{bound}\"}} {bucket}");
+                        }
+                        let _ = writeln!(text, "{name}_bucket{{{labels},le=\"+Inf\"}} {count}");
+                        let _ = writeln!(text, "{name}_sum{{{labels}}} {sum}");
+                        let _ = writeln!(text, "{name}_count{{{labels}}} {count}");
+                    }
+                }
+            }
+        }
+        text
+    }
+
+    ///Starts a thread that serves the metrics of a handler on `http://<addr>/metrics`.
+    ///When this process already serves metrics on `addr`, that exporter is returned.
+    /// # Arguments
+    /// * `addr` - The socket address to listen on, for example `0.0.0.0:9100`.
+    /// * `handler_name` - The name of the handler, see `PrometheusExporter::new`.
+    /// # Returns
+    /// * `Arc<PrometheusExporter>` - The exporter to record the metrics in.
+    pub fn serve(addr: &str, handler_name: &str) -> std::io::Result<Arc<PrometheusExporter>> {
+        let mut endpoints = ENDPOINTS.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some((_, exporter)) = endpoints.iter().find(|(address, _)| address == addr) {
+            return Ok(exporter.clone());
+        }
+        let listener = TcpListener::bind(addr)?;
+        let exporter = Arc::new(PrometheusExporter::new(handler_name));
+        let serving_exporter = exporter.clone();
+        thread::Builder::new()
+            .name("prometheus_exporter_thread".into())
+            .spawn(move || serving_exporter.accept(listener))?;
+        log::info!("serving metrics on http://{}/metrics", addr);
+        endpoints.push((addr.to_string(), exporter.clone()));
+        Ok(exporter)
+    }
+
+    fn accept(&self, listener: TcpListener) {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    if let Err(e) = self.respond(stream) {
+                        log::debug!("Error while serving metrics: {}", e);
+                    }
+                }
+                Err(e) => log::warn!("Error while accepting metrics request: {}", e),
+            }
+        }
+    }
+
+    ///Answers a single HTTP request.
+    fn respond(&self, mut stream: TcpStream) -> std::io::Result<()> {
+        stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+        let mut request = Vec::new();
+        let mut buffer = [0; 1024];
+        while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+            let length = stream.read(&mut buffer)?;
+            if length == 0 || request.len() + length > MAX_REQUEST_LEN {
+                break;
+            }
+            request.extend_from_slice(&buffer[..length]);
+        }
+        let request = String::from_utf8_lossy(&request);
+        let mut request_line = request.lines().next().unwrap_or_default().split(' ');
+        let (status, content_type, body) = match (request_line.next(), request_line.next()) {
+            (Some("GET"), Some("/metrics")) => {
+                ("200 OK", "text/plain; version=0.0.4", self.render())
+            }
+            _ => ("404 Not Found", "text/plain", "Not Found\n".to_string()),
+        };
+        write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            content_type,
+            body.len(),
+            body
+        )?;
+        stream.flush()
+    }
+}
+
+///Turns a statsd metric name into a Prometheus metric name.
+///Counters get the `_total` suffix and timers the `_seconds` suffix.
+fn metric_name(name: &str, value: &MetricValue) -> String {
+    let mut metric_name = String::from(METRIC_PREFIX);
+    metric_name.push_str(&sanitize(name));
+    match value {
+        MetricValue::Count(_) => metric_name.push_str("_total"),
+        MetricValue::Histogram(snapshot) if snapshot.is_timer() => metric_name.push_str("_seconds"),
+        _ => {}
+    }
+    metric_name
+}
+
+///Replaces every character that is not allowed in a Prometheus metric or label name by an underscore.
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+fn bucket_bounds(timer: bool) -> &'static [f64] {
+    if timer {
+        &TIMER_BUCKETS
+    } else {
+        &VALUE_BUCKETS
+    }
+}
+
+///Returns how many recorded units there are in one unit shown by Prometheus.
+fn unit(timer: bool) -> f64 {
+    if timer {
+        MICROS_PER_SECOND
+    } else {
+        1.0
+    }
+}
+
+///Converts a value shown by Prometheus to the unit recorded by a histogram.
+fn recorded_value(value: &f64, timer: bool) -> u64 {
+    (value * unit(timer)) as u64
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::histogram::Histogram;
+
+    const HANDLER_NAME: &str = "osdd.1.ingress.TestTopic.ph.kafka";
+    const LABELS: &str =
+        "instance=\"1\",network=\"ingress\",chain=\"TestTopic\",handler_type=\"ph\",handler=\"kafka\"";
+
+    #[test]
+    fn render_counter_and_gauge_test() {
+        let exporter = PrometheusExporter::new(HANDLER_NAME);
+        for value in [3.0, 4.0] {
+            let mut metrics = Metrics::default();
+            metrics.count("in.packets", value);
+            metrics.gauge("bip.fill", value);
+            exporter.record(&metrics);
+        }
+        let text = exporter.render();
+        //counts are added up, gauges keep their last value
+        assert!(text.contains("# TYPE osdd_in_packets_total counter\n"));
+        assert!(text.contains(&format!("osdd_in_packets_total{{{LABELS}}} 7\n")));
+        assert!(text.contains("# TYPE osdd_bip_fill gauge\n"));
+        assert!(text.contains(&format!("osdd_bip_fill{{{LABELS}}} 4\n")));
+    }
+
+    #[test]
+    fn render_handler_label_test() {
+        //a name osdd did not give the handler becomes the handler label, escaped
+        let exporter = PrometheusExporter::new("ph_\"udp\\\n");
+        let mut metrics = Metrics::default();
+        metrics.count("out.packets", 1.0);
+        exporter.record(&metrics);
+        assert_eq!(
+            exporter.render(),
+            "# TYPE osdd_out_packets_total counter\n\
+             osdd_out_packets_total{handler=\"ph_\\\"udp\\\\\\n\"} 1\n"
+        );
+    }
+
+    #[test]
+    fn render_labels_test() {
+        let exporter = PrometheusExporter::new("ph_udp");
+        let mut metrics = Metrics::default();
+        let labels = [("topic name".to_string(), "a\"b\\c\nd".to_string())];
+        metrics.add("topic.out.packets", &labels, MetricValue::Count(1.0));
+        exporter.record(&metrics);
+        assert_eq!(
+            exporter.render(),
+            "# TYPE osdd_topic_out_packets_total counter\n\
+             osdd_topic_out_packets_total{handler=\"ph_udp\",topic_name=\"a\\\"b\\\\c\\nd\"} 1\n"
+        );
+    }
+
+    #[test]
+    fn render_timer_test() {
+        let exporter = PrometheusExporter::new(HANDLER_NAME);
+        let timer = Histogram::new_timer();
+        timer.record_duration(Duration::from_micros(200));
+        timer.record_duration(Duration::from_millis(20));
+        let mut metrics = Metrics::default();
+        metrics.histogram("latency", timer.take());
+        exporter.record(&metrics);
+        timer.record_duration(Duration::from_secs(20));
+        let mut metrics = Metrics::default();
+        metrics.histogram("latency", timer.take());
+        exporter.record(&metrics);
+
+        let text = exporter.render();
+        assert!(text.contains("# TYPE osdd_latency_seconds histogram\n"));
+        //the buckets are cumulative, over all intervals
+        let bucket = |bound: &str| format!("osdd_latency_seconds_bucket{{{LABELS},le=\"{bound}\"}} ");
+        assert!(text.contains(&format!("{}0\n", bucket("0.0001"))));
+        assert!(text.contains(&format!("{}1\n", bucket("0.00025"))));
+        assert!(text.contains(&format!("{}1\n", bucket("0.01"))));
+        assert!(text.contains(&format!("{}2\n", bucket("0.025"))));
+        assert!(text.contains(&format!("{}2\n", bucket("10"))));
+        assert!(text.contains(&format!("{}3\n", bucket("+Inf"))));
+        assert!(text.contains(&format!("osdd_latency_seconds_count{{{LABELS}}} 3\n")));
+        let sum = text
+            .lines()
+            .find_map(|line| line.strip_prefix(&format!("osdd_latency_seconds_sum{{{LABELS}}} ")))
+            .and_then(|sum| sum.parse::<f64>().ok())
+            .unwrap();
+        assert!((sum - 20.0202).abs() < 0.05, "sum {}", sum);
+    }
+
+    #[test]
+    fn render_histogram_test() {
+        let exporter = PrometheusExporter::new(HANDLER_NAME);
+        let sizes = Histogram::new();
+        sizes.record(100);
+        sizes.record(5000);
+        let mut metrics = Metrics::default();
+        metrics.histogram("message_size", sizes.take());
+        exporter.record(&metrics);
+        let text = exporter.render();
+        //a histogram of values has no unit suffix
+        assert!(text.contains("# TYPE osdd_message_size histogram\n"));
+        assert!(text.contains(&format!("osdd_message_size_bucket{{{LABELS},le=\"64\"}} 0\n")));
+        assert!(text.contains(&format!("osdd_message_size_bucket{{{LABELS},le=\"256\"}} 1\n")));
+        assert!(text.contains(&format!("osdd_message_size_bucket{{{LABELS},le=\"16384\"}} 2\n")));
+        assert!(text.contains(&format!("osdd_message_size_count{{{LABELS}}} 2\n")));
+    }
+
+    #[test]
+    fn serve_test() {
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .unwrap()
+            .to_string();
+        let exporter = PrometheusExporter::serve(&addr, HANDLER_NAME).unwrap();
+        //a handler that restarts within its process gets the same exporter
+        let same = PrometheusExporter::serve(&addr, HANDLER_NAME).unwrap();
+        assert!(Arc::ptr_eq(&exporter, &same));
+        let mut metrics = Metrics::default();
+        metrics.count("in.packets", 2.0);
+        exporter.record(&metrics);
+
+        let get = |path: &str| {
+            let mut stream = TcpStream::connect(&addr).unwrap();
+            write!(stream, "GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+        let response = get("/metrics");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with(&format!("osdd_in_packets_total{{{LABELS}}} 2\n")));
+        assert!(get("/other").starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+}
@@ -0,0 +1,174 @@
+// Copyright 2020 Ministerie van Defensie
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::histogram::Histogram;
+use crate::metrics::{MetricValue, Metrics};
+use crate::{Counter, Gauge};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, PoisonError};
+
+///The name and labels that identify a metric in the registry.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct MetricKey {
+    name: String,
+    labels: Vec<(String, String)>,
+}
+
+impl MetricKey {
+    fn new(name: &str, labels: &[(&str, &str)]) -> MetricKey {
+        MetricKey {
+            name: name.to_string(),
+            labels: labels
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+        }
+    }
+}
+
+///Counters, gauges and histograms a handler creates while it runs, identified by a name and labels.
+///Asking twice for the metric with the same name and labels returns the same metric,
+///so a handler can ask for the metric of a topic every time it handles a message of that topic.
+#[derive(Default)]
+pub struct MetricsRegistry {
+    counters: Mutex<BTreeMap<MetricKey, Arc<Counter>>>,
+    gauges: Mutex<BTreeMap<MetricKey, Arc<Gauge>>>,
+    histograms: Mutex<BTreeMap<MetricKey, Arc<Histogram>>>,
+}
+
+impl MetricsRegistry {
+    ///Returns the counter with the given name and labels, it is created when it does not exist.
+    /// # Arguments
+    /// * `name` - The name of the metric, for example `topic.out.packets`.
+    /// * `labels` - The labels of the metric, for example `[("topic", "TestTopic")]`.
+    pub fn counter(&self, name: &str, labels: &[(&str, &str)]) -> Arc<Counter> {
+        get_or_create(&self.counters, name, labels, Counter::default)
+    }
+
+    ///Returns the gauge with the given name and labels, it is created when it does not exist.
+    pub fn gauge(&self, name: &str, labels: &[(&str, &str)]) -> Arc<Gauge> {
+        get_or_create(&self.gauges, name, labels, Gauge::default)
+    }
+
+    ///Returns the histogram of values with the given name and labels, it is created when it does not exist.
+    pub fn histogram(&self, name: &str, labels: &[(&str, &str)]) -> Arc<Histogram> {
+        get_or_create(&self.histograms, name, labels, Histogram::new)
+    }
+
+    ///Returns the timer with the given name and labels, it is created when it does not exist.
+    pub fn timer(&self, name: &str, labels: &[(&str, &str)]) -> Arc<Histogram> {
+        get_or_create(&self.histograms, name, labels, Histogram::new_timer)
+    }
+
+    ///Adds the values of all metrics in the registry, counters and histograms start over.
+    pub fn fill_metrics(&self, metrics: &mut Metrics) {
+        for (key, counter) in lock(&self.counters).iter() {
+            metrics.add(
+                &key.name,
+                &key.labels,
+                MetricValue::Count(counter.get_and_reset()),
+            );
+        }
+        for (key, gauge) in lock(&self.gauges).iter() {
+            metrics.add(&key.name, &key.labels, MetricValue::Gauge(gauge.get()));
+        }
+        for (key, histogram) in lock(&self.histograms).iter() {
+            metrics.add(
+                &key.name,
+                &key.labels,
+                MetricValue::Histogram(histogram.take()),
+            );
+        }
+    }
+}
+
+fn get_or_create<T>(
+    map: &Mutex<BTreeMap<MetricKey, Arc<T>>>,
+    name: &str,
+    labels: &[(&str, &str)],
+    create: impl FnOnce() -> T,
+) -> Arc<T> {
+    lock(map)
+        .entry(MetricKey::new(name, labels))
+        .or_insert_with(|| Arc::new(create()))
+        .clone()
+}
+
+fn lock<T>(map: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    map.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn get_or_create_test() {
+        let registry = MetricsRegistry::default();
+        let counter = registry.counter("topic.out.packets", &[("topic", "a")]);
+        //the same name and labels give the same metric, other labels another one
+        assert!(Arc::ptr_eq(
+            &counter,
+            &registry.counter("topic.out.packets", &[("topic", "a")])
+        ));
+        let other = registry.counter("topic.out.packets", &[("topic", "b")]);
+        assert!(!Arc::ptr_eq(&counter, &other));
+        assert!(!Arc::ptr_eq(&counter, &registry.counter("topic.out.packets", &[])));
+        assert!(Arc::ptr_eq(
+            &registry.gauge("spool.messages", &[]),
+            &registry.gauge("spool.messages", &[])
+        ));
+        assert!(Arc::ptr_eq(
+            &registry.timer("produce_time", &[]),
+            &registry.timer("produce_time", &[])
+        ));
+    }
+
+    #[test]
+    fn fill_metrics_test() {
+        let registry = MetricsRegistry::default();
+        registry.counter("topic.out.packets", &[("topic", "a")]).add(2);
+        registry.counter("topic.out.packets", &[("topic", "a")]).add(3);
+        registry.counter("topic.out.packets", &[("topic", "b")]).add(1);
+        registry.gauge("spool.messages", &[]).set(7);
+        registry.timer("produce_time", &[]).record(10);
+
+        let mut metrics = Metrics::default();
+        registry.fill_metrics(&mut metrics);
+        let metrics: Vec<_> = metrics.iter().cloned().collect();
+        assert_eq!(metrics.len(), 4);
+        assert_eq!(metrics[0].name, "topic.out.packets");
+        assert_eq!(
+            metrics[0].labels,
+            vec![("topic".to_string(), "a".to_string())]
+        );
+        assert_eq!(metrics[0].value, MetricValue::Count(5.0));
+        assert_eq!(metrics[1].value, MetricValue::Count(1.0));
+        assert_eq!(metrics[2].value, MetricValue::Gauge(7.0));
+        match &metrics[3].value {
+            MetricValue::Histogram(snapshot) => {
+                assert!(snapshot.is_timer());
+                assert_eq!(snapshot.count(), 1);
+            }
+            value => panic!("unexpected value {:?}", value),
+        }
+
+        //counters and histograms start over, gauges keep their value
+        let mut metrics = Metrics::default();
+        registry.fill_metrics(&mut metrics);
+        let values: Vec<_> = metrics.iter().map(|metric| metric.value.clone()).collect();
+        assert_eq!(values[0], MetricValue::Count(0.0));
+        assert_eq!(values[2], MetricValue::Gauge(7.0));
+    }
+}